@@ -0,0 +1,242 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+/// Bumped whenever a field is removed or changes meaning. New optional fields keep the version.
+pub const AUDIT_SCHEMA_VERSION: u32 = 1;
+
+/// The gateway's record of one MCP request. Every request yields exactly one event, denied and
+/// failed ones included.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub schema_version: u32,
+    /// When the request arrived, as RFC 3339 in UTC.
+    pub timestamp: String,
+    /// Gateway-generated, and returned to the client as `x-request-id`.
+    pub request_id: String,
+    /// Unset when the request was not authenticated.
+    pub principal_id: Option<String>,
+    pub auth_mode: Option<AuthMode>,
+    /// The capability token's `jti`, for capability auth.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_jti: Option<String>,
+    /// The JSON-RPC method, or the HTTP method for requests without one.
+    pub method: String,
+    pub tool_name: Option<String>,
+    /// The `LatchkeyTool` operation the call invoked, when the tool declares operations.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operation: Option<String>,
+    pub session_id: Option<String>,
+    /// The W3C trace the request belongs to, as 32 hex digits.
+    pub trace_id: Option<String>,
+    /// The gateway's server span for the request, as 16 hex digits.
+    pub span_id: Option<String>,
+    pub decision: Decision,
+    pub deny_reason: Option<String>,
+    pub status: Outcome,
+    pub http_status: u16,
+    pub error_class: Option<ErrorClass>,
+    pub latency_ms: u64,
+    /// The tool server endpoint (`address:port`) that answered, or was tried last.
+    pub backend_server: Option<String>,
+    /// The answering attempt's latency.
+    pub backend_latency_ms: Option<u64>,
+    /// Every try at a tool call, including retries and hedges.
+    #[serde(default)]
+    pub attempts: Vec<Attempt>,
+    /// The call's params after the tool's redaction rules, for principals audited verbosely.
+    pub request_params_redacted: Option<Value>,
+    /// HMAC-SHA256 of the redacted params under the gateway's salt, at every audit level.
+    pub request_params_hash: Option<String>,
+    /// Bytes of the call's params as the client sent them.
+    pub request_params_bytes: Option<u64>,
+    pub response_summary: Option<ResponseSummary>,
+    /// The gateway process whose hash chain holds the event. Each process starts a new chain.
+    pub chain_id: Option<String>,
+    /// The event's position in its chain, from 0.
+    pub sequence: Option<u64>,
+    /// The previous event's `hash()`. Unset for the first event of a chain.
+    pub prev_hash: Option<String>,
+}
+
+impl AuditEvent {
+    /// An event for a request that has not been decided yet.
+    pub fn new(request_id: String, timestamp: String, method: String) -> Self {
+        Self {
+            schema_version: AUDIT_SCHEMA_VERSION,
+            timestamp,
+            request_id,
+            principal_id: None,
+            auth_mode: None,
+            token_jti: None,
+            method,
+            tool_name: None,
+            operation: None,
+            session_id: None,
+            trace_id: None,
+            span_id: None,
+            decision: Decision::Allow,
+            deny_reason: None,
+            status: Outcome::Success,
+            http_status: 200,
+            error_class: None,
+            latency_ms: 0,
+            backend_server: None,
+            backend_latency_ms: None,
+            attempts: Vec::new(),
+            request_params_redacted: None,
+            request_params_hash: None,
+            request_params_bytes: None,
+            response_summary: None,
+            chain_id: None,
+            sequence: None,
+            prev_hash: None,
+        }
+    }
+
+    /// Hex SHA-256 of the event's JSON, which the next event in the chain carries as `prev_hash`.
+    pub fn hash(&self) -> String {
+        let json = serde_json::to_vec(self).expect("audit events always serialize");
+        format!("{:x}", Sha256::digest(json))
+    }
+}
+
+/// A signed statement that a chain's events up to `sequence` ended in one hashing to `hash`, so
+/// an exported log cannot lose its newest events unnoticed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditCheckpoint {
+    pub schema_version: u32,
+    pub timestamp: String,
+    pub chain_id: String,
+    pub sequence: u64,
+    pub hash: String,
+    /// The first 16 hex digits of the signing key's Ed25519 public key.
+    pub key_id: String,
+    /// Hex Ed25519 signature over `signed_content()`.
+    pub signature: String,
+}
+
+impl AuditCheckpoint {
+    pub fn signed_content(&self) -> String {
+        format!(
+            "latchkey-audit-checkpoint/v1\n{}\n{}\n{}\n{}",
+            self.chain_id, self.sequence, self.hash, self.timestamp
+        )
+    }
+}
+
+/// One line of an exported audit log.
+// Nearly every record is an event, so boxing it would only add an allocation.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AuditRecord {
+    Checkpoint(AuditCheckpoint),
+    Event(AuditEvent),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMode {
+    /// A bearer token from the gateway's static token list.
+    Static,
+    Oidc,
+    Capability,
+}
+
+impl AuthMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Static => "static",
+            Self::Oidc => "oidc",
+            Self::Capability => "capability",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    Allow,
+    Deny,
+}
+
+impl Decision {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Allow => "allow",
+            Self::Deny => "deny",
+        }
+    }
+}
+
+/// Whether the request succeeded. A tool that runs and reports `isError` is an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Success,
+    Error,
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// The broad kind of failure; `deny_reason` carries the specific one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorClass {
+    Authentication,
+    Authorization,
+    RateLimit,
+    Validation,
+    Timeout,
+    Backend,
+    /// The client went away before the response.
+    Client,
+    Internal,
+}
+
+impl ErrorClass {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Authentication => "authentication",
+            Self::Authorization => "authorization",
+            Self::RateLimit => "rate_limit",
+            Self::Validation => "validation",
+            Self::Timeout => "timeout",
+            Self::Backend => "backend",
+            Self::Client => "client",
+            Self::Internal => "internal",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attempt {
+    pub endpoint: String,
+    /// `answered`, a deny reason, or `cancelled` for the slower half of a hedge.
+    pub outcome: String,
+    pub latency_ms: u64,
+    pub hedged: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResponseSummary {
+    /// Bytes of the response sent to the client.
+    pub bytes: u64,
+    /// Events relayed, for streamed responses.
+    pub events: Option<u64>,
+    /// Whether the tool reported `isError` or the server answered with a JSON-RPC error.
+    pub is_error: bool,
+    /// HMAC-SHA256 of the redacted JSON-RPC response, like `request_params_hash`.
+    pub hash: Option<String>,
+    /// The JSON-RPC response after the tool's redaction rules, for principals audited verbosely.
+    pub redacted: Option<Value>,
+}
@@ -0,0 +1,12 @@
+/// Directory the bridge binary is copied into so it can wrap a stdio server in its own container.
+pub const INSTALL_DIR: &str = "/var/run/latchkey-bridge";
+pub const BINARY: &str = "latchkey-mcp-bridge";
+
+/// Listen address for the bridge's Streamable HTTP endpoint, e.g. `0.0.0.0:8081`.
+pub const BIND_ENV: &str = "LATCHKEY_BRIDGE_BIND";
+
+pub const MCP_PATH: &str = "/mcp";
+
+/// Returns 200 once the stdio server is running and initialized. `/healthz` only covers the bridge
+/// itself, so a crash-looping server is pulled from the Service without restarting the pod.
+pub const READY_PATH: &str = "/readyz";
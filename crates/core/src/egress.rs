@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+
+/// Env var carrying the JSON-encoded `Vec<AllowedHost>` the operator hands the egress proxy.
+pub const PROXY_ALLOW_ENV: &str = "LATCHKEY_EGRESS_ALLOW";
+
+pub const PROXY_PORT: u16 = 3128;
+
+/// A hostname the proxy may reach. `host` is either exact (`api.github.com`) or a leading
+/// wildcard (`*.githubusercontent.com`) that matches any subdomain but not the apex.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AllowedHost {
+    pub host: String,
+    pub ports: Vec<u16>,
+}
+
+impl AllowedHost {
+    pub fn allows(&self, host: &str, port: u16) -> bool {
+        self.ports.contains(&port) && host_matches(&self.host, host)
+    }
+}
+
+pub fn host_matches(pattern: &str, host: &str) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    let pattern = pattern.to_ascii_lowercase();
+
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => {
+            host.len() > suffix.len() + 1
+                && host.ends_with(suffix)
+                && host.as_bytes()[host.len() - suffix.len() - 1] == b'.'
+        }
+        None => host == pattern,
+    }
+}
+
+/// Whether `pattern` is a lowercase DNS name, optionally with a single leading `*.` label.
+pub fn is_host_pattern(pattern: &str) -> bool {
+    let name = pattern.strip_prefix("*.").unwrap_or(pattern);
+
+    !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        })
+}
@@ -1,3 +1,11 @@
+pub mod audit;
+pub mod bridge;
+pub mod egress;
+pub mod redaction;
+pub mod scope;
+pub mod trace;
+pub mod vault;
+
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -0,0 +1,205 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Where a `LatchkeyTool` redaction rule applies: a JSON pointer such as
+/// `/params/arguments/apiKey`, or a dotted glob such as `response.result.**.token` where `*`
+/// matches within one key and `**` matches any number of levels. Either form starts at the
+/// call's `params` or at its `response`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedactionPath {
+    pub target: RedactionTarget,
+    pub segments: Vec<PathSegment>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedactionTarget {
+    Params,
+    Response,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSegment {
+    /// An object key or array index, matched exactly.
+    Key(String),
+    /// A key pattern in which `*` matches any run of characters.
+    Glob(String),
+    /// `**`: zero or more levels.
+    AnyDepth,
+}
+
+impl PathSegment {
+    /// Whether a single key or array index matches. `AnyDepth` matches any one of them.
+    pub fn matches(&self, key: &str) -> bool {
+        match self {
+            Self::Key(literal) => literal == key,
+            Self::Glob(pattern) => glob_matches(pattern, key),
+            Self::AnyDepth => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RedactionAction {
+    /// Removes the value.
+    Drop,
+    /// Replaces the value with a fixed marker.
+    Mask,
+    /// Replaces the value with its HMAC under the gateway's salt, so equal values stay
+    /// recognizable without being readable.
+    Hash,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedactionPathParseError {
+    path: String,
+    reason: &'static str,
+}
+
+impl fmt::Display for RedactionPathParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid redaction path {:?}: {}", self.path, self.reason)
+    }
+}
+
+impl std::error::Error for RedactionPathParseError {}
+
+impl FromStr for RedactionPath {
+    type Err = RedactionPathParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let error = |reason| RedactionPathParseError { path: input.to_string(), reason };
+
+        let mut parts: Vec<String> = match input.strip_prefix('/') {
+            Some(pointer) => pointer.split('/').map(unescape_pointer).collect(),
+            None => input.split('.').map(str::to_string).collect(),
+        };
+        let target = match parts.first().map(String::as_str) {
+            Some("params") => RedactionTarget::Params,
+            Some("response") => RedactionTarget::Response,
+            _ => return Err(error("paths must start at `params` or `response`")),
+        };
+        parts.remove(0);
+        if parts.is_empty() {
+            return Err(error("paths must name a field below `params` or `response`"));
+        }
+
+        let segments: Vec<PathSegment> = if input.starts_with('/') {
+            parts.into_iter().map(PathSegment::Key).collect()
+        } else {
+            if parts.iter().any(String::is_empty) {
+                return Err(error("glob paths must not have empty segments"));
+            }
+            parts
+                .into_iter()
+                .map(|part| match part.as_str() {
+                    "**" => PathSegment::AnyDepth,
+                    _ if part.contains('*') => PathSegment::Glob(part),
+                    _ => PathSegment::Key(part),
+                })
+                .collect()
+        };
+        if segments.last() == Some(&PathSegment::AnyDepth) {
+            return Err(error("`**` must be followed by a key"));
+        }
+
+        Ok(Self { target, segments })
+    }
+}
+
+fn unescape_pointer(part: &str) -> String {
+    part.replace("~1", "/").replace("~0", "~")
+}
+
+/// Matches `key` against a pattern where `*` stands for any run of characters.
+fn glob_matches(pattern: &str, key: &str) -> bool {
+    let mut pieces = pattern.split('*');
+    let first = pieces.next().unwrap_or_default();
+    let Some(mut rest) = key.strip_prefix(first) else {
+        return false;
+    };
+    let pieces: Vec<&str> = pieces.collect();
+    let Some((last, middle)) = pieces.split_last() else {
+        return rest.is_empty();
+    };
+    for piece in middle {
+        match rest.find(piece) {
+            Some(index) => rest = &rest[index + piece.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key: &str) -> PathSegment {
+        PathSegment::Key(key.to_string())
+    }
+
+    fn glob(pattern: &str) -> PathSegment {
+        PathSegment::Glob(pattern.to_string())
+    }
+
+    #[test]
+    fn parses_pointers_and_globs() {
+        let pointer: RedactionPath = "/params/arguments/api~1key~0old/a*b".parse().unwrap();
+        assert_eq!(pointer.target, RedactionTarget::Params);
+        // Pointers are literal: `*` and `.` carry no meaning in them.
+        assert_eq!(pointer.segments, [key("arguments"), key("api/key~old"), key("a*b")]);
+
+        let dotted: RedactionPath = "response.result.**.*token*.0".parse().unwrap();
+        assert_eq!(dotted.target, RedactionTarget::Response);
+        assert_eq!(
+            dotted.segments,
+            [key("result"), PathSegment::AnyDepth, glob("*token*"), key("0")]
+        );
+    }
+
+    #[test]
+    fn rejects_paths_it_cannot_apply() {
+        for (path, reason) in [
+            ("arguments.token", "paths must start at `params` or `response`"),
+            ("/request/token", "paths must start at `params` or `response`"),
+            ("params", "paths must name a field below `params` or `response`"),
+            ("/response", "paths must name a field below `params` or `response`"),
+            ("params..token", "glob paths must not have empty segments"),
+            ("params.arguments.", "glob paths must not have empty segments"),
+            ("params.arguments.**", "`**` must be followed by a key"),
+        ] {
+            let err = path.parse::<RedactionPath>().unwrap_err();
+            assert_eq!(err.to_string(), format!("invalid redaction path {path:?}: {reason}"));
+        }
+    }
+
+    #[test]
+    fn matches_keys_literally_or_by_glob() {
+        for (segment, matching, other) in [
+            (key("token"), "token", "tokens"),
+            (key("0"), "0", "00"),
+            (glob("*password*"), "db_password_2", "passwd"),
+            (glob("*password*"), "password", "pass"),
+            (glob("api_*"), "api_key", "x_api_key"),
+            (glob("*_id"), "user_id", "id"),
+            (glob("to*en"), "token", "toke"),
+            (glob("a*b*c"), "axxbyyc", "acb"),
+            (glob("a*a"), "aba", "a"),
+        ] {
+            assert!(segment.matches(matching), "{segment:?} should match {matching:?}");
+            assert!(!segment.matches(other), "{segment:?} should not match {other:?}");
+        }
+        assert!(glob("*").matches(""));
+        assert!(PathSegment::AnyDepth.matches("anything"));
+    }
+
+    #[test]
+    fn reads_actions_in_lowercase() {
+        let actions: Vec<RedactionAction> =
+            serde_json::from_str(r#"["drop", "mask", "hash"]"#).unwrap();
+        assert_eq!(actions, [RedactionAction::Drop, RedactionAction::Mask, RedactionAction::Hash]);
+        assert!(serde_json::from_str::<RedactionAction>(r#""Drop""#).is_err());
+    }
+}
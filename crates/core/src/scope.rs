@@ -0,0 +1,88 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A tool scope such as `tools:github.search:call` or `tools:github.search:op:list`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Scope {
+    pub tool: String,
+    pub action: ScopeAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ScopeAction {
+    Call,
+    Read,
+    Write,
+    Admin,
+    Operation(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScopeParseError {
+    scope: String,
+    reason: &'static str,
+}
+
+impl fmt::Display for ScopeParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid scope {:?}: {}", self.scope, self.reason)
+    }
+}
+
+impl std::error::Error for ScopeParseError {}
+
+impl FromStr for Scope {
+    type Err = ScopeParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let error = |reason| ScopeParseError { scope: input.to_string(), reason };
+
+        let mut parts = input.split(':');
+        if parts.next() != Some("tools") {
+            return Err(error("scopes must start with `tools:`"));
+        }
+
+        let tool = parts
+            .next()
+            .filter(|tool| is_valid_name(tool))
+            .ok_or_else(|| error("tool name must be non-empty and use only [A-Za-z0-9._-]"))?;
+
+        let action = match (parts.next(), parts.next()) {
+            (Some("call"), None) => ScopeAction::Call,
+            (Some("read"), None) => ScopeAction::Read,
+            (Some("write"), None) => ScopeAction::Write,
+            (Some("admin"), None) => ScopeAction::Admin,
+            (Some("op"), Some(operation)) if is_valid_name(operation) => {
+                ScopeAction::Operation(operation.to_string())
+            }
+            (Some("op"), _) => {
+                return Err(error("operation must be non-empty and use only [A-Za-z0-9._-]"))
+            }
+            _ => return Err(error("action must be one of call, read, write, admin or op:<name>")),
+        };
+
+        if parts.next().is_some() {
+            return Err(error("unexpected trailing segments"));
+        }
+
+        Ok(Self { tool: tool.to_string(), action })
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.action {
+            ScopeAction::Call => write!(f, "tools:{}:call", self.tool),
+            ScopeAction::Read => write!(f, "tools:{}:read", self.tool),
+            ScopeAction::Write => write!(f, "tools:{}:write", self.tool),
+            ScopeAction::Admin => write!(f, "tools:{}:admin", self.tool),
+            ScopeAction::Operation(operation) => write!(f, "tools:{}:op:{operation}", self.tool),
+        }
+    }
+}
+
+/// Tool and operation names share the character set allowed inside scope segments.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
@@ -0,0 +1,463 @@
+//! W3C trace context propagation and OTLP span export, shared by every service on a tool call's
+//! path so the call reads as one trace: gateway, tool server and upstream.
+
+use serde_json::{json, Value};
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+pub const TRACEPARENT: &str = "traceparent";
+pub const TRACESTATE: &str = "tracestate";
+
+const QUEUE_CAPACITY: usize = 2048;
+const BATCH_SIZE: usize = 256;
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+const SAMPLED: u8 = 0x01;
+
+/// Where a span sits in a trace, and whether the trace is being recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    /// W3C trace flags. Only `sampled` is defined.
+    pub flags: u8,
+    /// `tracestate`, passed on untouched.
+    pub state: Option<String>,
+}
+
+impl TraceContext {
+    /// Parses `traceparent` and `tracestate` header values. Versions after `00` are read by their
+    /// `00` fields, as the spec asks; anything malformed starts a new trace instead.
+    pub fn parse(traceparent: &str, tracestate: Option<&str>) -> Option<Self> {
+        let mut fields = traceparent.trim().split('-');
+        let version = fields.next().filter(|version| version.len() == 2)?;
+        let version = u8::from_str_radix(version, 16).ok()?;
+        let trace_id: [u8; 16] = from_hex(fields.next()?)?.try_into().ok()?;
+        let span_id: [u8; 8] = from_hex(fields.next()?)?.try_into().ok()?;
+        let flags = fields.next().filter(|flags| flags.len() == 2)?;
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        if version == 0xff || (version == 0 && fields.next().is_some()) {
+            return None;
+        }
+        if trace_id == [0; 16] || span_id == [0; 8] {
+            return None;
+        }
+        let state = tracestate.map(str::trim).filter(|state| !state.is_empty()).map(str::to_string);
+        Some(Self { trace_id, span_id, flags, state })
+    }
+
+    /// Reads the context from request headers by name.
+    pub fn extract<'a>(header: impl Fn(&str) -> Option<&'a str>) -> Option<Self> {
+        Self::parse(header(TRACEPARENT)?, header(TRACESTATE))
+    }
+
+    /// The headers that carry this context to the next hop.
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = vec![(TRACEPARENT, self.traceparent())];
+        if let Some(state) = &self.state {
+            headers.push((TRACESTATE, state.clone()));
+        }
+        headers
+    }
+
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-{:02x}", self.trace_id_hex(), self.span_id_hex(), self.flags)
+    }
+
+    pub fn trace_id_hex(&self) -> String {
+        to_hex(&self.trace_id)
+    }
+
+    pub fn span_id_hex(&self) -> String {
+        to_hex(&self.span_id)
+    }
+
+    pub fn sampled(&self) -> bool {
+        self.flags & SAMPLED != 0
+    }
+
+    fn root() -> Self {
+        Self {
+            trace_id: *uuid::Uuid::new_v4().as_bytes(),
+            span_id: new_span_id(),
+            flags: SAMPLED,
+            state: None,
+        }
+    }
+
+    fn child(&self) -> Self {
+        Self { span_id: new_span_id(), ..self.clone() }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpanKind {
+    Internal = 1,
+    Server = 2,
+    Client = 3,
+}
+
+/// Starts spans and exports the sampled ones over OTLP/HTTP with JSON encoding. A disabled
+/// tracer still hands out ids, so trace context keeps flowing between services.
+#[derive(Clone, Default)]
+pub struct Tracer(Option<Arc<Exporter>>);
+
+struct Exporter {
+    sender: mpsc::Sender<Value>,
+}
+
+impl Tracer {
+    /// Exports to `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`, or to `OTEL_EXPORTER_OTLP_ENDPOINT` plus
+    /// `/v1/traces`, naming the service `OTEL_SERVICE_NAME` or else `service`. Without an
+    /// endpoint, spans are not exported. Must be called within a Tokio runtime.
+    pub fn from_env(service: &str) -> Result<Self, reqwest::Error> {
+        let endpoint = std::env::var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT").ok().or_else(|| {
+            let base = std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok()?;
+            Some(format!("{}/v1/traces", base.trim_end_matches('/')))
+        });
+        let Some(endpoint) = endpoint else {
+            return Ok(Self::default());
+        };
+        let service = std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| service.to_string());
+        Self::exporting(endpoint, service)
+    }
+
+    /// A tracer that exports every sampled span to `endpoint`.
+    pub fn exporting(endpoint: String, service: String) -> Result<Self, reqwest::Error> {
+        let client = reqwest::Client::builder().timeout(EXPORT_TIMEOUT).build()?;
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(export(client, endpoint, service, receiver));
+        Ok(Self(Some(Arc::new(Exporter { sender }))))
+    }
+
+    /// Starts a span under `parent`, or a new trace without one.
+    pub fn span(&self, name: &str, kind: SpanKind, parent: Option<&TraceContext>) -> Span {
+        let context = parent.map_or_else(TraceContext::root, TraceContext::child);
+        let data = self.0.as_ref().filter(|_| context.sampled()).map(|_| SpanData {
+            name: name.to_string(),
+            kind,
+            context: context.clone(),
+            parent: parent.map(|parent| parent.span_id),
+            start: SystemTime::now(),
+            attributes: Vec::new(),
+            error: None,
+        });
+        Span { tracer: self.clone(), context, data }
+    }
+}
+
+/// An open span, ended and queued for export when dropped.
+pub struct Span {
+    tracer: Tracer,
+    context: TraceContext,
+    /// Unset when the span is not exported.
+    data: Option<SpanData>,
+}
+
+impl Span {
+    /// The context for this span's children and for the next hop.
+    pub fn context(&self) -> &TraceContext {
+        &self.context
+    }
+
+    /// Starts a span under this one.
+    pub fn child(&self, name: &str, kind: SpanKind) -> Span {
+        self.tracer.span(name, kind, Some(&self.context))
+    }
+
+    pub fn set_attribute(&mut self, key: &str, value: impl Into<Value>) {
+        if let Some(data) = &mut self.data {
+            data.attributes.push((key.to_string(), value.into()));
+        }
+    }
+
+    /// Marks the span failed.
+    pub fn set_error(&mut self, message: impl Into<String>) {
+        if let Some(data) = &mut self.data {
+            data.error = Some(message.into());
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let (Some(data), Some(exporter)) = (self.data.take(), &self.tracer.0) else {
+            return;
+        };
+        // A full queue loses the span rather than holding up the request.
+        let _ = exporter.sender.try_send(data.ended(SystemTime::now()));
+    }
+}
+
+struct SpanData {
+    name: String,
+    kind: SpanKind,
+    context: TraceContext,
+    parent: Option<[u8; 8]>,
+    start: SystemTime,
+    attributes: Vec<(String, Value)>,
+    error: Option<String>,
+}
+
+impl SpanData {
+    /// The span as an OTLP `Span`.
+    fn ended(self, end: SystemTime) -> Value {
+        let attributes: Vec<Value> = self
+            .attributes
+            .into_iter()
+            .filter_map(|(key, value)| Some(json!({"key": key, "value": otlp_value(value)?})))
+            .collect();
+        let mut span = json!({
+            "traceId": self.context.trace_id_hex(),
+            "spanId": self.context.span_id_hex(),
+            "name": self.name,
+            "kind": self.kind as u8,
+            "startTimeUnixNano": unix_nanos(self.start),
+            "endTimeUnixNano": unix_nanos(end),
+            "attributes": attributes,
+            "status": match self.error {
+                Some(message) => json!({"code": 2, "message": message}),
+                None => json!({"code": 0}),
+            },
+        });
+        if let Some(parent) = self.parent {
+            span["parentSpanId"] = json!(to_hex(&parent));
+        }
+        if let Some(state) = self.context.state {
+            span["traceState"] = json!(state);
+        }
+        span
+    }
+}
+
+/// Posts queued spans in batches until every tracer is gone. Failed batches are dropped.
+async fn export(
+    client: reqwest::Client,
+    endpoint: String,
+    service: String,
+    mut receiver: mpsc::Receiver<Value>,
+) {
+    let mut spans = Vec::with_capacity(BATCH_SIZE);
+    while receiver.recv_many(&mut spans, BATCH_SIZE).await > 0 {
+        let body = otlp_traces(&service, std::mem::take(&mut spans));
+        let result = client.post(&endpoint).json(&body).send().await;
+        match result.and_then(reqwest::Response::error_for_status) {
+            Ok(_) => {}
+            Err(err) => warn!(endpoint = %endpoint, error = %err, "failed to export spans"),
+        }
+    }
+}
+
+/// An OTLP `ExportTraceServiceRequest` for one service's spans.
+pub fn otlp_traces(service: &str, spans: Vec<Value>) -> Value {
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [{"key": "service.name", "value": {"stringValue": service}}],
+            },
+            "scopeSpans": [{
+                "scope": {"name": "latchkey", "version": env!("CARGO_PKG_VERSION")},
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+/// An OTLP `AnyValue`. Lists and objects are sent as JSON text.
+pub fn otlp_value(value: Value) -> Option<Value> {
+    Some(match value {
+        Value::Null => return None,
+        Value::Bool(value) => json!({"boolValue": value}),
+        // OTLP's JSON encoding carries 64-bit integers as strings.
+        Value::Number(value) if value.is_i64() || value.is_u64() => {
+            json!({"intValue": value.to_string()})
+        }
+        Value::Number(value) => json!({"doubleValue": value.as_f64()}),
+        Value::String(value) => json!({"stringValue": value}),
+        other => json!({"stringValue": other.to_string()}),
+    })
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos().to_string()
+}
+
+fn new_span_id() -> [u8; 8] {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    let mut span_id = [0; 8];
+    span_id.copy_from_slice(&bytes[..8]);
+    span_id
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+/// Lowercase hex only, as trace context requires.
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::post, Json, Router};
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const SPAN_ID: &str = "00f067aa0ba902b7";
+
+    fn traceparent(version: &str, trace_id: &str, span_id: &str, flags: &str) -> String {
+        format!("{version}-{trace_id}-{span_id}-{flags}")
+    }
+
+    #[test]
+    fn parses_and_formats_traceparent() {
+        let header = traceparent("00", TRACE_ID, SPAN_ID, "01");
+        let context = TraceContext::parse(&format!(" {header} "), None).unwrap();
+        assert_eq!(
+            (context.trace_id_hex(), context.span_id_hex()),
+            (TRACE_ID.into(), SPAN_ID.into())
+        );
+        assert!(context.sampled());
+        assert_eq!(context.traceparent(), header);
+
+        let unsampled = TraceContext::parse(&traceparent("00", TRACE_ID, SPAN_ID, "00"), None);
+        assert!(!unsampled.unwrap().sampled());
+        // Unknown flags are kept, and only `sampled` is read from them.
+        let flagged = TraceContext::parse(&traceparent("00", TRACE_ID, SPAN_ID, "03"), None);
+        let flagged = flagged.unwrap();
+        assert!(flagged.sampled());
+        assert!(flagged.traceparent().ends_with("-03"));
+
+        // A later version is read by its version 00 fields and passed on as version 00.
+        let future = format!("{}-later-fields", traceparent("cc", TRACE_ID, SPAN_ID, "01"));
+        assert_eq!(TraceContext::parse(&future, None).unwrap().traceparent(), header);
+    }
+
+    #[test]
+    fn rejects_malformed_traceparent() {
+        let zeros = (&*"0".repeat(32), &*"0".repeat(16));
+        for header in [
+            traceparent("ff", TRACE_ID, SPAN_ID, "01"),
+            traceparent("0", TRACE_ID, SPAN_ID, "01"),
+            traceparent("zz", TRACE_ID, SPAN_ID, "01"),
+            format!("{}-extra", traceparent("00", TRACE_ID, SPAN_ID, "01")),
+            traceparent("00", zeros.0, SPAN_ID, "01"),
+            traceparent("00", TRACE_ID, zeros.1, "01"),
+            traceparent("00", &TRACE_ID.to_uppercase(), SPAN_ID, "01"),
+            traceparent("00", &TRACE_ID[2..], SPAN_ID, "01"),
+            traceparent("00", TRACE_ID, &format!("{SPAN_ID}00"), "01"),
+            traceparent("00", TRACE_ID, SPAN_ID, "1"),
+            traceparent("00", TRACE_ID, SPAN_ID, "0g"),
+            format!("00-{TRACE_ID}-{SPAN_ID}"),
+            String::new(),
+        ] {
+            assert_eq!(TraceContext::parse(&header, Some("vendor=1")), None, "{header:?}");
+        }
+    }
+
+    #[test]
+    fn passes_tracestate_through() {
+        let header = traceparent("00", TRACE_ID, SPAN_ID, "01");
+        let headers = [(TRACEPARENT, header.as_str()), (TRACESTATE, " vendor=1,other=a:b ")];
+        let lookup = |name: &str| headers.iter().find(|(key, _)| *key == name).map(|(_, v)| *v);
+        let context = TraceContext::extract(lookup).unwrap();
+        assert_eq!(context.state.as_deref(), Some("vendor=1,other=a:b"));
+
+        let child = context.child();
+        assert_eq!((child.trace_id, child.flags), (context.trace_id, context.flags));
+        assert_ne!(child.span_id, context.span_id);
+        assert_eq!(
+            child.headers(),
+            [
+                (TRACEPARENT, traceparent("00", TRACE_ID, &child.span_id_hex(), "01")),
+                (TRACESTATE, "vendor=1,other=a:b".to_string()),
+            ]
+        );
+
+        let stateless = TraceContext::parse(&header, Some("  ")).unwrap();
+        assert_eq!(stateless.state, None);
+        assert_eq!(stateless.headers(), [(TRACEPARENT, header)]);
+        assert_eq!(TraceContext::extract(|_| None), None);
+    }
+
+    #[tokio::test]
+    async fn exports_sampled_spans_to_the_collector() {
+        // A collector that passes on every span exported to it.
+        let (exported, mut spans) = mpsc::unbounded_channel();
+        let collector = Router::new().route(
+            "/v1/traces",
+            post(move |Json(body): Json<Value>| {
+                let exported = exported.clone();
+                async move {
+                    assert_eq!(
+                        body["resourceSpans"][0]["resource"]["attributes"][0]["value"],
+                        json!({ "stringValue": "latchkey-test" })
+                    );
+                    for span in
+                        body["resourceSpans"][0]["scopeSpans"][0]["spans"].as_array().unwrap()
+                    {
+                        exported.send(span.clone()).unwrap();
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/v1/traces", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, collector).await });
+        let tracer = Tracer::exporting(endpoint, "latchkey-test".to_string()).unwrap();
+
+        let unsampled = TraceContext::parse(&traceparent("00", TRACE_ID, SPAN_ID, "00"), None);
+        drop(tracer.span("unsampled", SpanKind::Server, unsampled.as_ref()));
+        let incoming =
+            TraceContext::parse(&traceparent("00", TRACE_ID, SPAN_ID, "01"), Some("vendor=1"));
+        let mut root = tracer.span("POST /v1/mcp", SpanKind::Server, incoming.as_ref());
+        root.set_attribute("http.response.status_code", 200);
+        let mut call = root.child("backend call", SpanKind::Client);
+        call.set_attribute("retries", json!([1, 2]));
+        call.set_attribute("skipped", Value::Null);
+        call.set_error("backend_timeout");
+        let (root_id, call_id) = (root.context().span_id_hex(), call.context().span_id_hex());
+        drop((call, root));
+
+        let mut exported = Vec::new();
+        while exported.len() < 2 {
+            let span = tokio::time::timeout(Duration::from_secs(5), spans.recv()).await;
+            exported.push(span.unwrap().unwrap());
+        }
+        exported.sort_by_key(|span| span["kind"].as_u64());
+        let [server, client] = &exported[..] else { unreachable!() };
+        assert_eq!(server["name"], "POST /v1/mcp");
+        assert_eq!((&server["traceId"], &server["spanId"]), (&json!(TRACE_ID), &json!(root_id)));
+        assert_eq!(server["parentSpanId"], SPAN_ID);
+        assert_eq!(server["traceState"], "vendor=1");
+        assert_eq!(server["status"], json!({ "code": 0 }));
+        assert_eq!(
+            server["attributes"],
+            json!([{ "key": "http.response.status_code", "value": { "intValue": "200" } }])
+        );
+        assert_eq!(client["name"], "backend call");
+        assert_eq!((&client["traceId"], &client["spanId"]), (&json!(TRACE_ID), &json!(call_id)));
+        assert_eq!(client["parentSpanId"], root_id);
+        assert_eq!(client["status"], json!({ "code": 2, "message": "backend_timeout" }));
+        assert_eq!(
+            client["attributes"],
+            json!([{ "key": "retries", "value": { "stringValue": "[1,2]" } }])
+        );
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(spans.try_recv().is_err(), "the unsampled span is never exported");
+    }
+}
@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+
+/// Env var carrying the JSON-encoded `Vec<AgentBinding>` the operator hands the Vault agent.
+pub const AGENT_CONFIG_ENV: &str = "LATCHKEY_VAULT_AGENT_CONFIG";
+
+/// Where the operator projects the audience-bound service account token for Kubernetes auth.
+pub const AGENT_TOKEN_DIR: &str = "/var/run/secrets/latchkey-vault";
+pub const AGENT_TOKEN_FILE: &str = "token";
+
+pub const AGENT_HEALTH_PORT: u16 = 8099;
+
+pub const DEFAULT_AUTH_MOUNT: &str = "kubernetes";
+
+/// One credential the agent keeps current on disk.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentBinding {
+    pub name: String,
+    pub address: String,
+    pub auth_mount: String,
+    pub role: String,
+    /// API path below `/v1/`, e.g. `secret/data/github` (KV v2) or `database/creds/readonly`.
+    pub path: String,
+    pub key: String,
+    /// Absolute file the value is atomically rewritten to.
+    pub file: String,
+}
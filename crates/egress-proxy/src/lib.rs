@@ -0,0 +1,9 @@
+//! Hostname-allowlisting egress proxy for tool servers.
+//!
+//! NetworkPolicy only speaks CIDRs, so servers that declare `egressPolicy.hosts` get this proxy as
+//! a sidecar and reach the outside world through it. It accepts `CONNECT` tunnels (which must
+//! carry TLS whose SNI names the tunnelled host) and plain-HTTP forward requests, and logs an
+//! audit event for every attempt.
+
+pub mod proxy;
+pub mod sni;
@@ -0,0 +1,46 @@
+use anyhow::Context;
+use latchkey_core::egress::{AllowedHost, PROXY_ALLOW_ENV, PROXY_PORT};
+use latchkey_egress_proxy::proxy::{self, ProxyConfig};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    init_tracing();
+
+    let allow =
+        std::env::var(PROXY_ALLOW_ENV).with_context(|| format!("{PROXY_ALLOW_ENV} must be set"))?;
+    let allow: Vec<AllowedHost> =
+        serde_json::from_str(&allow).with_context(|| format!("invalid {PROXY_ALLOW_ENV} value"))?;
+    let config = ProxyConfig {
+        server: std::env::var("LATCHKEY_EGRESS_SERVER").unwrap_or_else(|_| "unknown".to_string()),
+        allow,
+        permit_loopback: std::env::var("LATCHKEY_EGRESS_PERMIT_LOOPBACK")
+            .map(|value| value == "true")
+            .unwrap_or(false),
+    };
+
+    // Loopback only: the proxy is reachable from its own pod and nowhere else.
+    let bind = std::env::var("LATCHKEY_EGRESS_PROXY_BIND")
+        .unwrap_or_else(|_| format!("127.0.0.1:{PROXY_PORT}"));
+    let addr: SocketAddr = bind.parse().context("invalid LATCHKEY_EGRESS_PROXY_BIND value")?;
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("failed to bind egress proxy listener on {addr}"))?;
+
+    info!(%addr, server = %config.server, hosts = config.allow.len(), "egress proxy booted");
+    proxy::serve(listener, Arc::new(config)).await.context("egress proxy failed")
+}
+
+fn init_tracing() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    tracing_subscriber::fmt()
+        .json()
+        .with_env_filter(filter)
+        .with_current_span(false)
+        .with_span_list(false)
+        .init();
+}
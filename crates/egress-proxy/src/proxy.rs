@@ -0,0 +1,373 @@
+use crate::sni;
+use latchkey_core::egress::AllowedHost;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{timeout, Instant};
+use tracing::{info, warn};
+
+const MAX_HEAD_BYTES: usize = 16 * 1024;
+const HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_HTTP_PORT: u16 = 80;
+
+/// Request headers that only concern the hop to this proxy and are never forwarded.
+const HOP_HEADERS: &[&str] =
+    &["connection", "proxy-connection", "proxy-authorization", "keep-alive"];
+
+#[derive(Debug, Clone)]
+pub struct ProxyConfig {
+    /// The `LatchkeyServer` this proxy fronts, recorded on every audit event.
+    pub server: String,
+    pub allow: Vec<AllowedHost>,
+    /// Lets allowed names resolve to loopback. The sidecar shares the tool server's loopback, so
+    /// this is only useful for local runs against services on the same host.
+    pub permit_loopback: bool,
+}
+
+impl ProxyConfig {
+    fn allows(&self, host: &str, port: u16) -> bool {
+        self.allow.iter().any(|allowed| allowed.allows(host, port))
+    }
+}
+
+pub async fn serve(listener: TcpListener, config: Arc<ProxyConfig>) -> io::Result<()> {
+    loop {
+        let (client, _) = listener.accept().await?;
+        let config = config.clone();
+        tokio::spawn(async move { handle(client, &config).await });
+    }
+}
+
+/// One egress attempt, logged exactly once when it is decided or finishes.
+struct Attempt<'a> {
+    config: &'a ProxyConfig,
+    mode: &'static str,
+    host: String,
+    port: u16,
+    started: Instant,
+}
+
+impl Attempt<'_> {
+    fn allowed(&self, bytes_sent: u64, bytes_received: u64) {
+        self.emit("allow", "", bytes_sent, bytes_received);
+    }
+
+    fn denied(&self, reason: &str) {
+        self.emit("deny", reason, 0, 0);
+    }
+
+    fn emit(&self, decision: &str, deny_reason: &str, bytes_sent: u64, bytes_received: u64) {
+        let latency_ms = self.started.elapsed().as_millis() as u64;
+
+        info!(
+            event_type = "egress_audit",
+            server = %self.config.server,
+            mode = self.mode,
+            host = %self.host,
+            port = self.port,
+            decision,
+            deny_reason,
+            bytes_sent,
+            bytes_received,
+            latency_ms,
+            "egress attempt"
+        );
+    }
+}
+
+struct Head {
+    method: String,
+    target: String,
+    version: String,
+    headers: Vec<(String, String)>,
+    /// Bytes the client sent after the head (request body or TLS ClientHello).
+    rest: Vec<u8>,
+}
+
+async fn handle(mut client: TcpStream, config: &ProxyConfig) {
+    let started = Instant::now();
+    let head = match timeout(HEAD_TIMEOUT, read_head(&mut client)).await {
+        Ok(Ok(Some(head))) => head,
+        _ => {
+            let _ = respond(&mut client, "400 Bad Request", "malformed proxy request").await;
+            return;
+        }
+    };
+
+    if head.method.eq_ignore_ascii_case("CONNECT") {
+        tunnel(client, head, config, started).await;
+    } else {
+        forward(client, head, config, started).await;
+    }
+}
+
+/// `CONNECT host:port`: the tunnel must open with a TLS ClientHello whose SNI is `host`, so an
+/// allowed hostname cannot be used to reach some other virtual host on the same address.
+async fn tunnel(mut client: TcpStream, head: Head, config: &ProxyConfig, started: Instant) {
+    let Some((host, port)) = split_authority(&head.target, None) else {
+        let _ = respond(&mut client, "400 Bad Request", "CONNECT target must be host:port").await;
+        return;
+    };
+    let attempt = Attempt { config, mode: "connect", host, port, started };
+
+    let mut upstream = match open(&attempt).await {
+        Ok(upstream) => upstream,
+        Err((status, reason)) => {
+            attempt.denied(reason);
+            let _ = respond(&mut client, status, reason).await;
+            return;
+        }
+    };
+
+    if client.write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n").await.is_err() {
+        return;
+    }
+
+    let hello = match timeout(HEAD_TIMEOUT, read_client_hello(&mut client, head.rest)).await {
+        Ok(Ok(hello)) => hello,
+        _ => {
+            attempt.denied("tls_required");
+            return;
+        }
+    };
+    match hello.server_name.as_deref() {
+        Some(name) if names_match(name, &attempt.host) => {}
+        Some(_) => return attempt.denied("sni_mismatch"),
+        None => return attempt.denied("sni_missing"),
+    }
+
+    if upstream.write_all(&hello.bytes).await.is_err() {
+        return attempt.denied("upstream_unreachable");
+    }
+    let (sent, received) = pipe(&mut client, &mut upstream).await;
+    attempt.allowed(sent + hello.bytes.len() as u64, received);
+}
+
+/// Absolute-form `http://` requests. The Host header is rewritten to the checked target so the
+/// upstream cannot be steered to a different virtual host.
+async fn forward(mut client: TcpStream, head: Head, config: &ProxyConfig, started: Instant) {
+    let Some((authority, path)) = strip_http_scheme(&head.target) else {
+        let _ = respond(
+            &mut client,
+            "400 Bad Request",
+            "only http:// targets can be forwarded; use CONNECT for TLS",
+        )
+        .await;
+        return;
+    };
+    let Some((host, port)) = split_authority(authority, Some(DEFAULT_HTTP_PORT)) else {
+        let _ = respond(&mut client, "400 Bad Request", "malformed request target").await;
+        return;
+    };
+    let attempt = Attempt { config, mode: "forward", host, port, started };
+
+    let mut upstream = match open(&attempt).await {
+        Ok(upstream) => upstream,
+        Err((status, reason)) => {
+            attempt.denied(reason);
+            let _ = respond(&mut client, status, reason).await;
+            return;
+        }
+    };
+
+    let mut request = format!("{} {path} {}\r\n", head.method, head.version);
+    for (name, value) in &head.headers {
+        let lower = name.to_ascii_lowercase();
+        if lower != "host" && !HOP_HEADERS.contains(&lower.as_str()) {
+            request.push_str(&format!("{name}: {value}\r\n"));
+        }
+    }
+    request.push_str(&format!("Host: {authority}\r\nConnection: close\r\n\r\n"));
+
+    let mut initial = request.into_bytes();
+    initial.extend_from_slice(&head.rest);
+    if upstream.write_all(&initial).await.is_err() {
+        let _ = respond(&mut client, "502 Bad Gateway", "upstream_unreachable").await;
+        return attempt.denied("upstream_unreachable");
+    }
+    let (sent, received) = pipe(&mut client, &mut upstream).await;
+    attempt.allowed(sent + initial.len() as u64, received);
+}
+
+/// Checks the allowlist, then resolves and connects, refusing addresses no tool server should
+/// reach even through an allowed name (loopback, link-local metadata endpoints, and so on).
+async fn open(attempt: &Attempt<'_>) -> Result<TcpStream, (&'static str, &'static str)> {
+    if !attempt.config.allows(&attempt.host, attempt.port) {
+        return Err(("403 Forbidden", "host_not_allowed"));
+    }
+
+    let addrs: Vec<SocketAddr> = match timeout(
+        CONNECT_TIMEOUT,
+        tokio::net::lookup_host((attempt.host.as_str(), attempt.port)),
+    )
+    .await
+    {
+        Ok(Ok(addrs)) => addrs
+            .filter(|addr| {
+                !is_forbidden(addr.ip())
+                    || (attempt.config.permit_loopback && addr.ip().is_loopback())
+            })
+            .collect(),
+        _ => return Err(("502 Bad Gateway", "dns_failed")),
+    };
+    if addrs.is_empty() {
+        return Err(("403 Forbidden", "forbidden_address"));
+    }
+
+    for addr in addrs {
+        match timeout(CONNECT_TIMEOUT, TcpStream::connect(addr)).await {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(err)) => warn!(%addr, error = %err, "upstream connect failed"),
+            Err(_) => warn!(%addr, "upstream connect timed out"),
+        }
+    }
+
+    Err(("502 Bad Gateway", "upstream_unreachable"))
+}
+
+async fn pipe(client: &mut TcpStream, upstream: &mut TcpStream) -> (u64, u64) {
+    tokio::io::copy_bidirectional(client, upstream).await.unwrap_or((0, 0))
+}
+
+async fn respond(client: &mut TcpStream, status: &str, reason: &str) -> io::Result<()> {
+    let body = format!("egress denied: {reason}\n");
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    client.write_all(response.as_bytes()).await?;
+    client.shutdown().await
+}
+
+async fn read_head(client: &mut TcpStream) -> io::Result<Option<Head>> {
+    let mut buf = Vec::with_capacity(1024);
+    let end = loop {
+        if let Some(end) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end;
+        }
+        if buf.len() > MAX_HEAD_BYTES {
+            return Ok(None);
+        }
+        let mut chunk = [0; 4096];
+        let read = client.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk[..read]);
+    };
+
+    let rest = buf.split_off(end + 4);
+    let Ok(text) = String::from_utf8(buf) else {
+        return Ok(None);
+    };
+    let mut lines = text.trim_end().split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let (Some(method), Some(target), Some(version), None) =
+        (request_line.next(), request_line.next(), request_line.next(), request_line.next())
+    else {
+        return Ok(None);
+    };
+
+    let mut headers = Vec::new();
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            return Ok(None);
+        };
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    Ok(Some(Head {
+        method: method.to_string(),
+        target: target.to_string(),
+        version: version.to_string(),
+        headers,
+        rest,
+    }))
+}
+
+struct ClientHello {
+    bytes: Vec<u8>,
+    server_name: Option<String>,
+}
+
+async fn read_client_hello(client: &mut TcpStream, mut buf: Vec<u8>) -> io::Result<ClientHello> {
+    fill(client, &mut buf, sni::RECORD_HEADER_LEN).await?;
+    let header: [u8; sni::RECORD_HEADER_LEN] =
+        buf[..sni::RECORD_HEADER_LEN].try_into().expect("length checked by fill");
+    let len = sni::handshake_record_len(&header)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a TLS handshake"))?;
+    fill(client, &mut buf, sni::RECORD_HEADER_LEN + len).await?;
+
+    let server_name = sni::server_name(&buf[sni::RECORD_HEADER_LEN..sni::RECORD_HEADER_LEN + len]);
+    Ok(ClientHello { bytes: buf, server_name })
+}
+
+async fn fill(client: &mut TcpStream, buf: &mut Vec<u8>, len: usize) -> io::Result<()> {
+    while buf.len() < len {
+        let mut chunk = [0; 4096];
+        let read = client.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        buf.extend_from_slice(&chunk[..read]);
+    }
+    Ok(())
+}
+
+fn strip_http_scheme(target: &str) -> Option<(&str, &str)> {
+    let scheme = target.get(..7)?;
+    if !scheme.eq_ignore_ascii_case("http://") {
+        return None;
+    }
+    let rest = &target[7..];
+    Some(match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    })
+}
+
+/// Splits `host:port` (or `[v6]:port`), lowercasing the host.
+fn split_authority(authority: &str, default_port: Option<u16>) -> Option<(String, u16)> {
+    if authority.contains('@') {
+        return None;
+    }
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => (host, Some(port.parse().ok()?)),
+        _ => (authority, None),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase();
+    let port = port.or(default_port)?;
+
+    (!host.is_empty() && port != 0).then_some((host, port))
+}
+
+fn names_match(server_name: &str, host: &str) -> bool {
+    server_name.trim_end_matches('.').eq_ignore_ascii_case(host.trim_end_matches('.'))
+}
+
+fn is_forbidden(ip: IpAddr) -> bool {
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        IpAddr::V4(_) => ip,
+    };
+
+    match ip {
+        IpAddr::V4(v4) => {
+            v4.is_loopback()
+                || v4.is_unspecified()
+                || v4.is_link_local()
+                || v4.is_multicast()
+                || v4.is_broadcast()
+        }
+        IpAddr::V6(v6) => {
+            v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                || (v6.segments()[0] & 0xffc0) == 0xfe80
+        }
+    }
+}
@@ -0,0 +1,78 @@
+const HANDSHAKE: u8 = 0x16;
+const CLIENT_HELLO: u8 = 0x01;
+const SERVER_NAME: u16 = 0x0000;
+const HOST_NAME: u8 = 0x00;
+
+pub const RECORD_HEADER_LEN: usize = 5;
+
+/// Length of the record body announced by a TLS record header, if it opens a handshake.
+pub fn handshake_record_len(header: &[u8; RECORD_HEADER_LEN]) -> Option<usize> {
+    (header[0] == HANDSHAKE).then(|| usize::from(u16::from_be_bytes([header[3], header[4]])))
+}
+
+/// Extracts the `server_name` from a ClientHello record body.
+pub fn server_name(record: &[u8]) -> Option<String> {
+    let mut hello = Reader(record);
+    if hello.u8()? != CLIENT_HELLO {
+        return None;
+    }
+    let len = hello.u24()?;
+    let mut hello = Reader(hello.take(len)?);
+    hello.take(2 + 32)?; // client_version, random
+    let session_id = usize::from(hello.u8()?);
+    hello.take(session_id)?;
+    let cipher_suites = usize::from(hello.u16()?);
+    hello.take(cipher_suites)?;
+    let compression = usize::from(hello.u8()?);
+    hello.take(compression)?;
+
+    let extensions_len = usize::from(hello.u16()?);
+    let mut extensions = Reader(hello.take(extensions_len)?);
+    while !extensions.0.is_empty() {
+        let kind = extensions.u16()?;
+        let len = usize::from(extensions.u16()?);
+        let mut body = Reader(extensions.take(len)?);
+        if kind != SERVER_NAME {
+            continue;
+        }
+
+        let list_len = usize::from(body.u16()?);
+        let mut names = Reader(body.take(list_len)?);
+        while !names.0.is_empty() {
+            let name_type = names.u8()?;
+            let len = usize::from(names.u16()?);
+            let name = names.take(len)?;
+            if name_type == HOST_NAME {
+                return String::from_utf8(name.to_vec()).ok();
+            }
+        }
+    }
+
+    None
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u24(&mut self) -> Option<usize> {
+        self.take(3)
+            .map(|bytes| usize::from_be_bytes([0, 0, 0, 0, 0, bytes[0], bytes[1], bytes[2]]))
+    }
+}
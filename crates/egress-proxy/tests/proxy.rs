@@ -0,0 +1,174 @@
+//! Drives the proxy over real sockets against local upstreams.
+
+use latchkey_core::egress::AllowedHost;
+use latchkey_egress_proxy::proxy::{self, ProxyConfig};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+
+/// Accepts one connection, records what it receives, and answers like a tiny HTTP server.
+async fn upstream() -> (u16, oneshot::Receiver<Vec<u8>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (tx, rx) = oneshot::channel();
+
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = vec![0; 8192];
+        let read = stream.read(&mut buf).await.unwrap_or(0);
+        buf.truncate(read);
+        let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").await;
+        let _ = stream.shutdown().await;
+        let _ = tx.send(buf);
+    });
+
+    (port, rx)
+}
+
+async fn start_proxy(upstream_port: u16, permit_loopback: bool) -> u16 {
+    let config = ProxyConfig {
+        server: "github-tools".to_string(),
+        allow: vec![AllowedHost { host: "localhost".to_string(), ports: vec![upstream_port] }],
+        permit_loopback,
+    };
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(proxy::serve(listener, Arc::new(config)));
+    port
+}
+
+async fn exchange(proxy_port: u16, request: &[u8]) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", proxy_port)).await.unwrap();
+    stream.write_all(request).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+fn client_hello(server_name: &str) -> Vec<u8> {
+    let name = server_name.as_bytes();
+    let mut server_name_ext = ((name.len() + 3) as u16).to_be_bytes().to_vec();
+    server_name_ext.push(0);
+    server_name_ext.extend((name.len() as u16).to_be_bytes());
+    server_name_ext.extend(name);
+
+    let mut extensions = vec![0, 0];
+    extensions.extend((server_name_ext.len() as u16).to_be_bytes());
+    extensions.extend(server_name_ext);
+
+    let mut hello = vec![3, 3];
+    hello.extend([0; 32]);
+    hello.push(0);
+    hello.extend([0, 2, 0x13, 0x01, 1, 0]);
+    hello.extend((extensions.len() as u16).to_be_bytes());
+    hello.extend(extensions);
+
+    let mut handshake = vec![1];
+    handshake.extend(&(hello.len() as u32).to_be_bytes()[1..]);
+    handshake.extend(hello);
+
+    let mut record = vec![0x16, 3, 1];
+    record.extend((handshake.len() as u16).to_be_bytes());
+    record.extend(handshake);
+    record
+}
+
+async fn open_tunnel(proxy_port: u16, upstream_port: u16) -> TcpStream {
+    let mut stream = TcpStream::connect(("127.0.0.1", proxy_port)).await.unwrap();
+    let connect = format!("CONNECT localhost:{upstream_port} HTTP/1.1\r\n\r\n");
+    stream.write_all(connect.as_bytes()).await.unwrap();
+
+    let mut established = [0; 39];
+    stream.read_exact(&mut established).await.unwrap();
+    assert_eq!(&established, b"HTTP/1.1 200 Connection Established\r\n\r\n");
+    stream
+}
+
+#[tokio::test]
+async fn forward_request_is_rewritten_for_the_checked_host() {
+    let (upstream_port, received) = upstream().await;
+    let proxy_port = start_proxy(upstream_port, true).await;
+
+    let request = format!(
+        "GET http://localhost:{upstream_port}/v1/repos HTTP/1.1\r\nHost: internal.example\r\nProxy-Authorization: Basic Zm9v\r\nAccept: */*\r\n\r\n"
+    );
+    let response = exchange(proxy_port, request.as_bytes()).await;
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+
+    let received = String::from_utf8(received.await.unwrap()).unwrap();
+    assert!(received.starts_with("GET /v1/repos HTTP/1.1\r\n"), "{received}");
+    assert!(received.contains(&format!("Host: localhost:{upstream_port}\r\n")));
+    assert!(received.contains("Accept: */*\r\n"));
+    assert!(!received.contains("internal.example"));
+    assert!(!received.contains("Proxy-Authorization"));
+}
+
+#[tokio::test]
+async fn unlisted_host_is_denied() {
+    let (upstream_port, _) = upstream().await;
+    let proxy_port = start_proxy(upstream_port, true).await;
+
+    let response = exchange(proxy_port, b"GET http://example.com/ HTTP/1.1\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden"), "{response}");
+    assert!(response.contains("host_not_allowed"));
+
+    let connect = format!("CONNECT localhost:{} HTTP/1.1\r\n\r\n", upstream_port + 1);
+    let response = exchange(proxy_port, connect.as_bytes()).await;
+    assert!(response.contains("host_not_allowed"), "{response}");
+}
+
+#[tokio::test]
+async fn allowed_name_resolving_to_loopback_is_refused() {
+    let (upstream_port, _) = upstream().await;
+    let proxy_port = start_proxy(upstream_port, false).await;
+
+    let request = format!("GET http://localhost:{upstream_port}/ HTTP/1.1\r\n\r\n");
+    let response = exchange(proxy_port, request.as_bytes()).await;
+    assert!(response.contains("forbidden_address"), "{response}");
+}
+
+#[tokio::test]
+async fn tunnel_with_matching_sni_is_spliced() {
+    let (upstream_port, received) = upstream().await;
+    let proxy_port = start_proxy(upstream_port, true).await;
+
+    let mut tunnel = open_tunnel(proxy_port, upstream_port).await;
+    let hello = client_hello("localhost");
+    tunnel.write_all(&hello).await.unwrap();
+
+    assert_eq!(received.await.unwrap(), hello);
+}
+
+#[tokio::test]
+async fn tunnel_with_mismatched_sni_is_closed() {
+    let (upstream_port, received) = upstream().await;
+    let proxy_port = start_proxy(upstream_port, true).await;
+
+    let mut tunnel = open_tunnel(proxy_port, upstream_port).await;
+    tunnel.write_all(&client_hello("metadata.internal")).await.unwrap();
+
+    let mut rest = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), tunnel.read_to_end(&mut rest))
+        .await
+        .expect("proxy should close the tunnel")
+        .unwrap();
+    assert!(rest.is_empty());
+    // The upstream connection is dropped without a single byte from the client.
+    assert_eq!(received.await.unwrap(), Vec::<u8>::new());
+}
+
+#[tokio::test]
+async fn tunnel_without_tls_is_closed() {
+    let (upstream_port, received) = upstream().await;
+    let proxy_port = start_proxy(upstream_port, true).await;
+
+    let mut tunnel = open_tunnel(proxy_port, upstream_port).await;
+    tunnel.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+
+    let mut rest = Vec::new();
+    tunnel.read_to_end(&mut rest).await.unwrap();
+    assert!(rest.is_empty());
+    assert_eq!(received.await.unwrap(), Vec::<u8>::new());
+}
@@ -0,0 +1,365 @@
+//! Builds each MCP request's audit event as the request moves through the gateway, and emits it
+//! exactly once: when the last handle to it is dropped.
+
+use crate::backend::SESSION_HEADER;
+use crate::metrics::{InFlight, Metrics};
+use crate::redact;
+use crate::sinks::AuditSinks;
+use crate::{request_id_from_headers, AppState};
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::Response;
+use chrono::{SecondsFormat, Utc};
+use latchkey_core::audit::{AuditEvent, Decision, ErrorClass, Outcome};
+use latchkey_core::trace::{Span, SpanKind, TraceContext};
+use std::sync::{Arc, Mutex};
+use tokio::time::Instant;
+
+/// A shared handle on one request's audit event. Clones held past the response, such as by a
+/// relayed stream, keep the event open until they are dropped.
+#[derive(Clone)]
+pub struct Audit(Arc<Mutex<Draft>>);
+
+struct Draft {
+    event: AuditEvent,
+    started: Instant,
+    /// Whether a handler recorded how the request ended.
+    recorded: bool,
+    /// The response status, once the response is on its way.
+    responded: Option<StatusCode>,
+    sinks: Arc<AuditSinks>,
+    metrics: Arc<Metrics>,
+    /// Counts the request in flight once it is authenticated.
+    in_flight: Option<InFlight>,
+    /// The request's server span, under the caller's `traceparent` when it sent one.
+    span: Span,
+}
+
+impl Audit {
+    fn new(request: &Request, state: &AppState) -> Self {
+        let headers = request.headers();
+        let mut event = AuditEvent::new(
+            request_id_from_headers(headers),
+            Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            request.method().to_string(),
+        );
+        event.session_id =
+            headers.get(SESSION_HEADER).and_then(|id| id.to_str().ok()).map(str::to_string);
+        let parent = TraceContext::extract(|name| headers.get(name)?.to_str().ok());
+        let name = format!("{} {}", request.method(), request.uri().path());
+        let span = state.tracer.span(&name, SpanKind::Server, parent.as_ref());
+        event.trace_id = Some(span.context().trace_id_hex());
+        event.span_id = Some(span.context().span_id_hex());
+        Self(Arc::new(Mutex::new(Draft {
+            event,
+            started: Instant::now(),
+            recorded: false,
+            responded: None,
+            sinks: state.audit_sinks.clone(),
+            metrics: state.metrics.clone(),
+            in_flight: None,
+            span,
+        })))
+    }
+
+    /// Fills in fields as they become known.
+    pub fn update(&self, update: impl FnOnce(&mut AuditEvent)) {
+        update(&mut self.lock().event);
+    }
+
+    /// Records how the request ended. The first outcome recorded wins.
+    pub fn record(&self, update: impl FnOnce(&mut AuditEvent)) {
+        let mut draft = self.lock();
+        if !draft.recorded {
+            draft.recorded = true;
+            update(&mut draft.event);
+        }
+    }
+
+    /// Counts the request in `principal_id`'s in-flight requests until the event is emitted.
+    pub fn in_flight(&self, principal_id: &str) {
+        let mut draft = self.lock();
+        draft.in_flight = Some(draft.metrics.enter(principal_id));
+    }
+
+    /// Starts a span for one step of handling the request.
+    pub fn span(&self, name: &str) -> Span {
+        self.lock().span.child(name, SpanKind::Internal)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Draft> {
+        self.0.lock().expect("audit event poisoned")
+    }
+}
+
+impl Drop for Draft {
+    fn drop(&mut self) {
+        self.event.latency_ms = self.started.elapsed().as_millis() as u64;
+        // A handler that panicked never responds; the client sees the connection fail.
+        let responded = self
+            .responded
+            .or_else(|| std::thread::panicking().then_some(StatusCode::INTERNAL_SERVER_ERROR));
+        // A stream's status is sent before its outcome is known.
+        if let Some(status) = responded {
+            self.event.http_status = status.as_u16();
+        }
+        if !self.recorded {
+            settle(&mut self.event, responded);
+        }
+        redact::scrub(&mut self.event);
+        annotate(&mut self.span, &self.event);
+        self.metrics.observe(&self.event);
+        self.sinks.send(&self.event);
+    }
+}
+
+/// Describes the request on its server span, after the OpenTelemetry HTTP and RPC conventions.
+fn annotate(span: &mut Span, event: &AuditEvent) {
+    span.set_attribute("http.response.status_code", event.http_status);
+    span.set_attribute("rpc.method", event.method.clone());
+    span.set_attribute("latchkey.request_id", event.request_id.clone());
+    span.set_attribute("latchkey.decision", event.decision.as_str());
+    if let Some(principal_id) = &event.principal_id {
+        span.set_attribute("latchkey.principal_id", principal_id.clone());
+    }
+    if let Some(tool_name) = &event.tool_name {
+        span.set_attribute("latchkey.tool_name", tool_name.clone());
+    }
+    if event.status == Outcome::Error {
+        span.set_error(event.deny_reason.clone().unwrap_or_default());
+    }
+}
+
+/// Fills in an outcome no handler recorded, from the response status alone. That covers
+/// requests the gateway refused before a handler ran, such as unsupported methods.
+fn settle(event: &mut AuditEvent, responded: Option<StatusCode>) {
+    let Some(status) = responded else {
+        // The request was dropped before a response, which only a client hang-up does.
+        event.decision = Decision::Allow;
+        event.status = Outcome::Error;
+        event.http_status = 499;
+        event.deny_reason = Some("client_closed".to_string());
+        event.error_class = Some(ErrorClass::Client);
+        return;
+    };
+    let (decision, reason, class) = match status {
+        _ if status.is_success() || status.is_informational() || status.is_redirection() => {
+            return;
+        }
+        StatusCode::PAYLOAD_TOO_LARGE => {
+            (Decision::Deny, "payload_too_large", ErrorClass::Validation)
+        }
+        StatusCode::REQUEST_TIMEOUT => (Decision::Deny, "request_timeout", ErrorClass::Timeout),
+        _ if status.is_client_error() => {
+            (Decision::Deny, "invalid_request", ErrorClass::Validation)
+        }
+        _ => (Decision::Allow, "internal_error", ErrorClass::Internal),
+    };
+    event.decision = decision;
+    event.status = Outcome::Error;
+    event.deny_reason = Some(reason.to_string());
+    event.error_class = Some(class);
+}
+
+/// Opens an audit event for every request it wraps, available to handlers as an extension.
+pub async fn track(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    let audit = Audit::new(&request, &state);
+    request.extensions_mut().insert(audit.clone());
+    let response = next.run(request).await;
+    audit.lock().responded = Some(response.status());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::tests::chain;
+    use crate::sinks::{AuditSink, Overflow};
+    use crate::tests::{call, mcp_request, post_mcp, serve, state, tool_server};
+    use crate::tools::{ToolLimits, Tools};
+    use axum::body::Body;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use futures::future::BoxFuture;
+    use latchkey_core::audit::AuditRecord;
+    use serde_json::{json, Value};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    /// Keeps every event it is handed.
+    struct Collect(Arc<Mutex<Vec<AuditEvent>>>);
+
+    impl AuditSink for Collect {
+        fn name(&self) -> &'static str {
+            "collect"
+        }
+
+        fn write<'a>(
+            &'a mut self,
+            records: &'a [Arc<AuditRecord>],
+        ) -> BoxFuture<'a, anyhow::Result<()>> {
+            Box::pin(async move {
+                let mut events = self.0.lock().unwrap();
+                events.extend(records.iter().filter_map(|record| match &**record {
+                    AuditRecord::Event(event) => Some(event.clone()),
+                    AuditRecord::Checkpoint(_) => None,
+                }));
+                Ok(())
+            })
+        }
+    }
+
+    fn collected(mut state: AppState) -> (AppState, Arc<Mutex<Vec<AuditEvent>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        state.audit_sinks = Arc::new(AuditSinks::new(
+            false,
+            Overflow::Drop,
+            64,
+            chain(1000).0,
+            Duration::from_secs(60),
+            vec![Box::new(Collect(events.clone()))],
+        ));
+        (state, events)
+    }
+
+    /// The `(deny_reason, http_status)` of every event written, once `count` have arrived and
+    /// any duplicate has had time to follow.
+    async fn written(events: &Mutex<Vec<AuditEvent>>, count: usize) -> Vec<(Option<String>, u16)> {
+        for _ in 0..200 {
+            if events.lock().unwrap().len() >= count {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let events = events.lock().unwrap();
+        events.iter().map(|event| (event.deny_reason.clone(), event.http_status)).collect()
+    }
+
+    #[tokio::test]
+    async fn emits_one_event_per_answered_request() {
+        let (state, events) = collected(state(tool_server(&["demo.echo"]).await, &["demo.echo"]));
+        let router = crate::router(state);
+
+        let answered = post_mcp(router.clone(), &call("demo.echo", json!({ "q": 1 }))).await;
+        assert_eq!(answered.status(), StatusCode::OK);
+        drop(answered);
+        let listed = json!({ "jsonrpc": "2.0", "id": 2, "method": "tools/list" });
+        assert_eq!(post_mcp(router, &listed).await.status(), StatusCode::OK);
+
+        assert_eq!(written(&events, 2).await, [(None, 200), (None, 200)]);
+        let events = events.lock().unwrap();
+        assert_eq!(events[0].tool_name.as_deref(), Some("demo.echo"));
+        assert_eq!(events[0].principal_id.as_deref(), Some("demo-agent"));
+        assert_eq!(events[1].method, "tools/list");
+    }
+
+    #[tokio::test]
+    async fn emits_one_event_for_requests_refused_early() {
+        let (state, events) = collected(state(Vec::new(), &[]));
+        let router = crate::router(state);
+
+        let mut anonymous = mcp_request(call("demo.echo", json!({})).to_string());
+        anonymous.headers_mut().remove(axum::http::header::AUTHORIZATION);
+        let refused = router.clone().oneshot(anonymous).await.unwrap();
+        assert_eq!(refused.status(), StatusCode::UNAUTHORIZED);
+        let oversized = mcp_request(vec![b' '; ToolLimits::default().max_payload_bytes + 1]);
+        let refused = router.clone().oneshot(oversized).await.unwrap();
+        assert_eq!(refused.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let unrouted = axum::http::Request::put("/v1/mcp").body(Body::empty()).unwrap();
+        let refused = router.oneshot(unrouted).await.unwrap();
+        assert_eq!(refused.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        assert_eq!(
+            written(&events, 3).await,
+            [
+                (Some("missing_or_invalid_token".to_string()), 401),
+                (Some("payload_too_large".to_string()), 413),
+                (Some("invalid_request".to_string()), 405),
+            ]
+        );
+    }
+
+    async fn panics() -> StatusCode {
+        panic!("handler bug")
+    }
+
+    #[tokio::test]
+    async fn emits_one_event_for_calls_that_time_out_hang_up_or_panic() {
+        // Lists its tool at once but never answers a call in time.
+        let slow = Router::new().route(
+            "/mcp",
+            post(|Json(message): Json<Value>| async move {
+                if message["method"] == "tools/call" {
+                    tokio::time::sleep(Duration::from_secs(30)).await;
+                }
+                let tools = json!({ "tools": [{ "name": "demo.slow", "inputSchema": {} }] });
+                Json(json!({ "jsonrpc": "2.0", "id": message.get("id"), "result": tools }))
+            }),
+        );
+        let servers = vec![("slow".to_string(), format!("http://{}/mcp", serve(slow).await))];
+        let (mut state, events) = collected(state(servers, &["demo.slow"]));
+        let limits = ToolLimits { timeout: Duration::from_millis(100), ..ToolLimits::default() };
+        state.tools = Arc::new(Tools::new(limits));
+
+        let timed_out = post_mcp(crate::router(state.clone()), &call("demo.slow", json!({}))).await;
+        assert_eq!(timed_out.status(), StatusCode::GATEWAY_TIMEOUT);
+        drop(timed_out);
+
+        let mut unlimited = state.clone();
+        unlimited.tools = Arc::new(Tools::new(ToolLimits::default()));
+        let pending =
+            crate::router(unlimited).oneshot(mcp_request(call("demo.slow", json!({})).to_string()));
+        let hung_up = tokio::spawn(pending);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        hung_up.abort();
+        assert!(hung_up.await.unwrap_err().is_cancelled());
+
+        let panicking = Router::new()
+            .route("/v1/mcp", post(panics))
+            .route_layer(axum::middleware::from_fn_with_state(state.clone(), track))
+            .with_state(state);
+        let request = mcp_request(Body::empty());
+        let panicked = tokio::spawn(async move { panicking.oneshot(request).await });
+        assert!(panicked.await.unwrap_err().is_panic());
+
+        assert_eq!(
+            written(&events, 3).await,
+            [
+                (Some("backend_timeout".to_string()), 504),
+                (Some("client_closed".to_string()), 499),
+                (Some("internal_error".to_string()), 500),
+            ]
+        );
+    }
+
+    #[test]
+    fn settles_unrecorded_requests_from_the_response() {
+        let event = |responded| {
+            let mut event = AuditEvent::new("r".into(), "t".into(), "POST".into());
+            settle(&mut event, responded);
+            (event.decision, event.status, event.deny_reason, event.error_class)
+        };
+
+        assert_eq!(event(Some(StatusCode::OK)), (Decision::Allow, Outcome::Success, None, None));
+        assert_eq!(
+            event(Some(StatusCode::PAYLOAD_TOO_LARGE)),
+            (
+                Decision::Deny,
+                Outcome::Error,
+                Some("payload_too_large".to_string()),
+                Some(ErrorClass::Validation)
+            )
+        );
+        assert_eq!(
+            event(None),
+            (
+                Decision::Allow,
+                Outcome::Error,
+                Some("client_closed".to_string()),
+                Some(ErrorClass::Client)
+            )
+        );
+    }
+}
@@ -0,0 +1,899 @@
+use crate::breaker::{Breaker, BreakerConfig};
+use crate::session::Session;
+use crate::sse::{EventTooLarge, SseEvent, SseParser};
+use anyhow::Context;
+use axum::http::{HeaderMap, StatusCode};
+use latchkey_core::trace::TraceContext;
+use serde_json::{json, Value};
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock as StdRwLock};
+use std::time::Duration;
+use tokio::sync::{broadcast, Mutex, RwLock};
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+/// Protocol revision the gateway speaks to clients and to tool servers.
+pub const PROTOCOL_VERSION: &str = "2025-06-18";
+pub const SESSION_HEADER: &str = "mcp-session-id";
+const PROTOCOL_VERSION_HEADER: &str = "mcp-protocol-version";
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+const EVENT_STREAM: &str = "text/event-stream";
+/// `tools/call` re-lists servers at most this often before routing to a cached server.
+const CATALOG_TTL: Duration = Duration::from_secs(30);
+const MAX_LIST_PAGES: usize = 10;
+/// Cap on a single SSE event from a tool server.
+pub const MAX_EVENT_BYTES: usize = 1024 * 1024;
+/// Cap on the replies to the gateway's own requests, such as each `tools/list` page.
+const MAX_DISCOVERY_RESPONSE_BYTES: usize = 4 * 1024 * 1024;
+const NOTIFICATION_BUFFER: usize = 64;
+const LISTEN_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+/// How often an idle server-notification listener checks whether anyone is still subscribed.
+const LISTEN_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub enum BackendError {
+    Unreachable(reqwest::Error),
+    Status(StatusCode),
+    InvalidResponse,
+    EventTooLarge,
+    /// The reply passed the tool's `maxResponseBytes` and was cut off.
+    ResponseTooLarge,
+}
+
+impl BackendError {
+    /// Whether the error counts against the server's circuit breaker. Client errors (4xx) say
+    /// nothing about the server's health.
+    pub fn is_server_fault(&self) -> bool {
+        match self {
+            Self::Status(status) => !status.is_client_error(),
+            Self::ResponseTooLarge => false,
+            _ => true,
+        }
+    }
+
+    /// Whether another attempt may succeed: the server could not be reached or was briefly
+    /// unable to answer.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Unreachable(_) => true,
+            Self::Status(status) => matches!(
+                *status,
+                StatusCode::BAD_GATEWAY
+                    | StatusCode::SERVICE_UNAVAILABLE
+                    | StatusCode::GATEWAY_TIMEOUT
+            ),
+            Self::InvalidResponse | Self::EventTooLarge | Self::ResponseTooLarge => false,
+        }
+    }
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unreachable(err) => write!(f, "tool server unreachable: {err}"),
+            Self::Status(status) => write!(f, "tool server returned {status}"),
+            Self::InvalidResponse => f.write_str("tool server returned an invalid MCP response"),
+            Self::EventTooLarge => {
+                write!(f, "tool server sent an event over {MAX_EVENT_BYTES} bytes")
+            }
+            Self::ResponseTooLarge => f.write_str("tool server reply exceeded the response cap"),
+        }
+    }
+}
+
+/// A tool server's answer to one request: a plain JSON body, or an SSE stream that may carry
+/// notifications (such as progress) before the response.
+pub enum Reply {
+    Json(Value),
+    Stream(Box<EventStream>),
+}
+
+/// Parsed events of a `text/event-stream` response, read as they arrive.
+pub struct EventStream {
+    response: reqwest::Response,
+    parser: SseParser,
+    queued: VecDeque<SseEvent>,
+    pub events: usize,
+    pub bytes: usize,
+    /// Bytes read off the wire, cut off past `limit`.
+    received: usize,
+    limit: usize,
+    /// Counts a streamed reply against its endpoint until the stream is dropped.
+    _in_flight: Option<InFlight>,
+}
+
+impl EventStream {
+    fn new(response: reqwest::Response, limit: usize) -> Self {
+        Self {
+            response,
+            parser: SseParser::new(MAX_EVENT_BYTES),
+            queued: VecDeque::new(),
+            events: 0,
+            bytes: 0,
+            received: 0,
+            limit,
+            _in_flight: None,
+        }
+    }
+
+    /// The next event carrying `data`, or `None` once the server closes the stream.
+    pub async fn next(&mut self) -> Option<Result<SseEvent, BackendError>> {
+        loop {
+            if let Some(event) = self.queued.pop_front() {
+                self.events += 1;
+                self.bytes += event.size;
+                return Some(Ok(event));
+            }
+            let chunk = match self.response.chunk().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => return None,
+                Err(err) => return Some(Err(BackendError::Unreachable(err))),
+            };
+            self.received += chunk.len();
+            if self.received > self.limit {
+                return Some(Err(BackendError::ResponseTooLarge));
+            }
+            match self.parser.push(&chunk) {
+                Ok(events) => self.queued.extend(events),
+                Err(EventTooLarge) => return Some(Err(BackendError::EventTooLarge)),
+            }
+        }
+    }
+
+    /// Skips ahead to the response to `id`, dropping notifications on the way.
+    pub async fn response(&mut self, id: Option<&Value>) -> Result<Value, BackendError> {
+        while let Some(event) = self.next().await {
+            let Ok(message) = serde_json::from_str::<Value>(&event?.data) else {
+                continue;
+            };
+            if message.get("method").is_none() && message.get("id") == id {
+                return Ok(message);
+            }
+        }
+        Err(BackendError::InvalidResponse)
+    }
+}
+
+/// How requests outside a client session pick among a server's endpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Balancer {
+    /// Power of two choices: the less loaded of two random endpoints.
+    PowerOfTwo,
+    /// The endpoint with the fewest requests in flight.
+    LeastRequests,
+}
+
+impl FromStr for Balancer {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "p2c" => Ok(Self::PowerOfTwo),
+            "least-requests" => Ok(Self::LeastRequests),
+            _ => anyhow::bail!("expected p2c or least-requests, got {value}"),
+        }
+    }
+}
+
+/// One routed MCP server, reached over Streamable HTTP at one or more endpoints.
+pub struct Backend {
+    pub name: String,
+    pub url: String,
+    /// Ready endpoints; the configured URL while discovery has found none.
+    endpoints: StdRwLock<Vec<Arc<Endpoint>>>,
+    balancer: Balancer,
+    listening: AtomicBool,
+    /// Trips on failures across all endpoints, e.g. a server failing on every replica.
+    pub breaker: Arc<Breaker>,
+    breaker_config: BreakerConfig,
+}
+
+/// One replica of a server, holding every session opened with it. A replica that disappears
+/// takes its sessions with it; the client sessions they served rebind on their next request.
+pub struct Endpoint {
+    pub url: String,
+    /// `host:port`, as recorded in audit events.
+    pub address: String,
+    in_flight: AtomicUsize,
+    /// Ejects this replica from balancing while open.
+    pub breaker: Arc<Breaker>,
+    /// Keyed by client session id, or `None` for the gateway's own session used for discovery
+    /// and for clients without a session. A slot is `None` until initialized.
+    sessions: Mutex<HashMap<Option<String>, SessionSlot>>,
+}
+
+/// The tool server's session id, once initialized; servers may not issue one.
+type SessionSlot = Arc<Mutex<Option<Option<String>>>>;
+
+/// A request counted against its endpoint's load until dropped.
+pub struct InFlight(Arc<Endpoint>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Every routed server plus the tool-name routes learned from their `tools/list`.
+pub struct Backends {
+    servers: Vec<Backend>,
+    /// Bounded by a total timeout; used for discovery and session setup.
+    client: reqwest::Client,
+    /// No total timeout, so streamed replies can run as long as events keep arriving.
+    stream_client: reqwest::Client,
+    catalog: RwLock<Catalog>,
+    notifications: broadcast::Sender<Value>,
+}
+
+#[derive(Default)]
+struct Catalog {
+    routes: HashMap<String, usize>,
+    refreshed: Option<Instant>,
+}
+
+impl Backends {
+    pub fn new(
+        servers: Vec<(String, String)>,
+        client: reqwest::Client,
+        stream_client: reqwest::Client,
+        balancer: Balancer,
+        breakers: BreakerConfig,
+    ) -> Self {
+        Self {
+            servers: servers
+                .into_iter()
+                .map(|(name, url)| Backend::new(name, url, balancer, breakers))
+                .collect(),
+            client,
+            stream_client,
+            catalog: RwLock::new(Catalog::default()),
+            notifications: broadcast::channel(NOTIFICATION_BUFFER).0,
+        }
+    }
+
+    pub fn servers(&self) -> &[Backend] {
+        &self.servers
+    }
+
+    /// Lists every server's tools and refreshes the routes. A tool offered by more than one
+    /// server routes to the first server listed in `LATCHKEY_MCP_SERVERS`. A server whose
+    /// breaker is open is not asked; its tools keep their routes so calls to them fail fast.
+    pub async fn list_tools(&self) -> Vec<(&Backend, Value)> {
+        let mut lists = Vec::with_capacity(self.servers.len());
+        for (index, backend) in self.servers.iter().enumerate() {
+            if !backend.breaker.available() {
+                continue;
+            }
+            let endpoint = backend.discovery_endpoint();
+            lists.push(async move { (index, endpoint.list_tools(&self.client).await) });
+        }
+
+        let mut tools = Vec::new();
+        let mut routes = HashMap::new();
+        let listed = futures::future::join_all(lists).await;
+        let skipped: Vec<(String, usize)> = {
+            let catalog = self.catalog.read().await;
+            catalog
+                .routes
+                .iter()
+                .filter(|(_, index)| !listed.iter().any(|(listed, _)| listed == *index))
+                .map(|(name, index)| (name.clone(), *index))
+                .collect()
+        };
+        for (index, listed) in listed {
+            let backend = &self.servers[index];
+            match listed {
+                Ok(listed) => {
+                    for tool in listed {
+                        let Some(name) = tool.get("name").and_then(Value::as_str) else {
+                            continue;
+                        };
+                        if routes.contains_key(name) {
+                            warn!(server = %backend.name, tool_name = name, "duplicate tool");
+                            continue;
+                        }
+                        routes.insert(name.to_string(), index);
+                        tools.push((backend, tool));
+                    }
+                }
+                Err(err) => warn!(server = %backend.name, error = %err, "tools/list failed"),
+            }
+        }
+        for (name, index) in skipped {
+            routes.entry(name).or_insert(index);
+        }
+
+        *self.catalog.write().await = Catalog { routes, refreshed: Some(Instant::now()) };
+        tools
+    }
+
+    /// The server offering `tool`, re-listing when the catalog is stale or does not know it.
+    pub async fn route(&self, tool: &str) -> Option<&Backend> {
+        {
+            let catalog = self.catalog.read().await;
+            let fresh = catalog.refreshed.is_some_and(|at| at.elapsed() < CATALOG_TTL);
+            if let (true, Some(index)) = (fresh, catalog.routes.get(tool)) {
+                return Some(&self.servers[*index]);
+            }
+        }
+
+        self.list_tools().await;
+        let index = *self.catalog.read().await.routes.get(tool)?;
+        Some(&self.servers[index])
+    }
+
+    /// Forwards one request to `endpoint` without waiting for a streamed reply to finish. An
+    /// `Idempotency-Key` is passed on so the server can recognize a retried write, and `trace`
+    /// as `traceparent`. Replies past `max_response_bytes` fail with `ResponseTooLarge` as soon
+    /// as the cap is crossed.
+    pub async fn call(
+        &self,
+        endpoint: &Arc<Endpoint>,
+        session: Option<&Session>,
+        message: &Value,
+        idempotency_key: Option<&str>,
+        trace: Option<&TraceContext>,
+        max_response_bytes: usize,
+    ) -> Result<Reply, BackendError> {
+        let in_flight = endpoint.begin();
+        let (client, transport) = (&self.client, &self.stream_client);
+        let response = endpoint
+            .post_in_session(client, transport, session, message, idempotency_key, trace)
+            .await?;
+        if is_event_stream(response.headers()) {
+            let mut events = EventStream::new(response, max_response_bytes);
+            events._in_flight = Some(in_flight);
+            return Ok(Reply::Stream(Box::new(events)));
+        }
+        let body = read_body(response, max_response_bytes).await?;
+        serde_json::from_slice(&body).map(Reply::Json).map_err(|_| BackendError::InvalidResponse)
+    }
+
+    /// Ends a client session on every replica it reached.
+    pub async fn close_session(&self, session: &Session) {
+        for backend in &self.servers {
+            for endpoint in backend.endpoints() {
+                endpoint.close(&self.client, session).await;
+            }
+        }
+    }
+
+    /// Server-wide notifications relayed to clients' `GET` streams. Only
+    /// `notifications/tools/list_changed` is relayed, since the gateway shares one session per
+    /// server across clients and cannot attribute anything else.
+    pub fn subscribe(self: &Arc<Self>) -> broadcast::Receiver<Value> {
+        let receiver = self.notifications.subscribe();
+        for index in 0..self.servers.len() {
+            if !self.servers[index].listening.swap(true, Ordering::AcqRel) {
+                tokio::spawn(self.clone().listen(index));
+            }
+        }
+        receiver
+    }
+
+    /// Holds a `GET` stream open to one server while anyone is subscribed.
+    async fn listen(self: Arc<Self>, index: usize) {
+        let backend = &self.servers[index];
+        loop {
+            if self.notifications.receiver_count() == 0 {
+                backend.listening.store(false, Ordering::Release);
+                // A client may have subscribed between the check and the store.
+                if self.notifications.receiver_count() == 0
+                    || backend.listening.swap(true, Ordering::AcqRel)
+                {
+                    return;
+                }
+            }
+
+            match backend.discovery_endpoint().listen(&self.client, &self.stream_client).await {
+                Ok(mut events) => {
+                    info!(server = %backend.name, "listening for server notifications");
+                    self.relay(&mut events).await;
+                }
+                // The server offers no GET stream; keep `listening` set so it is not retried.
+                Err(BackendError::Status(StatusCode::METHOD_NOT_ALLOWED)) => return,
+                Err(err) => warn!(server = %backend.name, error = %err, "listen failed"),
+            }
+            tokio::time::sleep(LISTEN_RETRY_INTERVAL).await;
+        }
+    }
+
+    async fn relay(&self, events: &mut EventStream) {
+        loop {
+            let event = match tokio::time::timeout(LISTEN_POLL_INTERVAL, events.next()).await {
+                Err(_) if self.notifications.receiver_count() == 0 => return,
+                Err(_) => continue,
+                Ok(None) | Ok(Some(Err(_))) => return,
+                Ok(Some(Ok(event))) => event,
+            };
+            let Ok(message) = serde_json::from_str::<Value>(&event.data) else {
+                continue;
+            };
+            match message.get("method").and_then(Value::as_str) {
+                Some("notifications/tools/list_changed") => {
+                    self.catalog.write().await.refreshed = None;
+                    let _ = self.notifications.send(message);
+                }
+                method => debug!(?method, "dropping server message on shared stream"),
+            }
+        }
+    }
+}
+
+impl Backend {
+    fn new(name: String, url: String, balancer: Balancer, breakers: BreakerConfig) -> Self {
+        Self {
+            name,
+            endpoints: StdRwLock::new(vec![Arc::new(Endpoint::new(url.clone(), breakers))]),
+            url,
+            balancer,
+            listening: AtomicBool::new(false),
+            breaker: Arc::new(Breaker::new(breakers)),
+            breaker_config: breakers,
+        }
+    }
+
+    pub fn endpoints(&self) -> Vec<Arc<Endpoint>> {
+        self.endpoints.read().expect("endpoints lock poisoned").clone()
+    }
+
+    /// The replica for `session`, by rendezvous hashing so that adding or removing a replica
+    /// only moves the sessions on it. Requests outside a session go to the least loaded replica
+    /// by the configured balancer. Replicas whose breaker is open are ejected; `None` when
+    /// every replica is.
+    pub fn endpoint(&self, session: Option<&Session>) -> Option<Arc<Endpoint>> {
+        self.pick(session, None)
+    }
+
+    /// A replica other than `other`, picked the same way, for retrying or hedging elsewhere.
+    pub fn alternate(&self, session: Option<&Session>, other: &Endpoint) -> Option<Arc<Endpoint>> {
+        self.pick(session, Some(other))
+    }
+
+    fn pick(&self, session: Option<&Session>, except: Option<&Endpoint>) -> Option<Arc<Endpoint>> {
+        let endpoints = self.endpoints.read().expect("endpoints lock poisoned");
+        let endpoints: Vec<&Arc<Endpoint>> = endpoints
+            .iter()
+            .filter(|endpoint| except.is_none_or(|except| except.url != endpoint.url))
+            .filter(|endpoint| endpoint.breaker.available())
+            .collect();
+        let endpoint = match (session, self.balancer) {
+            (Some(session), _) => endpoints.iter().max_by_key(|endpoint| {
+                let mut hasher = DefaultHasher::new();
+                (&session.id, &endpoint.url).hash(&mut hasher);
+                hasher.finish()
+            }),
+            (None, Balancer::LeastRequests) => endpoints.iter().min_by_key(|e| e.load()),
+            (None, Balancer::PowerOfTwo) if endpoints.len() > 1 => {
+                let first = random(endpoints.len());
+                let second = (first + 1 + random(endpoints.len() - 1)) % endpoints.len();
+                let (first, second) = (&endpoints[first], &endpoints[second]);
+                Some(if second.load() < first.load() { second } else { first })
+            }
+            (None, Balancer::PowerOfTwo) => endpoints.first(),
+        };
+        endpoint.map(|endpoint| Arc::clone(endpoint))
+    }
+
+    /// A replica for the gateway's own discovery traffic, which also reaches ejected replicas
+    /// rather than none so that the catalog and notifications keep working.
+    fn discovery_endpoint(&self) -> Arc<Endpoint> {
+        self.endpoint(None).unwrap_or_else(|| {
+            let endpoints = self.endpoints.read().expect("endpoints lock poisoned");
+            endpoints.first().expect("a server always has an endpoint").clone()
+        })
+    }
+
+    /// Replaces the replica set, keeping the sessions of replicas that remain. With no ready
+    /// replicas the server falls back to its configured URL.
+    pub fn set_endpoints(&self, mut urls: Vec<String>) {
+        if urls.is_empty() {
+            urls.push(self.url.clone());
+        }
+        let mut endpoints = self.endpoints.write().expect("endpoints lock poisoned");
+        if endpoints.len() == urls.len() && endpoints.iter().all(|e| urls.contains(&e.url)) {
+            return;
+        }
+
+        let removed: Vec<&str> = endpoints
+            .iter()
+            .filter(|endpoint| !urls.contains(&endpoint.url))
+            .map(|endpoint| endpoint.url.as_str())
+            .collect();
+        info!(server = %self.name, endpoints = ?urls, ?removed, "server endpoints changed");
+
+        *endpoints = urls
+            .into_iter()
+            .map(|url| match endpoints.iter().find(|endpoint| endpoint.url == url) {
+                Some(endpoint) => endpoint.clone(),
+                None => Arc::new(Endpoint::new(url, self.breaker_config)),
+            })
+            .collect();
+    }
+}
+
+impl Endpoint {
+    fn new(url: String, breakers: BreakerConfig) -> Self {
+        let address = reqwest::Url::parse(&url)
+            .ok()
+            .and_then(|parsed| {
+                Some(format!("{}:{}", parsed.host_str()?, parsed.port_or_known_default()?))
+            })
+            .unwrap_or_else(|| url.clone());
+        Self {
+            url,
+            address,
+            in_flight: AtomicUsize::new(0),
+            breaker: Arc::new(Breaker::new(breakers)),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn load(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    fn begin(self: &Arc<Self>) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(self.clone())
+    }
+
+    async fn list_tools(&self, client: &reqwest::Client) -> Result<Vec<Value>, BackendError> {
+        let mut tools = Vec::new();
+        let mut cursor: Option<Value> = None;
+
+        for page in 0..MAX_LIST_PAGES {
+            let params = cursor.take().map(|cursor| json!({ "cursor": cursor }));
+            let request = json!({
+                "jsonrpc": "2.0",
+                "id": format!("latchkey-list-{page}"),
+                "method": "tools/list",
+                "params": params.unwrap_or_else(|| json!({})),
+            });
+            let response = self.post_in_session(client, client, None, &request, None, None).await?;
+            let response = read_response(response, request.get("id")).await?;
+            let result = response.get("result").ok_or(BackendError::InvalidResponse)?;
+            let listed = result.get("tools").and_then(Value::as_array);
+            tools.extend(listed.ok_or(BackendError::InvalidResponse)?.iter().cloned());
+
+            match result.get("nextCursor") {
+                Some(next) if !next.is_null() => cursor = Some(next.clone()),
+                _ => break,
+            }
+        }
+
+        Ok(tools)
+    }
+
+    /// Posts one message in `session`'s session with this replica, or the gateway's own,
+    /// initializing it first. A replica that forgot the session (404) is re-initialized once.
+    async fn post_in_session(
+        &self,
+        client: &reqwest::Client,
+        transport: &reqwest::Client,
+        session: Option<&Session>,
+        message: &Value,
+        idempotency_key: Option<&str>,
+        trace: Option<&TraceContext>,
+    ) -> Result<reqwest::Response, BackendError> {
+        let upstream = self.session(client, session, trace).await?;
+        match self.post(transport, message, upstream.as_deref(), idempotency_key, trace).await {
+            Err(BackendError::Status(StatusCode::NOT_FOUND)) if upstream.is_some() => {
+                self.sessions.lock().await.remove(&session.map(|session| session.id.clone()));
+                let upstream = self.session(client, session, trace).await?;
+                self.post(transport, message, upstream.as_deref(), idempotency_key, trace).await
+            }
+            result => result,
+        }
+    }
+
+    async fn session(
+        &self,
+        client: &reqwest::Client,
+        session: Option<&Session>,
+        trace: Option<&TraceContext>,
+    ) -> Result<Option<String>, BackendError> {
+        let key = session.map(|session| session.id.clone());
+        let slot = self.sessions.lock().await.entry(key).or_default().clone();
+        let mut slot = slot.lock().await;
+        if let Some(upstream) = slot.as_ref() {
+            return Ok(upstream.clone());
+        }
+
+        let params = match session {
+            Some(session) => session.initialize.clone(),
+            None => json!({
+                "protocolVersion": PROTOCOL_VERSION,
+                "capabilities": {},
+                "clientInfo": { "name": "latchkey-gateway", "version": env!("CARGO_PKG_VERSION") },
+            }),
+        };
+        let initialize = json!({
+            "jsonrpc": "2.0",
+            "id": "latchkey-initialize",
+            "method": "initialize",
+            "params": params,
+        });
+        let response = self.post(client, &initialize, None, None, trace).await?;
+        let id = response
+            .headers()
+            .get(SESSION_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(ToString::to_string);
+        read_response(response, initialize.get("id")).await?;
+
+        let initialized = json!({ "jsonrpc": "2.0", "method": "notifications/initialized" });
+        self.post(client, &initialized, id.as_deref(), None, trace).await?;
+
+        *slot = Some(id.clone());
+        Ok(id)
+    }
+
+    /// Forgets `session` here and, best effort, tells the replica to end it.
+    async fn close(&self, client: &reqwest::Client, session: &Session) {
+        let Some(slot) = self.sessions.lock().await.remove(&Some(session.id.clone())) else {
+            return;
+        };
+        let Some(Some(upstream)) = slot.lock().await.take() else {
+            return;
+        };
+        let request = client.delete(&self.url).header(SESSION_HEADER, upstream);
+        if let Err(err) = request.send().await {
+            debug!(endpoint = %self.url, error = %err, "failed to end tool server session");
+        }
+    }
+
+    async fn listen(
+        &self,
+        client: &reqwest::Client,
+        stream_client: &reqwest::Client,
+    ) -> Result<EventStream, BackendError> {
+        let session = self.session(client, None, None).await?;
+        let mut request = stream_client
+            .get(&self.url)
+            .header(reqwest::header::ACCEPT, EVENT_STREAM)
+            .header(PROTOCOL_VERSION_HEADER, PROTOCOL_VERSION);
+        if let Some(session) = session {
+            request = request.header(SESSION_HEADER, session);
+        }
+
+        let response = request.send().await.map_err(BackendError::Unreachable)?;
+        if !response.status().is_success() {
+            return Err(BackendError::Status(response.status()));
+        }
+        if !is_event_stream(response.headers()) {
+            return Err(BackendError::InvalidResponse);
+        }
+        Ok(EventStream::new(response, MAX_DISCOVERY_RESPONSE_BYTES))
+    }
+
+    async fn post(
+        &self,
+        client: &reqwest::Client,
+        message: &Value,
+        session: Option<&str>,
+        idempotency_key: Option<&str>,
+        trace: Option<&TraceContext>,
+    ) -> Result<reqwest::Response, BackendError> {
+        let mut request = client
+            .post(&self.url)
+            .header(reqwest::header::ACCEPT, "application/json, text/event-stream")
+            .header(PROTOCOL_VERSION_HEADER, PROTOCOL_VERSION)
+            .json(message);
+        if let Some(session) = session {
+            request = request.header(SESSION_HEADER, session);
+        }
+        if let Some(key) = idempotency_key {
+            request = request.header(IDEMPOTENCY_KEY_HEADER, key);
+        }
+        for (name, value) in trace.map(TraceContext::headers).unwrap_or_default() {
+            request = request.header(name, value);
+        }
+
+        let response = request.send().await.map_err(BackendError::Unreachable)?;
+        if !response.status().is_success() {
+            return Err(BackendError::Status(response.status()));
+        }
+        Ok(response)
+    }
+}
+
+/// Buffers a reply down to the response to `id`; `202 Accepted` counts as an empty success.
+async fn read_response(
+    response: reqwest::Response,
+    id: Option<&Value>,
+) -> Result<Value, BackendError> {
+    if response.status() == StatusCode::ACCEPTED {
+        return Ok(Value::Null);
+    }
+    if is_event_stream(response.headers()) {
+        return EventStream::new(response, MAX_DISCOVERY_RESPONSE_BYTES).response(id).await;
+    }
+    let body = read_body(response, MAX_DISCOVERY_RESPONSE_BYTES).await?;
+    serde_json::from_slice(&body).map_err(|_| BackendError::InvalidResponse)
+}
+
+/// Reads a whole body, giving up as soon as it passes `limit` bytes.
+async fn read_body(mut response: reqwest::Response, limit: usize) -> Result<Vec<u8>, BackendError> {
+    if response.content_length().is_some_and(|length| length > limit as u64) {
+        return Err(BackendError::ResponseTooLarge);
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(BackendError::Unreachable)? {
+        if body.len() + chunk.len() > limit {
+            return Err(BackendError::ResponseTooLarge);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// A uniformly random index below `bound`, from the per-process random hasher keys.
+pub fn random(bound: usize) -> usize {
+    (RandomState::new().build_hasher().finish() % bound as u64) as usize
+}
+
+fn is_event_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with(EVENT_STREAM))
+}
+
+/// Parses `name=url` pairs, e.g. `github=http://github-mcp.tools:8081/mcp`.
+pub fn parse_servers(input: &str) -> anyhow::Result<Vec<(String, String)>> {
+    let mut servers = Vec::new();
+
+    for pair in input.split(',') {
+        if pair.trim().is_empty() {
+            continue;
+        }
+
+        let (name, url) = pair.split_once('=').context("server entries must be name=url")?;
+        servers.push((name.trim().to_string(), url.trim().to_string()));
+    }
+
+    Ok(servers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::breaker::Permit;
+    use crate::tests::serve;
+
+    fn backend(urls: &[&str], balancer: Balancer) -> Backend {
+        let breakers = BreakerConfig { consecutive_failures: 1, ..BreakerConfig::default() };
+        let backend = Backend::new("test".to_string(), urls[0].to_string(), balancer, breakers);
+        backend.set_endpoints(urls.iter().map(ToString::to_string).collect());
+        backend
+    }
+
+    fn placements(backend: &Backend, sessions: &[Session]) -> Vec<String> {
+        sessions
+            .iter()
+            .map(|session| backend.endpoint(Some(session)).unwrap().url.clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn sends_the_call_span_as_traceparent() {
+        use axum::{routing::post, Json};
+        use latchkey_core::trace::{SpanKind, Tracer};
+        use tokio::sync::mpsc::unbounded_channel;
+
+        // A tool server that passes on the trace headers of every message.
+        let (received, mut parents) = unbounded_channel();
+        let server = axum::Router::new().route(
+            "/mcp",
+            post(move |headers: HeaderMap, Json(message): Json<Value>| {
+                let received = received.clone();
+                async move {
+                    let header = |name| headers.get(name).map(|v| v.to_str().unwrap().to_string());
+                    let method = message["method"].as_str().unwrap().to_string();
+                    received.send((method, header("traceparent"), header("tracestate"))).unwrap();
+                    Json(json!({ "jsonrpc": "2.0", "id": message.get("id"), "result": {} }))
+                }
+            }),
+        );
+
+        let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+        let incoming =
+            TraceContext::parse(&format!("00-{trace_id}-00f067aa0ba902b7-01"), Some("vendor=1"))
+                .unwrap();
+        let servers = vec![("test".to_string(), format!("http://{}/mcp", serve(server).await))];
+        let client = reqwest::Client::new();
+        let breakers = BreakerConfig::default();
+        let backends =
+            Backends::new(servers, client.clone(), client, Balancer::PowerOfTwo, breakers);
+        let endpoint = backends.servers()[0].endpoint(None).unwrap();
+
+        let root = Tracer::default().span("POST /v1/mcp", SpanKind::Server, Some(&incoming));
+        let call = root.child("backend call", SpanKind::Client);
+        let message = json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/call", "params": {} });
+        let reply = backends.call(&endpoint, None, &message, None, Some(call.context()), 1024);
+        assert!(matches!(reply.await, Ok(Reply::Json(_))));
+
+        // Session setup is part of the backend call too.
+        let parent = format!("00-{trace_id}-{}-01", call.context().span_id_hex());
+        for method in ["initialize", "notifications/initialized", "tools/call"] {
+            let state = Some("vendor=1".to_string());
+            assert_eq!(
+                parents.recv().await.unwrap(),
+                (method.to_string(), Some(parent.clone()), state)
+            );
+        }
+    }
+
+    #[test]
+    fn removing_an_endpoint_only_moves_its_sessions() {
+        let urls = ["http://10.0.0.1/mcp", "http://10.0.0.2/mcp", "http://10.0.0.3/mcp"];
+        let backend = backend(&urls, Balancer::PowerOfTwo);
+        let sessions: Vec<Session> = (0..64).map(|_| Session::new("alice", json!({}))).collect();
+
+        let before = placements(&backend, &sessions);
+        assert_eq!(before, placements(&backend, &sessions));
+        assert!(urls.iter().all(|url| before.contains(&url.to_string())));
+
+        backend.set_endpoints(vec![urls[0].to_string(), urls[2].to_string()]);
+        let after = placements(&backend, &sessions);
+        for (before, after) in before.iter().zip(&after) {
+            if before != urls[1] {
+                assert_eq!(before, after);
+            }
+        }
+        assert!(!after.contains(&urls[1].to_string()));
+    }
+
+    #[test]
+    fn balancers_prefer_idle_endpoints() {
+        let urls = ["http://10.0.0.1:8081/mcp", "http://10.0.0.2:8081/mcp"];
+        for balancer in [Balancer::PowerOfTwo, Balancer::LeastRequests] {
+            let backend = backend(&urls, balancer);
+            let busy = backend.endpoint(None).unwrap();
+            let _in_flight = busy.begin();
+
+            let picked = backend.endpoint(None).unwrap();
+            assert_ne!(picked.url, busy.url, "{balancer:?}");
+            assert_eq!(
+                picked.address,
+                if busy.url == urls[0] { "10.0.0.2:8081" } else { "10.0.0.1:8081" }
+            );
+        }
+    }
+
+    #[test]
+    fn falls_back_to_the_configured_url_without_endpoints() {
+        let backend = backend(&["http://10.0.0.1/mcp"], Balancer::LeastRequests);
+        backend.set_endpoints(vec!["http://10.0.0.2/mcp".to_string()]);
+        assert_eq!(backend.endpoint(None).unwrap().url, "http://10.0.0.2/mcp");
+
+        backend.set_endpoints(Vec::new());
+        assert_eq!(backend.endpoint(None).unwrap().url, "http://10.0.0.1/mcp");
+    }
+
+    #[test]
+    fn ejects_endpoints_with_open_breakers() {
+        let urls = ["http://10.0.0.1/mcp", "http://10.0.0.2/mcp"];
+        let backend = backend(&urls, Balancer::LeastRequests);
+        let failing = backend.endpoint(None).unwrap();
+        Permit::acquire(vec![failing.breaker.clone()]).unwrap().record(false);
+
+        for _ in 0..8 {
+            assert_ne!(backend.endpoint(None).unwrap().url, failing.url);
+        }
+        let session = Session::new("alice", json!({}));
+        assert_ne!(backend.endpoint(Some(&session)).unwrap().url, failing.url);
+        assert!(backend.alternate(Some(&session), &backend.endpoint(None).unwrap()).is_none());
+
+        let healthy = backend.endpoint(None).unwrap();
+        Permit::acquire(vec![healthy.breaker.clone()]).unwrap().record(false);
+        assert!(backend.endpoint(None).is_none());
+        assert_eq!(backend.discovery_endpoint().url, urls[0]);
+    }
+}
@@ -0,0 +1,300 @@
+//! Circuit breakers for tool servers and their endpoints.
+
+use crate::parse_env;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+const DEFAULT_CONSECUTIVE_FAILURES: u32 = 5;
+const DEFAULT_ERROR_RATE: f64 = 0.5;
+const DEFAULT_MIN_REQUESTS: u32 = 20;
+const DEFAULT_WINDOW_SECONDS: u64 = 30;
+const DEFAULT_OPEN_SECONDS: u64 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BreakerConfig {
+    /// Consecutive failures that open the breaker.
+    pub consecutive_failures: u32,
+    /// Failure ratio within one window that opens the breaker, once `min_requests` completed.
+    pub error_rate: f64,
+    pub min_requests: u32,
+    pub window: Duration,
+    /// How long an open breaker fails fast before letting one probe through.
+    pub open_for: Duration,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            consecutive_failures: DEFAULT_CONSECUTIVE_FAILURES,
+            error_rate: DEFAULT_ERROR_RATE,
+            min_requests: DEFAULT_MIN_REQUESTS,
+            window: Duration::from_secs(DEFAULT_WINDOW_SECONDS),
+            open_for: Duration::from_secs(DEFAULT_OPEN_SECONDS),
+        }
+    }
+}
+
+impl BreakerConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let defaults = Self::default();
+        let error_rate = parse_env("LATCHKEY_BREAKER_ERROR_RATE")?.unwrap_or(defaults.error_rate);
+        anyhow::ensure!(
+            (0.0..=1.0).contains(&error_rate),
+            "LATCHKEY_BREAKER_ERROR_RATE must be between 0 and 1"
+        );
+
+        Ok(Self {
+            consecutive_failures: parse_env("LATCHKEY_BREAKER_CONSECUTIVE_FAILURES")?
+                .unwrap_or(defaults.consecutive_failures),
+            error_rate,
+            min_requests: parse_env("LATCHKEY_BREAKER_MIN_REQUESTS")?
+                .unwrap_or(defaults.min_requests),
+            window: parse_env("LATCHKEY_BREAKER_WINDOW_SECONDS")?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.window),
+            open_for: parse_env("LATCHKEY_BREAKER_OPEN_SECONDS")?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.open_for),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Closed,
+    Open,
+    /// One probe request is allowed through to test recovery.
+    HalfOpen,
+}
+
+impl State {
+    pub const ALL: [State; 3] = [State::Closed, State::Open, State::HalfOpen];
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Closed => "closed",
+            Self::Open => "open",
+            Self::HalfOpen => "half_open",
+        })
+    }
+}
+
+pub struct Breaker {
+    config: BreakerConfig,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    state: State,
+    consecutive_failures: u32,
+    window_started: Instant,
+    window_requests: u32,
+    window_failures: u32,
+    opened: Instant,
+    probing: bool,
+}
+
+impl Breaker {
+    pub fn new(config: BreakerConfig) -> Self {
+        Self { config, inner: Mutex::new(Self::closed(Instant::now())) }
+    }
+
+    /// The current state, moving an open breaker whose wait is over to half-open.
+    pub fn state(&self) -> State {
+        let mut inner = self.lock();
+        self.advance(&mut inner);
+        inner.state
+    }
+
+    /// Whether a request could be let through right now, without claiming the probe.
+    pub fn available(&self) -> bool {
+        let mut inner = self.lock();
+        self.advance(&mut inner);
+        match inner.state {
+            State::Closed => true,
+            State::Open => false,
+            State::HalfOpen => !inner.probing,
+        }
+    }
+
+    /// Lets a request through, if possible, and reports whether it claimed the half-open probe.
+    fn acquire(&self) -> Option<bool> {
+        let mut inner = self.lock();
+        self.advance(&mut inner);
+        match inner.state {
+            State::Closed => Some(false),
+            State::Open => None,
+            State::HalfOpen if inner.probing => None,
+            State::HalfOpen => {
+                inner.probing = true;
+                Some(true)
+            }
+        }
+    }
+
+    fn record(&self, success: bool) {
+        let mut inner = self.lock();
+        let now = Instant::now();
+        if now.duration_since(inner.window_started) >= self.config.window {
+            inner.window_started = now;
+            inner.window_requests = 0;
+            inner.window_failures = 0;
+        }
+        inner.window_requests += 1;
+
+        if success {
+            inner.consecutive_failures = 0;
+            if inner.state == State::HalfOpen {
+                *inner = Self::closed(now);
+            }
+            return;
+        }
+
+        inner.consecutive_failures += 1;
+        inner.window_failures += 1;
+        let rate = f64::from(inner.window_failures) / f64::from(inner.window_requests);
+        let tripped = inner.consecutive_failures >= self.config.consecutive_failures
+            || (inner.window_requests >= self.config.min_requests
+                && rate >= self.config.error_rate);
+        if inner.state == State::HalfOpen || tripped {
+            inner.state = State::Open;
+            inner.opened = now;
+            inner.probing = false;
+        }
+    }
+
+    /// Gives back a probe whose request ended without an outcome, such as a client hang-up.
+    fn release(&self) {
+        self.lock().probing = false;
+    }
+
+    fn advance(&self, inner: &mut Inner) {
+        if inner.state == State::Open && inner.opened.elapsed() >= self.config.open_for {
+            inner.state = State::HalfOpen;
+            inner.probing = false;
+        }
+    }
+
+    fn closed(now: Instant) -> Inner {
+        Inner {
+            state: State::Closed,
+            consecutive_failures: 0,
+            window_started: now,
+            window_requests: 0,
+            window_failures: 0,
+            opened: now,
+            probing: false,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().expect("breaker lock poisoned")
+    }
+}
+
+/// Admission through one or more breakers. The outcome is recorded on all of them; dropping the
+/// permit without one only gives back any half-open probe.
+pub struct Permit {
+    /// Each breaker, and whether this permit holds its half-open probe.
+    breakers: Vec<(Arc<Breaker>, bool)>,
+    done: bool,
+}
+
+impl Permit {
+    /// Claims every breaker, or none if any refuses.
+    pub fn acquire(breakers: Vec<Arc<Breaker>>) -> Option<Self> {
+        let mut permit = Self { breakers: Vec::with_capacity(breakers.len()), done: false };
+        for breaker in breakers {
+            // Dropping the partial permit gives back the probes claimed so far.
+            let probe = breaker.acquire()?;
+            permit.breakers.push((breaker, probe));
+        }
+        Some(permit)
+    }
+
+    pub fn record(mut self, success: bool) {
+        self.done = true;
+        self.breakers.iter().for_each(|(breaker, _)| breaker.record(success));
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if !self.done {
+            self.breakers
+                .iter()
+                .filter(|(_, probe)| *probe)
+                .for_each(|(breaker, _)| breaker.release());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker() -> Arc<Breaker> {
+        Arc::new(Breaker::new(BreakerConfig {
+            consecutive_failures: 3,
+            error_rate: 0.5,
+            min_requests: 10,
+            window: Duration::from_secs(60),
+            open_for: Duration::from_secs(30),
+        }))
+    }
+
+    fn call(breaker: &Arc<Breaker>, success: bool) -> bool {
+        let permit = Permit::acquire(vec![breaker.clone()]);
+        let admitted = permit.is_some();
+        if let Some(permit) = permit {
+            permit.record(success);
+        }
+        admitted
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn opens_on_consecutive_failures_and_recovers_through_a_probe() {
+        let breaker = breaker();
+        for _ in 0..3 {
+            assert!(call(&breaker, false));
+        }
+        assert_eq!(breaker.state(), State::Open);
+        assert!(!call(&breaker, true));
+
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert_eq!(breaker.state(), State::HalfOpen);
+        let probe = Permit::acquire(vec![breaker.clone()]).expect("probe");
+        assert!(Permit::acquire(vec![breaker.clone()]).is_none());
+        probe.record(false);
+        assert_eq!(breaker.state(), State::Open);
+
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert!(call(&breaker, true));
+        assert_eq!(breaker.state(), State::Closed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn opens_on_error_rate_within_a_window() {
+        let breaker = breaker();
+        for n in 0..10 {
+            assert!(call(&breaker, n % 2 == 0));
+        }
+        assert_eq!(breaker.state(), State::Open);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dropped_probes_are_given_back() {
+        let breaker = breaker();
+        for _ in 0..3 {
+            call(&breaker, false);
+        }
+        tokio::time::advance(Duration::from_secs(30)).await;
+
+        drop(Permit::acquire(vec![breaker.clone()]).expect("probe"));
+        assert!(breaker.available());
+    }
+}
@@ -0,0 +1,183 @@
+//! Links audit events into a hash chain and signs periodic checkpoints of it, so an exported log
+//! shows any event that was removed, reordered or changed.
+
+use crate::parse_env;
+use anyhow::Context;
+use chrono::{SecondsFormat, Utc};
+use latchkey_core::audit::{AuditCheckpoint, AuditEvent, AUDIT_SCHEMA_VERSION};
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use std::fmt::Write;
+use tracing::{info, warn};
+
+pub struct Signer {
+    key: Ed25519KeyPair,
+    key_id: String,
+}
+
+impl Signer {
+    fn from_seed(seed: &[u8]) -> anyhow::Result<Self> {
+        let key = Ed25519KeyPair::from_seed_unchecked(seed)
+            .map_err(|_| anyhow::anyhow!("expected a 32-byte Ed25519 seed"))?;
+        let key_id = to_hex(key.public_key().as_ref())[..16].to_string();
+        Ok(Self { key, key_id })
+    }
+
+    /// Reads a hex-encoded 32-byte seed from `LATCHKEY_AUDIT_SIGNING_KEY_FILE`. Making up a key
+    /// for this run instead takes `LATCHKEY_AUDIT_EPHEMERAL_KEY=true`, meant for dev clusters.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let signer = match std::env::var("LATCHKEY_AUDIT_SIGNING_KEY_FILE") {
+            Ok(path) => {
+                let seed = std::fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read {path}"))?;
+                let seed = from_hex(seed.trim()).context("the signing key must be hex")?;
+                Self::from_seed(&seed).context("invalid LATCHKEY_AUDIT_SIGNING_KEY_FILE")?
+            }
+            Err(_) => {
+                anyhow::ensure!(
+                    parse_env::<bool>("LATCHKEY_AUDIT_EPHEMERAL_KEY")?.unwrap_or(false),
+                    "LATCHKEY_AUDIT_SIGNING_KEY_FILE is unset; set LATCHKEY_AUDIT_EPHEMERAL_KEY=true \
+                     to sign with a key for this run only"
+                );
+                let seed: [u8; 32] = ring::rand::generate(&SystemRandom::new())
+                    .map_err(|_| anyhow::anyhow!("no system randomness for an audit signing key"))?
+                    .expose();
+                let signer = Self::from_seed(&seed)?;
+                warn!(
+                    public_key = %signer.public_key(),
+                    "signing audit checkpoints with a key for this run only, they cannot be \
+                     verified after a restart"
+                );
+                return Ok(signer);
+            }
+        };
+        info!(public_key = %signer.public_key(), "signing audit checkpoints");
+        Ok(signer)
+    }
+
+    /// Hex Ed25519 public key, as the verifier takes it.
+    pub fn public_key(&self) -> String {
+        to_hex(self.key.public_key().as_ref())
+    }
+}
+
+/// This process's chain. Every event passes through `link` in the order sinks receive it.
+pub struct Chain {
+    id: String,
+    signer: Signer,
+    /// Events between checkpoints, at most.
+    checkpoint_events: u64,
+    next: u64,
+    last_hash: Option<String>,
+    /// Events since the last checkpoint.
+    unsigned: u64,
+}
+
+impl Chain {
+    pub fn new(signer: Signer, checkpoint_events: u64) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            signer,
+            checkpoint_events: checkpoint_events.max(1),
+            next: 0,
+            last_hash: None,
+            unsigned: 0,
+        }
+    }
+
+    /// Appends an event to the chain, returning a checkpoint when one is due.
+    pub fn link(&mut self, event: &mut AuditEvent) -> Option<AuditCheckpoint> {
+        event.chain_id = Some(self.id.clone());
+        event.sequence = Some(self.next);
+        event.prev_hash = self.last_hash.take();
+        self.last_hash = Some(event.hash());
+        self.next += 1;
+        self.unsigned += 1;
+        if self.unsigned >= self.checkpoint_events {
+            self.checkpoint()
+        } else {
+            None
+        }
+    }
+
+    /// Signs the newest event, unless the last checkpoint already covers it.
+    pub fn checkpoint(&mut self) -> Option<AuditCheckpoint> {
+        if self.unsigned == 0 {
+            return None;
+        }
+        self.unsigned = 0;
+        let mut checkpoint = AuditCheckpoint {
+            schema_version: AUDIT_SCHEMA_VERSION,
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            chain_id: self.id.clone(),
+            sequence: self.next - 1,
+            hash: self.last_hash.clone()?,
+            key_id: self.signer.key_id.clone(),
+            signature: String::new(),
+        };
+        let signature = self.signer.key.sign(checkpoint.signed_content().as_bytes());
+        checkpoint.signature = to_hex(signature.as_ref());
+        Some(checkpoint)
+    }
+}
+
+/// Whether a checkpoint was signed by the key with this hex public key.
+pub fn signed_by(checkpoint: &AuditCheckpoint, public_key: &[u8]) -> bool {
+    let Ok(signature) = from_hex(&checkpoint.signature) else {
+        return false;
+    };
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(checkpoint.signed_content().as_bytes(), &signature)
+        .is_ok()
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+pub fn from_hex(hex: &str) -> anyhow::Result<Vec<u8>> {
+    anyhow::ensure!(hex.len().is_multiple_of(2), "odd number of hex digits");
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| {
+            let digits = hex.get(index..index + 2).context("invalid hex digit")?;
+            u8::from_str_radix(digits, 16).context("invalid hex digit")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// A chain with a fixed key, checkpointing every `checkpoint_events` events.
+    pub fn chain(checkpoint_events: u64) -> (Chain, Vec<u8>) {
+        let signer = Signer::from_seed(&[7; 32]).unwrap();
+        let public_key = signer.key.public_key().as_ref().to_vec();
+        (Chain::new(signer, checkpoint_events), public_key)
+    }
+
+    #[test]
+    fn links_events_and_signs_checkpoints() {
+        let (mut chain, public_key) = chain(2);
+        let mut first = AuditEvent::new("a".into(), "t".into(), "tools/call".into());
+        let mut second = AuditEvent::new("b".into(), "t".into(), "tools/call".into());
+
+        assert_eq!(chain.link(&mut first), None);
+        let checkpoint = chain.link(&mut second).expect("a checkpoint every two events");
+        assert_eq!((first.sequence, second.sequence), (Some(0), Some(1)));
+        assert_eq!(first.prev_hash, None);
+        assert_eq!(second.prev_hash, Some(first.hash()));
+        assert_eq!((checkpoint.sequence, &checkpoint.hash), (1, &second.hash()));
+        assert!(signed_by(&checkpoint, &public_key));
+        assert_eq!(chain.checkpoint(), None);
+
+        let mut forged = checkpoint.clone();
+        forged.sequence = 0;
+        assert!(!signed_by(&forged, &public_key));
+        assert_eq!(from_hex(&to_hex(&[0, 171, 255])).unwrap(), vec![0, 171, 255]);
+    }
+}
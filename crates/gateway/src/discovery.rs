@@ -0,0 +1,288 @@
+//! Keeps each server's endpoint set current, from EndpointSlices when the server URL names a
+//! cluster Service, and from DNS otherwise.
+
+use crate::backend::{Backend, Backends};
+use crate::parse_env;
+use futures::StreamExt;
+use k8s_openapi::api::discovery::v1::EndpointSlice;
+use kube::runtime::watcher::{self, Event};
+use kube::runtime::WatchStreamExt;
+use kube::{Api, Client, ResourceExt};
+use reqwest::Url;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+const DEFAULT_NAMESPACE: &str = "latchkey-system";
+const DEFAULT_REFRESH_SECONDS: u64 = 10;
+const SERVICE_NAME_LABEL: &str = "kubernetes.io/service-name";
+/// EndpointSlice port used when a Service exposes more than one.
+const PORT_NAME: &str = "http";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    EndpointSlices,
+    Dns,
+}
+
+impl FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "endpointslices" => Ok(Self::EndpointSlices),
+            "dns" => Ok(Self::Dns),
+            _ => anyhow::bail!("expected endpointslices or dns, got {value}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DiscoveryConfig {
+    pub mode: Mode,
+    /// Namespace of Services named by a bare hostname.
+    pub namespace: String,
+    /// How often DNS names are re-resolved.
+    pub refresh: Duration,
+}
+
+impl DiscoveryConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mode = match std::env::var("LATCHKEY_ENDPOINT_DISCOVERY") {
+            Ok(mode) => mode.parse()?,
+            Err(_) => Mode::EndpointSlices,
+        };
+        let refresh =
+            parse_env("LATCHKEY_ENDPOINT_REFRESH_SECONDS")?.unwrap_or(DEFAULT_REFRESH_SECONDS);
+
+        Ok(Self {
+            mode,
+            namespace: std::env::var("POD_NAMESPACE")
+                .unwrap_or_else(|_| DEFAULT_NAMESPACE.to_string()),
+            refresh: Duration::from_secs(refresh),
+        })
+    }
+}
+
+/// Watches EndpointSlices for every server URL that names a Service and re-resolves the rest.
+/// Without a reachable Kubernetes API everything falls back to DNS.
+pub async fn run(backends: Arc<Backends>, config: DiscoveryConfig) {
+    let client = match config.mode {
+        Mode::EndpointSlices => match Client::try_default().await {
+            Ok(client) => Some(client),
+            Err(err) => {
+                warn!(error = %err, "no Kubernetes API, discovering endpoints through DNS");
+                None
+            }
+        },
+        Mode::Dns => None,
+    };
+
+    let mut resolved = Vec::new();
+    for (index, backend) in backends.servers().iter().enumerate() {
+        let Some(url) = discoverable(backend) else {
+            continue;
+        };
+        let service = url.host_str().and_then(|host| service(host, &config.namespace));
+        match (&client, service) {
+            (Some(client), Some((namespace, name))) => {
+                let client = client.clone();
+                tokio::spawn(watch_slices(backends.clone(), index, url, client, namespace, name));
+            }
+            _ => resolved.push((index, url)),
+        }
+    }
+
+    loop {
+        for (index, url) in &resolved {
+            resolve(&backends.servers()[*index], url).await;
+        }
+        tokio::time::sleep(config.refresh).await;
+    }
+}
+
+/// Only plain-HTTP hostnames are discovered, since TLS needs the name to verify the server.
+fn discoverable(backend: &Backend) -> Option<Url> {
+    let url = Url::parse(&backend.url).ok()?;
+    let host = url.host_str()?;
+    (url.scheme() == "http" && host.parse::<IpAddr>().is_err()).then_some(url)
+}
+
+/// The `(namespace, name)` of the Service a hostname names: `name` in `default_namespace`, or
+/// `name.namespace.svc` with an optional cluster domain. `name.namespace` is left to DNS, since
+/// it cannot be told apart from an external name.
+fn service(host: &str, default_namespace: &str) -> Option<(String, String)> {
+    match host.split('.').collect::<Vec<_>>().as_slice() {
+        [name] => Some((default_namespace.to_string(), name.to_string())),
+        [name, namespace, "svc", ..] => Some((namespace.to_string(), name.to_string())),
+        _ => None,
+    }
+}
+
+async fn watch_slices(
+    backends: Arc<Backends>,
+    index: usize,
+    url: Url,
+    client: Client,
+    namespace: String,
+    service: String,
+) {
+    let backend = &backends.servers()[index];
+    let api: Api<EndpointSlice> = Api::namespaced(client, &namespace);
+    let config = watcher::Config::default().labels(&format!("{SERVICE_NAME_LABEL}={service}"));
+    let mut stream = watcher::watcher(api, config).default_backoff().boxed();
+    info!(server = %backend.name, %namespace, %service, "watching endpoint slices");
+
+    // Ready URLs by slice name; `relisting` buffers a relist until it completes.
+    let mut slices: HashMap<String, Vec<String>> = HashMap::new();
+    let mut relisting: Option<HashMap<String, Vec<String>>> = None;
+    while let Some(event) = stream.next().await {
+        match event {
+            Ok(Event::Init) => {
+                relisting = Some(HashMap::new());
+                continue;
+            }
+            Ok(Event::InitApply(slice)) => {
+                let ready = ready_urls(&url, &slice);
+                relisting.get_or_insert_with(HashMap::new).insert(slice.name_any(), ready);
+                continue;
+            }
+            Ok(Event::InitDone) => slices = relisting.take().unwrap_or_default(),
+            Ok(Event::Apply(slice)) => {
+                slices.insert(slice.name_any(), ready_urls(&url, &slice));
+            }
+            Ok(Event::Delete(slice)) => {
+                slices.remove(&slice.name_any());
+            }
+            Err(err) => {
+                warn!(server = %backend.name, error = %err, "endpoint slice watch failed");
+                continue;
+            }
+        }
+
+        let mut urls: Vec<String> = slices.values().flatten().cloned().collect();
+        urls.sort();
+        urls.dedup();
+        backend.set_endpoints(urls);
+    }
+}
+
+/// URLs of a slice's ready endpoints. Terminating endpoints are never ready.
+fn ready_urls(url: &Url, slice: &EndpointSlice) -> Vec<String> {
+    if !matches!(slice.address_type.as_str(), "IPv4" | "IPv6") {
+        return Vec::new();
+    }
+    let ports = slice.ports.as_deref().unwrap_or_default();
+    let port = match ports {
+        [port] => Some(port),
+        _ => ports.iter().find(|port| port.name.as_deref() == Some(PORT_NAME)),
+    };
+    let Some(port) = port.and_then(|port| port.port).and_then(|port| u16::try_from(port).ok())
+    else {
+        return Vec::new();
+    };
+
+    slice
+        .endpoints
+        .iter()
+        .filter(|endpoint| {
+            endpoint.conditions.as_ref().and_then(|conditions| conditions.ready).unwrap_or(true)
+        })
+        .flat_map(|endpoint| &endpoint.addresses)
+        .filter_map(|address| {
+            let mut url = url.clone();
+            url.set_ip_host(address.parse().ok()?).ok()?;
+            url.set_port(Some(port)).ok()?;
+            Some(url.to_string())
+        })
+        .collect()
+}
+
+/// Routes across every address the server's hostname resolves to. A headless Service resolves
+/// to its ready pods; a ClusterIP Service to one address.
+async fn resolve(backend: &Backend, url: &Url) {
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        return;
+    };
+    let addrs = match tokio::net::lookup_host((host, port)).await {
+        Ok(addrs) => addrs,
+        Err(err) => {
+            warn!(server = %backend.name, error = %err, "failed to resolve server endpoints");
+            return;
+        }
+    };
+
+    let mut urls: Vec<String> = addrs
+        .filter_map(|addr| {
+            let mut url = url.clone();
+            url.set_ip_host(addr.ip()).ok()?;
+            Some(url.to_string())
+        })
+        .collect();
+    urls.sort();
+    urls.dedup();
+    // A failed lookup keeps the last known endpoints; an empty answer is not one.
+    if !urls.is_empty() {
+        backend.set_endpoints(urls);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::api::discovery::v1::{Endpoint, EndpointConditions, EndpointPort};
+
+    fn endpoint(address: &str, ready: Option<bool>) -> Endpoint {
+        Endpoint {
+            addresses: vec![address.to_string()],
+            conditions: Some(EndpointConditions { ready, ..EndpointConditions::default() }),
+            ..Endpoint::default()
+        }
+    }
+
+    #[test]
+    fn names_services_by_cluster_hostname() {
+        let service = |host| service(host, "latchkey-system");
+        let expected = |namespace: &str| Some((namespace.to_string(), "github".to_string()));
+
+        assert_eq!(service("github"), expected("latchkey-system"));
+        assert_eq!(service("github.tools.svc"), expected("tools"));
+        assert_eq!(service("github.tools.svc.cluster.local"), expected("tools"));
+        assert_eq!(service("github.tools"), None);
+        assert_eq!(service("api.github.com"), None);
+    }
+
+    #[test]
+    fn keeps_ready_endpoints_on_the_http_port() {
+        let url = Url::parse("http://github.tools.svc:80/mcp").unwrap();
+        let slice = EndpointSlice {
+            address_type: "IPv4".to_string(),
+            endpoints: vec![
+                endpoint("10.0.0.1", Some(true)),
+                endpoint("10.0.0.2", Some(false)),
+                endpoint("10.0.0.3", None),
+            ],
+            ports: Some(vec![
+                EndpointPort {
+                    name: Some("metrics".to_string()),
+                    port: Some(9090),
+                    ..Default::default()
+                },
+                EndpointPort {
+                    name: Some("http".to_string()),
+                    port: Some(8081),
+                    ..Default::default()
+                },
+            ]),
+            ..EndpointSlice::default()
+        };
+
+        assert_eq!(
+            ready_urls(&url, &slice),
+            vec!["http://10.0.0.1:8081/mcp".to_string(), "http://10.0.0.3:8081/mcp".to_string()]
+        );
+    }
+}
@@ -1,19 +1,49 @@
+mod audit;
+mod backend;
+mod breaker;
+mod chain;
+mod discovery;
+mod mcp;
+mod metrics;
+mod quota;
+mod ratelimit;
+mod redact;
+mod redis;
+mod resources;
+mod retry;
+mod session;
+mod sinks;
+mod sse;
+mod tools;
+mod verify;
+
 use anyhow::Context;
 use axum::error_handling::HandleErrorLayer;
 use axum::{
     extract::State,
     http::{HeaderMap, StatusCode},
-    response::IntoResponse,
     routing::{get, post},
     Json, Router,
 };
-use serde::{Deserialize, Serialize};
+use backend::{Backends, Balancer};
+use breaker::BreakerConfig;
+use discovery::DiscoveryConfig;
+use latchkey_core::trace::Tracer;
+use metrics::Metrics;
+use quota::Quotas;
+use ratelimit::RateLimits;
+use redact::Redaction;
+use redis::Redis;
+use retry::RetryConfig;
 use serde_json::{json, Value};
-use std::collections::{HashMap, HashSet, VecDeque};
+use session::Sessions;
+use sinks::AuditSinks;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::{net::SocketAddr, time::Duration};
-use tokio::sync::Mutex;
-use tokio::time::Instant;
+use tools::{ToolLimits, Tools};
 use tower::timeout::TimeoutLayer;
 use tower::{BoxError, ServiceBuilder};
 use tower_http::{
@@ -21,68 +51,79 @@ use tower_http::{
     request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
     trace::TraceLayer,
 };
-use tracing::{error, info};
+use tracing::{debug, error, info};
 use tracing_subscriber::EnvFilter;
 
 const DEFAULT_BIND: &str = "0.0.0.0:8080";
+/// Loopback unless deployed otherwise: the admin routes have no authentication.
+const DEFAULT_ADMIN_BIND: &str = "127.0.0.1:9090";
+/// Ceiling on request bodies, except `/v1/mcp` calls, which are held to their tool's
+/// `maxPayloadBytes` once the client is authenticated.
 const MAX_BODY_BYTES: usize = 64 * 1024;
 const REQUEST_TIMEOUT_SECONDS: u64 = 5;
-const DEFAULT_TOOL_SERVER_URL: &str =
-    "http://latchkey-tool-server.latchkey-system.svc.cluster.local:8081";
+const DEFAULT_MCP_SERVERS: &str =
+    "latchkey-tool-server=http://latchkey-tool-server.latchkey-system.svc.cluster.local:8081/mcp";
 const DEFAULT_TOKENS: &str = "demo-agent=demo-token";
 const DEFAULT_ALLOWLIST: &str = "demo-agent=demo.echo";
-const DEFAULT_RATE_LIMIT_PER_MINUTE: usize = 60;
+const DEFAULT_SESSION_IDLE_TIMEOUT_SECONDS: u64 = 30 * 60;
+const SESSION_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+const RATE_LIMIT_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
 
 #[derive(Clone)]
 struct AppState {
-    tool_server_url: String,
+    backends: Arc<Backends>,
+    sessions: Arc<Sessions>,
+    tools: Arc<Tools>,
     auth_tokens: HashMap<String, String>,
     allowlist: HashMap<String, HashSet<String>>,
-    rate_limit_per_minute: usize,
-    client: reqwest::Client,
-    request_windows: Arc<Mutex<HashMap<String, VecDeque<Instant>>>>,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct MpcRequest {
-    tool_name: String,
-    #[serde(default)]
-    operation: Option<String>,
-    #[serde(default)]
-    params: Value,
-}
-
-#[derive(Debug, Serialize)]
-struct MpcResponse {
-    request_id: String,
-    tool_name: String,
-    result: Value,
+    rate_limits: Arc<RateLimits>,
+    quotas: Arc<Quotas>,
+    retry: RetryConfig,
+    audit_sinks: Arc<AuditSinks>,
+    redaction: Arc<Redaction>,
+    metrics: Arc<Metrics>,
+    tracer: Tracer,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    if let Some(command) = args.next() {
+        return match command.as_str() {
+            "verify-audit" => verify::run(args),
+            other => anyhow::bail!("unknown subcommand: {other}"),
+        };
+    }
+
     init_tracing();
 
     let bind = std::env::var("LATCHKEY_GATEWAY_BIND").unwrap_or_else(|_| DEFAULT_BIND.to_string());
     let addr: SocketAddr = bind.parse().context("invalid LATCHKEY_GATEWAY_BIND value")?;
 
     let state = AppState::from_env()?;
-
-    let app = Router::new()
-        .route("/healthz", get(healthz))
-        .route("/readyz", get(readyz))
-        .route("/metrics", get(metrics))
-        .route("/v1/mcp", post(proxy_mcp))
-        .with_state(state)
-        .layer(
-            ServiceBuilder::new()
-                .layer(HandleErrorLayer::new(handle_timeout_error))
-                .layer(TimeoutLayer::new(Duration::from_secs(REQUEST_TIMEOUT_SECONDS)))
-                .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
-                .layer(PropagateRequestIdLayer::x_request_id())
-                .layer(RequestBodyLimitLayer::new(MAX_BODY_BYTES))
-                .layer(TraceLayer::new_for_http()),
-        );
+    let discovery = DiscoveryConfig::from_env().context("invalid LATCHKEY_ENDPOINT_DISCOVERY")?;
+    tokio::spawn(discovery::run(state.backends.clone(), discovery));
+    tokio::spawn(expire_sessions(state.backends.clone(), state.sessions.clone()));
+    tokio::spawn(tools::watch(state.tools.clone()));
+    tokio::spawn(ratelimit::watch(state.rate_limits.clone()));
+    tokio::spawn(redact::watch(state.redaction.clone()));
+    tokio::spawn(evict_rate_limits(state.rate_limits.clone()));
+    tokio::spawn(checkpoint_audit(state.audit_sinks.clone()));
+
+    let admin_bind =
+        std::env::var("LATCHKEY_ADMIN_BIND").unwrap_or_else(|_| DEFAULT_ADMIN_BIND.to_string());
+    let admin_addr: SocketAddr = admin_bind.parse().context("invalid LATCHKEY_ADMIN_BIND value")?;
+    let admin_listener = tokio::net::TcpListener::bind(admin_addr)
+        .await
+        .with_context(|| format!("failed to bind admin listener on {admin_addr}"))?;
+    let admin = admin(state.clone());
+    info!(%admin_addr, "serving metrics and admin endpoints");
+    tokio::spawn(async move {
+        if let Err(err) = axum::serve(admin_listener, admin).await {
+            error!(error = %err, "admin server failed");
+        }
+    });
+    let app = router(state);
 
     let listener = tokio::net::TcpListener::bind(addr)
         .await
@@ -92,6 +133,33 @@ async fn main() -> anyhow::Result<()> {
     axum::serve(listener, app).await.context("gateway server failed")
 }
 
+/// The routes gateway clients reach.
+fn router(state: AppState) -> Router {
+    let app = Router::new().route("/healthz", get(healthz)).route("/readyz", get(readyz));
+    let app = with_timeout(app).layer(RequestBodyLimitLayer::new(MAX_BODY_BYTES));
+    // Tool calls run to their tool's own timeout instead, and `mcp::handle` reads bodies only
+    // after authentication. Other paths are not audited.
+    let mcp = Router::new()
+        .route("/v1/mcp", post(mcp::handle).get(mcp::listen).delete(mcp::terminate))
+        .route_layer(axum::middleware::from_fn_with_state(state.clone(), audit::track));
+    app.merge(mcp).with_state(state).layer(
+        ServiceBuilder::new()
+            .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+            .layer(PropagateRequestIdLayer::x_request_id())
+            .layer(TraceLayer::new_for_http()),
+    )
+}
+
+/// Metrics and operator endpoints. They have no authentication, so they get their own listener
+/// and are never served to gateway clients.
+fn admin(state: AppState) -> Router {
+    let admin = Router::new()
+        .route("/metrics", get(metrics))
+        .route("/admin/breakers", get(breakers))
+        .route("/admin/quotas", get(quotas));
+    with_timeout(admin).with_state(state)
+}
+
 async fn healthz() -> StatusCode {
     StatusCode::OK
 }
@@ -100,138 +168,103 @@ async fn readyz() -> StatusCode {
     StatusCode::OK
 }
 
-async fn metrics() -> &'static str {
-    "# latchkey metrics placeholder\nlatchkey_requests_total 0\n"
+/// Bounds every route but `/v1/mcp` by the gateway-wide request timeout.
+fn with_timeout(router: Router<AppState>) -> Router<AppState> {
+    router.route_layer(
+        ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(handle_timeout_error))
+            .layer(TimeoutLayer::new(Duration::from_secs(REQUEST_TIMEOUT_SECONDS))),
+    )
 }
 
-async fn proxy_mcp(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    Json(request): Json<MpcRequest>,
-) -> impl IntoResponse {
-    let started = Instant::now();
-    let request_id = request_id_from_headers(&headers);
-    let tool_name = request.tool_name.clone();
-
-    let Some(principal_id) = principal_id_from_headers(&headers, &state.auth_tokens) else {
-        emit_audit(
-            &request_id,
-            "anonymous",
-            &tool_name,
-            "deny",
-            "error",
-            StatusCode::UNAUTHORIZED,
-            Some("missing_or_invalid_token"),
-            started,
-        );
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(json!({"error": "unauthorized", "request_id": request_id})),
-        );
-    };
-
-    if !is_tool_allowed(&state.allowlist, &principal_id, &tool_name) {
-        emit_audit(
-            &request_id,
-            &principal_id,
-            &tool_name,
-            "deny",
-            "error",
-            StatusCode::FORBIDDEN,
-            Some("tool_not_allowed"),
-            started,
-        );
-        return (
-            StatusCode::FORBIDDEN,
-            Json(json!({"error": "forbidden", "request_id": request_id})),
-        );
+async fn metrics(State(state): State<AppState>) -> String {
+    let mut metrics = String::new();
+    state.metrics.write(&mut metrics);
+    metrics.push_str(
+        "# HELP latchkey_circuit_breaker_state Circuit breaker state per server and endpoint.\n\
+         # TYPE latchkey_circuit_breaker_state gauge\n",
+    );
+    for backend in state.backends.servers() {
+        let mut breakers = vec![("", backend.breaker.state())];
+        let endpoints = backend.endpoints();
+        breakers.extend(endpoints.iter().map(|e| (e.address.as_str(), e.breaker.state())));
+        for (endpoint, current) in breakers {
+            for state in breaker::State::ALL {
+                let _ = writeln!(
+                    metrics,
+                    "latchkey_circuit_breaker_state{{server=\"{}\",endpoint=\"{endpoint}\",\
+                     state=\"{state}\"}} {}",
+                    backend.name,
+                    u8::from(state == current)
+                );
+            }
+        }
     }
+    state.audit_sinks.write_metrics(&mut metrics);
+    metrics
+}
 
-    if !consume_rate_limit(&state, &principal_id).await {
-        emit_audit(
-            &request_id,
-            &principal_id,
-            &tool_name,
-            "deny",
-            "error",
-            StatusCode::TOO_MANY_REQUESTS,
-            Some("rate_limited"),
-            started,
-        );
-        return (
-            StatusCode::TOO_MANY_REQUESTS,
-            Json(json!({"error": "rate_limited", "request_id": request_id})),
-        );
-    }
+/// Breaker state of every server and its endpoints.
+async fn breakers(State(state): State<AppState>) -> Json<Value> {
+    let servers: Vec<Value> = state
+        .backends
+        .servers()
+        .iter()
+        .map(|backend| {
+            let endpoints: Vec<Value> = backend
+                .endpoints()
+                .iter()
+                .map(|endpoint| {
+                    json!({
+                        "address": endpoint.address,
+                        "state": endpoint.breaker.state().to_string(),
+                        "in_flight": endpoint.load(),
+                    })
+                })
+                .collect();
+            json!({
+                "server": backend.name,
+                "state": backend.breaker.state().to_string(),
+                "endpoints": endpoints,
+            })
+        })
+        .collect();
+    Json(Value::Array(servers))
+}
+
+/// Consumption of every quota in its current period.
+async fn quotas(State(state): State<AppState>) -> Json<Vec<quota::Usage>> {
+    Json(state.quotas.usage().await)
+}
 
-    let url = format!("{}/v1/tool", state.tool_server_url.trim_end_matches('/'));
-    let upstream = state.client.post(url).json(&request).send().await;
-
-    let response = match upstream {
-        Ok(response) if response.status().is_success() => {
-            let payload =
-                response.json::<Value>().await.unwrap_or_else(|_| json!({"status": "ok"}));
-            emit_audit(
-                &request_id,
-                &principal_id,
-                &tool_name,
-                "allow",
-                "success",
-                StatusCode::OK,
-                None,
-                started,
-            );
-            (StatusCode::OK, Json(json!(MpcResponse { request_id, tool_name, result: payload })))
+async fn expire_sessions(backends: Arc<Backends>, sessions: Arc<Sessions>) {
+    loop {
+        tokio::time::sleep(SESSION_SWEEP_INTERVAL).await;
+        let expired = sessions.expire();
+        for session in &expired {
+            backends.close_session(session).await;
         }
-        Ok(response) => {
-            error!(
-                request_id = %request_id,
-                principal_id = %principal_id,
-                tool_name = %tool_name,
-                upstream_status = %response.status(),
-                "tool server returned non-success status"
-            );
-            emit_audit(
-                &request_id,
-                &principal_id,
-                &tool_name,
-                "allow",
-                "error",
-                StatusCode::BAD_GATEWAY,
-                Some("tool_server_error"),
-                started,
-            );
-            (
-                StatusCode::BAD_GATEWAY,
-                Json(json!({"error": "tool_server_error", "request_id": request_id})),
-            )
+        if !expired.is_empty() {
+            debug!(expired = expired.len(), active = sessions.len(), "expired idle sessions");
         }
-        Err(err) => {
-            error!(
-                request_id = %request_id,
-                principal_id = %principal_id,
-                tool_name = %tool_name,
-                error = %err,
-                "tool server request failed"
-            );
-            emit_audit(
-                &request_id,
-                &principal_id,
-                &tool_name,
-                "allow",
-                "error",
-                StatusCode::BAD_GATEWAY,
-                Some("tool_server_unreachable"),
-                started,
-            );
-            (
-                StatusCode::BAD_GATEWAY,
-                Json(json!({"error": "tool_server_unreachable", "request_id": request_id})),
-            )
+    }
+}
+
+async fn evict_rate_limits(rate_limits: Arc<RateLimits>) {
+    loop {
+        tokio::time::sleep(RATE_LIMIT_SWEEP_INTERVAL).await;
+        let (evicted, active) = rate_limits.evict();
+        if evicted > 0 {
+            debug!(evicted, active, "evicted idle rate limit keys");
         }
-    };
+    }
+}
 
-    response
+async fn checkpoint_audit(audit_sinks: Arc<AuditSinks>) {
+    loop {
+        tokio::time::sleep(audit_sinks.checkpoint_interval()).await;
+        audit_sinks.checkpoint();
+    }
 }
 
 async fn handle_timeout_error(error: BoxError) -> (StatusCode, &'static str) {
@@ -255,8 +288,9 @@ fn init_tracing() {
 
 impl AppState {
     fn from_env() -> anyhow::Result<Self> {
-        let tool_server_url = std::env::var("LATCHKEY_TOOL_SERVER_URL")
-            .unwrap_or_else(|_| DEFAULT_TOOL_SERVER_URL.to_string());
+        let servers = std::env::var("LATCHKEY_MCP_SERVERS")
+            .unwrap_or_else(|_| DEFAULT_MCP_SERVERS.to_string());
+        let servers = backend::parse_servers(&servers).context("invalid LATCHKEY_MCP_SERVERS")?;
 
         let tokens =
             std::env::var("LATCHKEY_STATIC_TOKENS").unwrap_or_else(|_| DEFAULT_TOKENS.to_string());
@@ -266,23 +300,50 @@ impl AppState {
             .unwrap_or_else(|_| DEFAULT_ALLOWLIST.to_string());
         let allowlist = parse_allowlist(&allowlist).context("invalid LATCHKEY_TOOL_ALLOWLIST")?;
 
-        let rate_limit_per_minute = std::env::var("LATCHKEY_RATE_LIMIT_PER_MINUTE")
-            .ok()
-            .and_then(|value| value.parse::<usize>().ok())
-            .unwrap_or(DEFAULT_RATE_LIMIT_PER_MINUTE);
+        let balancer = match std::env::var("LATCHKEY_LOAD_BALANCER") {
+            Ok(balancer) => balancer.parse().context("invalid LATCHKEY_LOAD_BALANCER")?,
+            Err(_) => Balancer::PowerOfTwo,
+        };
+
+        let session_idle_timeout = parse_env("LATCHKEY_SESSION_IDLE_TIMEOUT_SECONDS")?
+            .unwrap_or(DEFAULT_SESSION_IDLE_TIMEOUT_SECONDS);
+
+        let breakers = BreakerConfig::from_env()?;
+        let retry = RetryConfig::from_env()?;
+        let shared = Redis::from_env()?.map(Arc::new);
+        let quotas = Quotas::from_env(shared.clone())?;
+        let audit_sinks = AuditSinks::from_env()?;
+        let redaction = Redaction::from_env()?;
+        let tracer =
+            Tracer::from_env("latchkey-gateway").context("failed to construct trace exporter")?;
 
         let client = reqwest::Client::builder()
             .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECONDS))
             .build()
             .context("failed to construct http client")?;
+        // Streamed replies are bounded per event by the MCP handler rather than in total.
+        let stream_client = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(REQUEST_TIMEOUT_SECONDS))
+            .build()
+            .context("failed to construct streaming http client")?;
+
+        let tools = Arc::new(Tools::new(ToolLimits::from_env()?));
+        let allowlisted = allowlist.values().flatten().cloned().collect();
+        let metrics = Metrics::from_env(allowlisted, tools.clone())?;
 
         Ok(Self {
-            tool_server_url,
+            backends: Arc::new(Backends::new(servers, client, stream_client, balancer, breakers)),
+            sessions: Arc::new(Sessions::new(Duration::from_secs(session_idle_timeout))),
+            tools,
             auth_tokens,
             allowlist,
-            rate_limit_per_minute,
-            client,
-            request_windows: Arc::new(Mutex::new(HashMap::new())),
+            rate_limits: Arc::new(RateLimits::from_env(shared)?),
+            quotas: Arc::new(quotas),
+            retry,
+            audit_sinks: Arc::new(audit_sinks),
+            redaction: Arc::new(redaction),
+            metrics: Arc::new(metrics),
+            tracer,
         })
     }
 }
@@ -305,6 +366,19 @@ fn principal_id_from_headers(
     tokens.get(bearer).cloned()
 }
 
+/// An optional setting, parsed. A value that does not parse fails startup rather than quietly
+/// falling back to the default.
+pub(crate) fn parse_env<T>(name: &str) -> anyhow::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match std::env::var(name) {
+        Ok(value) => value.parse().map(Some).with_context(|| format!("invalid {name}")),
+        Err(_) => Ok(None),
+    }
+}
+
 fn parse_tokens(input: &str) -> anyhow::Result<HashMap<String, String>> {
     let mut tokens = HashMap::new();
 
@@ -353,51 +427,144 @@ fn is_tool_allowed(
     allowlist.get(principal_id).map(|tools| tools.contains(tool_name)).unwrap_or(false)
 }
 
-async fn consume_rate_limit(state: &AppState, principal_id: &str) -> bool {
-    let mut windows = state.request_windows.lock().await;
-    let window = windows.entry(principal_id.to_string()).or_default();
-    let now = Instant::now();
+async fn consume_rate_limit(
+    state: &AppState,
+    principal_id: &str,
+    tool_name: &str,
+) -> Result<ratelimit::Status, ratelimit::Denied> {
+    let tool_limit = state.tools.rate_limit(tool_name);
+    state.rate_limits.acquire(principal_id, tool_name, tool_limit).await
+}
 
-    while let Some(entry) = window.front() {
-        if now.duration_since(*entry) >= Duration::from_secs(60) {
-            window.pop_front();
-        } else {
-            break;
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use axum::response::Response;
+    use redact::AuditLevel;
+    use tower::ServiceExt;
+
+    pub(crate) const TOKEN: &str = "demo-token";
+
+    /// A gateway routing to `servers`, where `demo-agent` holds `TOKEN` and may call `allowed`.
+    pub(crate) fn state(servers: Vec<(String, String)>, allowed: &[&str]) -> AppState {
+        let tools = Arc::new(Tools::new(ToolLimits::default()));
+        let allowed: HashSet<String> = allowed.iter().map(ToString::to_string).collect();
+        let sinks = AuditSinks::new(
+            false,
+            sinks::Overflow::Drop,
+            1,
+            chain::tests::chain(1000).0,
+            Duration::from_secs(60),
+            Vec::new(),
+        );
+        AppState {
+            backends: Arc::new(Backends::new(
+                servers,
+                reqwest::Client::new(),
+                reqwest::Client::new(),
+                Balancer::PowerOfTwo,
+                BreakerConfig::default(),
+            )),
+            sessions: Arc::new(Sessions::new(Duration::from_secs(60))),
+            tools: tools.clone(),
+            auth_tokens: HashMap::from([(TOKEN.to_string(), "demo-agent".to_string())]),
+            allowlist: HashMap::from([("demo-agent".to_string(), allowed.clone())]),
+            rate_limits: Arc::new(RateLimits::new(ratelimit::Limit::per_minute(600), None)),
+            quotas: Arc::new(Quotas::parse("", None).unwrap()),
+            retry: RetryConfig::from_env().unwrap(),
+            audit_sinks: Arc::new(sinks),
+            redaction: Arc::new(Redaction::new(&[7; 16], AuditLevel::Normal)),
+            metrics: Arc::new(Metrics::new(100, allowed, tools)),
+            tracer: Tracer::default(),
         }
     }
 
-    if window.len() >= state.rate_limit_per_minute {
-        return false;
+    /// Serves `router` on a free local port.
+    pub(crate) async fn serve(router: Router) -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        addr
     }
 
-    window.push_back(now);
-    true
-}
+    /// A tool server named `tools` offering `names`, each answering with the arguments it got.
+    pub(crate) async fn tool_server(names: &[&str]) -> Vec<(String, String)> {
+        let listed: Vec<Value> =
+            names.iter().map(|name| json!({ "name": name, "inputSchema": {} })).collect();
+        let server = Router::new().route(
+            "/mcp",
+            post(move |Json(message): Json<Value>| async move {
+                let result = match message["method"].as_str() {
+                    Some("tools/list") => json!({ "tools": listed }),
+                    Some("tools/call") => {
+                        let text = message["params"]["arguments"].to_string();
+                        json!({ "content": [{ "type": "text", "text": text }] })
+                    }
+                    _ => json!({}),
+                };
+                Json(json!({ "jsonrpc": "2.0", "id": message.get("id"), "result": result }))
+            }),
+        );
+        vec![("tools".to_string(), format!("http://{}/mcp", serve(server).await))]
+    }
 
-#[allow(clippy::too_many_arguments)]
-fn emit_audit(
-    request_id: &str,
-    principal_id: &str,
-    tool_name: &str,
-    decision: &str,
-    outcome: &str,
-    status: StatusCode,
-    deny_reason: Option<&str>,
-    started: Instant,
-) {
-    let latency_ms = started.elapsed().as_millis() as u64;
-    let deny_reason = deny_reason.unwrap_or("");
-
-    info!(
-        event_type = "audit",
-        request_id,
-        principal_id,
-        tool_name,
-        decision,
-        outcome,
-        deny_reason,
-        status = %status,
-        latency_ms,
-        "mcp decision"
-    );
+    /// A `/v1/mcp` request from `demo-agent` carrying `body` as is.
+    pub(crate) fn mcp_request(body: impl Into<Body>) -> Request<Body> {
+        Request::post("/v1/mcp")
+            .header(axum::http::header::AUTHORIZATION, format!("Bearer {TOKEN}"))
+            .header(axum::http::header::CONTENT_TYPE, "application/json")
+            .header(axum::http::header::ACCEPT, "application/json, text/event-stream")
+            .body(body.into())
+            .unwrap()
+    }
+
+    /// Sends one JSON-RPC message to `/v1/mcp` as `demo-agent`.
+    pub(crate) async fn post_mcp(router: Router, message: &Value) -> Response {
+        router.oneshot(mcp_request(message.to_string())).await.unwrap()
+    }
+
+    /// The response's status, headers and JSON body.
+    pub(crate) async fn read(response: Response) -> (StatusCode, HeaderMap, Value) {
+        let (parts, body) = response.into_parts();
+        let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        (parts.status, parts.headers, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    /// A `tools/call` of `tool` with `arguments`.
+    pub(crate) fn call(tool: &str, arguments: Value) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "tools/call",
+            "params": { "name": tool, "arguments": arguments },
+        })
+    }
+
+    #[tokio::test]
+    async fn serves_admin_routes_only_on_the_admin_listener() {
+        let state = state(Vec::new(), &[]);
+        for path in ["/metrics", "/admin/breakers", "/admin/quotas"] {
+            let get = || Request::get(path).body(Body::empty()).unwrap();
+            let public = router(state.clone()).oneshot(get()).await.unwrap();
+            assert_eq!(public.status(), StatusCode::NOT_FOUND, "{path}");
+            let admin = admin(state.clone()).oneshot(get()).await.unwrap();
+            assert_eq!(admin.status(), StatusCode::OK, "{path}");
+        }
+    }
+
+    #[test]
+    fn settings_that_do_not_parse_fail_startup() {
+        // Names no other test reads, since the environment is shared across test threads.
+        std::env::set_var("LATCHKEY_TEST_TIMEOUT_MS", "5s");
+        std::env::set_var("LATCHKEY_TEST_RETRIES", " 3");
+        std::env::set_var("LATCHKEY_TEST_BURST", "7");
+
+        let err = parse_env::<u64>("LATCHKEY_TEST_TIMEOUT_MS").unwrap_err();
+        assert_eq!(err.to_string(), "invalid LATCHKEY_TEST_TIMEOUT_MS");
+        assert!(parse_env::<u32>("LATCHKEY_TEST_RETRIES").is_err());
+        assert_eq!(parse_env::<u32>("LATCHKEY_TEST_BURST").unwrap(), Some(7));
+        assert_eq!(parse_env::<u32>("LATCHKEY_TEST_UNSET").unwrap(), None);
+    }
 }
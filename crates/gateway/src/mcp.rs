@@ -0,0 +1,1306 @@
+use crate::audit::Audit;
+use crate::backend::{
+    Backend, BackendError, Endpoint, EventStream, Reply, IDEMPOTENCY_KEY_HEADER, PROTOCOL_VERSION,
+    SESSION_HEADER,
+};
+use crate::breaker::Permit;
+use crate::ratelimit::Status;
+use crate::redact::Redactor;
+use crate::retry::Risk;
+use crate::session::{Session, SessionError};
+use crate::sse::SseEvent;
+use crate::tools::ToolLimits;
+use crate::{
+    consume_rate_limit, is_tool_allowed, principal_id_from_headers, request_id_from_headers,
+    AppState,
+};
+use axum::body::Body;
+use axum::extract::{Extension, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use http_body_util::LengthLimitError;
+use latchkey_core::audit::{Attempt, AuthMode, Decision, Outcome, ResponseSummary};
+use latchkey_core::trace::{SpanKind, TraceContext};
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+use tracing::{debug, error, info, warn};
+
+const EVENT_STREAM: &str = "text/event-stream";
+/// A streamed reply fails once the tool server goes this long without sending an event, or at
+/// the tool's deadline if that comes first.
+const STREAM_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Why a request was refused or failed. Each class has a fixed HTTP status and JSON-RPC code so
+/// clients can tell Latchkey denials from tool errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    ParseError,
+    InvalidRequest,
+    MethodNotFound,
+    InvalidParams,
+    /// The request body is over the tool's `maxPayloadBytes`.
+    PayloadTooLarge,
+    Unauthorized,
+    ToolNotAllowed,
+    RateLimited,
+    /// A daily or monthly call budget is spent until its period resets.
+    QuotaExceeded,
+    UnknownTool,
+    SessionNotFound,
+    ToolServerError,
+    ToolServerUnreachable,
+    /// The server's or every endpoint's circuit breaker is open, so the call was not attempted.
+    BackendUnavailable,
+    /// The tool server did not finish answering within the tool's `timeoutMs`.
+    BackendTimeout,
+    StreamIdleTimeout,
+    EventTooLarge,
+    /// The reply passed the tool's `maxResponseBytes` and was cut off.
+    ResponseTooLarge,
+    /// The client went away before a streamed reply finished. Only ever audited.
+    ClientClosed,
+}
+
+impl ErrorClass {
+    /// Deny reason recorded in audit events.
+    pub fn reason(self) -> &'static str {
+        match self {
+            Self::ParseError => "parse_error",
+            Self::InvalidRequest => "invalid_request",
+            Self::MethodNotFound => "method_not_found",
+            Self::InvalidParams => "invalid_params",
+            Self::PayloadTooLarge => "payload_too_large",
+            Self::Unauthorized => "missing_or_invalid_token",
+            Self::ToolNotAllowed => "tool_not_allowed",
+            Self::RateLimited => "rate_limited",
+            Self::QuotaExceeded => "quota_exceeded",
+            Self::UnknownTool => "unknown_tool",
+            Self::SessionNotFound => "session_not_found",
+            Self::ToolServerError => "tool_server_error",
+            Self::ToolServerUnreachable => "tool_server_unreachable",
+            Self::BackendUnavailable => "backend_unavailable",
+            Self::BackendTimeout => "backend_timeout",
+            Self::StreamIdleTimeout => "stream_idle_timeout",
+            Self::EventTooLarge => "event_too_large",
+            Self::ResponseTooLarge => "response_too_large",
+            Self::ClientClosed => "client_closed",
+        }
+    }
+
+    pub fn code(self) -> i64 {
+        match self {
+            Self::ParseError => -32700,
+            Self::InvalidRequest => -32600,
+            Self::MethodNotFound => -32601,
+            Self::InvalidParams | Self::UnknownTool => -32602,
+            Self::Unauthorized => -32001,
+            Self::ToolServerError => -32002,
+            Self::ToolNotAllowed => -32003,
+            Self::ToolServerUnreachable => -32004,
+            Self::StreamIdleTimeout => -32005,
+            Self::EventTooLarge => -32006,
+            Self::ClientClosed => -32007,
+            Self::SessionNotFound => -32008,
+            Self::BackendUnavailable => -32009,
+            Self::PayloadTooLarge => -32010,
+            Self::ResponseTooLarge => -32011,
+            Self::BackendTimeout => -32012,
+            Self::QuotaExceeded => -32013,
+            Self::RateLimited => -32029,
+        }
+    }
+
+    pub fn status(self) -> StatusCode {
+        match self {
+            Self::ParseError | Self::InvalidRequest | Self::InvalidParams => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::MethodNotFound | Self::UnknownTool | Self::SessionNotFound => {
+                StatusCode::NOT_FOUND
+            }
+            Self::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::ToolNotAllowed => StatusCode::FORBIDDEN,
+            Self::RateLimited | Self::QuotaExceeded => StatusCode::TOO_MANY_REQUESTS,
+            Self::ToolServerError
+            | Self::ToolServerUnreachable
+            | Self::EventTooLarge
+            | Self::ResponseTooLarge => StatusCode::BAD_GATEWAY,
+            Self::BackendUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            Self::StreamIdleTimeout | Self::BackendTimeout => StatusCode::GATEWAY_TIMEOUT,
+            // nginx's "client closed request"; never sent, since nobody is listening.
+            Self::ClientClosed => StatusCode::from_u16(499).expect("499 is a valid status"),
+        }
+    }
+
+    fn message(self) -> &'static str {
+        match self {
+            Self::ParseError => "request body is not valid JSON",
+            Self::InvalidRequest => "request is not a JSON-RPC 2.0 message",
+            Self::MethodNotFound => "method not supported by the gateway",
+            Self::InvalidParams => "invalid params",
+            Self::PayloadTooLarge => "request exceeds the tool's payload limit",
+            Self::Unauthorized => "missing or invalid bearer token",
+            Self::ToolNotAllowed => "tool not allowed for this principal",
+            Self::RateLimited => "rate limit exceeded",
+            Self::QuotaExceeded => "call quota exhausted until it resets",
+            Self::UnknownTool => "unknown tool",
+            Self::SessionNotFound => "session not found; initialize a new session",
+            Self::ToolServerError => "tool server returned an error",
+            Self::ToolServerUnreachable => "tool server unreachable",
+            Self::BackendUnavailable => "tool server unavailable; circuit breaker open",
+            Self::BackendTimeout => "tool server did not answer within the tool's timeout",
+            Self::StreamIdleTimeout => "tool server stream went idle",
+            Self::EventTooLarge => "tool server sent an oversized event",
+            Self::ResponseTooLarge => "tool server reply exceeds the tool's response limit",
+            Self::ClientClosed => "client closed the stream",
+        }
+    }
+
+    /// Backend failures happen after policy allowed the call.
+    fn decision(self) -> Decision {
+        match self {
+            Self::ToolServerError
+            | Self::ToolServerUnreachable
+            | Self::BackendTimeout
+            | Self::StreamIdleTimeout
+            | Self::EventTooLarge
+            | Self::ResponseTooLarge
+            | Self::ClientClosed => Decision::Allow,
+            _ => Decision::Deny,
+        }
+    }
+
+    /// The broad class recorded in the audit event's `error_class`.
+    fn audit_class(self) -> latchkey_core::audit::ErrorClass {
+        use latchkey_core::audit::ErrorClass as Class;
+        match self {
+            Self::Unauthorized => Class::Authentication,
+            Self::ToolNotAllowed => Class::Authorization,
+            Self::RateLimited | Self::QuotaExceeded => Class::RateLimit,
+            Self::ParseError
+            | Self::InvalidRequest
+            | Self::MethodNotFound
+            | Self::InvalidParams
+            | Self::PayloadTooLarge
+            | Self::UnknownTool
+            | Self::SessionNotFound => Class::Validation,
+            Self::BackendTimeout | Self::StreamIdleTimeout => Class::Timeout,
+            Self::ToolServerError
+            | Self::ToolServerUnreachable
+            | Self::BackendUnavailable
+            | Self::EventTooLarge
+            | Self::ResponseTooLarge => Class::Backend,
+            Self::ClientClosed => Class::Client,
+        }
+    }
+}
+
+impl From<&BackendError> for ErrorClass {
+    fn from(err: &BackendError) -> Self {
+        match err {
+            BackendError::Unreachable(_) => Self::ToolServerUnreachable,
+            BackendError::Status(_) | BackendError::InvalidResponse => Self::ToolServerError,
+            BackendError::EventTooLarge => Self::EventTooLarge,
+            BackendError::ResponseTooLarge => Self::ResponseTooLarge,
+        }
+    }
+}
+
+/// One JSON-RPC exchange on `POST /v1/mcp`, carried through authn, authz, rate limiting, and
+/// audit.
+#[derive(Clone)]
+struct Exchange {
+    request_id: String,
+    principal_id: String,
+    method: String,
+    id: Value,
+    /// The endpoint a tool call was sent to, once picked.
+    backend_server: String,
+    /// Every try at a tool call, including retries and hedges.
+    attempts: Vec<Attempt>,
+    /// The request's audit event, emitted once every clone of the exchange is gone.
+    event: Audit,
+}
+
+impl Exchange {
+    /// An exchange for requests that carry no JSON-RPC message, such as `GET` and `DELETE`.
+    fn outside_rpc(headers: &HeaderMap, event: Audit) -> Self {
+        Self {
+            request_id: request_id_from_headers(headers),
+            principal_id: "anonymous".to_string(),
+            method: String::new(),
+            id: Value::Null,
+            backend_server: String::new(),
+            attempts: Vec::new(),
+            event,
+        }
+    }
+
+    fn authenticated(&mut self, principal_id: String) {
+        self.event.update(|event| {
+            event.principal_id = Some(principal_id.clone());
+            event.auth_mode = Some(AuthMode::Static);
+        });
+        self.event.in_flight(&principal_id);
+        self.principal_id = principal_id;
+    }
+
+    /// Records how the exchange ended on its audit event.
+    fn audit(&self, tool_name: &str, outcome: Result<Outcome, ErrorClass>) {
+        self.event.record(|event| {
+            if !self.method.is_empty() {
+                event.method = self.method.clone();
+            }
+            if !tool_name.is_empty() {
+                event.tool_name = Some(tool_name.to_string());
+            }
+            if !self.backend_server.is_empty() {
+                event.backend_server = Some(self.backend_server.clone());
+            }
+            // The attempt whose result was used comes last.
+            event.backend_latency_ms = self.attempts.last().map(|attempt| attempt.latency_ms);
+            event.attempts = self.attempts.clone();
+            match outcome {
+                Ok(status) => event.status = status,
+                Err(class) => {
+                    event.decision = class.decision();
+                    event.status = Outcome::Error;
+                    event.http_status = class.status().as_u16();
+                    event.deny_reason = Some(class.reason().to_string());
+                    event.error_class = Some(class.audit_class());
+                }
+            }
+        });
+    }
+
+    fn fail(&self, tool_name: &str, class: ErrorClass) -> Response {
+        self.audit(tool_name, Err(class));
+        (class.status(), Json(error_body(self.id.clone(), class, &self.request_id))).into_response()
+    }
+
+    /// Like `fail`, with `data`'s fields added to the error's `data`.
+    fn fail_with(&self, tool_name: &str, class: ErrorClass, data: Value) -> Response {
+        self.audit(tool_name, Err(class));
+        let mut body = error_body(self.id.clone(), class, &self.request_id);
+        if let (Some(target), Value::Object(data)) =
+            (body.pointer_mut("/error/data").and_then(Value::as_object_mut), data)
+        {
+            target.extend(data);
+        }
+        (class.status(), Json(body)).into_response()
+    }
+
+    fn result(&self, result: Value) -> Response {
+        Json(json!({ "jsonrpc": "2.0", "id": self.id, "result": result })).into_response()
+    }
+}
+
+pub async fn handle(
+    State(state): State<AppState>,
+    Extension(event): Extension<Audit>,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    let mut exchange = Exchange::outside_rpc(&headers, event);
+
+    // Authenticate before reading the body, which is then held to the largest tool's cap.
+    let Some(principal_id) = authenticate(&state, &exchange, &headers) else {
+        return unauthorized(&exchange);
+    };
+    exchange.authenticated(principal_id);
+    let body = match axum::body::to_bytes(body, state.tools.max_payload_bytes()).await {
+        Ok(body) => body,
+        Err(err)
+            if std::error::Error::source(&err).is_some_and(|err| err.is::<LengthLimitError>()) =>
+        {
+            return exchange.fail("", ErrorClass::PayloadTooLarge);
+        }
+        Err(_) => return exchange.fail("", ErrorClass::ClientClosed),
+    };
+
+    let Ok(message) = serde_json::from_slice::<Value>(&body) else {
+        return exchange.fail("", ErrorClass::ParseError);
+    };
+    exchange.id = message.get("id").cloned().unwrap_or(Value::Null);
+    let method = message.get("method").and_then(Value::as_str);
+    if message.get("jsonrpc").and_then(Value::as_str) != Some("2.0") || method.is_none() {
+        return exchange.fail("", ErrorClass::InvalidRequest);
+    }
+    exchange.method = method.unwrap_or_default().to_string();
+    // `tools/call` is held to its tool's cap once routed.
+    if exchange.method != "tools/call" && body.len() > state.tools.defaults().max_payload_bytes {
+        return exchange.fail("", ErrorClass::PayloadTooLarge);
+    }
+
+    // `initialize` starts a new session whatever the client sent.
+    let session = match exchange.method.as_str() {
+        "initialize" => None,
+        _ => match session(&state, &headers, &exchange.principal_id) {
+            Ok(session) => session,
+            Err(class) => return exchange.fail("", class),
+        },
+    };
+
+    // Notifications get no response; none of them change gateway state yet.
+    if message.get("id").is_none() {
+        exchange.audit("", Ok(Outcome::Success));
+        return StatusCode::ACCEPTED.into_response();
+    }
+
+    match exchange.method.as_str() {
+        "initialize" => {
+            exchange.audit("", Ok(Outcome::Success));
+            let params = message.get("params").cloned().unwrap_or_else(|| json!({}));
+            let session = state.sessions.create(&exchange.principal_id, params);
+            exchange.event.update(|event| event.session_id = Some(session.id.clone()));
+            let mut response = exchange.result(initialize_result(&message));
+            let session = HeaderValue::from_str(&session.id).expect("uuid is a valid header value");
+            response.headers_mut().insert(SESSION_HEADER, session);
+            response
+        }
+        "ping" => {
+            exchange.audit("", Ok(Outcome::Success));
+            exchange.result(json!({}))
+        }
+        "tools/list" => list_tools(&state, &exchange).await,
+        "tools/call" => {
+            call_tool(&state, &exchange, session.as_deref(), message, body.len(), &headers).await
+        }
+        _ => exchange.fail("", ErrorClass::MethodNotFound),
+    }
+}
+
+/// Merges every server's tools, keeping only those on the principal's allowlist.
+async fn list_tools(state: &AppState, exchange: &Exchange) -> Response {
+    let tools: Vec<Value> = state
+        .backends
+        .list_tools()
+        .await
+        .into_iter()
+        .map(|(_, tool)| tool)
+        .filter(|tool| {
+            tool.get("name")
+                .and_then(Value::as_str)
+                .is_some_and(|name| is_tool_allowed(&state.allowlist, &exchange.principal_id, name))
+        })
+        .collect();
+
+    exchange.audit("", Ok(Outcome::Success));
+    exchange.result(json!({ "tools": tools }))
+}
+
+async fn call_tool(
+    state: &AppState,
+    exchange: &Exchange,
+    session: Option<&Session>,
+    message: Value,
+    payload_bytes: usize,
+    headers: &HeaderMap,
+) -> Response {
+    let Some(tool_name) = message.pointer("/params/name").and_then(Value::as_str) else {
+        return exchange.fail("", ErrorClass::InvalidParams);
+    };
+    let redactor = redactor(state, exchange, tool_name);
+    exchange.event.update(|event| {
+        event.tool_name = Some(tool_name.to_string());
+        event.operation = state.tools.operation(tool_name, message.pointer("/params/arguments"));
+        redactor.params(message.get("params"), event);
+    });
+
+    let mut policy = exchange.event.span("policy evaluation");
+    if !is_tool_allowed(&state.allowlist, &exchange.principal_id, tool_name) {
+        policy.set_error(ErrorClass::ToolNotAllowed.reason());
+        return exchange.fail(tool_name, ErrorClass::ToolNotAllowed);
+    }
+    drop(policy);
+
+    let mut limiting = exchange.event.span("rate limiting");
+    let status = match consume_rate_limit(state, &exchange.principal_id, tool_name).await {
+        Ok(status) => status,
+        Err(denied) => {
+            limiting.set_error(ErrorClass::RateLimited.reason());
+            let data = json!({
+                "retry_after": whole_seconds(denied.retry_after),
+                "rate_limit": {
+                    "limit": denied.status.limit,
+                    "remaining": denied.status.remaining,
+                    "reset": whole_seconds(denied.status.reset),
+                },
+            });
+            let mut response = exchange.fail_with(tool_name, ErrorClass::RateLimited, data);
+            set_rate_limit_headers(response.headers_mut(), &denied.status);
+            set_retry_after(response.headers_mut(), denied.retry_after);
+            return response;
+        }
+    };
+    drop(limiting);
+
+    let mut response =
+        forward_call(state, exchange, session, &message, tool_name, payload_bytes, headers).await;
+    set_rate_limit_headers(response.headers_mut(), &status);
+    response
+}
+
+/// The rest of a tool call, once admitted by the rate limits.
+async fn forward_call(
+    state: &AppState,
+    exchange: &Exchange,
+    session: Option<&Session>,
+    message: &Value,
+    tool_name: &str,
+    payload_bytes: usize,
+    headers: &HeaderMap,
+) -> Response {
+    let Some(backend) = state.backends.route(tool_name).await else {
+        return exchange.fail(tool_name, ErrorClass::UnknownTool);
+    };
+
+    let limits = state.tools.limits(tool_name);
+    if payload_bytes > limits.max_payload_bytes {
+        return exchange.fail(tool_name, ErrorClass::PayloadTooLarge);
+    }
+
+    // Calls that could never be sent are not charged.
+    if let Err(retry_after) = state.quotas.consume(&exchange.principal_id, tool_name).await {
+        let data = json!({ "retry_after": whole_seconds(retry_after) });
+        let mut response = exchange.fail_with(tool_name, ErrorClass::QuotaExceeded, data);
+        set_retry_after(response.headers_mut(), retry_after);
+        return response;
+    }
+
+    let mut dispatching = exchange.event.span("mcp dispatch");
+    dispatching.set_attribute("latchkey.server", backend.name.clone());
+    let call = Call {
+        backend,
+        tool_name,
+        session,
+        message,
+        idempotency_key: headers.get(IDEMPOTENCY_KEY_HEADER).and_then(|key| key.to_str().ok()),
+        trace: dispatching.context().clone(),
+        limits,
+        deadline: Instant::now() + limits.timeout,
+    };
+    let mut exchange = exchange.clone();
+    let dispatched = dispatch(state, &mut exchange, &call).await;
+    if let Err((class, _)) = &dispatched {
+        dispatching.set_error(class.reason());
+    }
+    drop(dispatching);
+    let exchange = &exchange;
+
+    let reply = match dispatched {
+        Ok((Reply::Json(response), permit)) => {
+            permit.record(true);
+            Ok(response)
+        }
+        Ok((Reply::Stream(events), permit)) if wants_event_stream(headers) => {
+            let relay = Relay {
+                exchange: exchange.clone(),
+                tool_name: tool_name.to_string(),
+                server: backend.name.clone(),
+                events: *events,
+                redactor: redactor(state, exchange, tool_name),
+                deadline: call.deadline,
+                permit: Some(permit),
+                finished: false,
+            };
+            return relay.into_response();
+        }
+        // The client only takes JSON, so wait out the stream for the response.
+        Ok((Reply::Stream(mut events), permit)) => {
+            let reply = loop {
+                match next_event(&mut events, call.deadline).await {
+                    Ok(event) => match serde_json::from_str::<Value>(&event.data) {
+                        Ok(message) if is_response_to(&message, &exchange.id) => break Ok(message),
+                        _ => continue,
+                    },
+                    Err(class) => break Err((class, None)),
+                }
+            };
+            settle(permit, reply.as_ref().err().map(|(class, _)| *class));
+            reply
+        }
+        Err(failure) => Err(failure),
+    };
+
+    match reply {
+        Ok(mut response) => {
+            response["id"] = exchange.id.clone();
+            let body = serde_json::to_vec(&response).expect("JSON values always serialize");
+            let outcome = outcome(&response);
+            let mut summary = ResponseSummary {
+                bytes: body.len() as u64,
+                events: None,
+                is_error: outcome == Outcome::Error,
+                hash: None,
+                redacted: None,
+            };
+            redactor(state, exchange, tool_name).response(&response, &mut summary);
+            exchange.event.update(|event| event.response_summary = Some(summary));
+            exchange.audit(tool_name, Ok(outcome));
+            ([(header::CONTENT_TYPE, "application/json")], body).into_response()
+        }
+        Err((class, err)) => {
+            error!(
+                request_id = %exchange.request_id,
+                principal_id = %exchange.principal_id,
+                tool_name,
+                server = %backend.name,
+                backend_server = %exchange.backend_server,
+                error = err.as_ref().map_or(class.message().to_string(), ToString::to_string),
+                "tool server request failed"
+            );
+            exchange.fail(tool_name, class)
+        }
+    }
+}
+
+/// A failed call's class, plus the backend error behind it when there is one.
+type Failure = (ErrorClass, Option<BackendError>);
+
+/// What every attempt at one tool call shares.
+struct Call<'a> {
+    backend: &'a Backend,
+    tool_name: &'a str,
+    session: Option<&'a Session>,
+    message: &'a Value,
+    idempotency_key: Option<&'a str>,
+    /// The dispatch span, which every attempt's span sits under.
+    trace: TraceContext,
+    limits: ToolLimits,
+    /// The tool's timeout, covering retries and any streamed reply.
+    deadline: Instant,
+}
+
+/// Sends a tool call, retrying and hedging as its risk class allows before the deadline. Each
+/// attempt is recorded on `exchange`, which ends up naming the endpoint that answered or was
+/// tried last.
+async fn dispatch(
+    state: &AppState,
+    exchange: &mut Exchange,
+    call: &Call<'_>,
+) -> Result<(Reply, Permit), Failure> {
+    let (backend, session) = (call.backend, call.session);
+    let risk = state.tools.risk(call.tool_name).unwrap_or_else(|| state.retry.risk(call.tool_name));
+    let attempts = state.retry.attempts(risk, call.idempotency_key.is_some());
+    let hedge_after = state.retry.hedge_after.filter(|_| risk == Risk::Read);
+
+    // Requests in a client session keep reaching the same replica while it stays up.
+    let mut endpoint = backend.endpoint(session);
+    let mut attempt = 1;
+    loop {
+        let Some(current) = endpoint else {
+            return Err((ErrorClass::BackendUnavailable, None));
+        };
+        exchange.backend_server = current.address.clone();
+
+        let hedge =
+            hedge_after.and_then(|after| Some((after, backend.alternate(session, &current)?)));
+        let result = match hedge {
+            Some((after, other)) => {
+                let started = Instant::now();
+                let primary = send(state, call, current.clone(), false);
+                tokio::pin!(primary);
+                match tokio::time::timeout(after, &mut primary).await {
+                    Ok((tried, result)) => {
+                        exchange.attempts.push(tried);
+                        result
+                    }
+                    Err(_) => {
+                        let addresses = [current.address.clone(), other.address.clone()];
+                        let hedge = send(state, call, other, true);
+                        let (tried, result) = hedged(primary, hedge, addresses, started).await;
+                        exchange.backend_server = tried[1].endpoint.clone();
+                        exchange.attempts.extend(tried);
+                        result
+                    }
+                }
+            }
+            None => {
+                let (tried, result) = send(state, call, current.clone(), false).await;
+                exchange.attempts.push(tried);
+                result
+            }
+        };
+        let failure = match result {
+            Ok(answered) => return Ok(answered),
+            Err(failure) => failure,
+        };
+
+        let backoff = state.retry.backoff(attempt);
+        let retryable = failure.1.as_ref().is_some_and(BackendError::is_retryable);
+        if attempt >= attempts || !retryable || Instant::now() + backoff >= call.deadline {
+            return Err(failure);
+        }
+        debug!(
+            request_id = %exchange.request_id,
+            tool_name = call.tool_name,
+            attempt,
+            backoff_ms = backoff.as_millis() as u64,
+            "retrying tool call"
+        );
+        tokio::time::sleep(backoff).await;
+        endpoint = backend.alternate(session, &current).or_else(|| backend.endpoint(session));
+        attempt += 1;
+    }
+}
+
+/// Races a slow call against its hedge. The first answer wins and the other is cancelled; a
+/// failure waits for the other attempt. The attempt whose result is returned comes last.
+async fn hedged<F, H>(
+    mut primary: Pin<&mut F>,
+    hedge: H,
+    addresses: [String; 2],
+    started: Instant,
+) -> (Vec<Attempt>, Result<(Reply, Permit), Failure>)
+where
+    F: Future<Output = (Attempt, Result<(Reply, Permit), Failure>)>,
+    H: Future<Output = (Attempt, Result<(Reply, Permit), Failure>)>,
+{
+    let hedge_started = Instant::now();
+    tokio::pin!(hedge);
+    let (first, primary_first) = tokio::select! {
+        done = &mut primary => (done, true),
+        done = &mut hedge => (done, false),
+    };
+    if first.1.is_err() {
+        let other = if primary_first { hedge.await } else { primary.await };
+        return (vec![first.0, other.0], other.1);
+    }
+
+    let [primary_address, hedge_address] = addresses;
+    let (endpoint, since) =
+        if primary_first { (hedge_address, hedge_started) } else { (primary_address, started) };
+    let cancelled = Attempt {
+        endpoint,
+        outcome: "cancelled".to_string(),
+        latency_ms: since.elapsed().as_millis() as u64,
+        hedged: primary_first,
+    };
+    (vec![cancelled, first.0], first.1)
+}
+
+/// One attempt at `endpoint`, admitted by its own and the server's circuit breakers. A failure
+/// is recorded on the breakers here; an answer carries its permit on to the caller.
+async fn send(
+    state: &AppState,
+    call: &Call<'_>,
+    endpoint: Arc<Endpoint>,
+    hedged: bool,
+) -> (Attempt, Result<(Reply, Permit), Failure>) {
+    let started = Instant::now();
+    let mut span = state.tracer.span("backend call", SpanKind::Client, Some(&call.trace));
+    span.set_attribute("server.address", endpoint.address.clone());
+    span.set_attribute("latchkey.hedged", hedged);
+    let breakers = vec![call.backend.breaker.clone(), endpoint.breaker.clone()];
+    let result = match Permit::acquire(breakers) {
+        None => Err((ErrorClass::BackendUnavailable, None)),
+        Some(permit) => {
+            let reply = state.backends.call(
+                &endpoint,
+                call.session,
+                call.message,
+                call.idempotency_key,
+                Some(span.context()),
+                call.limits.max_response_bytes,
+            );
+            let reply = tokio::time::timeout_at(call.deadline, reply).await;
+            state.metrics.observe_backend(&call.backend.name, started.elapsed());
+            match reply {
+                Ok(Ok(reply)) => Ok((reply, permit)),
+                Ok(Err(err)) => {
+                    // Rejected requests (4xx) come from a healthy server.
+                    permit.record(!err.is_server_fault());
+                    Err((ErrorClass::from(&err), Some(err)))
+                }
+                Err(_) => {
+                    permit.record(false);
+                    Err((ErrorClass::BackendTimeout, None))
+                }
+            }
+        }
+    };
+    if let Err((class, _)) = &result {
+        span.set_error(class.reason());
+    }
+    let attempt = Attempt {
+        endpoint: endpoint.address.clone(),
+        outcome: result
+            .as_ref()
+            .map_or_else(|(class, _)| class.reason(), |_| "answered")
+            .to_string(),
+        latency_ms: started.elapsed().as_millis() as u64,
+        hedged,
+    };
+    (attempt, result)
+}
+
+/// Audits a call's params and response by the tool's rules at the principal's level.
+fn redactor(state: &AppState, exchange: &Exchange, tool_name: &str) -> Redactor {
+    let rules = state.tools.redaction_rules(tool_name);
+    state.redaction.redactor(rules, &exchange.principal_id, tool_name)
+}
+
+/// Records how a call ended on its breakers. An oversized reply still came from a working
+/// server, and a client hang-up says nothing about the server either way.
+fn settle(permit: Permit, failure: Option<ErrorClass>) {
+    match failure {
+        Some(ErrorClass::ClientClosed) => drop(permit),
+        None | Some(ErrorClass::ResponseTooLarge) => permit.record(true),
+        Some(_) => permit.record(false),
+    }
+}
+
+/// Tool failures are reported in-band as `isError` results.
+fn outcome(response: &Value) -> Outcome {
+    let tool_failed = response.pointer("/result/isError").and_then(Value::as_bool).unwrap_or(false);
+    if tool_failed || response.get("error").is_some() {
+        Outcome::Error
+    } else {
+        Outcome::Success
+    }
+}
+
+fn is_response_to(message: &Value, id: &Value) -> bool {
+    message.get("method").is_none() && message.get("id") == Some(id)
+}
+
+/// The next event, failing once the stream goes idle or the tool's deadline passes.
+async fn next_event(events: &mut EventStream, deadline: Instant) -> Result<SseEvent, ErrorClass> {
+    let idle = Instant::now() + STREAM_IDLE_TIMEOUT;
+    match tokio::time::timeout_at(idle.min(deadline), events.next()).await {
+        Ok(Some(Ok(event))) => Ok(event),
+        Ok(Some(Err(err))) => Err(ErrorClass::from(&err)),
+        // The server closed the stream without answering.
+        Ok(None) => Err(ErrorClass::ToolServerError),
+        Err(_) if deadline <= idle => Err(ErrorClass::BackendTimeout),
+        Err(_) => Err(ErrorClass::StreamIdleTimeout),
+    }
+}
+
+/// Relays a tool server's SSE reply event by event: notifications such as progress pass
+/// through, and the response ends the stream. The exchange is audited exactly once, when the
+/// stream ends or when the client drops it.
+struct Relay {
+    exchange: Exchange,
+    tool_name: String,
+    server: String,
+    events: EventStream,
+    redactor: Redactor,
+    deadline: Instant,
+    /// Records the stream's outcome on the server's and endpoint's breakers.
+    permit: Option<Permit>,
+    finished: bool,
+}
+
+impl Relay {
+    fn into_response(self) -> Response {
+        let frames = futures::stream::unfold(self, |mut relay| async move {
+            let frame = relay.next_frame().await?;
+            Some((Ok::<_, Infallible>(frame), relay))
+        });
+        event_stream_response(Body::from_stream(frames))
+    }
+
+    async fn next_frame(&mut self) -> Option<String> {
+        while !self.finished {
+            let mut message = match next_event(&mut self.events, self.deadline).await {
+                Ok(event) => match serde_json::from_str::<Value>(&event.data) {
+                    Ok(message) => message,
+                    Err(_) => continue,
+                },
+                Err(class) => {
+                    self.finish(Err(class), None);
+                    let id = self.exchange.id.clone();
+                    return Some(frame(&error_body(id, class, &self.exchange.request_id)));
+                }
+            };
+
+            if is_response_to(&message, &self.exchange.id) {
+                message["id"] = self.exchange.id.clone();
+                self.finish(Ok(outcome(&message)), Some(&message));
+                return Some(frame(&message));
+            }
+            // The backend session is shared, so server-initiated requests have nowhere to go.
+            if message.get("id").is_some() {
+                debug!(server = %self.server, "dropping server request on a relayed stream");
+                continue;
+            }
+            return Some(frame(&message));
+        }
+        None
+    }
+
+    fn finish(&mut self, outcome: Result<Outcome, ErrorClass>, response: Option<&Value>) {
+        self.finished = true;
+        if let Some(permit) = self.permit.take() {
+            settle(permit, outcome.err());
+        }
+        let mut summary = ResponseSummary {
+            bytes: self.events.bytes as u64,
+            events: Some(self.events.events as u64),
+            is_error: outcome != Ok(Outcome::Success),
+            hash: None,
+            redacted: None,
+        };
+        if let Some(response) = response {
+            self.redactor.response(response, &mut summary);
+        }
+        self.exchange.event.update(|event| event.response_summary = Some(summary));
+        self.exchange.audit(&self.tool_name, outcome);
+        info!(
+            request_id = %self.exchange.request_id,
+            tool_name = %self.tool_name,
+            server = %self.server,
+            events = self.events.events,
+            bytes = self.events.bytes,
+            "relayed tool server stream"
+        );
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.finish(Err(ErrorClass::ClientClosed), None);
+        }
+    }
+}
+
+/// `GET /v1/mcp`: a long-lived stream of server notifications, currently
+/// `notifications/tools/list_changed` from any routed server.
+pub async fn listen(
+    State(state): State<AppState>,
+    Extension(event): Extension<Audit>,
+    headers: HeaderMap,
+) -> Response {
+    let mut exchange = Exchange::outside_rpc(&headers, event);
+    let Some(principal_id) = authenticate(&state, &exchange, &headers) else {
+        return unauthorized(&exchange);
+    };
+    exchange.authenticated(principal_id);
+    if let Err(class) = session(&state, &headers, &exchange.principal_id) {
+        return exchange.fail("", class);
+    }
+    if !wants_event_stream(&headers) {
+        return StatusCode::NOT_ACCEPTABLE.into_response();
+    }
+
+    let receiver = state.backends.subscribe();
+    let frames = futures::stream::unfold(receiver, |mut receiver| async move {
+        let frame = match tokio::time::timeout(KEEPALIVE_INTERVAL, receiver.recv()).await {
+            Ok(Ok(message)) => frame(&message),
+            Ok(Err(broadcast::error::RecvError::Lagged(_))) | Err(_) => ": keep-alive\n\n".into(),
+            Ok(Err(broadcast::error::RecvError::Closed)) => return None,
+        };
+        Some((Ok::<_, Infallible>(frame), receiver))
+    });
+    event_stream_response(Body::from_stream(frames))
+}
+
+/// `DELETE /v1/mcp`: ends the client's session here and on every tool server replica it reached.
+pub async fn terminate(
+    State(state): State<AppState>,
+    Extension(event): Extension<Audit>,
+    headers: HeaderMap,
+) -> Response {
+    let mut exchange = Exchange::outside_rpc(&headers, event);
+    let Some(principal_id) = authenticate(&state, &exchange, &headers) else {
+        return unauthorized(&exchange);
+    };
+    exchange.authenticated(principal_id);
+    let Some(id) = headers.get(SESSION_HEADER).and_then(|value| value.to_str().ok()) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    match state.sessions.remove(id, &exchange.principal_id) {
+        Ok(session) => {
+            state.backends.close_session(&session).await;
+            info!(principal_id = %exchange.principal_id, session_id = id, "session terminated");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(err) => exchange.fail("", session_error(err, id, &exchange.principal_id)),
+    }
+}
+
+/// The session named by the request, if any. Another principal's session is reported exactly
+/// like an unknown one, so session ids cannot be probed.
+fn session(
+    state: &AppState,
+    headers: &HeaderMap,
+    principal_id: &str,
+) -> Result<Option<Arc<Session>>, ErrorClass> {
+    let Some(id) = headers.get(SESSION_HEADER) else {
+        return Ok(None);
+    };
+    let id = id.to_str().map_err(|_| ErrorClass::SessionNotFound)?;
+    state
+        .sessions
+        .get(id, principal_id)
+        .map(Some)
+        .map_err(|err| session_error(err, id, principal_id))
+}
+
+fn session_error(err: SessionError, id: &str, principal_id: &str) -> ErrorClass {
+    if err == SessionError::WrongPrincipal {
+        warn!(principal_id, session_id = id, "rejected another principal's session");
+    }
+    ErrorClass::SessionNotFound
+}
+
+/// The principal named by the request's credentials, if any.
+fn authenticate(state: &AppState, exchange: &Exchange, headers: &HeaderMap) -> Option<String> {
+    let mut span = exchange.event.span("authn");
+    let principal_id = principal_id_from_headers(headers, &state.auth_tokens);
+    if principal_id.is_none() {
+        span.set_error(ErrorClass::Unauthorized.reason());
+    }
+    principal_id
+}
+
+fn unauthorized(exchange: &Exchange) -> Response {
+    let mut response = exchange.fail("", ErrorClass::Unauthorized);
+    response.headers_mut().insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    response
+}
+
+fn wants_event_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.contains(EVENT_STREAM))
+}
+
+fn event_stream_response(body: Body) -> Response {
+    let headers = [
+        (header::CONTENT_TYPE, HeaderValue::from_static(EVENT_STREAM)),
+        (header::CACHE_CONTROL, HeaderValue::from_static("no-cache")),
+    ];
+    (headers, body).into_response()
+}
+
+fn frame(message: &Value) -> String {
+    format!("event: message\ndata: {message}\n\n")
+}
+
+fn initialize_result(message: &Value) -> Value {
+    // Echo the client's revision when it is one we speak; otherwise offer ours.
+    let requested = message.pointer("/params/protocolVersion").and_then(Value::as_str);
+    let version = match requested {
+        Some(version @ ("2025-06-18" | "2025-03-26")) => version,
+        _ => PROTOCOL_VERSION,
+    };
+
+    json!({
+        "protocolVersion": version,
+        "capabilities": { "tools": { "listChanged": true } },
+        "serverInfo": { "name": "latchkey-gateway", "version": env!("CARGO_PKG_VERSION") },
+    })
+}
+
+/// `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset` for the tightest bucket.
+fn set_rate_limit_headers(headers: &mut HeaderMap, status: &Status) {
+    headers.insert("ratelimit-limit", HeaderValue::from(status.limit));
+    headers.insert("ratelimit-remaining", HeaderValue::from(status.remaining));
+    headers.insert("ratelimit-reset", HeaderValue::from(whole_seconds(status.reset)));
+}
+
+fn set_retry_after(headers: &mut HeaderMap, retry_after: Duration) {
+    headers.insert(header::RETRY_AFTER, HeaderValue::from(whole_seconds(retry_after).max(1)));
+}
+
+/// Seconds, rounded up so a client that waits them out is not refused again.
+fn whole_seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+fn error_body(id: Value, class: ErrorClass, request_id: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {
+            "code": class.code(),
+            "message": class.message(),
+            "data": { "reason": class.reason(), "request_id": request_id },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::PROTOCOL_VERSION;
+    use crate::quota::Quotas;
+    use crate::ratelimit::{Limit, RateLimits};
+    use crate::tests::{call, mcp_request, post_mcp, read, state, tool_server};
+    use tower::ServiceExt;
+
+    fn rpc(id: Value, method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+    }
+
+    async fn answer(
+        delay: u64,
+        text: &str,
+        hedged: bool,
+    ) -> (Attempt, Result<(Reply, Permit), Failure>) {
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+        let attempt = Attempt {
+            endpoint: text.to_string(),
+            outcome: "answered".to_string(),
+            latency_ms: delay,
+            hedged,
+        };
+        let permit = Permit::acquire(Vec::new()).expect("no breakers to refuse");
+        (attempt, Ok((Reply::Json(json!(text)), permit)))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_faster_of_a_hedged_pair_wins() {
+        let primary = answer(1000, "primary", false);
+        tokio::pin!(primary);
+        let addresses = ["primary".to_string(), "hedge".to_string()];
+        let (tried, result) =
+            hedged(primary, answer(100, "hedge", true), addresses, Instant::now()).await;
+
+        let Ok((Reply::Json(answer), _)) = result else { panic!("hedge should answer") };
+        assert_eq!(answer, json!("hedge"));
+        let outcomes: Vec<_> =
+            tried.iter().map(|t| (t.endpoint.as_str(), t.outcome.as_str())).collect();
+        assert_eq!(outcomes, vec![("primary", "cancelled"), ("hedge", "answered")]);
+    }
+
+    #[tokio::test]
+    async fn charges_quotas_only_for_calls_that_are_sent() {
+        let mut state = state(tool_server(&["demo.echo"]).await, &["demo.echo", "demo.missing"]);
+        state.quotas = Arc::new(Quotas::parse("demo-agent/*=1/daily", None).unwrap());
+        let router = crate::router(state.clone());
+        let used = || async { state.quotas.usage().await[0].used };
+
+        let unknown = post_mcp(router.clone(), &call("demo.missing", json!({}))).await;
+        assert_eq!(unknown.status(), ErrorClass::UnknownTool.status());
+        let oversized = "x".repeat(state.tools.defaults().max_payload_bytes);
+        let too_large = post_mcp(router.clone(), &call("demo.echo", json!(oversized))).await;
+        assert_eq!(too_large.status(), ErrorClass::PayloadTooLarge.status());
+        assert_eq!(used().await, 0);
+
+        let (status, _, body) =
+            read(post_mcp(router.clone(), &call("demo.echo", json!({}))).await).await;
+        assert_eq!(
+            (status, body["result"]["content"][0]["text"].as_str()),
+            (StatusCode::OK, Some("{}"))
+        );
+        assert_eq!(used().await, 1);
+        let spent = post_mcp(router, &call("demo.echo", json!({}))).await;
+        assert_eq!(spent.status(), ErrorClass::QuotaExceeded.status());
+    }
+
+    #[tokio::test]
+    async fn negotiates_the_protocol_and_opens_a_session() {
+        let router = crate::router(state(Vec::new(), &[]));
+
+        for (requested, negotiated) in [
+            (json!("2025-03-26"), "2025-03-26"),
+            (json!("2025-06-18"), "2025-06-18"),
+            (json!("1999-01-01"), PROTOCOL_VERSION),
+            (Value::Null, PROTOCOL_VERSION),
+        ] {
+            let initialize = rpc(json!(1), "initialize", json!({ "protocolVersion": requested }));
+            let (status, headers, body) = read(post_mcp(router.clone(), &initialize).await).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(
+                body["result"],
+                json!({
+                    "protocolVersion": negotiated,
+                    "capabilities": { "tools": { "listChanged": true } },
+                    "serverInfo": {
+                        "name": "latchkey-gateway",
+                        "version": env!("CARGO_PKG_VERSION"),
+                    },
+                })
+            );
+
+            // The session it hands out is accepted on later requests; unknown ones are not.
+            let session = headers.get(SESSION_HEADER).unwrap().clone();
+            let mut ping = mcp_request(rpc(json!(2), "ping", json!({})).to_string());
+            ping.headers_mut().insert(SESSION_HEADER, session);
+            let (status, _, body) = read(router.clone().oneshot(ping).await.unwrap()).await;
+            assert_eq!((status, &body["result"]), (StatusCode::OK, &json!({})));
+        }
+
+        let mut stale = mcp_request(rpc(json!(3), "ping", json!({})).to_string());
+        stale.headers_mut().insert(SESSION_HEADER, HeaderValue::from_static("gone"));
+        let (status, _, body) = read(router.oneshot(stale).await.unwrap()).await;
+        assert_eq!(status, ErrorClass::SessionNotFound.status());
+        assert_eq!(body["error"]["code"], ErrorClass::SessionNotFound.code());
+    }
+
+    #[tokio::test]
+    async fn lists_only_the_principals_allowed_tools() {
+        let servers = tool_server(&["demo.echo", "demo.search", "admin.delete"]).await;
+        let router = crate::router(state(servers, &["demo.echo", "admin.delete", "demo.missing"]));
+
+        let (status, _, body) =
+            read(post_mcp(router, &rpc(json!(1), "tools/list", json!({}))).await).await;
+        assert_eq!(status, StatusCode::OK);
+        let mut names: Vec<_> = body["result"]["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|tool| tool["name"].as_str().unwrap())
+            .collect();
+        names.sort_unstable();
+        assert_eq!(names, ["admin.delete", "demo.echo"]);
+    }
+
+    #[tokio::test]
+    async fn maps_refusals_to_json_rpc_errors() {
+        let servers = tool_server(&["demo.echo", "admin.delete"]).await;
+        let router = crate::router(state(servers, &["demo.echo"]));
+
+        let cases = [
+            ("{\"jsonrpc\": \"2.0\",".to_string(), ErrorClass::ParseError, Value::Null),
+            (
+                json!({ "id": 4, "method": "ping" }).to_string(),
+                ErrorClass::InvalidRequest,
+                json!(4),
+            ),
+            (
+                rpc(json!("a"), "sampling/createMessage", json!({})).to_string(),
+                ErrorClass::MethodNotFound,
+                json!("a"),
+            ),
+            (call("admin.delete", json!({})).to_string(), ErrorClass::ToolNotAllowed, json!(1)),
+            (
+                rpc(json!(5), "tools/call", json!({ "arguments": {} })).to_string(),
+                ErrorClass::InvalidParams,
+                json!(5),
+            ),
+        ];
+        for (request, class, id) in cases {
+            let response = router.clone().oneshot(mcp_request(request)).await.unwrap();
+            let (status, _, body) = read(response).await;
+            assert_eq!(status, class.status(), "{class:?}");
+            assert_eq!(body["id"], id, "{class:?}");
+            assert_eq!(body["error"]["code"], class.code(), "{class:?}");
+            assert_eq!(body["error"]["message"], class.message(), "{class:?}");
+            assert_eq!(body["error"]["data"]["reason"], class.reason(), "{class:?}");
+        }
+
+        let mut anonymous = mcp_request(call("demo.echo", json!({})).to_string());
+        anonymous.headers_mut().remove(header::AUTHORIZATION);
+        let (status, headers, body) = read(router.oneshot(anonymous).await.unwrap()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(headers[header::WWW_AUTHENTICATE], "Bearer");
+        assert_eq!(body["error"]["code"], ErrorClass::Unauthorized.code());
+    }
+
+    #[tokio::test]
+    async fn relays_a_tool_call_to_its_server_and_back() {
+        let router = crate::router(state(tool_server(&["demo.echo"]).await, &["demo.echo"]));
+
+        let request = rpc(
+            json!("call-7"),
+            "tools/call",
+            json!({ "name": "demo.echo", "arguments": { "query": "latchkey", "limit": 3 } }),
+        );
+        let (status, headers, body) = read(post_mcp(router, &request).await).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "application/json");
+        let echoed = body["result"]["content"][0]["text"].as_str().unwrap();
+        assert_eq!(
+            (&body["jsonrpc"], &body["id"], serde_json::from_str::<Value>(echoed).unwrap()),
+            (&json!("2.0"), &json!("call-7"), json!({ "query": "latchkey", "limit": 3 }))
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_rate_limits_on_allowed_and_limited_calls() {
+        let mut state = state(tool_server(&["demo.echo"]).await, &["demo.echo"]);
+        // Two calls at once, then one a second.
+        state.rate_limits = Arc::new(RateLimits::new(Limit { per_minute: 60, burst: 2 }, None));
+        let router = crate::router(state);
+        let rate_limit = |headers: &HeaderMap| {
+            ["ratelimit-limit", "ratelimit-remaining", "ratelimit-reset"]
+                .map(|name| headers[name].to_str().unwrap().to_string())
+        };
+
+        let (status, headers, _) =
+            read(post_mcp(router.clone(), &call("demo.echo", json!({}))).await).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(rate_limit(&headers), ["2", "1", "1"]);
+        assert!(headers.get(header::RETRY_AFTER).is_none());
+
+        let (_, headers, _) =
+            read(post_mcp(router.clone(), &call("demo.echo", json!({}))).await).await;
+        assert_eq!(rate_limit(&headers), ["2", "0", "2"]);
+
+        let (status, headers, body) =
+            read(post_mcp(router, &call("demo.echo", json!({}))).await).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(rate_limit(&headers), ["2", "0", "2"]);
+        assert_eq!(headers[header::RETRY_AFTER], "1");
+        assert_eq!(body["error"]["code"], ErrorClass::RateLimited.code());
+        assert_eq!(body["error"]["data"]["retry_after"], 1);
+        assert_eq!(
+            body["error"]["data"]["rate_limit"],
+            json!({ "limit": 2, "remaining": 0, "reset": 2 })
+        );
+    }
+
+    #[test]
+    fn rounds_header_durations_up_to_whole_seconds() {
+        let mut headers = HeaderMap::new();
+        let status = Status { limit: 10, remaining: 3, reset: Duration::from_millis(4001) };
+        set_rate_limit_headers(&mut headers, &status);
+        assert_eq!(headers["ratelimit-reset"], "5");
+
+        for (retry_after, seconds) in [(0, "1"), (1, "1"), (1200, "2"), (3000, "3")] {
+            set_retry_after(&mut headers, Duration::from_millis(retry_after));
+            assert_eq!(headers[header::RETRY_AFTER], seconds, "{retry_after}ms");
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_large_bodies_only_for_authenticated_calls() {
+        let mut state = state(tool_server(&["demo.echo"]).await, &["demo.echo"]);
+        let limits = ToolLimits { max_payload_bytes: 256 * 1024, ..ToolLimits::default() };
+        state.tools = Arc::new(crate::tools::Tools::new(limits));
+        let router = crate::router(state);
+        let sized = |bytes: usize| call("demo.echo", json!("x".repeat(bytes))).to_string();
+
+        // Past the gateway-wide cap, but within the tool's.
+        let answered = router.clone().oneshot(mcp_request(sized(128 * 1024))).await.unwrap();
+        let (status, _, body) = read(answered).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["result"]["content"][0]["text"].as_str().unwrap().len(), 128 * 1024 + 2);
+
+        let mut anonymous = mcp_request(sized(128 * 1024));
+        anonymous.headers_mut().remove(header::AUTHORIZATION);
+        let refused = router.clone().oneshot(anonymous).await.unwrap();
+        assert_eq!(refused.status(), StatusCode::UNAUTHORIZED);
+
+        let (status, _, body) =
+            read(router.clone().oneshot(mcp_request(sized(300 * 1024))).await.unwrap()).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body["error"]["code"], ErrorClass::PayloadTooLarge.code());
+
+        // Every other route keeps the tight cap.
+        let health = axum::http::Request::get("/healthz")
+            .header(header::CONTENT_LENGTH, 128 * 1024)
+            .body(Body::from(vec![b' '; 128 * 1024]))
+            .unwrap();
+        let refused = router.oneshot(health).await.unwrap();
+        assert_eq!(refused.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
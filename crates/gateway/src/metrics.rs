@@ -0,0 +1,318 @@
+//! The request metrics of spec §13.1, kept in memory and rendered in the Prometheus text format.
+//! Label values are bounded: principals past a configured number share hashed buckets, and tool
+//! names the gateway does not know are counted as `other`.
+
+use crate::parse_env;
+use crate::tools::Tools;
+use latchkey_core::audit::{AuditEvent, ErrorClass};
+use ring::digest::{digest, SHA256};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const DEFAULT_MAX_PRINCIPALS: usize = 100;
+/// Buckets shared by the principals past the limit.
+const PRINCIPAL_OVERFLOW_BUCKETS: u8 = 16;
+/// Upper bounds of the latency histograms' buckets, in milliseconds.
+const LATENCY_BUCKETS_MS: [u64; 12] =
+    [5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000, 30000];
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    /// Observations at or below each bound, not cumulative.
+    buckets: [u64; LATENCY_BUCKETS_MS.len()],
+    sum: u64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: u64) {
+        if let Some(index) = LATENCY_BUCKETS_MS.iter().position(|bound| value <= *bound) {
+            self.buckets[index] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn write(&self, out: &mut String, name: &str, label: &str, value: &str) {
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS_MS.iter().zip(self.buckets) {
+            cumulative += count;
+            let _ =
+                writeln!(out, "{name}_bucket{{{label}=\"{value}\",le=\"{bound}\"}} {cumulative}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{{label}=\"{value}\",le=\"+Inf\"}} {}", self.count);
+        let _ = writeln!(out, "{name}_sum{{{label}=\"{value}\"}} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{{{label}=\"{value}\"}} {}", self.count);
+    }
+}
+
+/// Every series, by label values. Sorted so `/metrics` reads the same from scrape to scrape.
+#[derive(Default)]
+struct Series {
+    requests: BTreeMap<(String, String, &'static str), u64>,
+    request_latency: BTreeMap<String, Histogram>,
+    backend_latency: BTreeMap<String, Histogram>,
+    rate_limited: BTreeMap<(String, String), u64>,
+    validation_fail: BTreeMap<String, u64>,
+    inflight: BTreeMap<String, u64>,
+}
+
+pub struct Metrics {
+    /// Principals labelled by name; later ones are hashed into shared buckets.
+    max_principals: usize,
+    principals: Mutex<HashSet<String>>,
+    /// Tools on some principal's allowlist. Those with a `LatchkeyTool` are known as well.
+    allowlisted: HashSet<String>,
+    tools: Arc<Tools>,
+    series: Mutex<Series>,
+}
+
+impl Metrics {
+    pub fn new(max_principals: usize, allowlisted: HashSet<String>, tools: Arc<Tools>) -> Self {
+        Self {
+            max_principals,
+            principals: Mutex::new(HashSet::new()),
+            allowlisted,
+            tools,
+            series: Mutex::new(Series::default()),
+        }
+    }
+
+    pub fn from_env(allowlisted: HashSet<String>, tools: Arc<Tools>) -> anyhow::Result<Self> {
+        let max_principals =
+            parse_env("LATCHKEY_METRICS_MAX_PRINCIPALS")?.unwrap_or(DEFAULT_MAX_PRINCIPALS);
+        Ok(Self::new(max_principals, allowlisted, tools))
+    }
+
+    /// Counts a finished request from its audit event.
+    pub fn observe(&self, event: &AuditEvent) {
+        let principal = self.principal(event.principal_id.as_deref());
+        let tool = self.tool(event.tool_name.as_deref());
+        let mut series = self.lock();
+        *series
+            .requests
+            .entry((tool.clone(), principal.clone(), event.decision.as_str()))
+            .or_default() += 1;
+        series.request_latency.entry(tool.clone()).or_default().observe(event.latency_ms);
+        if event.deny_reason.as_deref() == Some("rate_limited") {
+            *series.rate_limited.entry((principal, tool.clone())).or_default() += 1;
+        }
+        if event.error_class == Some(ErrorClass::Validation) {
+            *series.validation_fail.entry(tool).or_default() += 1;
+        }
+    }
+
+    /// Times one attempt at a tool server.
+    pub fn observe_backend(&self, server: &str, latency: Duration) {
+        let mut series = self.lock();
+        let histogram = series.backend_latency.entry(server.to_string()).or_default();
+        histogram.observe(latency.as_millis() as u64);
+    }
+
+    /// Counts an authenticated request as in flight until the guard is dropped.
+    pub fn enter(self: &Arc<Self>, principal_id: &str) -> InFlight {
+        let principal = self.principal(Some(principal_id));
+        *self.lock().inflight.entry(principal.clone()).or_default() += 1;
+        InFlight { metrics: self.clone(), principal }
+    }
+
+    pub fn write(&self, out: &mut String) {
+        let series = self.lock();
+        out.push_str(
+            "# HELP latchkey_requests_total MCP requests by tool, principal and decision.\n\
+             # TYPE latchkey_requests_total counter\n",
+        );
+        for ((tool, principal, decision), count) in &series.requests {
+            let _ = writeln!(
+                out,
+                "latchkey_requests_total{{tool=\"{tool}\",principal=\"{principal}\",\
+                 decision=\"{decision}\"}} {count}"
+            );
+        }
+        out.push_str(
+            "# HELP latchkey_request_latency_ms MCP request latency through the gateway.\n\
+             # TYPE latchkey_request_latency_ms histogram\n",
+        );
+        for (tool, histogram) in &series.request_latency {
+            histogram.write(out, "latchkey_request_latency_ms", "tool", tool);
+        }
+        out.push_str(
+            "# HELP latchkey_backend_latency_ms Latency of each attempt at a tool server.\n\
+             # TYPE latchkey_backend_latency_ms histogram\n",
+        );
+        for (server, histogram) in &series.backend_latency {
+            histogram.write(out, "latchkey_backend_latency_ms", "server", server);
+        }
+        out.push_str(
+            "# HELP latchkey_rate_limited_total Tool calls refused by a rate limit.\n\
+             # TYPE latchkey_rate_limited_total counter\n",
+        );
+        for ((principal, tool), count) in &series.rate_limited {
+            let _ = writeln!(
+                out,
+                "latchkey_rate_limited_total{{principal=\"{principal}\",tool=\"{tool}\"}} {count}"
+            );
+        }
+        out.push_str(
+            "# HELP latchkey_validation_fail_total Requests refused as invalid.\n\
+             # TYPE latchkey_validation_fail_total counter\n",
+        );
+        for (tool, count) in &series.validation_fail {
+            let _ = writeln!(out, "latchkey_validation_fail_total{{tool=\"{tool}\"}} {count}");
+        }
+        out.push_str(
+            "# HELP latchkey_inflight_requests Authenticated MCP requests in progress.\n\
+             # TYPE latchkey_inflight_requests gauge\n",
+        );
+        for (principal, count) in &series.inflight {
+            let _ =
+                writeln!(out, "latchkey_inflight_requests{{principal=\"{principal}\"}} {count}");
+        }
+    }
+
+    /// The principal's label: its id while fewer than `max_principals` have been seen, else a
+    /// hashed bucket. Unauthenticated requests count as `anonymous`.
+    fn principal(&self, principal_id: Option<&str>) -> String {
+        let Some(id) = principal_id else {
+            return "anonymous".to_string();
+        };
+        let mut principals = self.principals.lock().expect("metrics principals poisoned");
+        if principals.contains(id) {
+            return label(id);
+        }
+        if principals.len() < self.max_principals {
+            principals.insert(id.to_string());
+            return label(id);
+        }
+        let bucket = digest(&SHA256, id.as_bytes()).as_ref()[0] % PRINCIPAL_OVERFLOW_BUCKETS;
+        format!("overflow-{bucket:02}")
+    }
+
+    /// The tool's label: its name if allowlisted or declared by a `LatchkeyTool`, else `other`.
+    /// Requests without a tool have an empty label.
+    fn tool(&self, tool_name: Option<&str>) -> String {
+        match tool_name {
+            None => String::new(),
+            Some(name) if self.allowlisted.contains(name) || self.tools.is_declared(name) => {
+                label(name)
+            }
+            Some(_) => "other".to_string(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Series> {
+        self.series.lock().expect("metrics poisoned")
+    }
+}
+
+/// Escapes a label value for the text format.
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// A request counted in `latchkey_inflight_requests`.
+pub struct InFlight {
+    metrics: Arc<Metrics>,
+    principal: String,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let mut series = self.metrics.lock();
+        if let Some(count) = series.inflight.get_mut(&self.principal) {
+            *count = count.saturating_sub(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::ToolLimits;
+    use latchkey_core::audit::Decision;
+
+    fn metrics(max_principals: usize) -> Arc<Metrics> {
+        let tools = Tools::new(ToolLimits {
+            max_payload_bytes: 1024,
+            max_response_bytes: 1024,
+            timeout: Duration::from_secs(1),
+        });
+        let allowlisted = HashSet::from(["demo.echo".to_string()]);
+        Arc::new(Metrics::new(max_principals, allowlisted, Arc::new(tools)))
+    }
+
+    fn event(principal: &str, tool: &str) -> AuditEvent {
+        let mut event = AuditEvent::new("r".into(), "t".into(), "tools/call".into());
+        event.principal_id = Some(principal.to_string());
+        event.tool_name = Some(tool.to_string());
+        event.latency_ms = 30;
+        event
+    }
+
+    #[test]
+    fn bounds_principal_and_tool_labels() {
+        let metrics = metrics(1);
+        metrics.observe(&event("agent", "demo.echo"));
+        metrics.observe(&event("agent", "made.up"));
+        metrics.observe(&event("intruder", "demo.echo"));
+
+        let mut out = String::new();
+        metrics.write(&mut out);
+        let requests: Vec<&str> =
+            out.lines().filter(|line| line.starts_with("latchkey_requests_total{")).collect();
+        let bucket = metrics.principal(Some("intruder"));
+        assert!(bucket.starts_with("overflow-"));
+        assert_eq!(
+            requests,
+            vec![
+                "latchkey_requests_total{tool=\"demo.echo\",principal=\"agent\",decision=\"allow\"} 1"
+                    .to_string(),
+                format!(
+                    "latchkey_requests_total{{tool=\"demo.echo\",principal=\"{bucket}\",\
+                     decision=\"allow\"}} 1"
+                ),
+                "latchkey_requests_total{tool=\"other\",principal=\"agent\",decision=\"allow\"} 1"
+                    .to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_denials_latency_and_requests_in_flight() {
+        let metrics = metrics(10);
+        let mut limited = event("agent", "demo.echo");
+        limited.decision = Decision::Deny;
+        limited.deny_reason = Some("rate_limited".into());
+        limited.error_class = Some(ErrorClass::RateLimit);
+        metrics.observe(&limited);
+        let mut invalid = event("agent", "demo.echo");
+        invalid.error_class = Some(ErrorClass::Validation);
+        metrics.observe(&invalid);
+        metrics.observe_backend("github", Duration::from_millis(7));
+        let in_flight = metrics.enter("agent");
+
+        let mut out = String::new();
+        metrics.write(&mut out);
+        for line in [
+            "latchkey_rate_limited_total{principal=\"agent\",tool=\"demo.echo\"} 1",
+            "latchkey_validation_fail_total{tool=\"demo.echo\"} 1",
+            "latchkey_request_latency_ms_bucket{tool=\"demo.echo\",le=\"25\"} 0",
+            "latchkey_request_latency_ms_bucket{tool=\"demo.echo\",le=\"50\"} 2",
+            "latchkey_request_latency_ms_sum{tool=\"demo.echo\"} 60",
+            "latchkey_backend_latency_ms_bucket{server=\"github\",le=\"10\"} 1",
+            "latchkey_backend_latency_ms_count{server=\"github\"} 1",
+            "latchkey_inflight_requests{principal=\"agent\"} 1",
+        ] {
+            assert!(out.lines().any(|written| written == line), "missing {line}");
+        }
+        // Nothing issues capability tokens yet, so nothing is reported for them.
+        assert!(!out.contains("token_exchange") && !out.contains("replay_reject"));
+
+        drop(in_flight);
+        let mut out = String::new();
+        metrics.write(&mut out);
+        assert!(out.contains("latchkey_inflight_requests{principal=\"agent\"} 0"));
+    }
+}
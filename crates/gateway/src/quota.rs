@@ -0,0 +1,331 @@
+//! Long-horizon call budgets that reset on a calendar schedule, counted in the shared Redis
+//! store when one is configured and in gateway memory otherwise.
+
+use crate::redis::{Redis, Reply};
+use anyhow::Context;
+use chrono::{DateTime, Datelike, Months, NaiveDate, NaiveTime, Utc};
+use serde::Serialize;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Counts a call against every quota in `KEYS`, or against none if any is spent. `ARGV` holds
+/// each quota's budget and the Unix time its counter expires, in pairs. Returns 0, or the
+/// 1-based index of the spent quota.
+const CONSUME_SCRIPT: &str = r#"
+for i, key in ipairs(KEYS) do
+  if (tonumber(redis.call('GET', key)) or 0) >= tonumber(ARGV[2 * i - 1]) then
+    return i
+  end
+end
+for i, key in ipairs(KEYS) do
+  redis.call('INCR', key)
+  redis.call('EXPIREAT', key, ARGV[2 * i])
+end
+return 0
+"#;
+/// Counters outlive their period by this much, so replicas with slightly slow clocks still find
+/// them.
+const EXPIRY_GRACE_SECONDS: i64 = 3600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    /// Resets at midnight UTC.
+    Daily,
+    /// Resets at midnight UTC on the first of each month.
+    Monthly,
+}
+
+impl FromStr for Period {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "daily" => Ok(Self::Daily),
+            "monthly" => Ok(Self::Monthly),
+            _ => anyhow::bail!("expected daily or monthly, got {value}"),
+        }
+    }
+}
+
+impl Period {
+    /// When the period containing `now` started and when it resets.
+    fn window(self, now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+        let today = now.date_naive();
+        let start = match self {
+            Self::Daily => today,
+            Self::Monthly => NaiveDate::from_ymd_opt(today.year(), today.month(), 1)
+                .expect("the first of a month exists"),
+        };
+        let reset = match self {
+            Self::Daily => start.succ_opt().expect("date in range"),
+            Self::Monthly => start.checked_add_months(Months::new(1)).expect("date in range"),
+        };
+        let midnight = |date: NaiveDate| date.and_time(NaiveTime::MIN).and_utc();
+        (midnight(start), midnight(reset))
+    }
+}
+
+/// A budget of `calls` per period. A missing principal or tool matches every one, sharing the
+/// budget among them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Quota {
+    principal: Option<String>,
+    tool: Option<String>,
+    calls: u64,
+    period: Period,
+}
+
+impl Quota {
+    fn applies(&self, principal: &str, tool: &str) -> bool {
+        self.principal.as_deref().is_none_or(|p| p == principal)
+            && self.tool.as_deref().is_none_or(|t| t == tool)
+    }
+
+    fn shared_name(&self, start: DateTime<Utc>) -> String {
+        format!(
+            "{{latchkey}}:quota:{}:{}:{}",
+            self.principal.as_deref().unwrap_or("*"),
+            self.tool.as_deref().unwrap_or("*"),
+            start.format("%Y-%m-%d"),
+        )
+    }
+}
+
+/// Parses `principal/tool=calls/period` entries, where either side may be `*`, e.g.
+/// `demo-agent/*=10000/daily,*/github.search=50000/monthly`.
+fn parse_quotas(input: &str) -> anyhow::Result<Vec<Quota>> {
+    let mut quotas = Vec::new();
+
+    for entry in input.split(',') {
+        if entry.trim().is_empty() {
+            continue;
+        }
+
+        let (scope, budget) = entry.split_once('=').context("quota entries must be key=budget")?;
+        let (principal, tool) =
+            scope.split_once('/').context("quota keys must be principal/tool")?;
+        let (calls, period) =
+            budget.split_once('/').context("quota budgets must be calls/period")?;
+        let any = |value: &str| Some(value.trim()).filter(|v| *v != "*").map(str::to_string);
+        quotas.push(Quota {
+            principal: any(principal),
+            tool: any(tool),
+            calls: calls.trim().parse().context("invalid quota call count")?,
+            period: period.trim().parse()?,
+        });
+    }
+
+    Ok(quotas)
+}
+
+/// One quota's consumption, as shown by the admin API.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Usage {
+    pub principal: String,
+    pub tool: String,
+    pub period: Period,
+    pub calls: u64,
+    pub used: u64,
+    pub remaining: u64,
+    pub resets_at: String,
+}
+
+pub struct Quotas {
+    quotas: Vec<Quota>,
+    /// Local counters, by quota, with the start of the period they count.
+    local: Mutex<Vec<(DateTime<Utc>, u64)>>,
+    shared: Option<Arc<Redis>>,
+}
+
+impl Quotas {
+    fn new(quotas: Vec<Quota>, shared: Option<Arc<Redis>>) -> Self {
+        let local = Mutex::new(vec![(DateTime::<Utc>::MIN_UTC, 0); quotas.len()]);
+        Self { quotas, local, shared }
+    }
+
+    pub fn from_env(shared: Option<Arc<Redis>>) -> anyhow::Result<Self> {
+        let quotas = std::env::var("LATCHKEY_QUOTAS").unwrap_or_default();
+        Self::parse(&quotas, shared).context("invalid LATCHKEY_QUOTAS")
+    }
+
+    /// Quotas written as `LATCHKEY_QUOTAS` entries.
+    pub fn parse(input: &str, shared: Option<Arc<Redis>>) -> anyhow::Result<Self> {
+        Ok(Self::new(parse_quotas(input)?, shared))
+    }
+
+    /// Counts one call against every quota that applies, or returns how long until the spent
+    /// one resets.
+    pub async fn consume(&self, principal: &str, tool: &str) -> Result<(), Duration> {
+        let now = Utc::now();
+        let applicable: Vec<usize> = (0..self.quotas.len())
+            .filter(|&index| self.quotas[index].applies(principal, tool))
+            .collect();
+        if applicable.is_empty() {
+            return Ok(());
+        }
+
+        let spent = match &self.shared {
+            Some(redis) => match self.consume_shared(redis, &applicable, now).await {
+                Ok(spent) => spent,
+                Err(_) => self.consume_local(&applicable, now),
+            },
+            None => self.consume_local(&applicable, now),
+        };
+        match spent {
+            None => Ok(()),
+            Some(index) => {
+                let (_, reset) = self.quotas[index].period.window(now);
+                Err((reset - now).to_std().unwrap_or_default())
+            }
+        }
+    }
+
+    async fn consume_shared(
+        &self,
+        redis: &Redis,
+        applicable: &[usize],
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<Option<usize>> {
+        let mut names = Vec::new();
+        let mut budgets = Vec::new();
+        for &index in applicable {
+            let quota = &self.quotas[index];
+            let (start, reset) = quota.period.window(now);
+            names.push(quota.shared_name(start));
+            budgets.push(quota.calls.to_string());
+            budgets.push((reset.timestamp() + EXPIRY_GRACE_SECONDS).to_string());
+        }
+        let count = applicable.len().to_string();
+        let mut args = vec!["EVAL", CONSUME_SCRIPT, &count];
+        args.extend(names.iter().map(String::as_str));
+        args.extend(budgets.iter().map(String::as_str));
+
+        let spent = redis.command(&args).await?.integer()?;
+        Ok(match usize::try_from(spent) {
+            Ok(0) => None,
+            Ok(position) => {
+                Some(*applicable.get(position - 1).context("spent quota out of range")?)
+            }
+            Err(_) => anyhow::bail!("unexpected quota reply {spent}"),
+        })
+    }
+
+    fn consume_local(&self, applicable: &[usize], now: DateTime<Utc>) -> Option<usize> {
+        let mut local = self.local.lock().expect("quota counters poisoned");
+        for &index in applicable {
+            let (start, _) = self.quotas[index].period.window(now);
+            let counter = &mut local[index];
+            if counter.0 != start {
+                *counter = (start, 0);
+            }
+            if counter.1 >= self.quotas[index].calls {
+                return Some(index);
+            }
+        }
+        for &index in applicable {
+            local[index].1 += 1;
+        }
+        None
+    }
+
+    /// Consumption of every quota in the current period.
+    pub async fn usage(&self) -> Vec<Usage> {
+        let now = Utc::now();
+        let windows: Vec<_> = self.quotas.iter().map(|quota| quota.period.window(now)).collect();
+
+        let mut used = self.usage_local(&windows);
+        if let (Some(redis), false) = (&self.shared, self.quotas.is_empty()) {
+            let names: Vec<String> = self
+                .quotas
+                .iter()
+                .zip(&windows)
+                .map(|(quota, (start, _))| quota.shared_name(*start))
+                .collect();
+            let mut args = vec!["MGET"];
+            args.extend(names.iter().map(String::as_str));
+            if let Ok(Reply::Array(values)) = redis.command(&args).await {
+                used = values
+                    .iter()
+                    .map(|value| if *value == Reply::Nil { Ok(0) } else { value.integer() })
+                    .map(|value| value.map_or(0, |value| u64::try_from(value).unwrap_or(0)))
+                    .collect();
+            }
+        }
+
+        self.quotas
+            .iter()
+            .zip(windows)
+            .zip(used)
+            .map(|((quota, (_, reset)), used)| Usage {
+                principal: quota.principal.clone().unwrap_or_else(|| "*".to_string()),
+                tool: quota.tool.clone().unwrap_or_else(|| "*".to_string()),
+                period: quota.period,
+                calls: quota.calls,
+                used,
+                remaining: quota.calls.saturating_sub(used),
+                resets_at: reset.to_rfc3339(),
+            })
+            .collect()
+    }
+
+    fn usage_local(&self, windows: &[(DateTime<Utc>, DateTime<Utc>)]) -> Vec<u64> {
+        let local = self.local.lock().expect("quota counters poisoned");
+        local
+            .iter()
+            .zip(windows)
+            .map(|((start, used), (current, _))| if start == current { *used } else { 0 })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(date).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn periods_reset_on_calendar_boundaries() {
+        let now = at("2026-01-31T17:45:00Z");
+        assert_eq!(
+            Period::Daily.window(now),
+            (at("2026-01-31T00:00:00Z"), at("2026-02-01T00:00:00Z"))
+        );
+        assert_eq!(
+            Period::Monthly.window(now),
+            (at("2026-01-01T00:00:00Z"), at("2026-02-01T00:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn shares_budgets_among_matching_calls() {
+        let quotas =
+            Quotas::new(parse_quotas("agent/*=3/daily, */search=2/monthly").unwrap(), None);
+        let now = at("2026-01-15T17:45:00Z");
+        let consume = |principal, tool| {
+            quotas.consume_local(
+                &(0..2).filter(|&i| quotas.quotas[i].applies(principal, tool)).collect::<Vec<_>>(),
+                now,
+            )
+        };
+
+        assert_eq!(consume("agent", "search"), None);
+        assert_eq!(consume("other", "search"), None);
+        assert_eq!(consume("agent", "search"), Some(1));
+        assert_eq!(consume("agent", "fetch"), None);
+        assert_eq!(consume("agent", "fetch"), None);
+        assert_eq!(consume("agent", "fetch"), Some(0));
+
+        // A new day restores the daily budget but not the monthly one.
+        let tomorrow = at("2026-01-16T00:00:00Z");
+        assert_eq!(quotas.consume_local(&[0, 1], tomorrow), Some(1));
+        assert_eq!(quotas.consume_local(&[0], tomorrow), None);
+        assert_eq!(quotas.usage_local(&[Period::Daily.window(tomorrow)])[0], 1);
+        assert!(parse_quotas("agent=3/daily").is_err());
+        assert!(parse_quotas("agent/*=3/weekly").is_err());
+    }
+}
@@ -0,0 +1,475 @@
+//! Token-bucket rate limits per principal, per tool and per (principal, tool) pair, kept in a
+//! shared Redis store when one is configured and in gateway memory otherwise.
+
+use crate::parse_env;
+use crate::redis::{Redis, Reply};
+use crate::resources;
+use latchkey_core::scope::Scope;
+use serde::Deserialize;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hash};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::time::Instant;
+
+const DEFAULT_PER_MINUTE: u32 = 60;
+const SHARDS: usize = 16;
+/// How long a caller waits out a limit that never refills.
+const EXHAUSTED_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// The shared counterpart of `Buckets::acquire`, atomic across replicas. `KEYS` are the buckets
+/// and `ARGV` their per-minute rates and bursts in pairs. Returns the tightest bucket as
+/// `{burst, remaining, reset_ms, retry_after_ms}`, where a non-zero `retry_after_ms` means the
+/// call was refused. Idle buckets expire once they would be full.
+const ACQUIRE_SCRIPT: &str = r#"
+local time = redis.call('TIME')
+local now = time[1] * 1000 + math.floor(time[2] / 1000)
+local function reset(tokens, rate, burst)
+  if tokens >= burst then return 0 end
+  if rate > 0 then return math.ceil((burst - tokens) / rate) end
+  return 60000
+end
+local tokens = {}
+local denied = nil
+for i, key in ipairs(KEYS) do
+  local rate = tonumber(ARGV[2 * i - 1]) / 60000
+  local burst = tonumber(ARGV[2 * i])
+  local bucket = redis.call('HMGET', key, 'tokens', 'updated')
+  local updated = tonumber(bucket[2]) or now
+  tokens[i] = math.min(burst, (tonumber(bucket[1]) or burst) + math.max(0, now - updated) * rate)
+  if tokens[i] < 1 then
+    local wait = 60000
+    if rate > 0 and burst > 0 then wait = math.ceil((1 - tokens[i]) / rate) end
+    if denied == nil or wait > denied[4] then
+      denied = {burst, 0, reset(tokens[i], rate, burst), wait}
+    end
+  end
+end
+if denied then
+  return denied
+end
+local tightest = nil
+for i, key in ipairs(KEYS) do
+  local rate = tonumber(ARGV[2 * i - 1]) / 60000
+  local burst = tonumber(ARGV[2 * i])
+  local left = tokens[i] - 1
+  redis.call('HSET', key, 'tokens', left, 'updated', now)
+  redis.call('PEXPIRE', key, reset(left, rate, burst) + 1000)
+  local status = {burst, math.floor(left), reset(left, rate, burst), 0}
+  if tightest == nil or status[2] < tightest[2]
+      or (status[2] == tightest[2] and status[3] > tightest[3]) then
+    tightest = status
+  end
+end
+return tightest
+"#;
+
+/// A sustained rate, and how many requests may arrive at once after an idle spell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limit {
+    pub per_minute: u32,
+    pub burst: u32,
+}
+
+impl Limit {
+    /// A limit whose burst is one minute's worth of requests.
+    pub fn per_minute(per_minute: u32) -> Self {
+        Self { per_minute, burst: per_minute }
+    }
+
+    fn refill_per_second(self) -> f64 {
+        f64::from(self.per_minute) / 60.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Principal(String),
+    Tool(String),
+    PrincipalTool(String, String),
+}
+
+impl Key {
+    /// The key's name in the shared store. The `{latchkey}` hash tag keeps every key in one
+    /// Redis Cluster slot, so one script can touch several.
+    fn shared_name(&self) -> String {
+        match self {
+            Self::Principal(principal) => format!("{{latchkey}}:rate:principal:{principal}"),
+            Self::Tool(tool) => format!("{{latchkey}}:rate:tool:{tool}"),
+            Self::PrincipalTool(principal, tool) => {
+                format!("{{latchkey}}:rate:pair:{principal}:{tool}")
+            }
+        }
+    }
+}
+
+/// The tightest bucket a call was checked against, as reported in the `RateLimit-*` headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Status {
+    /// The bucket's burst.
+    pub limit: u32,
+    /// Whole tokens left after the call.
+    pub remaining: u32,
+    /// Until the bucket is full again.
+    pub reset: Duration,
+}
+
+impl Status {
+    /// Whether `self` leaves less room than `other`: fewer tokens, or a longer refill.
+    fn tighter_than(&self, other: &Status) -> bool {
+        (self.remaining, std::cmp::Reverse(self.reset))
+            < (other.remaining, std::cmp::Reverse(other.reset))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Denied {
+    pub status: Status,
+    /// Until the refusing bucket has a token again.
+    pub retry_after: Duration,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    limit: Limit,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.refill_per_second())
+            .min(f64::from(self.limit.burst));
+        self.updated = now;
+    }
+
+    /// Takes a token, or says how long until one is available.
+    fn take(&mut self, now: Instant) -> Result<Status, Denied> {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(self.status());
+        }
+        let rate = self.limit.refill_per_second();
+        let retry_after = if rate <= 0.0 || self.limit.burst == 0 {
+            EXHAUSTED_RETRY_AFTER
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / rate)
+        };
+        Err(Denied { status: self.status(), retry_after })
+    }
+
+    fn status(&self) -> Status {
+        let missing = f64::from(self.limit.burst) - self.tokens;
+        let rate = self.limit.refill_per_second();
+        let reset = if missing <= 0.0 {
+            Duration::ZERO
+        } else if rate <= 0.0 {
+            EXHAUSTED_RETRY_AFTER
+        } else {
+            Duration::from_secs_f64(missing / rate)
+        };
+        Status { limit: self.limit.burst, remaining: self.tokens as u32, reset }
+    }
+
+    fn full(&self, now: Instant) -> bool {
+        let mut bucket = *self;
+        bucket.refill(now);
+        bucket.tokens >= f64::from(bucket.limit.burst)
+    }
+}
+
+/// Buckets spread over independently locked shards, so concurrent callers rarely contend.
+struct Buckets {
+    hasher: RandomState,
+    shards: Vec<Mutex<HashMap<Key, Bucket>>>,
+}
+
+impl Buckets {
+    fn new() -> Self {
+        Self {
+            hasher: RandomState::new(),
+            shards: (0..SHARDS).map(|_| Mutex::new(HashMap::new())).collect(),
+        }
+    }
+
+    /// Takes a token from every bucket, or from none: tokens already taken are put back when a
+    /// later bucket is empty. Only one shard is locked at a time.
+    fn acquire(&self, keys: &[(Key, Limit)]) -> Result<Status, Denied> {
+        let now = Instant::now();
+        let mut tightest: Option<Status> = None;
+        for (taken, (key, limit)) in keys.iter().enumerate() {
+            let result = self.with(key, |buckets| {
+                let bucket = buckets.entry(key.clone()).or_insert(Bucket {
+                    limit: *limit,
+                    tokens: f64::from(limit.burst),
+                    updated: now,
+                });
+                bucket.refill(now);
+                // A changed limit applies from now on, without refunding or forfeiting tokens
+                // beyond the new burst.
+                bucket.limit = *limit;
+                bucket.take(now)
+            });
+            match result {
+                Ok(status) => {
+                    if tightest.is_none_or(|tightest| status.tighter_than(&tightest)) {
+                        tightest = Some(status);
+                    }
+                }
+                Err(denied) => {
+                    for (key, _) in &keys[..taken] {
+                        self.with(key, |buckets| {
+                            if let Some(bucket) = buckets.get_mut(key) {
+                                bucket.tokens =
+                                    (bucket.tokens + 1.0).min(f64::from(bucket.limit.burst));
+                            }
+                        });
+                    }
+                    return Err(denied);
+                }
+            }
+        }
+        // Every call has at least the principal's bucket.
+        Ok(tightest.expect("no rate limit keys"))
+    }
+
+    /// Drops buckets that have refilled completely, which behave exactly like new ones.
+    fn evict(&self) -> usize {
+        let now = Instant::now();
+        self.shards
+            .iter()
+            .map(|shard| {
+                let mut buckets = shard.lock().expect("rate limit shard poisoned");
+                let before = buckets.len();
+                buckets.retain(|_, bucket| !bucket.full(now));
+                before - buckets.len()
+            })
+            .sum()
+    }
+
+    fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().expect("rate limit shard poisoned").len()).sum()
+    }
+
+    fn with<T>(&self, key: &Key, f: impl FnOnce(&mut HashMap<Key, Bucket>) -> T) -> T {
+        let shard = self.hasher.hash_one(key) as usize % self.shards.len();
+        f(&mut self.shards[shard].lock().expect("rate limit shard poisoned"))
+    }
+}
+
+/// The fields of a `LatchkeyPolicy` spec that limit request rates.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PolicySpec {
+    subjects: Vec<String>,
+    scopes: Vec<String>,
+    #[serde(default)]
+    constraints: Option<Constraints>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Constraints {
+    rate_limit_per_minute: Option<u32>,
+}
+
+pub struct RateLimits {
+    principal: Limit,
+    /// `rateLimitPerMinute` from `LatchkeyPolicy` resources, by principal and tool.
+    policies: RwLock<HashMap<(String, String), u32>>,
+    /// Local buckets, used whenever the shared store is missing or unreachable.
+    buckets: Buckets,
+    shared: Option<Arc<Redis>>,
+}
+
+impl RateLimits {
+    pub fn new(principal: Limit, shared: Option<Arc<Redis>>) -> Self {
+        Self { principal, policies: RwLock::new(HashMap::new()), buckets: Buckets::new(), shared }
+    }
+
+    pub fn from_env(shared: Option<Arc<Redis>>) -> anyhow::Result<Self> {
+        let per_minute = parse_env("LATCHKEY_RATE_LIMIT_PER_MINUTE")?.unwrap_or(DEFAULT_PER_MINUTE);
+        let burst = parse_env("LATCHKEY_RATE_LIMIT_BURST")?.unwrap_or(per_minute);
+        Ok(Self::new(Limit { per_minute, burst }, shared))
+    }
+
+    /// Takes one request from the principal's, the tool's and the pair's limits and reports the
+    /// tightest of them, or the one that refused. `tool_limit` is the tool's own `rateLimit`.
+    pub async fn acquire(
+        &self,
+        principal: &str,
+        tool: &str,
+        tool_limit: Option<u32>,
+    ) -> Result<Status, Denied> {
+        let mut keys = vec![(Key::Principal(principal.to_string()), self.principal)];
+        if let Some(per_minute) = tool_limit {
+            keys.push((Key::Tool(tool.to_string()), Limit::per_minute(per_minute)));
+        }
+        let pair = (principal.to_string(), tool.to_string());
+        let policy =
+            self.policies.read().expect("rate limit policies poisoned").get(&pair).copied();
+        if let Some(per_minute) = policy {
+            keys.push((Key::PrincipalTool(pair.0, pair.1), Limit::per_minute(per_minute)));
+        }
+        if let Some(redis) = &self.shared {
+            if let Ok(result) = acquire_shared(redis, &keys).await {
+                return result;
+            }
+        }
+        self.buckets.acquire(&keys)
+    }
+
+    /// Forgets keys idle long enough to be back at full burst, and returns how many remain.
+    pub fn evict(&self) -> (usize, usize) {
+        (self.buckets.evict(), self.buckets.len())
+    }
+
+    /// Replaces the pair limits from the current policies. When several policies grant the same
+    /// principal a tool, the strictest limit applies.
+    fn update(&self, specs: &BTreeMap<String, PolicySpec>) {
+        let mut policies: HashMap<(String, String), u32> = HashMap::new();
+        for spec in specs.values() {
+            let Some(per_minute) = spec.constraints.as_ref().and_then(|c| c.rate_limit_per_minute)
+            else {
+                continue;
+            };
+            let tools = spec.scopes.iter().filter_map(|scope| scope.parse::<Scope>().ok());
+            for tool in tools.map(|scope| scope.tool) {
+                for subject in &spec.subjects {
+                    policies
+                        .entry((subject.clone(), tool.clone()))
+                        .and_modify(|limit| *limit = (*limit).min(per_minute))
+                        .or_insert(per_minute);
+                }
+            }
+        }
+        *self.policies.write().expect("rate limit policies poisoned") = policies;
+    }
+}
+
+async fn acquire_shared(
+    redis: &Redis,
+    keys: &[(Key, Limit)],
+) -> anyhow::Result<Result<Status, Denied>> {
+    let names: Vec<String> = keys.iter().map(|(key, _)| key.shared_name()).collect();
+    let limits: Vec<String> = keys
+        .iter()
+        .flat_map(|(_, limit)| [limit.per_minute.to_string(), limit.burst.to_string()])
+        .collect();
+    let count = keys.len().to_string();
+    let mut args = vec!["EVAL", ACQUIRE_SCRIPT, &count];
+    args.extend(names.iter().map(String::as_str));
+    args.extend(limits.iter().map(String::as_str));
+
+    let Reply::Array(fields) = redis.command(&args).await? else {
+        anyhow::bail!("expected an array reply");
+    };
+    let fields = fields
+        .iter()
+        .map(|field| Ok(u64::try_from(field.integer()?)?))
+        .collect::<anyhow::Result<Vec<u64>>>()?;
+    let [limit, remaining, reset_ms, retry_after_ms] = fields[..] else {
+        anyhow::bail!("expected four fields, got {}", fields.len());
+    };
+    let status = Status {
+        limit: u32::try_from(limit)?,
+        remaining: u32::try_from(remaining)?,
+        reset: Duration::from_millis(reset_ms),
+    };
+    Ok(match retry_after_ms {
+        0 => Ok(status),
+        retry_after => Err(Denied { status, retry_after: Duration::from_millis(retry_after) }),
+    })
+}
+
+/// Follows every `LatchkeyPolicy` in the cluster for per-principal tool limits.
+pub async fn watch(limits: Arc<RateLimits>) {
+    resources::watch("LatchkeyPolicy", "latchkeypolicies", |specs| limits.update(specs)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test(start_paused = true)]
+    async fn allows_a_burst_then_refills_at_the_sustained_rate() {
+        let limits = RateLimits::new(Limit { per_minute: 60, burst: 3 }, None);
+        for remaining in [2, 1, 0] {
+            let status = limits.acquire("agent", "search", None).await.unwrap();
+            assert_eq!(status.remaining, remaining);
+        }
+        let denied = limits.acquire("agent", "search", None).await.unwrap_err();
+        assert_eq!(denied.retry_after, Duration::from_secs(1));
+        assert_eq!(denied.status, Status { limit: 3, remaining: 0, reset: Duration::from_secs(3) });
+        assert!(limits.acquire("other", "search", None).await.is_ok());
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(limits.acquire("agent", "search", None).await.is_ok());
+        assert!(limits.acquire("agent", "search", None).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_denied_request_spends_no_tokens() {
+        let limits = RateLimits::new(Limit::per_minute(10), None);
+        assert!(limits.acquire("a", "search", Some(1)).await.is_ok());
+        for _ in 0..5 {
+            assert!(limits.acquire("a", "search", Some(1)).await.is_err());
+        }
+        // The tool's bucket is the tighter one.
+        let status = limits.acquire("a", "fetch", Some(5)).await.unwrap();
+        assert_eq!(status, Status { limit: 5, remaining: 4, reset: Duration::from_secs(12) });
+        // Only the successful calls counted against the principal.
+        for _ in 0..8 {
+            assert!(limits.acquire("a", "other", None).await.is_ok());
+        }
+        assert!(limits.acquire("a", "other", None).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn applies_the_strictest_policy_per_principal_and_tool() {
+        let limits = RateLimits::new(Limit::per_minute(100), None);
+        let spec = |value| serde_json::from_value::<PolicySpec>(value).unwrap();
+        limits.update(&BTreeMap::from([
+            (
+                "a/loose".to_string(),
+                spec(json!({
+                    "subjects": ["agent"],
+                    "scopes": ["tools:github.search:call"],
+                    "constraints": { "rateLimitPerMinute": 5 },
+                })),
+            ),
+            (
+                "a/strict".to_string(),
+                spec(json!({
+                    "subjects": ["agent"],
+                    "scopes": ["tools:github.search:read", "tools:github.star:call"],
+                    "constraints": { "rateLimitPerMinute": 2 },
+                })),
+            ),
+        ]));
+
+        for _ in 0..2 {
+            assert!(limits.acquire("agent", "github.search", None).await.is_ok());
+        }
+        assert!(limits.acquire("agent", "github.search", None).await.is_err());
+        assert!(limits.acquire("other", "github.search", None).await.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn evicts_buckets_once_they_have_refilled() {
+        let limits = RateLimits::new(Limit { per_minute: 60, burst: 10 }, None);
+        limits.acquire("a", "search", Some(60)).await.unwrap();
+        limits.acquire("b", "search", Some(60)).await.unwrap();
+        assert_eq!(limits.evict(), (0, 3));
+
+        // The principals refill within a second, the shared tool bucket a second later.
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(limits.evict(), (2, 1));
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(limits.evict(), (1, 0));
+    }
+}
@@ -0,0 +1,217 @@
+//! Just enough of the Redis protocol (RESP2) to keep rate limits and quotas in a store shared
+//! by every gateway replica.
+
+use crate::parse_env;
+use anyhow::Context;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+use tracing::{info, warn};
+
+const DEFAULT_PORT: u16 = 6379;
+const DEFAULT_TIMEOUT_MS: u64 = 50;
+const MAX_IDLE_CONNECTIONS: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    Nil,
+    Status(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    pub fn integer(&self) -> anyhow::Result<i64> {
+        match self {
+            Self::Integer(value) => Ok(*value),
+            Self::Bulk(value) => Ok(std::str::from_utf8(value)?.parse()?),
+            other => anyhow::bail!("expected an integer reply, got {other:?}"),
+        }
+    }
+}
+
+pub struct Redis {
+    address: String,
+    username: Option<String>,
+    password: Option<String>,
+    database: Option<u32>,
+    /// Bounds each command, connecting included, so an unreachable store costs callers little.
+    timeout: Duration,
+    idle: Mutex<Vec<BufStream<TcpStream>>>,
+    reachable: AtomicBool,
+}
+
+impl Redis {
+    /// Parses `redis://[[user]:password@]host[:port][/database]`.
+    pub fn new(url: &str, timeout: Duration) -> anyhow::Result<Self> {
+        let url = reqwest::Url::parse(url).context("invalid URL")?;
+        anyhow::ensure!(url.scheme() == "redis", "only redis:// URLs are supported");
+        let host = url.host_str().context("missing host")?;
+        let database = match url.path().trim_start_matches('/') {
+            "" => None,
+            database => Some(database.parse().context("invalid database number")?),
+        };
+
+        Ok(Self {
+            address: format!("{host}:{}", url.port().unwrap_or(DEFAULT_PORT)),
+            username: Some(url.username()).filter(|user| !user.is_empty()).map(str::to_string),
+            password: url.password().map(str::to_string),
+            database,
+            timeout,
+            idle: Mutex::new(Vec::new()),
+            reachable: AtomicBool::new(true),
+        })
+    }
+
+    /// `LATCHKEY_REDIS_URL`, if set.
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(url) = std::env::var("LATCHKEY_REDIS_URL") else {
+            return Ok(None);
+        };
+        let timeout = parse_env("LATCHKEY_REDIS_TIMEOUT_MS")?.unwrap_or(DEFAULT_TIMEOUT_MS);
+        Self::new(&url, Duration::from_millis(timeout))
+            .context("invalid LATCHKEY_REDIS_URL")
+            .map(Some)
+    }
+
+    /// Runs one command. Failures are logged once until the store answers again, since callers
+    /// fall back to local state.
+    pub async fn command(&self, args: &[&str]) -> anyhow::Result<Reply> {
+        let result = tokio::time::timeout(self.timeout, self.run(args))
+            .await
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("timed out after {:?}", self.timeout)));
+        match &result {
+            Ok(_) if !self.reachable.swap(true, Ordering::Relaxed) => {
+                info!(address = %self.address, "shared store reachable again");
+            }
+            Err(err) if self.reachable.swap(false, Ordering::Relaxed) => {
+                warn!(address = %self.address, error = %err, "shared store unreachable, using local state");
+            }
+            _ => {}
+        }
+        result
+    }
+
+    async fn run(&self, args: &[&str]) -> anyhow::Result<Reply> {
+        let idle = self.idle.lock().expect("redis pool poisoned").pop();
+        let mut connection = match idle {
+            Some(connection) => connection,
+            None => self.connect().await?,
+        };
+        // A connection that failed mid-reply is out of step with the server, so it is dropped.
+        let reply = send(&mut connection, args).await?;
+        let mut idle = self.idle.lock().expect("redis pool poisoned");
+        if idle.len() < MAX_IDLE_CONNECTIONS {
+            idle.push(connection);
+        }
+        reply
+    }
+
+    async fn connect(&self) -> anyhow::Result<BufStream<TcpStream>> {
+        let stream = TcpStream::connect(&self.address)
+            .await
+            .with_context(|| format!("failed to connect to {}", self.address))?;
+        stream.set_nodelay(true)?;
+        let mut connection = BufStream::new(stream);
+        if let Some(password) = &self.password {
+            match &self.username {
+                Some(username) => send(&mut connection, &["AUTH", username, password]).await??,
+                None => send(&mut connection, &["AUTH", password]).await??,
+            };
+        }
+        if let Some(database) = self.database {
+            send(&mut connection, &["SELECT", &database.to_string()]).await??;
+        }
+        Ok(connection)
+    }
+}
+
+/// Sends a command and reads its reply. The outer error means the connection is unusable; the
+/// inner one is an error reply.
+async fn send(
+    connection: &mut BufStream<TcpStream>,
+    args: &[&str],
+) -> anyhow::Result<anyhow::Result<Reply>> {
+    let mut request = format!("*{}\r\n", args.len());
+    for arg in args {
+        request.push_str(&format!("${}\r\n{arg}\r\n", arg.len()));
+    }
+    connection.write_all(request.as_bytes()).await?;
+    connection.flush().await?;
+    read_reply(connection).await
+}
+
+fn read_reply<R>(
+    reader: &mut R,
+) -> Pin<Box<dyn Future<Output = anyhow::Result<anyhow::Result<Reply>>> + Send + '_>>
+where
+    R: AsyncBufRead + Unpin + Send,
+{
+    Box::pin(async move {
+        let mut line = Vec::new();
+        reader.read_until(b'\n', &mut line).await?;
+        let line = line.strip_suffix(b"\r\n").context("truncated reply")?;
+        let (kind, rest) = line.split_first().context("empty reply")?;
+        let rest = std::str::from_utf8(rest)?;
+        let reply = match kind {
+            b'+' => Reply::Status(rest.to_string()),
+            b'-' => return Ok(Err(anyhow::anyhow!("redis error: {rest}"))),
+            b':' => Reply::Integer(rest.parse()?),
+            b'$' if rest == "-1" => Reply::Nil,
+            b'$' => {
+                let mut value = vec![0; rest.parse::<usize>()? + 2];
+                reader.read_exact(&mut value).await?;
+                value.truncate(value.len() - 2);
+                Reply::Bulk(value)
+            }
+            b'*' if rest == "-1" => Reply::Nil,
+            b'*' => {
+                let len: usize = rest.parse()?;
+                let mut items = Vec::with_capacity(len);
+                for _ in 0..len {
+                    // Drain every item even after an error, to keep the connection usable.
+                    items.push(read_reply(reader).await?);
+                }
+                Reply::Array(items.into_iter().collect::<anyhow::Result<_>>()?)
+            }
+            other => anyhow::bail!("unexpected reply type {:?}", char::from(*other)),
+        };
+        Ok(Ok(reply))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reads_nested_replies() {
+        let mut input: &[u8] = b"*4\r\n:7\r\n$5\r\nhello\r\n$-1\r\n*1\r\n+OK\r\n-ERR boom\r\n";
+        let reply = read_reply(&mut input).await.unwrap().unwrap();
+        assert_eq!(
+            reply,
+            Reply::Array(vec![
+                Reply::Integer(7),
+                Reply::Bulk(b"hello".to_vec()),
+                Reply::Nil,
+                Reply::Array(vec![Reply::Status("OK".to_string())]),
+            ])
+        );
+        assert!(read_reply(&mut input).await.unwrap().is_err());
+    }
+
+    #[test]
+    fn parses_urls() {
+        let redis = Redis::new("redis://:secret@cache.latchkey:6380/2", Duration::ZERO).unwrap();
+        assert_eq!(redis.address, "cache.latchkey:6380");
+        assert_eq!(redis.username, None);
+        assert_eq!(redis.password.as_deref(), Some("secret"));
+        assert_eq!(redis.database, Some(2));
+        assert!(Redis::new("http://cache", Duration::ZERO).is_err());
+    }
+}
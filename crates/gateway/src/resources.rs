@@ -0,0 +1,85 @@
+//! Follows the Latchkey custom resources the gateway enforces.
+
+use futures::StreamExt;
+use kube::api::{ApiResource, DynamicObject, GroupVersionKind};
+use kube::runtime::watcher::{self, Event};
+use kube::runtime::WatchStreamExt;
+use kube::{Api, Client, ResourceExt};
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use tracing::{info, warn};
+
+const GROUP: &str = "latchkey.dev";
+const VERSION: &str = "v1beta1";
+
+/// Watches every `kind` resource in the cluster and calls `update` with all their specs, keyed
+/// by `namespace/name`, whenever one changes. Specs that fail to parse are left out. Without a
+/// Kubernetes API nothing is ever reported.
+pub async fn watch<T, F>(kind: &str, plural: &str, update: F)
+where
+    T: DeserializeOwned,
+    F: Fn(&BTreeMap<String, T>),
+{
+    let client = match Client::try_default().await {
+        Ok(client) => client,
+        Err(err) => {
+            warn!(kind, error = %err, "no Kubernetes API, not watching resources");
+            return;
+        }
+    };
+    let resource =
+        ApiResource::from_gvk_with_plural(&GroupVersionKind::gvk(GROUP, VERSION, kind), plural);
+    let api: Api<DynamicObject> = Api::all_with(client, &resource);
+    let mut stream = watcher::watcher(api, watcher::Config::default()).default_backoff().boxed();
+    info!(kind, "watching resources");
+
+    // `relisting` buffers a relist until it completes.
+    let mut specs: BTreeMap<String, T> = BTreeMap::new();
+    let mut relisting: Option<BTreeMap<String, T>> = None;
+    while let Some(event) = stream.next().await {
+        match event {
+            Ok(Event::Init) => {
+                relisting = Some(BTreeMap::new());
+                continue;
+            }
+            Ok(Event::InitApply(object)) => {
+                if let Some(spec) = parse(kind, &object) {
+                    relisting.get_or_insert_with(BTreeMap::new).insert(key(&object), spec);
+                }
+                continue;
+            }
+            Ok(Event::InitDone) => specs = relisting.take().unwrap_or_default(),
+            Ok(Event::Apply(object)) => match parse(kind, &object) {
+                Some(spec) => {
+                    specs.insert(key(&object), spec);
+                }
+                None => {
+                    specs.remove(&key(&object));
+                }
+            },
+            Ok(Event::Delete(object)) => {
+                specs.remove(&key(&object));
+            }
+            Err(err) => {
+                warn!(kind, error = %err, "resource watch failed");
+                continue;
+            }
+        }
+        update(&specs);
+    }
+}
+
+fn key(object: &DynamicObject) -> String {
+    format!("{}/{}", object.namespace().unwrap_or_default(), object.name_any())
+}
+
+fn parse<T: DeserializeOwned>(kind: &str, object: &DynamicObject) -> Option<T> {
+    let spec = object.data.get("spec")?.clone();
+    match serde_json::from_value(spec) {
+        Ok(spec) => Some(spec),
+        Err(err) => {
+            warn!(kind, resource = %key(object), error = %err, "ignoring invalid resource");
+            None
+        }
+    }
+}
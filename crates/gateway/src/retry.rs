@@ -0,0 +1,141 @@
+//! Retry and hedging policy for tool calls, keyed on each tool's risk class.
+
+use crate::backend::random;
+use crate::parse_env;
+use anyhow::Context;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+const DEFAULT_TOOL_RISK: &str = "demo.echo=read";
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+const DEFAULT_BACKOFF_MS: u64 = 50;
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+/// How much harm repeating a tool call can do, in increasing order. Tools without a configured
+/// class are treated as `write`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Risk {
+    Read,
+    Write,
+    Destructive,
+}
+
+impl FromStr for Risk {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "read" => Ok(Self::Read),
+            "write" => Ok(Self::Write),
+            "destructive" => Ok(Self::Destructive),
+            _ => anyhow::bail!("expected read, write or destructive, got {value}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    risks: HashMap<String, Risk>,
+    /// Attempts per call, including the first.
+    pub max_attempts: u32,
+    /// Backoff before the first retry, doubling with each retry up to one second.
+    pub backoff: Duration,
+    /// A read still unanswered after this long is also sent to a second endpoint.
+    pub hedge_after: Option<Duration>,
+}
+
+impl RetryConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let risks =
+            std::env::var("LATCHKEY_TOOL_RISK").unwrap_or_else(|_| DEFAULT_TOOL_RISK.to_string());
+        let risks = parse_risks(&risks).context("invalid LATCHKEY_TOOL_RISK")?;
+
+        Ok(Self {
+            risks,
+            max_attempts: parse_env("LATCHKEY_RETRY_MAX_ATTEMPTS")?
+                .unwrap_or(DEFAULT_MAX_ATTEMPTS)
+                .max(1),
+            backoff: Duration::from_millis(
+                parse_env("LATCHKEY_RETRY_BACKOFF_MS")?.unwrap_or(DEFAULT_BACKOFF_MS),
+            ),
+            hedge_after: parse_env("LATCHKEY_HEDGE_AFTER_MS")?.map(Duration::from_millis),
+        })
+    }
+
+    pub fn risk(&self, tool: &str) -> Risk {
+        self.risks.get(tool).copied().unwrap_or(Risk::Write)
+    }
+
+    /// Attempts allowed for a call: reads always retry, writes only under an `Idempotency-Key`,
+    /// and destructive calls never.
+    pub fn attempts(&self, risk: Risk, idempotency_key: bool) -> u32 {
+        match (risk, idempotency_key) {
+            (Risk::Read, _) | (Risk::Write, true) => self.max_attempts,
+            _ => 1,
+        }
+    }
+
+    /// Full-jitter backoff before retry number `retry` (from 1).
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let cap =
+            self.backoff.saturating_mul(1 << retry.saturating_sub(1).min(16)).min(MAX_BACKOFF);
+        let millis = u64::try_from(cap.as_millis()).unwrap_or(u64::MAX);
+        Duration::from_millis(random(millis as usize + 1) as u64)
+    }
+}
+
+/// Parses `tool=risk` pairs, e.g. `github.search=read,github.delete_repo=destructive`.
+fn parse_risks(input: &str) -> anyhow::Result<HashMap<String, Risk>> {
+    let mut risks = HashMap::new();
+
+    for pair in input.split(',') {
+        if pair.trim().is_empty() {
+            continue;
+        }
+
+        let (tool, risk) = pair.split_once('=').context("risk entries must be tool=risk")?;
+        risks.insert(tool.trim().to_string(), risk.trim().parse()?);
+    }
+
+    Ok(risks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> RetryConfig {
+        RetryConfig {
+            risks: parse_risks("search=read, create=write,delete=destructive").unwrap(),
+            max_attempts: 3,
+            backoff: Duration::from_millis(100),
+            hedge_after: None,
+        }
+    }
+
+    #[test]
+    fn retries_only_what_is_safe_to_repeat() {
+        let config = config();
+        let attempts = |tool, key| config.attempts(config.risk(tool), key);
+
+        assert_eq!(attempts("search", false), 3);
+        assert_eq!(attempts("create", false), 1);
+        assert_eq!(attempts("create", true), 3);
+        assert_eq!(attempts("delete", true), 1);
+        assert_eq!(attempts("unclassified", false), 1);
+        assert!(parse_risks("search=safe").is_err());
+    }
+
+    #[test]
+    fn backoff_is_jittered_below_a_doubling_cap() {
+        let config = config();
+        for (retry, cap) in [(1, 100), (2, 200), (3, 400), (5, 1000), (40, 1000)] {
+            for _ in 0..32 {
+                assert!(config.backoff(retry) <= Duration::from_millis(cap), "retry {retry}");
+            }
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Client MCP sessions created by `initialize` and bound to the principal that created them.
+
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Debug)]
+pub struct Session {
+    pub id: String,
+    pub principal_id: String,
+    /// The client's `initialize` params, replayed to each tool server replica the session reaches.
+    pub initialize: Value,
+    last_seen: Mutex<Instant>,
+}
+
+impl Session {
+    pub fn new(principal_id: &str, initialize: Value) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            principal_id: principal_id.to_string(),
+            initialize,
+            last_seen: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionError {
+    NotFound,
+    /// The session exists but belongs to another principal.
+    WrongPrincipal,
+}
+
+pub struct Sessions {
+    sessions: Mutex<HashMap<String, Arc<Session>>>,
+    idle_timeout: Duration,
+}
+
+impl Sessions {
+    pub fn new(idle_timeout: Duration) -> Self {
+        Self { sessions: Mutex::new(HashMap::new()), idle_timeout }
+    }
+
+    pub fn create(&self, principal_id: &str, initialize: Value) -> Arc<Session> {
+        let session = Arc::new(Session::new(principal_id, initialize));
+        self.lock().insert(session.id.clone(), session.clone());
+        session
+    }
+
+    /// Looks up a session for `principal_id` and marks it active.
+    pub fn get(&self, id: &str, principal_id: &str) -> Result<Arc<Session>, SessionError> {
+        let session = self.lock().get(id).cloned().ok_or(SessionError::NotFound)?;
+        if session.principal_id != principal_id {
+            return Err(SessionError::WrongPrincipal);
+        }
+        *session.last_seen.lock().expect("session lock poisoned") = Instant::now();
+        Ok(session)
+    }
+
+    pub fn remove(&self, id: &str, principal_id: &str) -> Result<Arc<Session>, SessionError> {
+        let mut sessions = self.lock();
+        match sessions.get(id) {
+            None => Err(SessionError::NotFound),
+            Some(session) if session.principal_id != principal_id => {
+                Err(SessionError::WrongPrincipal)
+            }
+            Some(_) => Ok(sessions.remove(id).expect("session present")),
+        }
+    }
+
+    /// Drops sessions idle for longer than the idle timeout and returns them.
+    pub fn expire(&self) -> Vec<Arc<Session>> {
+        let mut expired = Vec::new();
+        self.lock().retain(|_, session| {
+            let idle = session.last_seen.lock().expect("session lock poisoned").elapsed();
+            if idle < self.idle_timeout {
+                return true;
+            }
+            expired.push(session.clone());
+            false
+        });
+        expired
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Arc<Session>>> {
+        self.sessions.lock().expect("sessions lock poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn binds_sessions_to_their_principal() {
+        let sessions = Sessions::new(Duration::from_secs(60));
+        let session = sessions.create("alice", json!({}));
+
+        assert_eq!(sessions.get(&session.id, "alice").unwrap().id, session.id);
+        assert_eq!(sessions.get(&session.id, "mallory").unwrap_err(), SessionError::WrongPrincipal);
+        assert_eq!(
+            sessions.remove(&session.id, "mallory").unwrap_err(),
+            SessionError::WrongPrincipal
+        );
+        assert!(sessions.remove(&session.id, "alice").is_ok());
+        assert_eq!(sessions.get(&session.id, "alice").unwrap_err(), SessionError::NotFound);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expires_idle_sessions() {
+        let sessions = Sessions::new(Duration::from_secs(60));
+        let idle = sessions.create("alice", json!({}));
+        let active = sessions.create("alice", json!({}));
+
+        tokio::time::advance(Duration::from_secs(45)).await;
+        sessions.get(&active.id, "alice").unwrap();
+        tokio::time::advance(Duration::from_secs(30)).await;
+
+        let expired: Vec<String> = sessions.expire().iter().map(|s| s.id.clone()).collect();
+        assert_eq!(expired, vec![idle.id.clone()]);
+        assert_eq!(sessions.len(), 1);
+    }
+}
@@ -0,0 +1,1069 @@
+//! Where audit events go besides stdout. Each sink drains its own bounded queue on its own task,
+//! so a slow or unreachable sink delays only itself.
+
+use crate::chain::{Chain, Signer};
+use crate::parse_env;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use latchkey_core::audit::{
+    AuditCheckpoint, AuditEvent, AuditRecord, Decision, Outcome, AUDIT_SCHEMA_VERSION,
+};
+use latchkey_core::trace::otlp_value;
+use serde_json::{json, Value};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+const DEFAULT_SINKS: &str = "stdout";
+const DEFAULT_QUEUE_CAPACITY: usize = 10_000;
+const DEFAULT_FILE_PATH: &str = "/var/log/latchkey/audit.jsonl";
+const DEFAULT_FILE_MAX_BYTES: u64 = 100 * 1024 * 1024;
+const DEFAULT_FILE_MAX_FILES: usize = 5;
+const DEFAULT_BATCH_SIZE: usize = 100;
+const DEFAULT_FLUSH_MS: u64 = 1000;
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_SPILL_PATH: &str = "/var/lib/latchkey/audit-spill.jsonl";
+const DEFAULT_SPILL_MAX_BYTES: u64 = 64 * 1024 * 1024;
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+const EXPORT_BACKOFF: Duration = Duration::from_millis(200);
+const MAX_EXPORT_BACKOFF: Duration = Duration::from_secs(5);
+/// Events written at once by sinks that do not batch for the receiver's sake.
+const WRITE_BATCH: usize = 256;
+/// RFC 5424 facility 13, "log audit".
+const SYSLOG_FACILITY: u8 = 13;
+const DEFAULT_CHECKPOINT_EVENTS: u64 = 1000;
+const DEFAULT_CHECKPOINT_SECONDS: u64 = 60;
+
+/// A destination for audit events and checkpoints. Records arrive in batches, in chain order. A
+/// sink handles its own retries; an error loses the batch.
+pub trait AuditSink: Send + 'static {
+    fn name(&self) -> &'static str;
+
+    /// The largest batch to write at once, and how long to wait for one to fill.
+    fn batching(&self) -> (usize, Duration) {
+        (WRITE_BATCH, Duration::ZERO)
+    }
+
+    fn write<'a>(
+        &'a mut self,
+        records: &'a [Arc<AuditRecord>],
+    ) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+/// What to do with an event when a sink's queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Drop the event and count it in `latchkey_audit_events_dropped_total`.
+    Drop,
+    /// Hold up the request until the sink catches up.
+    Block,
+}
+
+impl FromStr for Overflow {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "drop" => Ok(Self::Drop),
+            "block" => Ok(Self::Block),
+            _ => anyhow::bail!("expected drop or block, got {value}"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    written: AtomicU64,
+    dropped: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl Stats {
+    /// Written, dropped and failed, in that order.
+    fn counts(&self) -> [u64; 3] {
+        [&self.written, &self.dropped, &self.failed].map(|count| count.load(Ordering::Relaxed))
+    }
+}
+
+struct Queue {
+    name: &'static str,
+    sender: mpsc::Sender<Arc<AuditRecord>>,
+    stats: Arc<Stats>,
+}
+
+/// A record for the fan-out task, and where to report that every sink has it.
+type Delivery = (Arc<AuditRecord>, oneshot::Sender<()>);
+
+pub struct AuditSinks {
+    stdout: bool,
+    queues: Vec<Queue>,
+    /// With `Overflow::Block`, records pass through one task that waits for room, so callers
+    /// can wait for it without holding the chain lock.
+    fan_out: Option<mpsc::UnboundedSender<Delivery>>,
+    chain: Mutex<Chain>,
+    /// How often to sign the newest event when fewer than a checkpoint's worth arrive.
+    checkpoint_interval: Duration,
+}
+
+impl AuditSinks {
+    /// Starts a task draining each sink's queue, so it must run inside the runtime.
+    pub fn new(
+        stdout: bool,
+        overflow: Overflow,
+        capacity: usize,
+        chain: Chain,
+        checkpoint_interval: Duration,
+        sinks: Vec<Box<dyn AuditSink>>,
+    ) -> Self {
+        let queues: Vec<Queue> = sinks
+            .into_iter()
+            .map(|sink| {
+                let (sender, receiver) = mpsc::channel(capacity.max(1));
+                let stats = Arc::new(Stats::default());
+                let name = sink.name();
+                tokio::spawn(drain(sink, receiver, stats.clone()));
+                Queue { name, sender, stats }
+            })
+            .collect();
+        let fan_out = (overflow == Overflow::Block && !queues.is_empty()).then(|| {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let targets = queues.iter().map(|q| (q.sender.clone(), q.stats.clone())).collect();
+            tokio::spawn(fan_out(receiver, targets));
+            sender
+        });
+        Self { stdout, queues, fan_out, chain: Mutex::new(chain), checkpoint_interval }
+    }
+
+    pub fn from_env() -> anyhow::Result<Self> {
+        let names =
+            std::env::var("LATCHKEY_AUDIT_SINKS").unwrap_or_else(|_| DEFAULT_SINKS.to_string());
+        let overflow = match std::env::var("LATCHKEY_AUDIT_OVERFLOW") {
+            Ok(overflow) => overflow.parse().context("invalid LATCHKEY_AUDIT_OVERFLOW")?,
+            Err(_) => Overflow::Drop,
+        };
+        let capacity =
+            parse_env("LATCHKEY_AUDIT_QUEUE_CAPACITY")?.unwrap_or(DEFAULT_QUEUE_CAPACITY);
+
+        let mut stdout = false;
+        let mut sinks: Vec<Box<dyn AuditSink>> = Vec::new();
+        for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match name {
+                "stdout" => stdout = true,
+                "file" => sinks.push(Box::new(FileSink::from_env()?)),
+                "webhook" => sinks.push(Box::new(WebhookSink::from_env()?)),
+                "syslog" => sinks.push(Box::new(SyslogSink::from_env()?)),
+                "otlp" => sinks.push(Box::new(OtlpSink::from_env()?)),
+                _ => anyhow::bail!("unknown audit sink {name} in LATCHKEY_AUDIT_SINKS"),
+            }
+        }
+        let chain = Chain::new(
+            Signer::from_env()?,
+            parse_env("LATCHKEY_AUDIT_CHECKPOINT_EVENTS")?.unwrap_or(DEFAULT_CHECKPOINT_EVENTS),
+        );
+        let checkpoint_interval = Duration::from_secs(
+            parse_env("LATCHKEY_AUDIT_CHECKPOINT_SECONDS")?.unwrap_or(DEFAULT_CHECKPOINT_SECONDS),
+        );
+        Ok(Self::new(stdout, overflow, capacity, chain, checkpoint_interval, sinks))
+    }
+
+    /// Adds an event to the chain and hands it to every sink, followed by a checkpoint when one
+    /// is due.
+    pub fn send(&self, event: &AuditEvent) {
+        let mut event = event.clone();
+        let delivered = {
+            // The chain stays locked until the event is handed off, so every sink sees chain
+            // order, but never while waiting for room.
+            let mut chain = self.chain.lock().expect("audit chain poisoned");
+            let checkpoint = chain.link(&mut event);
+            if self.stdout {
+                log_event(&event);
+            }
+            let delivered = self.enqueue(AuditRecord::Event(event));
+            checkpoint.and_then(|checkpoint| self.publish_checkpoint(checkpoint)).or(delivered)
+        };
+        if let Some(delivered) = delivered {
+            wait_for_delivery(delivered);
+        }
+    }
+
+    /// Signs the newest event, if no checkpoint covers it yet.
+    pub fn checkpoint(&self) {
+        let mut chain = self.chain.lock().expect("audit chain poisoned");
+        if let Some(checkpoint) = chain.checkpoint() {
+            self.publish_checkpoint(checkpoint);
+        }
+    }
+
+    pub fn checkpoint_interval(&self) -> Duration {
+        self.checkpoint_interval
+    }
+
+    fn publish_checkpoint(&self, checkpoint: AuditCheckpoint) -> Option<oneshot::Receiver<()>> {
+        if self.stdout {
+            log_checkpoint(&checkpoint);
+        }
+        self.enqueue(AuditRecord::Checkpoint(checkpoint))
+    }
+
+    /// Queues a record for every sink. With `Overflow::Block`, returns what to wait on until
+    /// every sink has it.
+    fn enqueue(&self, record: AuditRecord) -> Option<oneshot::Receiver<()>> {
+        if self.queues.is_empty() {
+            return None;
+        }
+        let record = Arc::new(record);
+        if let Some(fan_out) = &self.fan_out {
+            let (done, delivered) = oneshot::channel();
+            return fan_out.send((record, done)).is_ok().then_some(delivered);
+        }
+        for queue in &self.queues {
+            if queue.sender.try_send(record.clone()).is_err() {
+                queue.stats.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        None
+    }
+
+    /// Appends each sink's counters and queue depth in Prometheus text format.
+    pub fn write_metrics(&self, metrics: &mut String) {
+        if self.queues.is_empty() {
+            return;
+        }
+        let counters = [
+            ("written", "Audit events written per sink."),
+            ("dropped", "Audit events dropped because a sink queue was full."),
+            ("failed", "Audit events lost to sink errors."),
+        ];
+        for (index, (result, help)) in counters.into_iter().enumerate() {
+            let _ = writeln!(
+                metrics,
+                "# HELP latchkey_audit_events_{result}_total {help}\n\
+                 # TYPE latchkey_audit_events_{result}_total counter"
+            );
+            for queue in &self.queues {
+                let _ = writeln!(
+                    metrics,
+                    "latchkey_audit_events_{result}_total{{sink=\"{}\"}} {}",
+                    queue.name,
+                    queue.stats.counts()[index]
+                );
+            }
+        }
+        metrics.push_str(
+            "# HELP latchkey_audit_queue_depth Audit events waiting per sink.\n\
+             # TYPE latchkey_audit_queue_depth gauge\n",
+        );
+        for queue in &self.queues {
+            let depth = queue.sender.max_capacity() - queue.sender.capacity();
+            let _ =
+                writeln!(metrics, "latchkey_audit_queue_depth{{sink=\"{}\"}} {depth}", queue.name);
+        }
+    }
+}
+
+/// Holds up the caller until every sink has queued its record. Events are emitted from
+/// synchronous code, so this parks the worker thread. On a single-threaded runtime that would
+/// stall the fan-out task too, so the caller goes on and the record is queued later.
+fn wait_for_delivery(delivered: oneshot::Receiver<()>) {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            let _ = tokio::task::block_in_place(|| handle.block_on(delivered));
+        }
+        _ => {}
+    }
+}
+
+/// Hands records to every sink in the order they arrive, waiting for room in full queues.
+async fn fan_out(
+    mut receiver: mpsc::UnboundedReceiver<Delivery>,
+    queues: Vec<(mpsc::Sender<Arc<AuditRecord>>, Arc<Stats>)>,
+) {
+    while let Some((record, done)) = receiver.recv().await {
+        for (sender, stats) in &queues {
+            if sender.send(record.clone()).await.is_err() {
+                stats.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let _ = done.send(());
+    }
+}
+
+async fn drain(
+    mut sink: Box<dyn AuditSink>,
+    mut receiver: mpsc::Receiver<Arc<AuditRecord>>,
+    stats: Arc<Stats>,
+) {
+    let (max_batch, linger) = sink.batching();
+    let mut batch = Vec::with_capacity(max_batch);
+    while receiver.recv_many(&mut batch, max_batch).await > 0 {
+        let deadline = Instant::now() + linger;
+        while batch.len() < max_batch {
+            let room = max_batch - batch.len();
+            match tokio::time::timeout_at(deadline, receiver.recv_many(&mut batch, room)).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+        }
+
+        let count = batch.len() as u64;
+        match sink.write(&batch).await {
+            Ok(()) => stats.written.fetch_add(count, Ordering::Relaxed),
+            Err(err) => {
+                warn!(sink = sink.name(), events = count, error = %err, "audit sink failed, events lost");
+                stats.failed.fetch_add(count, Ordering::Relaxed)
+            }
+        };
+        batch.clear();
+    }
+}
+
+/// Appends events as JSON lines, rotating to `<path>.1`, `<path>.2`, ... when the file would
+/// pass `max_bytes`.
+pub struct FileSink {
+    path: PathBuf,
+    max_bytes: u64,
+    /// Rotated files kept, besides the one being written.
+    max_files: usize,
+    file: Option<BufWriter<File>>,
+    size: u64,
+}
+
+impl FileSink {
+    fn new(path: PathBuf, max_bytes: u64, max_files: usize) -> Self {
+        Self { path, max_bytes, max_files, file: None, size: 0 }
+    }
+
+    fn from_env() -> anyhow::Result<Self> {
+        let path = std::env::var("LATCHKEY_AUDIT_FILE_PATH")
+            .unwrap_or_else(|_| DEFAULT_FILE_PATH.to_string());
+        Ok(Self::new(
+            path.into(),
+            parse_env("LATCHKEY_AUDIT_FILE_MAX_BYTES")?.unwrap_or(DEFAULT_FILE_MAX_BYTES),
+            parse_env("LATCHKEY_AUDIT_FILE_MAX_FILES")?.unwrap_or(DEFAULT_FILE_MAX_FILES),
+        ))
+    }
+
+    async fn append(&mut self, records: &[Arc<AuditRecord>]) -> anyhow::Result<()> {
+        for record in records {
+            let mut line = serde_json::to_vec(&**record)?;
+            line.push(b'\n');
+            if self.size > 0 && self.size + line.len() as u64 > self.max_bytes {
+                self.rotate().await?;
+            }
+            let file = match &mut self.file {
+                Some(file) => file,
+                None => {
+                    let file = open_append(&self.path).await?;
+                    self.size = file.metadata().await?.len();
+                    self.file.insert(BufWriter::new(file))
+                }
+            };
+            file.write_all(&line).await?;
+            self.size += line.len() as u64;
+        }
+        if let Some(file) = &mut self.file {
+            file.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn rotate(&mut self) -> anyhow::Result<()> {
+        if let Some(mut file) = self.file.take() {
+            file.flush().await?;
+        }
+        self.size = 0;
+        if self.max_files == 0 {
+            return Ok(tokio::fs::remove_file(&self.path).await?);
+        }
+        for index in (1..self.max_files).rev() {
+            match tokio::fs::rename(self.rotated(index), self.rotated(index + 1)).await {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        tokio::fs::rename(&self.path, self.rotated(1)).await?;
+        Ok(())
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{index}"));
+        name.into()
+    }
+}
+
+impl AuditSink for FileSink {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn write<'a>(
+        &'a mut self,
+        records: &'a [Arc<AuditRecord>],
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let result = self.append(records).await;
+            if result.is_err() {
+                // Reopen next time, in case the file was moved or its disk recovered.
+                self.file = None;
+            }
+            result.with_context(|| format!("failed to write {}", self.path.display()))
+        })
+    }
+}
+
+/// Posts batches as a JSON array. Batches that still fail after retries are spilled to disk and
+/// sent again, oldest first, once the receiver answers.
+pub struct WebhookSink {
+    client: reqwest::Client,
+    url: String,
+    batch_size: usize,
+    flush: Duration,
+    max_attempts: u32,
+    spill_path: PathBuf,
+    spill_max_bytes: u64,
+    /// Whether the spill file may hold events. Assumed at startup, for spills left by a previous run.
+    spilled: bool,
+}
+
+impl WebhookSink {
+    fn from_env() -> anyhow::Result<Self> {
+        let url = std::env::var("LATCHKEY_AUDIT_WEBHOOK_URL")
+            .context("the webhook audit sink needs LATCHKEY_AUDIT_WEBHOOK_URL")?;
+        let spill_path = std::env::var("LATCHKEY_AUDIT_WEBHOOK_SPILL_PATH")
+            .unwrap_or_else(|_| DEFAULT_SPILL_PATH.to_string());
+        Ok(Self {
+            client: export_client()?,
+            url,
+            batch_size: parse_env("LATCHKEY_AUDIT_WEBHOOK_BATCH_SIZE")?
+                .unwrap_or(DEFAULT_BATCH_SIZE)
+                .max(1),
+            flush: Duration::from_millis(
+                parse_env("LATCHKEY_AUDIT_WEBHOOK_FLUSH_MS")?.unwrap_or(DEFAULT_FLUSH_MS),
+            ),
+            max_attempts: parse_env("LATCHKEY_AUDIT_WEBHOOK_MAX_ATTEMPTS")?
+                .unwrap_or(DEFAULT_MAX_ATTEMPTS)
+                .max(1),
+            spill_path: spill_path.into(),
+            spill_max_bytes: parse_env("LATCHKEY_AUDIT_WEBHOOK_SPILL_MAX_BYTES")?
+                .unwrap_or(DEFAULT_SPILL_MAX_BYTES),
+            spilled: true,
+        })
+    }
+
+    async fn post(&self, lines: &[String]) -> Result<(), reqwest::Error> {
+        let body = format!("[{}]", lines.join(","));
+        self.client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn deliver(&mut self, lines: Vec<String>) -> anyhow::Result<()> {
+        // While older events wait on disk, new ones queue up behind them.
+        if self.spilled && self.replay().await.is_err() {
+            return self.spill(&lines).await;
+        }
+
+        let mut backoff = EXPORT_BACKOFF;
+        for attempt in 1.. {
+            match self.post(&lines).await {
+                Ok(()) => break,
+                Err(err) if rejected(&err) => return Err(err.into()),
+                Err(_) if attempt < self.max_attempts => {}
+                Err(_) => return self.spill(&lines).await,
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_EXPORT_BACKOFF);
+        }
+        Ok(())
+    }
+
+    /// Sends spilled events, one try per batch. Whatever is not delivered stays on disk.
+    async fn replay(&mut self) -> anyhow::Result<()> {
+        let spilled = match tokio::fs::read_to_string(&self.spill_path).await {
+            Ok(spilled) => spilled,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                self.spilled = false;
+                return Ok(());
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let lines: Vec<String> =
+            spilled.lines().filter(|line| !line.is_empty()).map(str::to_string).collect();
+
+        for (index, batch) in lines.chunks(self.batch_size).enumerate() {
+            match self.post(batch).await {
+                Ok(()) => {}
+                Err(err) if rejected(&err) => {
+                    warn!(events = batch.len(), error = %err, "audit webhook rejected spilled events, dropping them");
+                }
+                Err(err) => {
+                    if index > 0 {
+                        let rest = &lines[index * self.batch_size..];
+                        tokio::fs::write(&self.spill_path, rest.join("\n") + "\n").await?;
+                    }
+                    return Err(err.into());
+                }
+            }
+        }
+        tokio::fs::remove_file(&self.spill_path).await?;
+        self.spilled = false;
+        info!(events = lines.len(), "replayed spilled audit events");
+        Ok(())
+    }
+
+    async fn spill(&mut self, lines: &[String]) -> anyhow::Result<()> {
+        let size = tokio::fs::metadata(&self.spill_path).await.map_or(0, |spill| spill.len());
+        let bytes: u64 = lines.iter().map(|line| line.len() as u64 + 1).sum();
+        anyhow::ensure!(
+            size + bytes <= self.spill_max_bytes,
+            "webhook unreachable and spill file {} is full",
+            self.spill_path.display()
+        );
+
+        let mut file = open_append(&self.spill_path).await?;
+        file.write_all((lines.join("\n") + "\n").as_bytes()).await?;
+        file.flush().await?;
+        self.spilled = true;
+        warn!(events = lines.len(), path = %self.spill_path.display(), "audit webhook unreachable, spilled events to disk");
+        Ok(())
+    }
+}
+
+impl AuditSink for WebhookSink {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn batching(&self) -> (usize, Duration) {
+        (self.batch_size, self.flush)
+    }
+
+    fn write<'a>(
+        &'a mut self,
+        records: &'a [Arc<AuditRecord>],
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let lines = records
+                .iter()
+                .map(|record| serde_json::to_string(&**record))
+                .collect::<Result<_, _>>()?;
+            self.deliver(lines).await
+        })
+    }
+}
+
+/// Whether the receiver refused the request itself, so sending it again cannot help.
+fn rejected(err: &reqwest::Error) -> bool {
+    err.status().is_some_and(|status| {
+        status.is_client_error()
+            && status != reqwest::StatusCode::REQUEST_TIMEOUT
+            && status != reqwest::StatusCode::TOO_MANY_REQUESTS
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Protocol {
+    Udp,
+    Tcp,
+}
+
+impl FromStr for Protocol {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "udp" => Ok(Self::Udp),
+            "tcp" => Ok(Self::Tcp),
+            _ => anyhow::bail!("expected udp or tcp, got {value}"),
+        }
+    }
+}
+
+/// Sends each record as an RFC 5424 message with its JSON as the body: one datagram per record
+/// over UDP, or octet-counted frames (RFC 6587) over TCP.
+pub struct SyslogSink {
+    address: String,
+    protocol: Protocol,
+    hostname: String,
+    udp: Option<UdpSocket>,
+    tcp: Option<TcpStream>,
+}
+
+impl SyslogSink {
+    fn from_env() -> anyhow::Result<Self> {
+        let address = std::env::var("LATCHKEY_AUDIT_SYSLOG_ADDRESS")
+            .context("the syslog audit sink needs LATCHKEY_AUDIT_SYSLOG_ADDRESS")?;
+        let protocol = match std::env::var("LATCHKEY_AUDIT_SYSLOG_PROTOCOL") {
+            Ok(protocol) => protocol.parse().context("invalid LATCHKEY_AUDIT_SYSLOG_PROTOCOL")?,
+            Err(_) => Protocol::Udp,
+        };
+        let hostname = std::env::var("HOSTNAME").unwrap_or_else(|_| "-".to_string());
+        Ok(Self { address, protocol, hostname, udp: None, tcp: None })
+    }
+
+    async fn send(&mut self, records: &[Arc<AuditRecord>]) -> anyhow::Result<()> {
+        let messages = records
+            .iter()
+            .map(|record| syslog_message(record, &self.hostname, std::process::id()))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        match self.protocol {
+            Protocol::Udp => {
+                let socket = match &mut self.udp {
+                    Some(socket) => socket,
+                    None => {
+                        let address = tokio::net::lookup_host(&self.address)
+                            .await?
+                            .next()
+                            .context("syslog address did not resolve")?;
+                        let local = if address.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+                        let socket = UdpSocket::bind(local).await?;
+                        socket.connect(address).await?;
+                        self.udp.insert(socket)
+                    }
+                };
+                for message in messages {
+                    socket.send(message.as_bytes()).await?;
+                }
+            }
+            Protocol::Tcp => {
+                let stream = match &mut self.tcp {
+                    Some(stream) => stream,
+                    None => self.tcp.insert(TcpStream::connect(&self.address).await?),
+                };
+                let mut frames = String::new();
+                for message in messages {
+                    let _ = write!(frames, "{} {message}", message.len());
+                }
+                stream.write_all(frames.as_bytes()).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AuditSink for SyslogSink {
+    fn name(&self) -> &'static str {
+        "syslog"
+    }
+
+    fn write<'a>(
+        &'a mut self,
+        records: &'a [Arc<AuditRecord>],
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let result = self.send(records).await;
+            if result.is_err() {
+                // Reconnect next time.
+                self.udp = None;
+                self.tcp = None;
+            }
+            result.with_context(|| format!("failed to send to syslog at {}", self.address))
+        })
+    }
+}
+
+/// Whether a record is a denial or failure, which sinks with severities log one level up.
+fn notable(record: &AuditRecord) -> bool {
+    match record {
+        AuditRecord::Event(event) => {
+            event.decision == Decision::Deny || event.status == Outcome::Error
+        }
+        AuditRecord::Checkpoint(_) => false,
+    }
+}
+
+fn timestamp(record: &AuditRecord) -> &str {
+    match record {
+        AuditRecord::Event(event) => &event.timestamp,
+        AuditRecord::Checkpoint(checkpoint) => &checkpoint.timestamp,
+    }
+}
+
+fn syslog_message(record: &AuditRecord, hostname: &str, pid: u32) -> anyhow::Result<String> {
+    // Notice for denials and failures, informational otherwise.
+    let severity = if notable(record) { 5 } else { 6 };
+    let message_id = match record {
+        AuditRecord::Event(_) => "audit",
+        AuditRecord::Checkpoint(_) => "checkpoint",
+    };
+    Ok(format!(
+        "<{}>1 {} {hostname} latchkey-gateway {pid} {message_id} - {}",
+        SYSLOG_FACILITY * 8 + severity,
+        timestamp(record),
+        serde_json::to_string(record)?
+    ))
+}
+
+/// Exports events as OpenTelemetry log records, over OTLP/HTTP with JSON encoding.
+pub struct OtlpSink {
+    client: reqwest::Client,
+    endpoint: String,
+    max_attempts: u32,
+}
+
+impl OtlpSink {
+    /// `LATCHKEY_AUDIT_OTLP_ENDPOINT`, or else the standard `OTEL_EXPORTER_OTLP_ENDPOINT` with
+    /// `/v1/logs` appended.
+    fn from_env() -> anyhow::Result<Self> {
+        let endpoint = match std::env::var("LATCHKEY_AUDIT_OTLP_ENDPOINT") {
+            Ok(endpoint) => endpoint,
+            Err(_) => std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+                .map(|base| format!("{}/v1/logs", base.trim_end_matches('/')))
+                .context(
+                    "the otlp audit sink needs LATCHKEY_AUDIT_OTLP_ENDPOINT or \
+                     OTEL_EXPORTER_OTLP_ENDPOINT",
+                )?,
+        };
+        Ok(Self {
+            client: export_client()?,
+            endpoint,
+            max_attempts: parse_env("LATCHKEY_AUDIT_OTLP_MAX_ATTEMPTS")?
+                .unwrap_or(DEFAULT_MAX_ATTEMPTS)
+                .max(1),
+        })
+    }
+
+    async fn export(&self, body: &Value) -> Result<(), reqwest::Error> {
+        self.client.post(&self.endpoint).json(body).send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+impl AuditSink for OtlpSink {
+    fn name(&self) -> &'static str {
+        "otlp"
+    }
+
+    fn batching(&self) -> (usize, Duration) {
+        (512, Duration::from_millis(DEFAULT_FLUSH_MS))
+    }
+
+    fn write<'a>(
+        &'a mut self,
+        records: &'a [Arc<AuditRecord>],
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let body = otlp_logs(records, Utc::now());
+            let mut backoff = EXPORT_BACKOFF;
+            for attempt in 1.. {
+                match self.export(&body).await {
+                    Ok(()) => break,
+                    Err(err) if rejected(&err) || attempt >= self.max_attempts => {
+                        return Err(err).context("OTLP log export failed");
+                    }
+                    Err(_) => {}
+                }
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_EXPORT_BACKOFF);
+            }
+            Ok(())
+        })
+    }
+}
+
+/// An `ExportLogsServiceRequest` with one log record per event or checkpoint, its fields as
+/// attributes.
+fn otlp_logs(records: &[Arc<AuditRecord>], observed: DateTime<Utc>) -> Value {
+    let observed = observed.timestamp_nanos_opt().unwrap_or_default().to_string();
+    let records: Vec<Value> = records
+        .iter()
+        .map(|record| {
+            let time = DateTime::parse_from_rfc3339(timestamp(record))
+                .ok()
+                .and_then(|time| time.timestamp_nanos_opt())
+                .unwrap_or_default();
+            let attributes: Vec<Value> = match serde_json::to_value(&**record) {
+                Ok(Value::Object(fields)) => fields
+                    .into_iter()
+                    .filter_map(|(key, value)| {
+                        Some(json!({"key": key, "value": otlp_value(value)?}))
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            };
+            let (severity_number, severity_text) =
+                if notable(record) { (13, "WARN") } else { (9, "INFO") };
+            let body = match **record {
+                AuditRecord::Event(_) => "mcp decision",
+                AuditRecord::Checkpoint(_) => "audit checkpoint",
+            };
+            json!({
+                "timeUnixNano": time.to_string(),
+                "observedTimeUnixNano": observed,
+                "severityNumber": severity_number,
+                "severityText": severity_text,
+                "body": {"stringValue": body},
+                "attributes": attributes,
+            })
+        })
+        .collect();
+
+    json!({
+        "resourceLogs": [{
+            "resource": {
+                "attributes": [
+                    {"key": "service.name", "value": {"stringValue": "latchkey-gateway"}},
+                ],
+            },
+            "scopeLogs": [{
+                "scope": {"name": "latchkey.audit", "version": AUDIT_SCHEMA_VERSION.to_string()},
+                "logRecords": records,
+            }],
+        }],
+    })
+}
+
+fn log_event(event: &AuditEvent) {
+    let attempts = serde_json::to_string(&event.attempts).unwrap_or_default();
+    let params = event.request_params_redacted.as_ref().map(Value::to_string);
+    let summary = event.response_summary.as_ref().and_then(|s| serde_json::to_string(s).ok());
+    info!(
+        event_type = "audit",
+        schema_version = event.schema_version,
+        timestamp = %event.timestamp,
+        request_id = %event.request_id,
+        principal_id = event.principal_id.as_deref(),
+        auth_mode = event.auth_mode.map(|mode| mode.as_str()),
+        token_jti = event.token_jti.as_deref(),
+        method = %event.method,
+        tool_name = event.tool_name.as_deref(),
+        operation = event.operation.as_deref(),
+        session_id = event.session_id.as_deref(),
+        trace_id = event.trace_id.as_deref(),
+        span_id = event.span_id.as_deref(),
+        decision = event.decision.as_str(),
+        deny_reason = event.deny_reason.as_deref(),
+        status = event.status.as_str(),
+        http_status = event.http_status,
+        error_class = event.error_class.map(|class| class.as_str()),
+        latency_ms = event.latency_ms,
+        backend_server = event.backend_server.as_deref(),
+        backend_latency_ms = event.backend_latency_ms,
+        attempts,
+        request_params_redacted = params,
+        request_params_hash = event.request_params_hash.as_deref(),
+        request_params_bytes = event.request_params_bytes,
+        response_summary = summary,
+        chain_id = event.chain_id.as_deref(),
+        sequence = event.sequence,
+        prev_hash = event.prev_hash.as_deref(),
+        "mcp decision"
+    );
+}
+
+fn log_checkpoint(checkpoint: &AuditCheckpoint) {
+    info!(
+        event_type = "audit_checkpoint",
+        schema_version = checkpoint.schema_version,
+        timestamp = %checkpoint.timestamp,
+        chain_id = %checkpoint.chain_id,
+        sequence = checkpoint.sequence,
+        hash = %checkpoint.hash,
+        key_id = %checkpoint.key_id,
+        signature = %checkpoint.signature,
+        "audit checkpoint"
+    );
+}
+
+fn export_client() -> anyhow::Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .timeout(EXPORT_TIMEOUT)
+        .build()
+        .context("failed to construct audit export client")
+}
+
+async fn open_append(path: &Path) -> anyhow::Result<File> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    Ok(OpenOptions::new().create(true).append(true).open(path).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::tests::chain;
+
+    fn event(request_id: &str) -> AuditEvent {
+        AuditEvent::new(
+            request_id.to_string(),
+            "2026-01-15T17:45:00.000Z".to_string(),
+            "tools/call".to_string(),
+        )
+    }
+
+    fn record(request_id: &str) -> Arc<AuditRecord> {
+        Arc::new(AuditRecord::Event(event(request_id)))
+    }
+
+    /// Never finishes a write, so its queue only fills.
+    struct Stuck;
+
+    impl AuditSink for Stuck {
+        fn name(&self) -> &'static str {
+            "stuck"
+        }
+
+        fn write<'a>(&'a mut self, _: &'a [Arc<AuditRecord>]) -> BoxFuture<'a, anyhow::Result<()>> {
+            Box::pin(futures::future::pending())
+        }
+    }
+
+    #[tokio::test]
+    async fn drops_and_counts_events_past_a_full_queue() {
+        let sinks = AuditSinks::new(
+            false,
+            Overflow::Drop,
+            1,
+            chain(1000).0,
+            Duration::from_secs(60),
+            vec![Box::new(Stuck)],
+        );
+        for id in ["a", "b", "c"] {
+            sinks.send(&event(id));
+        }
+
+        let mut metrics = String::new();
+        sinks.write_metrics(&mut metrics);
+        assert!(metrics.contains("latchkey_audit_events_dropped_total{sink=\"stuck\"} 2\n"));
+        assert!(metrics.contains("latchkey_audit_queue_depth{sink=\"stuck\"} 1\n"));
+    }
+
+    /// Holds each single-record write until a permit is added, then records its sequence number.
+    struct Gated {
+        gate: Arc<tokio::sync::Semaphore>,
+        written: Arc<Mutex<Vec<u64>>>,
+    }
+
+    impl AuditSink for Gated {
+        fn name(&self) -> &'static str {
+            "gated"
+        }
+
+        fn batching(&self) -> (usize, Duration) {
+            (1, Duration::ZERO)
+        }
+
+        fn write<'a>(
+            &'a mut self,
+            records: &'a [Arc<AuditRecord>],
+        ) -> BoxFuture<'a, anyhow::Result<()>> {
+            Box::pin(async move {
+                self.gate.acquire().await?.forget();
+                let mut written = self.written.lock().unwrap();
+                written.extend(records.iter().filter_map(|record| match &**record {
+                    AuditRecord::Event(event) => event.sequence,
+                    AuditRecord::Checkpoint(_) => None,
+                }));
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn blocks_on_a_stalled_sink_without_holding_the_chain() {
+        let gate = Arc::new(tokio::sync::Semaphore::new(0));
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let sinks = Arc::new(AuditSinks::new(
+            false,
+            Overflow::Block,
+            1,
+            chain(1000).0,
+            Duration::from_secs(60),
+            vec![Box::new(Gated { gate: gate.clone(), written: written.clone() })],
+        ));
+        let senders: Vec<_> = ["a", "b", "c", "d"]
+            .into_iter()
+            .map(|id| {
+                let sinks = sinks.clone();
+                tokio::spawn(async move { sinks.send(&event(id)) })
+            })
+            .collect();
+
+        // The sink holds one event and its queue one more, so the other senders wait.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(senders.iter().any(|sender| !sender.is_finished()));
+        assert!(sinks.chain.try_lock().is_ok());
+
+        gate.add_permits(16);
+        for sender in senders {
+            tokio::time::timeout(Duration::from_secs(5), sender).await.unwrap().unwrap();
+        }
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while written.lock().unwrap().len() < 4 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(*written.lock().unwrap(), vec![0, 1, 2, 3]);
+
+        let mut metrics = String::new();
+        sinks.write_metrics(&mut metrics);
+        assert!(metrics.contains("latchkey_audit_events_dropped_total{sink=\"gated\"} 0\n"));
+    }
+
+    #[tokio::test]
+    async fn rotates_the_audit_file() {
+        let dir = std::env::temp_dir().join(format!("latchkey-audit-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("audit.jsonl");
+        let line = serde_json::to_vec(&*record("a")).unwrap().len() as u64 + 1;
+        let mut sink = FileSink::new(path.clone(), line * 2, 1);
+
+        sink.write(&[record("a"), record("b"), record("c")]).await.unwrap();
+        sink.write(&[record("d"), record("e")]).await.unwrap();
+
+        let read = |path: &Path| std::fs::read_to_string(path).unwrap().lines().count();
+        assert_eq!(read(&path), 1);
+        assert_eq!(read(&sink.rotated(1)), 2);
+        assert!(!sink.rotated(2).exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn formats_rfc5424_messages() {
+        let mut denied = event("r");
+        denied.decision = Decision::Deny;
+
+        let allowed = syslog_message(&record("r"), "gateway-0", 7).unwrap();
+        assert!(allowed
+            .starts_with("<110>1 2026-01-15T17:45:00.000Z gateway-0 latchkey-gateway 7 audit - {"));
+        assert!(syslog_message(&AuditRecord::Event(denied), "-", 7)
+            .unwrap()
+            .starts_with("<109>1 "));
+    }
+
+    #[test]
+    fn exports_events_as_otlp_log_records() {
+        let logs = otlp_logs(&[record("r")], Utc::now());
+        let record = &logs["resourceLogs"][0]["scopeLogs"][0]["logRecords"][0];
+        assert_eq!(record["timeUnixNano"], "1768499100000000000");
+        assert_eq!(record["severityText"], "INFO");
+
+        let attributes = record["attributes"].as_array().unwrap();
+        let attribute =
+            |key: &str| attributes.iter().find(|a| a["key"] == key).map(|a| &a["value"]);
+        assert_eq!(attribute("request_id"), Some(&json!({"stringValue": "r"})));
+        assert_eq!(attribute("http_status"), Some(&json!({"intValue": "200"})));
+        assert_eq!(attribute("attempts"), Some(&json!({"stringValue": "[]"})));
+        assert_eq!(attribute("principal_id"), None);
+    }
+}
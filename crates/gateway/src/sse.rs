@@ -0,0 +1,116 @@
+//! Incremental `text/event-stream` parsing, so backend streams can be relayed event by event.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SseEvent {
+    pub event: Option<String>,
+    pub id: Option<String>,
+    pub data: String,
+    /// Bytes the event took, line endings normalized to LF, for accounting.
+    pub size: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventTooLarge;
+
+/// Buffers partial events across chunks. An event, complete or not, may not exceed
+/// `max_event_bytes`.
+#[derive(Debug)]
+pub struct SseParser {
+    buf: Vec<u8>,
+    max_event_bytes: usize,
+}
+
+impl SseParser {
+    pub fn new(max_event_bytes: usize) -> Self {
+        Self { buf: Vec::new(), max_event_bytes }
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<SseEvent>, EventTooLarge> {
+        // Field values cannot contain CR, so dropping it folds CRLF line endings into LF.
+        self.buf.extend(chunk.iter().filter(|byte| **byte != b'\r'));
+
+        let mut events = Vec::new();
+        let mut start = 0;
+        while let Some(end) = find(&self.buf[start..], b"\n\n") {
+            let raw = &self.buf[start..start + end];
+            if raw.len() > self.max_event_bytes {
+                return Err(EventTooLarge);
+            }
+            if let Some(event) = parse(raw) {
+                events.push(event);
+            }
+            start += end + 2;
+        }
+        self.buf.drain(..start);
+
+        if self.buf.len() > self.max_event_bytes {
+            return Err(EventTooLarge);
+        }
+        Ok(events)
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Comment-only events (keep-alives) and events without `data` yield `None`.
+fn parse(raw: &[u8]) -> Option<SseEvent> {
+    let text = String::from_utf8_lossy(raw);
+    let mut event = None;
+    let mut id = None;
+    let mut data: Vec<&str> = Vec::new();
+
+    for line in text.split('\n') {
+        if line.is_empty() || line.starts_with(':') {
+            continue;
+        }
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "data" => data.push(value),
+            "event" => event = Some(value.to_string()),
+            "id" => id = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    if data.is_empty() {
+        return None;
+    }
+    Some(SseEvent { event, id, data: data.join("\n"), size: raw.len() + 2 })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_events_split_across_chunks() {
+        let mut parser = SseParser::new(1024);
+
+        assert_eq!(parser.push(b"event: message\r\nid: 7\r\ndata: {\"a\"").unwrap(), vec![]);
+        let events = parser.push(b":1}\r\n\r\n: keep-alive\n\ndata: x\ndata: y\n\n").unwrap();
+
+        assert_eq!(
+            events,
+            vec![
+                SseEvent {
+                    event: Some("message".to_string()),
+                    id: Some("7".to_string()),
+                    data: "{\"a\":1}".to_string(),
+                    size: 36,
+                },
+                SseEvent { event: None, id: None, data: "x\ny".to_string(), size: 17 },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_oversized_events_before_they_complete() {
+        let mut parser = SseParser::new(16);
+
+        assert_eq!(parser.push(b"data: 0123456789"), Ok(vec![]));
+        assert_eq!(parser.push(b"abcdef"), Err(EventTooLarge));
+    }
+}
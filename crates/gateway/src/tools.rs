@@ -0,0 +1,312 @@
+//! Per-tool limits, risk classes and audit redaction rules, from `LatchkeyTool` resources when the
+//! Kubernetes API is reachable and from defaults otherwise.
+
+use crate::parse_env;
+use crate::redact::RedactionRule;
+use crate::resources;
+use crate::retry::Risk;
+use latchkey_core::redaction::RedactionAction;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::warn;
+
+const DEFAULT_MAX_PAYLOAD_BYTES: usize = 64 * 1024;
+/// No request body is read past this, whatever a tool allows.
+const MAX_PAYLOAD_BYTES: usize = 4 * 1024 * 1024;
+const DEFAULT_MAX_RESPONSE_BYTES: usize = 4 * 1024 * 1024;
+const DEFAULT_TIMEOUT_MS: u64 = 5_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ToolLimits {
+    /// Largest `tools/call` request body.
+    pub max_payload_bytes: usize,
+    /// Largest reply, counted as it arrives, so an oversized reply is cut off rather than
+    /// buffered.
+    pub max_response_bytes: usize,
+    /// Deadline for the whole call, retries and streamed replies included.
+    pub timeout: Duration,
+}
+
+impl Default for ToolLimits {
+    fn default() -> Self {
+        Self {
+            max_payload_bytes: DEFAULT_MAX_PAYLOAD_BYTES,
+            max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+            timeout: Duration::from_millis(DEFAULT_TIMEOUT_MS),
+        }
+    }
+}
+
+impl ToolLimits {
+    /// Limits for tools without a `LatchkeyTool`, and for fields one leaves unset.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let defaults = Self::default();
+        Ok(Self {
+            max_payload_bytes: parse_env("LATCHKEY_TOOL_MAX_PAYLOAD_BYTES")?
+                .unwrap_or(defaults.max_payload_bytes),
+            max_response_bytes: parse_env("LATCHKEY_TOOL_MAX_RESPONSE_BYTES")?
+                .unwrap_or(defaults.max_response_bytes),
+            timeout: parse_env("LATCHKEY_TOOL_TIMEOUT_MS")?
+                .map_or(defaults.timeout, Duration::from_millis),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ToolPolicy {
+    limits: ToolLimits,
+    /// The riskiest of the tool's operations, if any declares one.
+    risk: Option<Risk>,
+    operations: Vec<String>,
+    /// Requests per minute across all principals.
+    rate_limit: Option<u32>,
+    redaction_rules: Arc<[RedactionRule]>,
+}
+
+/// The fields of a `LatchkeyTool` spec the gateway enforces.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ToolSpec {
+    tool_name: String,
+    #[serde(default)]
+    operations: Option<Vec<Operation>>,
+    #[serde(default)]
+    limits: Option<Limits>,
+    #[serde(default)]
+    audit: Option<Audit>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Operation {
+    op_name: String,
+    #[serde(default)]
+    risk: Option<Risk>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Limits {
+    max_payload_bytes: Option<u64>,
+    max_response_bytes: Option<u64>,
+    timeout_ms: Option<u64>,
+    rate_limit: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Audit {
+    #[serde(default)]
+    redaction_rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Rule {
+    path: String,
+    action: RedactionAction,
+}
+
+pub struct Tools {
+    defaults: ToolLimits,
+    policies: RwLock<HashMap<String, ToolPolicy>>,
+}
+
+impl Tools {
+    pub fn new(defaults: ToolLimits) -> Self {
+        Self { defaults, policies: RwLock::new(HashMap::new()) }
+    }
+
+    pub fn defaults(&self) -> ToolLimits {
+        self.defaults
+    }
+
+    /// The most any request may need to send: the largest payload cap of any tool.
+    pub fn max_payload_bytes(&self) -> usize {
+        let largest = self.read().values().map(|policy| policy.limits.max_payload_bytes).max();
+        largest.unwrap_or(0).max(self.defaults.max_payload_bytes).min(MAX_PAYLOAD_BYTES)
+    }
+
+    pub fn limits(&self, tool: &str) -> ToolLimits {
+        self.read().get(tool).map_or(self.defaults, |policy| policy.limits)
+    }
+
+    /// The risk class a `LatchkeyTool` declares for `tool`, if any.
+    pub fn risk(&self, tool: &str) -> Option<Risk> {
+        self.read().get(tool).and_then(|policy| policy.risk)
+    }
+
+    /// The declared operation a call to `tool` invokes: the one its `arguments.operation` names,
+    /// or the only one when the tool declares a single operation.
+    pub fn operation(&self, tool: &str, arguments: Option<&Value>) -> Option<String> {
+        let policies = self.read();
+        let operations = &policies.get(tool)?.operations;
+        match (&operations[..], arguments.and_then(|args| args.get("operation"))) {
+            ([only], _) => Some(only.clone()),
+            (_, Some(Value::String(named))) => operations.iter().find(|op| *op == named).cloned(),
+            _ => None,
+        }
+    }
+
+    /// The `rateLimit` a `LatchkeyTool` sets for `tool`, shared by every principal.
+    pub fn rate_limit(&self, tool: &str) -> Option<u32> {
+        self.read().get(tool).and_then(|policy| policy.rate_limit)
+    }
+
+    /// Whether a `LatchkeyTool` declares `tool`.
+    pub fn is_declared(&self, tool: &str) -> bool {
+        self.read().contains_key(tool)
+    }
+
+    /// The `audit.redactionRules` a `LatchkeyTool` sets for `tool`, in order.
+    pub fn redaction_rules(&self, tool: &str) -> Arc<[RedactionRule]> {
+        self.read()
+            .get(tool)
+            .map_or_else(|| Arc::from(Vec::new()), |policy| policy.redaction_rules.clone())
+    }
+
+    /// Replaces every policy from the current specs, keyed by resource. When two resources name
+    /// the same tool, the first by namespace and name wins.
+    fn update(&self, specs: &BTreeMap<String, ToolSpec>) {
+        let mut policies = HashMap::new();
+        for (key, spec) in specs {
+            if policies.contains_key(&spec.tool_name) {
+                warn!(tool_name = %spec.tool_name, resource = %key, "duplicate LatchkeyTool");
+                continue;
+            }
+            policies.insert(spec.tool_name.clone(), self.policy(spec));
+        }
+        *self.policies.write().expect("tools lock poisoned") = policies;
+    }
+
+    fn policy(&self, spec: &ToolSpec) -> ToolPolicy {
+        let limits = spec.limits.clone().unwrap_or_default();
+        let bytes = |value: Option<u64>, default| {
+            value.map_or(default, |value| usize::try_from(value).unwrap_or(usize::MAX))
+        };
+        ToolPolicy {
+            limits: ToolLimits {
+                max_payload_bytes: bytes(limits.max_payload_bytes, self.defaults.max_payload_bytes),
+                max_response_bytes: bytes(
+                    limits.max_response_bytes,
+                    self.defaults.max_response_bytes,
+                ),
+                timeout: limits.timeout_ms.map_or(self.defaults.timeout, Duration::from_millis),
+            },
+            risk: spec.operations.iter().flatten().filter_map(|operation| operation.risk).max(),
+            operations: spec.operations.iter().flatten().map(|op| op.op_name.clone()).collect(),
+            rate_limit: limits.rate_limit,
+            redaction_rules: spec
+                .audit
+                .iter()
+                .flat_map(|audit| &audit.redaction_rules)
+                .filter_map(|rule| match rule.path.parse() {
+                    Ok(path) => Some(RedactionRule { path, action: rule.action }),
+                    Err(err) => {
+                        // The operator's webhook refuses these, so only old resources have any.
+                        warn!(tool_name = %spec.tool_name, error = %err, "skipping redaction rule");
+                        None
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashMap<String, ToolPolicy>> {
+        self.policies.read().expect("tools lock poisoned")
+    }
+}
+
+/// Follows every `LatchkeyTool` in the cluster. Without a Kubernetes API, every tool keeps the
+/// default limits.
+pub async fn watch(tools: Arc<Tools>) {
+    resources::watch("LatchkeyTool", "latchkeytools", |specs| tools.update(specs)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn overrides_defaults_per_tool() {
+        let defaults = ToolLimits {
+            max_payload_bytes: 1024,
+            max_response_bytes: 4096,
+            timeout: Duration::from_secs(5),
+        };
+        let tools = Tools::new(defaults);
+        let spec = |value| serde_json::from_value::<ToolSpec>(value).unwrap();
+        let specs = BTreeMap::from([
+            (
+                "a/search".to_string(),
+                spec(json!({
+                    "toolName": "github.search",
+                    "serverRef": "github",
+                    "operations": [
+                        { "opName": "list", "risk": "read" },
+                        { "opName": "star", "risk": "write" },
+                        { "opName": "get" },
+                    ],
+                    "limits": { "maxResponseBytes": 65536, "timeoutMs": 250, "rateLimit": 30 },
+                    "audit": {
+                        "redactionRules": [
+                            { "path": "/params/arguments/token", "action": "hash" },
+                            { "path": "params", "action": "drop" },
+                        ],
+                    },
+                })),
+            ),
+            (
+                "b/search".to_string(),
+                spec(json!({ "toolName": "github.search", "serverRef": "other" })),
+            ),
+        ]);
+        tools.update(&specs);
+
+        assert_eq!(
+            tools.limits("github.search"),
+            ToolLimits {
+                max_payload_bytes: 1024,
+                max_response_bytes: 65536,
+                timeout: Duration::from_millis(250),
+            }
+        );
+        assert_eq!(tools.risk("github.search"), Some(Risk::Write));
+        assert_eq!(tools.rate_limit("github.search"), Some(30));
+        let rules = tools.redaction_rules("github.search");
+        assert_eq!(rules.len(), 1, "the rule without a field below params is skipped");
+        assert_eq!(rules[0].action, RedactionAction::Hash);
+        let operation = |arguments| tools.operation("github.search", Some(&arguments));
+        assert_eq!(operation(json!({ "operation": "star" })).as_deref(), Some("star"));
+        assert_eq!(operation(json!({ "operation": "fork" })), None);
+        assert_eq!(operation(json!({ "query": "latchkey" })), None);
+        assert_eq!(tools.limits("demo.echo"), defaults);
+        assert_eq!(tools.operation("demo.echo", None), None);
+        assert_eq!(tools.risk("demo.echo"), None);
+        assert!(tools.redaction_rules("demo.echo").is_empty());
+    }
+
+    #[test]
+    fn reads_bodies_up_to_the_largest_tool_cap() {
+        let defaults = ToolLimits {
+            max_payload_bytes: 1024,
+            max_response_bytes: 4096,
+            timeout: Duration::from_secs(5),
+        };
+        let tools = Tools::new(defaults);
+        assert_eq!(tools.max_payload_bytes(), 1024);
+
+        let spec = |name: &str, bytes: u64| {
+            let limits = json!({ "maxPayloadBytes": bytes });
+            let spec = json!({ "toolName": name, "serverRef": "github", "limits": limits });
+            (name.to_string(), serde_json::from_value::<ToolSpec>(spec).unwrap())
+        };
+        tools.update(&BTreeMap::from([spec("small", 512), spec("large", 8192)]));
+        assert_eq!(tools.max_payload_bytes(), 8192);
+        tools.update(&BTreeMap::from([spec("huge", 1 << 30)]));
+        assert_eq!(tools.max_payload_bytes(), MAX_PAYLOAD_BYTES);
+    }
+}
@@ -0,0 +1,316 @@
+//! `latchkey-gateway verify-audit`: checks an audit log exported by the file or webhook sink for
+//! events that were removed, reordered or changed.
+
+use crate::chain::{from_hex, signed_by};
+use anyhow::Context;
+use latchkey_core::audit::{AuditCheckpoint, AuditRecord, AUDIT_SCHEMA_VERSION};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+const USAGE: &str = "usage: latchkey-gateway verify-audit <file.jsonl> --public-key <hex>";
+
+pub fn run(mut args: impl Iterator<Item = String>) -> anyhow::Result<()> {
+    let path = args.next().context(USAGE)?;
+    // Without the key, anyone who rewrites the log can recompute every hash, so it is required.
+    let public_key = match (args.next().as_deref(), args.next(), args.next()) {
+        (Some("--public-key"), Some(key), None) => from_hex(&key).context("invalid public key")?,
+        _ => anyhow::bail!(USAGE),
+    };
+
+    let log = std::fs::read_to_string(Path::new(&path))
+        .with_context(|| format!("failed to read {path}"))?;
+    let report = verify(&log, &public_key);
+    for line in &report.summary {
+        println!("{line}");
+    }
+    for problem in &report.problems {
+        println!("problem: {problem}");
+    }
+    anyhow::ensure!(report.problems.is_empty(), "{} problems found", report.problems.len());
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    /// One line per chain, plus notes that do not fail verification.
+    pub summary: Vec<String>,
+    pub problems: Vec<String>,
+}
+
+/// A chain's events by sequence: the line, the event's `prev_hash` and its own hash.
+#[derive(Default)]
+struct Chain {
+    events: BTreeMap<u64, (usize, Option<String>, String)>,
+    checkpoints: Vec<(usize, AuditCheckpoint)>,
+    /// The highest sequence read so far, to spot events out of file order.
+    highest: Option<u64>,
+}
+
+/// Checks every chain in `log` against checkpoints signed with `public_key`.
+pub fn verify(log: &str, public_key: &[u8]) -> Report {
+    let mut report = Report::default();
+    let mut chains: BTreeMap<String, Chain> = BTreeMap::new();
+    let mut unchained = 0;
+
+    for (index, line) in log.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        let line_number = index + 1;
+        match serde_json::from_str::<AuditRecord>(line) {
+            Ok(AuditRecord::Event(event)) => {
+                // An event is hashed as its own schema version serialized it.
+                if event.schema_version != AUDIT_SCHEMA_VERSION {
+                    report.problems.push(format!(
+                        "line {line_number}: schema_version {} events must be checked by a \
+                         gateway that writes them",
+                        event.schema_version
+                    ));
+                    continue;
+                }
+                let (Some(chain_id), Some(sequence)) = (&event.chain_id, event.sequence) else {
+                    unchained += 1;
+                    continue;
+                };
+                let chain = chains.entry(chain_id.clone()).or_default();
+                if chain.events.contains_key(&sequence) {
+                    report.problems.push(format!(
+                        "line {line_number}: chain {chain_id} repeats sequence {sequence}"
+                    ));
+                    continue;
+                }
+                if let Some(highest) = chain.highest.filter(|highest| sequence < *highest) {
+                    report.problems.push(format!(
+                        "line {line_number}: chain {chain_id} has sequence {sequence} after \
+                         {highest}, out of order"
+                    ));
+                }
+                chain.highest = chain.highest.max(Some(sequence));
+                let hash = event.hash();
+                chain.events.insert(sequence, (line_number, event.prev_hash, hash));
+            }
+            Ok(AuditRecord::Checkpoint(checkpoint)) => {
+                let chain = chains.entry(checkpoint.chain_id.clone()).or_default();
+                chain.checkpoints.push((line_number, checkpoint));
+            }
+            Err(err) => {
+                report.problems.push(format!("line {line_number}: not an audit record: {err}"))
+            }
+        }
+    }
+
+    for (chain_id, chain) in &chains {
+        check_links(chain_id, chain, &mut report);
+        check_checkpoints(chain_id, chain, public_key, &mut report);
+    }
+    if unchained > 0 {
+        report.problems.push(format!("{unchained} events carry no chain and cannot be checked"));
+    }
+    report
+}
+
+/// Walks a chain in sequence order, reporting gaps and events whose predecessor changed.
+fn check_links(chain_id: &str, chain: &Chain, report: &mut Report) {
+    let (Some(&first), Some(&last)) = (chain.events.keys().next(), chain.events.keys().last())
+    else {
+        report.summary.push(format!("chain {chain_id}: no events"));
+        return;
+    };
+    report.summary.push(format!(
+        "chain {chain_id}: {} events, sequences {first}-{last}, {} checkpoints",
+        chain.events.len(),
+        chain.checkpoints.len()
+    ));
+    if first > 0 {
+        report.summary.push(format!(
+            "chain {chain_id} starts at sequence {first}; earlier events are not in this log"
+        ));
+    }
+
+    let mut previous: Option<(u64, &String)> = None;
+    for (&sequence, (line, prev_hash, hash)) in &chain.events {
+        match previous {
+            Some((before, _)) if sequence > before + 1 => {
+                report.problems.push(format!(
+                    "chain {chain_id} is missing sequences {}-{}",
+                    before + 1,
+                    sequence - 1
+                ));
+            }
+            Some((before, before_hash)) if prev_hash.as_ref() != Some(before_hash) => {
+                report.problems.push(format!(
+                    "line {line}: chain {chain_id} sequence {sequence} does not follow \
+                     sequence {before}; one of them was changed"
+                ));
+            }
+            None if sequence == 0 && prev_hash.is_some() => {
+                report
+                    .problems
+                    .push(format!("line {line}: chain {chain_id} sequence 0 claims a predecessor"));
+            }
+            _ => {}
+        }
+        previous = Some((sequence, hash));
+    }
+}
+
+fn check_checkpoints(chain_id: &str, chain: &Chain, public_key: &[u8], report: &mut Report) {
+    let mut signed = None;
+    let first = chain.events.keys().next().copied();
+    let last = chain.events.keys().last().copied();
+    for (line, checkpoint) in &chain.checkpoints {
+        if !signed_by(checkpoint, public_key) {
+            report.problems.push(format!(
+                "line {line}: checkpoint for chain {chain_id} sequence {} has a bad signature",
+                checkpoint.sequence
+            ));
+            continue;
+        }
+        signed = signed.max(Some(checkpoint.sequence));
+        match chain.events.get(&checkpoint.sequence) {
+            Some((_, _, hash)) if *hash != checkpoint.hash => report.problems.push(format!(
+                "line {line}: chain {chain_id} sequence {} differs from its checkpoint",
+                checkpoint.sequence
+            )),
+            Some(_) => {}
+            None if last.is_none_or(|last| checkpoint.sequence > last) => {
+                report.problems.push(format!(
+                    "line {line}: chain {chain_id} was signed up to sequence {} but the log ends \
+                     at {}",
+                    checkpoint.sequence,
+                    last.map_or("no events".to_string(), |last| last.to_string())
+                ))
+            }
+            // Inside the log, a missing event is already reported as a gap.
+            None => {}
+        }
+    }
+
+    // Hashes alone can be recomputed by whoever rewrote the log; only a signature ties a chain
+    // to the gateway.
+    if last.is_some() && signed.is_none() {
+        report.problems.push(format!(
+            "chain {chain_id} has no checkpoint signed by this key, so none of its events are \
+             proven"
+        ));
+        return;
+    }
+    if let Some(last) = last.filter(|last| signed.is_none_or(|signed| signed < *last)) {
+        let from = signed.map_or(first.unwrap_or_default(), |signed| signed + 1);
+        report.summary.push(format!(
+            "chain {chain_id} sequences {from}-{last} are not covered by a checkpoint yet"
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::tests::chain;
+    use latchkey_core::audit::AuditEvent;
+
+    /// A log of five events with a checkpoint after every two.
+    fn log() -> (Vec<String>, Vec<u8>) {
+        let (mut chain, public_key) = chain(2);
+        let mut lines = Vec::new();
+        for id in 0..5 {
+            let mut event = AuditEvent::new(id.to_string(), "t".into(), "tools/call".into());
+            let checkpoint = chain.link(&mut event);
+            lines.push(serde_json::to_string(&AuditRecord::Event(event)).unwrap());
+            if let Some(checkpoint) = checkpoint {
+                lines.push(serde_json::to_string(&AuditRecord::Checkpoint(checkpoint)).unwrap());
+            }
+        }
+        (lines, public_key)
+    }
+
+    fn problems(lines: &[String], public_key: &[u8]) -> Vec<String> {
+        verify(&lines.join("\n"), public_key).problems
+    }
+
+    #[test]
+    fn accepts_an_intact_log() {
+        let (lines, public_key) = log();
+        let report = verify(&lines.join("\n"), &public_key);
+        assert!(report.problems.is_empty(), "{:?}", report.problems);
+        assert!(report.summary[0].ends_with(": 5 events, sequences 0-4, 2 checkpoints"));
+        assert!(report.summary[1].ends_with("sequences 4-4 are not covered by a checkpoint yet"));
+    }
+
+    #[test]
+    fn reports_gaps_reordering_and_tampering() {
+        let (lines, public_key) = log();
+        // Lines: events 0 and 1, checkpoint 1, events 2 and 3, checkpoint 3, event 4.
+
+        let mut removed = lines.clone();
+        removed.remove(3);
+        assert_eq!(problems(&removed, &public_key).len(), 1);
+        assert!(problems(&removed, &public_key)[0].ends_with("is missing sequences 2-2"));
+
+        let mut reordered = lines.clone();
+        reordered.swap(3, 4);
+        assert_eq!(problems(&reordered, &public_key).len(), 1);
+        assert!(problems(&reordered, &public_key)[0].contains("sequence 2 after 3, out of order"));
+
+        let mut changed = lines.clone();
+        changed[3] = changed[3].replace("\"decision\":\"allow\"", "\"decision\":\"deny\"");
+        assert!(
+            problems(&changed, &public_key)[0].contains("sequence 3 does not follow sequence 2")
+        );
+
+        // Without a later event, only the checkpoint catches a change to the newest one.
+        let mut newest_changed = lines[..6].to_vec();
+        newest_changed[4] = newest_changed[4].replace("\"allow\"", "\"deny\"");
+        assert_eq!(problems(&newest_changed, &public_key).len(), 1);
+        assert!(problems(&newest_changed, &public_key)[0].ends_with("differs from its checkpoint"));
+
+        let mut cut = lines[..6].to_vec();
+        cut.remove(4);
+        assert!(problems(&cut, &public_key)[0]
+            .ends_with("signed up to sequence 3 but the log ends at 2"));
+
+        let mut newer = lines.clone();
+        newer[0] = newer[0].replace("\"schema_version\":1", "\"schema_version\":2");
+        assert_eq!(
+            problems(&newer, &public_key),
+            ["line 1: schema_version 2 events must be checked by a gateway that writes them"]
+        );
+
+        let mut wrong_key = public_key.clone();
+        wrong_key[0] ^= 1;
+        let wrong = problems(&lines, &wrong_key);
+        assert_eq!(wrong.len(), 3);
+        assert!(wrong[2]
+            .ends_with("has no checkpoint signed by this key, so none of its events are proven"));
+    }
+
+    #[test]
+    fn rejects_logs_rewritten_without_the_signing_key() {
+        let (lines, public_key) = log();
+
+        // Recomputed hashes chain up fine, but the checkpoints had to go.
+        let unsigned: Vec<_> =
+            lines.iter().filter(|line| !line.contains("\"signature\"")).cloned().collect();
+        let problems_found = problems(&unsigned, &public_key);
+        assert_eq!(problems_found.len(), 1);
+        assert!(problems_found[0].ends_with("so none of its events are proven"));
+
+        let unchained: Vec<_> = lines
+            .iter()
+            .filter(|line| !line.contains("\"signature\""))
+            .map(|line| {
+                let mut event: serde_json::Value = serde_json::from_str(line).unwrap();
+                for field in ["chain_id", "sequence", "prev_hash"] {
+                    event[field] = serde_json::Value::Null;
+                }
+                event.to_string()
+            })
+            .collect();
+        assert_eq!(
+            problems(&unchained, &public_key),
+            ["5 events carry no chain and cannot be checked"]
+        );
+
+        assert!(run(["audit.jsonl".to_string()].into_iter())
+            .unwrap_err()
+            .to_string()
+            .contains(USAGE));
+    }
+}
@@ -0,0 +1,63 @@
+use crate::process::{Bridge, BridgeError};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use latchkey_core::bridge::{MCP_PATH, READY_PATH};
+use serde_json::{json, Value};
+
+const INVALID_REQUEST: i64 = -32600;
+const INTERNAL_ERROR: i64 = -32603;
+
+/// Streamable HTTP endpoint plus the probes the operator points at the bridge.
+pub fn router(bridge: Bridge) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route(READY_PATH, get(readyz))
+        .route(MCP_PATH, post(mcp))
+        .with_state(bridge)
+}
+
+async fn healthz() -> StatusCode {
+    StatusCode::OK
+}
+
+async fn readyz(State(bridge): State<Bridge>) -> StatusCode {
+    if bridge.ready() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+async fn mcp(State(bridge): State<Bridge>, Json(message): Json<Value>) -> Response {
+    if message.is_array() {
+        return rpc_error(
+            StatusCode::BAD_REQUEST,
+            Value::Null,
+            INVALID_REQUEST,
+            "batches are not supported",
+        );
+    }
+
+    let id = message.get("id").cloned().unwrap_or(Value::Null);
+    match bridge.call(message).await {
+        Ok(Some(response)) => Json(response).into_response(),
+        Ok(None) => StatusCode::ACCEPTED.into_response(),
+        Err(err) => {
+            let (status, code) = match err {
+                BridgeError::Invalid => (StatusCode::BAD_REQUEST, INVALID_REQUEST),
+                BridgeError::Unavailable => (StatusCode::SERVICE_UNAVAILABLE, INTERNAL_ERROR),
+                BridgeError::Exited => (StatusCode::BAD_GATEWAY, INTERNAL_ERROR),
+                BridgeError::Timeout => (StatusCode::GATEWAY_TIMEOUT, INTERNAL_ERROR),
+            };
+            rpc_error(status, id, code, &err.to_string())
+        }
+    }
+}
+
+fn rpc_error(status: StatusCode, id: Value, code: i64, message: &str) -> Response {
+    let body = json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } });
+    (status, Json(body)).into_response()
+}
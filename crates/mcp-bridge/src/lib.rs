@@ -0,0 +1,9 @@
+//! Serves a stdio-only MCP server over Streamable HTTP.
+//!
+//! The operator injects this bridge when a `LatchkeyServer` sets `transport: stdio`. The bridge
+//! spawns the server as a child process and relays JSON-RPC over its stdin and stdout. It gives
+//! every request a bridge-unique id so concurrent callers cannot collide, and restarts the child
+//! when it exits. The child's stderr ends up in the bridge's logs.
+
+pub mod http;
+pub mod process;
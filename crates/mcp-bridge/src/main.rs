@@ -0,0 +1,71 @@
+use anyhow::Context;
+use latchkey_core::bridge::{BINARY, BIND_ENV};
+use latchkey_mcp_bridge::http;
+use latchkey_mcp_bridge::process::{Bridge, BridgeConfig};
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::Duration;
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+
+const DEFAULT_BIND: &str = "0.0.0.0:8081";
+const DEFAULT_REQUEST_TIMEOUT_SECONDS: u64 = 60;
+const USAGE: &str = "usage: latchkey-mcp-bridge [--] <command> [args...] | install <dir>";
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("install") {
+        let dir = args.get(1).context(USAGE)?;
+        return install(Path::new(dir));
+    }
+    if args.first().map(String::as_str) == Some("--") {
+        args.remove(0);
+    }
+    anyhow::ensure!(!args.is_empty(), USAGE);
+
+    init_tracing();
+
+    let bind = std::env::var(BIND_ENV).unwrap_or_else(|_| DEFAULT_BIND.to_string());
+    let addr: SocketAddr = bind.parse().with_context(|| format!("invalid {BIND_ENV} value"))?;
+    let request_timeout = std::env::var("LATCHKEY_BRIDGE_REQUEST_TIMEOUT_SECONDS")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_REQUEST_TIMEOUT_SECONDS);
+
+    let bridge = Bridge::spawn(BridgeConfig {
+        command: args,
+        request_timeout: Duration::from_secs(request_timeout),
+    })?;
+
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("failed to bind mcp bridge listener on {addr}"))?;
+
+    info!(%addr, "mcp bridge booted");
+    axum::serve(listener, http::router(bridge)).await.context("mcp bridge failed")
+}
+
+/// Copies this binary into `dir` so the operator's init container can hand it to a tool server
+/// image that has no bridge of its own.
+fn install(dir: &Path) -> anyhow::Result<()> {
+    let source = std::env::current_exe().context("failed to locate the bridge binary")?;
+    let target = dir.join(BINARY);
+    std::fs::copy(&source, &target)
+        .with_context(|| format!("failed to copy the bridge to {}", target.display()))?;
+    std::fs::set_permissions(&target, std::fs::Permissions::from_mode(0o555))
+        .with_context(|| format!("failed to mark {} executable", target.display()))?;
+    Ok(())
+}
+
+fn init_tracing() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    tracing_subscriber::fmt()
+        .json()
+        .with_env_filter(filter)
+        .with_current_span(false)
+        .with_span_list(false)
+        .init();
+}
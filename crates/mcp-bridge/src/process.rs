@@ -0,0 +1,307 @@
+use anyhow::Context;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::process::{ExitStatus, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::process::{ChildStdin, Command};
+use tokio::sync::{oneshot, watch, Mutex};
+use tokio::time::Instant;
+use tracing::{debug, error, info, warn};
+
+const MIN_RESTART_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(30);
+/// A child that stayed up this long resets the restart backoff.
+const STABLE_RUN: Duration = Duration::from_secs(60);
+const MAX_STDERR_LINE_CHARS: usize = 4096;
+
+const METHOD_NOT_FOUND: i64 = -32601;
+
+#[derive(Debug, Clone)]
+pub struct BridgeConfig {
+    /// Program and arguments of the stdio MCP server.
+    pub command: Vec<String>,
+    /// Bounds both the wait for a running child and the wait for its response.
+    pub request_timeout: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BridgeError {
+    /// The message is not a JSON-RPC object.
+    Invalid,
+    /// No child was running and initialized within the request timeout.
+    Unavailable,
+    /// The child exited before answering.
+    Exited,
+    /// The child did not answer within the request timeout.
+    Timeout,
+}
+
+impl fmt::Display for BridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Invalid => "invalid JSON-RPC message",
+            Self::Unavailable => "mcp server unavailable",
+            Self::Exited => "mcp server exited before responding",
+            Self::Timeout => "mcp server did not respond in time",
+        })
+    }
+}
+
+impl std::error::Error for BridgeError {}
+
+/// Handle to a supervised stdio MCP server. Clones share the same child.
+#[derive(Clone)]
+pub struct Bridge {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    config: BridgeConfig,
+    stdin: Mutex<Option<ChildStdin>>,
+    /// In-flight requests keyed by the bridge id written to the child.
+    pending: StdMutex<HashMap<u64, oneshot::Sender<Value>>>,
+    next_id: AtomicU64,
+    /// Params of the last client `initialize`, replayed into every restarted child.
+    initialize: StdMutex<Option<Value>>,
+    ready: watch::Sender<bool>,
+}
+
+impl Bridge {
+    /// Spawns the child and keeps restarting it, with backoff, whenever it exits.
+    pub fn spawn(config: BridgeConfig) -> anyhow::Result<Self> {
+        anyhow::ensure!(!config.command.is_empty(), "mcp server command must not be empty");
+
+        let inner = Arc::new(Inner {
+            config,
+            stdin: Mutex::new(None),
+            pending: StdMutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+            initialize: StdMutex::new(None),
+            ready: watch::Sender::new(false),
+        });
+        tokio::spawn(supervise(inner.clone()));
+        Ok(Self { inner })
+    }
+
+    pub fn ready(&self) -> bool {
+        *self.inner.ready.borrow()
+    }
+
+    /// Relays one JSON-RPC message. Requests resolve to the child's response carrying the
+    /// caller's original id; notifications and responses resolve to `None` once written.
+    pub async fn call(&self, message: Value) -> Result<Option<Value>, BridgeError> {
+        let object = message.as_object().ok_or(BridgeError::Invalid)?;
+        let (Some(method), Some(id)) =
+            (object.get("method").and_then(Value::as_str), object.get("id").cloned())
+        else {
+            self.wait_ready().await?;
+            self.inner.write(&message).await?;
+            return Ok(None);
+        };
+
+        if method == "initialize" {
+            *self.inner.initialize.lock().expect("initialize lock poisoned") =
+                object.get("params").cloned();
+        }
+        self.wait_ready().await?;
+
+        let mut forwarded = message;
+        let (bridge_id, response) = self.inner.register();
+        forwarded["id"] = json!(bridge_id);
+        if let Err(err) = self.inner.write(&forwarded).await {
+            self.inner.forget(bridge_id);
+            return Err(err);
+        }
+
+        match tokio::time::timeout(self.inner.config.request_timeout, response).await {
+            Ok(Ok(mut response)) => {
+                response["id"] = id;
+                Ok(Some(response))
+            }
+            Ok(Err(_)) => Err(BridgeError::Exited),
+            Err(_) => {
+                self.inner.forget(bridge_id);
+                let cancel = json!({
+                    "jsonrpc": "2.0",
+                    "method": "notifications/cancelled",
+                    "params": { "requestId": bridge_id, "reason": "timeout" },
+                });
+                let _ = self.inner.write(&cancel).await;
+                Err(BridgeError::Timeout)
+            }
+        }
+    }
+
+    async fn wait_ready(&self) -> Result<(), BridgeError> {
+        let mut ready = self.inner.ready.subscribe();
+        let waited = tokio::time::timeout(self.inner.config.request_timeout, async {
+            ready.wait_for(|ready| *ready).await.is_ok()
+        })
+        .await;
+        if waited == Ok(true) {
+            Ok(())
+        } else {
+            Err(BridgeError::Unavailable)
+        }
+    }
+}
+
+impl Inner {
+    fn register(&self) -> (u64, oneshot::Receiver<Value>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = oneshot::channel();
+        self.pending.lock().expect("pending lock poisoned").insert(id, sender);
+        (id, receiver)
+    }
+
+    fn forget(&self, id: u64) {
+        self.pending.lock().expect("pending lock poisoned").remove(&id);
+    }
+
+    async fn write(&self, message: &Value) -> Result<(), BridgeError> {
+        let mut line = serde_json::to_vec(message).map_err(|_| BridgeError::Invalid)?;
+        line.push(b'\n');
+
+        let mut stdin = self.stdin.lock().await;
+        let pipe = stdin.as_mut().ok_or(BridgeError::Unavailable)?;
+        if let Err(err) = async {
+            pipe.write_all(&line).await?;
+            pipe.flush().await
+        }
+        .await
+        {
+            warn!(error = %err, "failed to write to mcp server stdin");
+            return Err(BridgeError::Unavailable);
+        }
+        Ok(())
+    }
+
+    /// Routes one line of child stdout.
+    async fn dispatch(&self, line: &str) {
+        let Ok(message) = serde_json::from_str::<Value>(line) else {
+            warn!("mcp server wrote a non-JSON line to stdout");
+            return;
+        };
+
+        match (message.get("id"), message.get("method").and_then(Value::as_str)) {
+            (Some(id), None) => {
+                let sender = id
+                    .as_u64()
+                    .and_then(|id| self.pending.lock().expect("pending lock poisoned").remove(&id));
+                match sender {
+                    Some(sender) => {
+                        let _ = sender.send(message);
+                    }
+                    None => debug!(%id, "dropping response to an abandoned request"),
+                }
+            }
+            // Streamable HTTP callers have no channel for server-initiated requests yet, so
+            // answer them here rather than leave the child waiting.
+            (Some(id), Some(method)) => {
+                debug!(method, "rejecting server-initiated request");
+                let reply = json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": METHOD_NOT_FOUND, "message": "not supported by the bridge" },
+                });
+                let _ = self.write(&reply).await;
+            }
+            (None, Some(method)) => debug!(method, "dropping mcp server notification"),
+            (None, None) => warn!("mcp server wrote an invalid JSON-RPC message"),
+        }
+    }
+
+    /// Brings a fresh child to the state the callers left it in, then opens it to traffic.
+    async fn replay_initialize(self: Arc<Self>) {
+        let params = self.initialize.lock().expect("initialize lock poisoned").clone();
+        if let Some(params) = params {
+            let (id, response) = self.register();
+            let request =
+                json!({ "jsonrpc": "2.0", "id": id, "method": "initialize", "params": params });
+            if self.write(&request).await.is_err() {
+                self.forget(id);
+                return;
+            }
+            match tokio::time::timeout(self.config.request_timeout, response).await {
+                Ok(Ok(_)) => {}
+                _ => {
+                    self.forget(id);
+                    warn!("restarted mcp server did not answer initialize");
+                    return;
+                }
+            }
+            let initialized = json!({ "jsonrpc": "2.0", "method": "notifications/initialized" });
+            if self.write(&initialized).await.is_err() {
+                return;
+            }
+        }
+        self.ready.send_replace(true);
+    }
+}
+
+async fn supervise(inner: Arc<Inner>) {
+    let mut backoff = MIN_RESTART_BACKOFF;
+    loop {
+        let started = Instant::now();
+        match run_child(&inner).await {
+            Ok(status) => warn!(%status, "mcp server exited"),
+            Err(err) => error!(error = %format!("{err:#}"), "mcp server failed"),
+        }
+
+        inner.ready.send_replace(false);
+        *inner.stdin.lock().await = None;
+        // Dropping the senders fails every in-flight request with `Exited`.
+        inner.pending.lock().expect("pending lock poisoned").clear();
+
+        if started.elapsed() >= STABLE_RUN {
+            backoff = MIN_RESTART_BACKOFF;
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_RESTART_BACKOFF);
+    }
+}
+
+async fn run_child(inner: &Arc<Inner>) -> anyhow::Result<ExitStatus> {
+    let (program, args) = inner.config.command.split_first().context("empty command")?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("failed to spawn {program}"))?;
+
+    let stdout = child.stdout.take().context("child stdout missing")?;
+    let stderr = child.stderr.take().context("child stderr missing")?;
+    *inner.stdin.lock().await = child.stdin.take();
+    tokio::spawn(log_stderr(stderr));
+    info!(pid = child.id(), "mcp server started");
+
+    let replay = tokio::spawn(inner.clone().replay_initialize());
+    let mut lines = BufReader::new(stdout).lines();
+    while let Some(line) = lines.next_line().await.context("failed to read mcp server stdout")? {
+        inner.dispatch(&line).await;
+    }
+    replay.abort();
+
+    // A closed stdout means the child is gone or can no longer answer.
+    let _ = child.start_kill();
+    child.wait().await.context("failed to wait for mcp server")
+}
+
+/// Drains stderr even when it is not UTF-8, so a chatty child never blocks on a full pipe.
+async fn log_stderr(stderr: impl AsyncRead + Unpin) {
+    let mut reader = BufReader::new(stderr);
+    let mut buf = Vec::new();
+    while reader.read_until(b'\n', &mut buf).await.is_ok_and(|read| read > 0) {
+        let line = String::from_utf8_lossy(&buf);
+        let line: String = line.trim_end().chars().take(MAX_STDERR_LINE_CHARS).collect();
+        info!(stream = "stderr", line, "mcp server output");
+        buf.clear();
+    }
+}
@@ -0,0 +1,108 @@
+//! Drives the bridge against a tiny stdio MCP server written in `sh`.
+
+use latchkey_mcp_bridge::http;
+use latchkey_mcp_bridge::process::{Bridge, BridgeConfig, BridgeError};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// Answers requests by bridge id. `slow` replies out of order, `hang` never replies, `crash` exits,
+/// and `state` reports whether this process has seen `initialize`.
+const SERVER: &str = r#"
+initialized=0
+while IFS= read -r line; do
+  id=$(printf '%s' "$line" | sed -n 's/.*"id":\([0-9]*\).*/\1/p')
+  case "$line" in
+    *'"method":"initialize"'*)
+      initialized=1
+      echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"protocolVersion\":\"2025-06-18\"}}" ;;
+    *'"method":"notifications/'*) ;;
+    *'"method":"hang"'*) ;;
+    *'"method":"slow"'*)
+      (sleep 0.3; echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"slow\":true}}") & ;;
+    *'"method":"crash"'*) echo "crashing" >&2; exit 3 ;;
+    *'"method":"state"'*)
+      echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"initialized\":$initialized}}" ;;
+    *) echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"echo\":$line}}" ;;
+  esac
+done
+"#;
+
+fn bridge(request_timeout: Duration) -> Bridge {
+    Bridge::spawn(BridgeConfig {
+        command: vec!["sh".to_string(), "-c".to_string(), SERVER.to_string()],
+        request_timeout,
+    })
+    .expect("spawn bridge")
+}
+
+fn request(id: Value, method: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": { "tag": method } })
+}
+
+#[tokio::test]
+async fn multiplexes_colliding_caller_ids() {
+    let bridge = bridge(Duration::from_secs(5));
+
+    let (slow, fast) = tokio::join!(
+        bridge.call(request(json!("a"), "slow")),
+        bridge.call(request(json!("a"), "tools/list")),
+    );
+    let slow = slow.expect("slow call").expect("slow response");
+    let fast = fast.expect("fast call").expect("fast response");
+
+    assert_eq!(slow["id"], "a");
+    assert_eq!(slow["result"], json!({ "slow": true }));
+    assert_eq!(fast["id"], "a");
+    assert_eq!(fast["result"]["echo"]["params"], json!({ "tag": "tools/list" }));
+    // The child saw a bridge id, never the caller's.
+    assert!(fast["result"]["echo"]["id"].is_u64());
+}
+
+#[tokio::test]
+async fn restarts_the_server_and_replays_initialize() {
+    let bridge = bridge(Duration::from_secs(5));
+
+    let initialize = bridge.call(request(json!(1), "initialize")).await.expect("initialize");
+    assert_eq!(initialize.expect("response")["result"]["protocolVersion"], "2025-06-18");
+
+    let crashed = bridge.call(request(json!(2), "crash")).await;
+    assert_eq!(crashed, Err(BridgeError::Exited));
+
+    let state = bridge.call(request(json!(3), "state")).await.expect("state").expect("response");
+    assert_eq!(state["result"]["initialized"], 1);
+}
+
+#[tokio::test]
+async fn times_out_unanswered_requests() {
+    let bridge = bridge(Duration::from_millis(500));
+
+    let hung = bridge.call(request(json!(1), "hang")).await;
+    assert_eq!(hung, Err(BridgeError::Timeout));
+    assert!(bridge.call(request(json!(2), "ping")).await.expect("ping").is_some());
+}
+
+#[tokio::test]
+async fn serves_streamable_http() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let addr = listener.local_addr().expect("addr");
+    let app = http::router(bridge(Duration::from_secs(5)));
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let client = reqwest::Client::new();
+    let url = format!("http://{addr}/mcp");
+
+    let response = client.post(&url).json(&request(json!(7), "ping")).send().await.expect("post");
+    assert_eq!(response.status(), 200);
+    let body: Value = response.json().await.expect("json");
+    assert_eq!(body["id"], 7);
+
+    let notification = json!({ "jsonrpc": "2.0", "method": "notifications/initialized" });
+    let response = client.post(&url).json(&notification).send().await.expect("post");
+    assert_eq!(response.status(), 202);
+
+    let response = client.post(&url).json(&json!([request(json!(8), "ping")])).send().await;
+    assert_eq!(response.expect("post").status(), 400);
+
+    let ready = client.get(format!("http://{addr}/readyz")).send().await.expect("readyz");
+    assert_eq!(ready.status(), 200);
+}
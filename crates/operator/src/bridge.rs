@@ -0,0 +1,104 @@
+use crate::crd::{LatchkeyServer, Transport};
+use crate::server;
+use anyhow::Context;
+use k8s_openapi::api::core::v1::{Container, EmptyDirVolumeSource, EnvVar, Volume, VolumeMount};
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+use latchkey_core::bridge::{BINARY, BIND_ENV, INSTALL_DIR};
+
+const DEFAULT_MCP_BRIDGE_IMAGE: &str = "ghcr.io/latchkey/latchkey-mcp-bridge:dev";
+const BRIDGE_VOLUME: &str = "latchkey-bridge";
+const BRIDGE_VOLUME_SIZE_LIMIT: &str = "64Mi";
+
+#[derive(Debug, Clone)]
+pub struct BridgeConfig {
+    /// Must ship a statically linked bridge, since it runs inside arbitrary tool server images.
+    pub image: String,
+}
+
+impl BridgeConfig {
+    pub fn from_env() -> Self {
+        Self {
+            image: std::env::var("LATCHKEY_MCP_BRIDGE_IMAGE")
+                .unwrap_or_else(|_| DEFAULT_MCP_BRIDGE_IMAGE.to_string()),
+        }
+    }
+}
+
+/// Pod fragments that put the MCP bridge in front of a stdio tool server.
+#[derive(Debug)]
+pub struct StdioBridge {
+    /// Copies the bridge binary into the shared volume.
+    pub init_container: Container,
+    pub volume: Volume,
+    /// Read-only view of the volume for the tool server container.
+    pub mount: VolumeMount,
+    /// Entrypoint for the tool server container: the bridge, wrapping `spec.stdio`.
+    pub command: Vec<String>,
+    pub args: Vec<String>,
+    pub env: Vec<EnvVar>,
+}
+
+/// The bridge for `transport: stdio` servers, or `None` for HTTP servers.
+///
+/// A stdio server can only be reached through its own stdin and stdout, so the bridge has to run
+/// as the tool server container's entrypoint rather than as a separate sidecar.
+pub fn stdio_bridge(
+    server: &LatchkeyServer,
+    config: &BridgeConfig,
+) -> anyhow::Result<Option<StdioBridge>> {
+    if server.spec.transport != Some(Transport::Stdio) {
+        return Ok(None);
+    }
+    let stdio = server
+        .spec
+        .stdio
+        .as_ref()
+        .filter(|stdio| !stdio.command.is_empty())
+        .context("transport stdio requires spec.stdio.command")?;
+
+    let init_container = Container {
+        name: "mcp-bridge-install".to_string(),
+        image: Some(config.image.clone()),
+        command: Some(vec![
+            format!("/bin/{BINARY}"),
+            "install".to_string(),
+            INSTALL_DIR.to_string(),
+        ]),
+        volume_mounts: Some(vec![VolumeMount {
+            name: BRIDGE_VOLUME.to_string(),
+            mount_path: INSTALL_DIR.to_string(),
+            ..VolumeMount::default()
+        }]),
+        security_context: Some(server::container_security_context(true)),
+        ..Container::default()
+    };
+
+    let mut args = vec!["--".to_string()];
+    args.extend(stdio.command.iter().cloned());
+    args.extend(stdio.args.iter().flatten().cloned());
+
+    Ok(Some(StdioBridge {
+        init_container,
+        volume: Volume {
+            name: BRIDGE_VOLUME.to_string(),
+            empty_dir: Some(EmptyDirVolumeSource {
+                size_limit: Some(Quantity(BRIDGE_VOLUME_SIZE_LIMIT.to_string())),
+                ..EmptyDirVolumeSource::default()
+            }),
+            ..Volume::default()
+        },
+        mount: VolumeMount {
+            name: BRIDGE_VOLUME.to_string(),
+            mount_path: INSTALL_DIR.to_string(),
+            read_only: Some(true),
+            ..VolumeMount::default()
+        },
+        command: vec![format!("{INSTALL_DIR}/{BINARY}")],
+        args,
+        env: vec![EnvVar {
+            name: BIND_ENV.to_string(),
+            value: Some(format!("0.0.0.0:{}", server::service_port(server))),
+            ..EnvVar::default()
+        }],
+    }))
+}
@@ -0,0 +1,246 @@
+use crate::crdgen;
+use crate::server::FIELD_MANAGER;
+use crate::webhook::WebhookConfig;
+use anyhow::Context;
+use axum_server::tls_rustls::RustlsConfig;
+use k8s_openapi::api::admissionregistration::v1::ValidatingWebhookConfiguration;
+use k8s_openapi::api::core::v1::Secret;
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use k8s_openapi::ByteString;
+use kube::api::{Patch, PatchParams, PostParams};
+use kube::{Api, Client};
+use rcgen::{
+    BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+    KeyUsagePurpose,
+};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::Path;
+use tracing::info;
+
+const CA_CERT_KEY: &str = "ca.crt";
+const CA_KEY_KEY: &str = "ca.key";
+const CA_COMMON_NAME: &str = "latchkey-webhook-ca";
+
+/// Serving material for the admission webhook.
+///
+/// When `LATCHKEY_WEBHOOK_CERT_DIR` holds a `tls.crt`/`tls.key` pair (for example from
+/// cert-manager) it is used as-is and the caBundle is left to whoever issued it. Otherwise a
+/// self-signed CA is kept in the `LATCHKEY_WEBHOOK_CA_SECRET` Secret, created on first boot, and
+/// a serving certificate is minted from it on every boot. The CA is applied to the
+/// ValidatingWebhookConfiguration and the CRD conversion webhooks, and since it outlives
+/// restarts, replicas that are still serving keep a certificate the API server trusts.
+pub async fn bootstrap(client: &Client, config: &WebhookConfig) -> anyhow::Result<RustlsConfig> {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    if let Some(dir) = &config.cert_dir {
+        let dir = Path::new(dir);
+        info!(cert_dir = %dir.display(), "loading webhook certificate from disk");
+        return RustlsConfig::from_pem_file(dir.join("tls.crt"), dir.join("tls.key"))
+            .await
+            .context("failed to load webhook certificate");
+    }
+
+    let ca = load_or_create_ca(client, &config.namespace, &config.ca_secret).await?;
+    let serving = serving_cert(&ca, &config.service, &config.namespace)?;
+    inject_ca_bundle(client, &config.configuration, &ca.cert_pem).await?;
+    inject_conversion_ca_bundle(client, &ca.cert_pem).await?;
+    info!(configuration = %config.configuration, "issued webhook certificate and applied caBundle");
+
+    RustlsConfig::from_pem(serving.cert_pem.into_bytes(), serving.key_pem.into_bytes())
+        .await
+        .context("failed to build webhook tls config")
+}
+
+/// A certificate and its private key, PEM encoded.
+struct Pem {
+    cert_pem: String,
+    key_pem: String,
+}
+
+impl Pem {
+    fn from_secret(secret: &Secret) -> Option<Self> {
+        let data = secret.data.as_ref()?;
+        let text = |key: &str| String::from_utf8(data.get(key)?.0.clone()).ok();
+        Some(Self { cert_pem: text(CA_CERT_KEY)?, key_pem: text(CA_KEY_KEY)? })
+    }
+}
+
+fn ca_params() -> anyhow::Result<CertificateParams> {
+    let mut params = CertificateParams::new(Vec::<String>::new())?;
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.distinguished_name.push(DnType::CommonName, CA_COMMON_NAME);
+    params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    Ok(params)
+}
+
+fn generate_ca() -> anyhow::Result<Pem> {
+    let key = KeyPair::generate().context("failed to generate webhook ca key")?;
+    let cert = ca_params()?.self_signed(&key).context("failed to self-sign webhook ca")?;
+    Ok(Pem { cert_pem: cert.pem(), key_pem: key.serialize_pem() })
+}
+
+/// Reads the CA from its Secret, creating the Secret if this is the first boot.
+async fn load_or_create_ca(client: &Client, namespace: &str, name: &str) -> anyhow::Result<Pem> {
+    let api: Api<Secret> = Api::namespaced(client.clone(), namespace);
+    let stored = |secret: Secret| {
+        Pem::from_secret(&secret).with_context(|| {
+            format!("Secret {namespace}/{name} must hold {CA_CERT_KEY} and {CA_KEY_KEY}")
+        })
+    };
+    if let Some(secret) = api
+        .get_opt(name)
+        .await
+        .with_context(|| format!("failed to read Secret {namespace}/{name}"))?
+    {
+        return stored(secret);
+    }
+
+    let ca = generate_ca()?;
+    let secret = Secret {
+        metadata: ObjectMeta {
+            name: Some(name.to_string()),
+            namespace: Some(namespace.to_string()),
+            ..ObjectMeta::default()
+        },
+        data: Some(BTreeMap::from([
+            (CA_CERT_KEY.to_string(), ByteString(ca.cert_pem.clone().into_bytes())),
+            (CA_KEY_KEY.to_string(), ByteString(ca.key_pem.clone().into_bytes())),
+        ])),
+        ..Secret::default()
+    };
+    match api.create(&PostParams::default(), &secret).await {
+        Ok(_) => {
+            info!(secret = %format!("{namespace}/{name}"), "stored new webhook ca");
+            Ok(ca)
+        }
+        // Another replica got there first; every replica must serve under the same CA.
+        Err(kube::Error::Api(err)) if err.code == 409 => stored(
+            api.get(name)
+                .await
+                .with_context(|| format!("failed to read Secret {namespace}/{name}"))?,
+        ),
+        Err(err) => Err(err).with_context(|| format!("failed to create Secret {namespace}/{name}")),
+    }
+}
+
+fn serving_cert(ca: &Pem, service: &str, namespace: &str) -> anyhow::Result<Pem> {
+    let ca_key = KeyPair::from_pem(&ca.key_pem).context("invalid webhook ca key")?;
+    // Signing only uses the issuer's name and key, so the stored CA need not be parsed.
+    let issuer = ca_params()?.self_signed(&ca_key).context("failed to load webhook ca")?;
+
+    let dns_names = vec![
+        service.to_string(),
+        format!("{service}.{namespace}"),
+        format!("{service}.{namespace}.svc"),
+        format!("{service}.{namespace}.svc.cluster.local"),
+    ];
+    let key = KeyPair::generate().context("failed to generate webhook serving key")?;
+    let mut params = CertificateParams::new(dns_names)?;
+    params.distinguished_name.push(DnType::CommonName, format!("{service}.{namespace}.svc"));
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    let cert = params
+        .signed_by(&key, &issuer, &ca_key)
+        .context("failed to sign webhook serving certificate")?;
+
+    Ok(Pem { cert_pem: cert.pem(), key_pem: key.serialize_pem() })
+}
+
+/// Applies the caBundle to every webhook in the configuration, owning only that field.
+async fn inject_ca_bundle(client: &Client, name: &str, ca_pem: &str) -> anyhow::Result<()> {
+    let api: Api<ValidatingWebhookConfiguration> = Api::all(client.clone());
+    let configuration = api
+        .get(name)
+        .await
+        .with_context(|| format!("failed to read ValidatingWebhookConfiguration {name}"))?;
+
+    let webhooks: Vec<_> = configuration
+        .webhooks
+        .iter()
+        .flatten()
+        .map(|webhook| {
+            json!({
+                "name": webhook.name,
+                "clientConfig": { "caBundle": ByteString(ca_pem.as_bytes().to_vec()) },
+            })
+        })
+        .collect();
+    let patch = json!({
+        "apiVersion": "admissionregistration.k8s.io/v1",
+        "kind": "ValidatingWebhookConfiguration",
+        "metadata": { "name": name },
+        "webhooks": webhooks,
+    });
+
+    api.patch(name, &PatchParams::apply(FIELD_MANAGER).force(), &Patch::Apply(&patch))
+        .await
+        .with_context(|| format!("failed to apply caBundle on {name}"))?;
+
+    Ok(())
+}
+
+async fn inject_conversion_ca_bundle(client: &Client, ca_pem: &str) -> anyhow::Result<()> {
+    let api: Api<CustomResourceDefinition> = Api::all(client.clone());
+    let patch = Patch::Merge(json!({
+        "spec": {
+            "conversion": {
+                "webhook": {
+                    "clientConfig": {
+                        "caBundle": ByteString(ca_pem.as_bytes().to_vec()),
+                    },
+                },
+            },
+        },
+    }));
+
+    for crd in crdgen::crds()? {
+        let name = crd.metadata.name.unwrap_or_default();
+        api.patch(&name, &PatchParams::default(), &patch)
+            .await
+            .with_context(|| format!("failed to patch conversion caBundle on {name}"))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::client::danger::ServerCertVerifier;
+    use rustls::client::WebPkiServerVerifier;
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+    use rustls::RootCertStore;
+    use std::sync::Arc;
+
+    #[test]
+    fn serving_certificates_chain_to_the_stored_ca() {
+        let ca = generate_ca().unwrap();
+        let secret = Secret {
+            data: Some(BTreeMap::from([
+                (CA_CERT_KEY.to_string(), ByteString(ca.cert_pem.clone().into_bytes())),
+                (CA_KEY_KEY.to_string(), ByteString(ca.key_pem.clone().into_bytes())),
+            ])),
+            ..Secret::default()
+        };
+        let stored = Pem::from_secret(&secret).unwrap();
+        assert!(Pem::from_secret(&Secret::default()).is_none());
+
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_slice(ca.cert_pem.as_bytes()).unwrap()).unwrap();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let verifier =
+            WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider).build().unwrap();
+
+        // Certificates minted on separate boots are both trusted through the one caBundle.
+        for _ in 0..2 {
+            let serving = serving_cert(&stored, "latchkey-operator-webhook", "latchkey-system");
+            let cert = CertificateDer::from_pem_slice(serving.unwrap().cert_pem.as_bytes());
+            let name = ServerName::try_from("latchkey-operator-webhook.latchkey-system.svc");
+            verifier
+                .verify_server_cert(&cert.unwrap(), &[], &name.unwrap(), &[], UnixTime::now())
+                .unwrap();
+        }
+    }
+}
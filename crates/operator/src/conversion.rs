@@ -0,0 +1,318 @@
+use anyhow::Context;
+use serde_json::{Map, Value};
+
+pub const V1ALPHA1: &str = "latchkey.dev/v1alpha1";
+pub const V1BETA1: &str = "latchkey.dev/v1beta1";
+
+/// Holds the `v1beta1` spec fields that have no `v1alpha1` representation, so that a
+/// `v1beta1 -> v1alpha1 -> v1beta1` round trip is lossless.
+pub const PRESERVED_FIELDS_ANNOTATION: &str = "latchkey.dev/v1beta1-fields";
+
+/// Tool limits that `v1alpha1` exposes as top-level spec fields.
+const TOOL_LIMIT_FIELDS: &[&str] = &["maxPayloadBytes", "timeoutMs"];
+
+/// Spec fields each kind can carry in `v1alpha1`; everything else is preserved in the
+/// annotation on the way down. `None` means the spec is identical in both versions.
+fn alpha_fields(kind: &str) -> anyhow::Result<Option<&'static [&'static str]>> {
+    Ok(match kind {
+        "LatchkeyServer" => Some(&["image", "replicas", "transport", "servicePort"]),
+        "LatchkeyTool" => {
+            Some(&["toolName", "serverRef", "toolSelector", "maxPayloadBytes", "timeoutMs"])
+        }
+        "LatchkeyPolicy" => Some(&["subjects", "scopes", "breakGlass", "auditLevel"]),
+        "LatchkeyPrincipal" => None,
+        other => anyhow::bail!("unsupported kind {other}"),
+    })
+}
+
+pub fn convert(mut object: Value, desired_api_version: &str) -> anyhow::Result<Value> {
+    let current = object
+        .get("apiVersion")
+        .and_then(Value::as_str)
+        .context("object has no apiVersion")?
+        .to_string();
+    if current == desired_api_version {
+        return Ok(object);
+    }
+
+    let kind =
+        object.get("kind").and_then(Value::as_str).context("object has no kind")?.to_string();
+    let alpha_fields = alpha_fields(&kind)?;
+
+    match (current.as_str(), desired_api_version) {
+        (V1BETA1, V1ALPHA1) => down(&mut object, &kind, alpha_fields)?,
+        (V1ALPHA1, V1BETA1) => up(&mut object, &kind)?,
+        (from, to) => anyhow::bail!("unsupported conversion from {from} to {to}"),
+    }
+
+    object["apiVersion"] = Value::String(desired_api_version.to_string());
+    Ok(object)
+}
+
+fn down(object: &mut Value, kind: &str, alpha_fields: Option<&[&str]>) -> anyhow::Result<()> {
+    let Some(alpha_fields) = alpha_fields else {
+        return Ok(());
+    };
+    let spec = spec_mut(object)?;
+
+    if kind == "LatchkeyTool" {
+        if let Some(Value::Object(mut limits)) = spec.remove("limits") {
+            for field in TOOL_LIMIT_FIELDS {
+                if let Some(value) = limits.remove(*field) {
+                    spec.insert((*field).to_string(), value);
+                }
+            }
+            if !limits.is_empty() {
+                spec.insert("limits".to_string(), Value::Object(limits));
+            }
+        }
+    }
+
+    let dropped: Vec<String> =
+        spec.keys().filter(|key| !alpha_fields.contains(&key.as_str())).cloned().collect();
+    let preserved: Map<String, Value> =
+        dropped.into_iter().filter_map(|key| spec.remove(&key).map(|value| (key, value))).collect();
+
+    if preserved.is_empty() {
+        remove_annotation(object);
+    } else {
+        let encoded = serde_json::to_string(&preserved)?;
+        annotations_mut(object)?.insert(PRESERVED_FIELDS_ANNOTATION.to_string(), encoded.into());
+    }
+
+    Ok(())
+}
+
+fn up(object: &mut Value, kind: &str) -> anyhow::Result<()> {
+    let preserved = match remove_annotation(object) {
+        Some(encoded) => serde_json::from_str::<Map<String, Value>>(&encoded)
+            .context("preserved v1beta1 fields annotation is not a JSON object")?,
+        None => Map::new(),
+    };
+    let spec = spec_mut(object)?;
+
+    if kind == "LatchkeyTool" {
+        let mut limits = Map::new();
+        for field in TOOL_LIMIT_FIELDS {
+            if let Some(value) = spec.remove(*field) {
+                limits.insert((*field).to_string(), value);
+            }
+        }
+        if !limits.is_empty() {
+            spec.insert("limits".to_string(), Value::Object(limits));
+        }
+    }
+
+    // Fields written through v1alpha1 win over anything preserved from an earlier v1beta1 write.
+    for (key, value) in preserved {
+        match (spec.get_mut(&key), value) {
+            (Some(Value::Object(current)), Value::Object(preserved)) => {
+                for (field, value) in preserved {
+                    current.entry(field).or_insert(value);
+                }
+            }
+            (Some(_), _) => {}
+            (None, value) => {
+                spec.insert(key, value);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn spec_mut(object: &mut Value) -> anyhow::Result<&mut Map<String, Value>> {
+    object.get_mut("spec").and_then(Value::as_object_mut).context("object has no spec")
+}
+
+fn annotations_mut(object: &mut Value) -> anyhow::Result<&mut Map<String, Value>> {
+    let metadata = object
+        .get_mut("metadata")
+        .and_then(Value::as_object_mut)
+        .context("object has no metadata")?;
+    metadata
+        .entry("annotations")
+        .or_insert_with(|| Value::Object(Map::new()))
+        .as_object_mut()
+        .context("metadata.annotations is not an object")
+}
+
+fn remove_annotation(object: &mut Value) -> Option<String> {
+    let metadata = object.get_mut("metadata")?.as_object_mut()?;
+    let annotations = metadata.get_mut("annotations")?.as_object_mut()?;
+    let value = annotations.remove(PRESERVED_FIELDS_ANNOTATION)?;
+
+    if annotations.is_empty() {
+        metadata.remove("annotations");
+    }
+
+    value.as_str().map(ToString::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn object(api_version: &str, kind: &str, spec: Value) -> Value {
+        json!({
+            "apiVersion": api_version,
+            "kind": kind,
+            "metadata": {
+                "name": "github",
+                "namespace": "tools",
+                "annotations": { "team.example.com/owner": "platform" },
+            },
+            "spec": spec,
+        })
+    }
+
+    /// One `v1beta1` object of each kind, using fields `v1alpha1` lacks where the kind has any.
+    fn beta_objects() -> Vec<Value> {
+        vec![
+            object(
+                V1BETA1,
+                "LatchkeyServer",
+                json!({
+                    "image": "ghcr.io/example/github-mcp:1",
+                    "replicas": 2,
+                    "secrets": [{
+                        "name": "token",
+                        "source": { "kubernetesSecret": { "name": "github", "key": "token" } },
+                        "mount": { "mode": "env", "envName": "GITHUB_TOKEN" },
+                    }],
+                    "egressPolicy": { "allow": [{ "cidr": "140.82.112.0/20" }] },
+                }),
+            ),
+            object(
+                V1BETA1,
+                "LatchkeyTool",
+                json!({
+                    "toolName": "github.search",
+                    "serverRef": "github",
+                    "limits": { "maxPayloadBytes": 1024, "timeoutMs": 2000, "rateLimit": 60 },
+                    "operations": [{ "opName": "search", "risk": "read" }],
+                }),
+            ),
+            object(
+                V1BETA1,
+                "LatchkeyPrincipal",
+                json!({ "principalId": "agent", "authMode": "oidc", "enabled": true }),
+            ),
+            object(
+                V1BETA1,
+                "LatchkeyPolicy",
+                json!({
+                    "subjects": ["agent"],
+                    "scopes": ["tools:github.search:read"],
+                    "constraints": { "rateLimitPerMinute": 30 },
+                }),
+            ),
+        ]
+    }
+
+    fn alpha_objects() -> Vec<Value> {
+        vec![
+            object(V1ALPHA1, "LatchkeyServer", json!({ "image": "tool:1", "transport": "http" })),
+            object(
+                V1ALPHA1,
+                "LatchkeyTool",
+                json!({
+                    "toolName": "github.search",
+                    "serverRef": "github",
+                    "maxPayloadBytes": 1024,
+                    "timeoutMs": 2000,
+                }),
+            ),
+            object(
+                V1ALPHA1,
+                "LatchkeyPrincipal",
+                json!({ "principalId": "agent", "authMode": "oidc", "enabled": true }),
+            ),
+            object(
+                V1ALPHA1,
+                "LatchkeyPolicy",
+                json!({ "subjects": ["agent"], "scopes": ["tools:github.search:read"] }),
+            ),
+        ]
+    }
+
+    fn annotations(object: &Value) -> &Value {
+        &object["metadata"]["annotations"]
+    }
+
+    #[test]
+    fn beta_round_trips_through_alpha() {
+        for beta in beta_objects() {
+            let alpha = convert(beta.clone(), V1ALPHA1).unwrap();
+            assert_eq!(alpha["apiVersion"], V1ALPHA1);
+            assert_eq!(annotations(&alpha)["team.example.com/owner"], "platform");
+            assert_eq!(convert(alpha, V1BETA1).unwrap(), beta, "{}", beta["kind"]);
+        }
+    }
+
+    #[test]
+    fn alpha_round_trips_through_beta() {
+        for alpha in alpha_objects() {
+            let beta = convert(alpha.clone(), V1BETA1).unwrap();
+            assert_eq!(beta["apiVersion"], V1BETA1);
+            assert_eq!(convert(beta, V1ALPHA1).unwrap(), alpha, "{}", alpha["kind"]);
+        }
+    }
+
+    #[test]
+    fn stashes_beta_only_fields_in_an_annotation() {
+        let [server, tool, principal, _] = <[Value; 4]>::try_from(beta_objects()).unwrap();
+
+        let alpha = convert(server, V1ALPHA1).unwrap();
+        assert_eq!(
+            alpha["spec"],
+            json!({ "image": "ghcr.io/example/github-mcp:1", "replicas": 2 })
+        );
+        let stashed: Value = serde_json::from_str(
+            annotations(&alpha)[PRESERVED_FIELDS_ANNOTATION].as_str().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(stashed.as_object().unwrap().len(), 2);
+        assert_eq!(stashed["egressPolicy"]["allow"][0]["cidr"], "140.82.112.0/20");
+
+        // Limits alpha has are flattened; the rest of `limits` waits in the annotation.
+        let alpha = convert(tool, V1ALPHA1).unwrap();
+        assert_eq!(alpha["spec"]["maxPayloadBytes"], 1024);
+        assert_eq!(alpha["spec"]["timeoutMs"], 2000);
+        assert!(alpha["spec"].get("limits").is_none());
+        let stashed: Value = serde_json::from_str(
+            annotations(&alpha)[PRESERVED_FIELDS_ANNOTATION].as_str().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(stashed["limits"], json!({ "rateLimit": 60 }));
+
+        // A kind without beta-only fields is passed through untouched.
+        let alpha = convert(principal.clone(), V1ALPHA1).unwrap();
+        assert_eq!(alpha["spec"], principal["spec"]);
+        assert!(annotations(&alpha).get(PRESERVED_FIELDS_ANNOTATION).is_none());
+    }
+
+    #[test]
+    fn alpha_writes_win_over_stashed_fields() {
+        let tool = beta_objects().remove(1);
+        let mut alpha = convert(tool, V1ALPHA1).unwrap();
+        alpha["spec"]["timeoutMs"] = json!(500);
+
+        let beta = convert(alpha, V1BETA1).unwrap();
+        assert_eq!(
+            beta["spec"]["limits"],
+            json!({ "maxPayloadBytes": 1024, "timeoutMs": 500, "rateLimit": 60 })
+        );
+        assert_eq!(annotations(&beta), &json!({ "team.example.com/owner": "platform" }));
+    }
+
+    #[test]
+    fn refuses_what_it_cannot_convert() {
+        let server = beta_objects().remove(0);
+        assert!(convert(server.clone(), "latchkey.dev/v2").is_err());
+        let mut unknown = server;
+        unknown["kind"] = json!("LatchkeyGateway");
+        assert!(convert(unknown, V1ALPHA1).is_err());
+    }
+}
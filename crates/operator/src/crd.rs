@@ -1,91 +1,122 @@
-use kube::CustomResource;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(CustomResource, Debug, Clone, Serialize, Deserialize, JsonSchema)]
-#[kube(
-    group = "latchkey.dev",
-    version = "v1alpha1",
-    kind = "LatchkeyServer",
-    plural = "latchkeyservers",
-    namespaced,
-    status = "LatchkeyServerStatus"
-)]
-pub struct LatchkeyServerSpec {
-    pub image: String,
-    pub replicas: Option<i32>,
-    pub transport: Option<String>,
-    pub service_port: Option<u16>,
+pub mod v1alpha1;
+pub mod v1beta1;
+
+/// The storage version. The rest of the operator works against these types; objects served as
+/// `v1alpha1` are converted by the conversion webhook.
+pub use v1beta1::{
+    LatchkeyPolicy, LatchkeyPolicySpec, LatchkeyPrincipal, LatchkeyPrincipalSpec, LatchkeyServer,
+    LatchkeyServerSpec, LatchkeyTool, LatchkeyToolSpec,
+};
+
+pub const STORAGE_VERSION: &str = "v1beta1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    Http,
+    Stdio,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct LatchkeyServerStatus {
     pub ready_replicas: Option<i32>,
     pub endpoints: Option<Vec<String>>,
     pub conditions: Option<Vec<String>>,
+    pub secrets: Option<Vec<SecretBindingStatus>>,
+    /// RFC 3339 time at which a write to a bound Secret last rolled the Deployment.
+    pub last_secret_rotation: Option<String>,
+    /// Digest of the bound Secret versions last rolled out, matching the pod template's
+    /// `latchkey.dev/secret-hash`.
+    pub secret_hash: Option<String>,
 }
 
-#[derive(CustomResource, Debug, Clone, Serialize, Deserialize, JsonSchema)]
-#[kube(
-    group = "latchkey.dev",
-    version = "v1alpha1",
-    kind = "LatchkeyTool",
-    plural = "latchkeytools",
-    namespaced,
-    status = "LatchkeyToolStatus"
-)]
-pub struct LatchkeyToolSpec {
-    pub tool_name: String,
-    pub server_ref: String,
-    pub tool_selector: Option<String>,
-    pub max_payload_bytes: Option<u64>,
-    pub timeout_ms: Option<u64>,
+/// Whether a bound secret resolved. Never carries the secret value.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretBindingStatus {
+    pub name: String,
+    pub ready: bool,
+    pub message: Option<String>,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct LatchkeyToolStatus {
     pub resolved_server: Option<String>,
     pub conditions: Option<Vec<String>>,
 }
 
-#[derive(CustomResource, Debug, Clone, Serialize, Deserialize, JsonSchema)]
-#[kube(
-    group = "latchkey.dev",
-    version = "v1alpha1",
-    kind = "LatchkeyPrincipal",
-    plural = "latchkeyprincipals",
-    namespaced,
-    status = "LatchkeyPrincipalStatus"
-)]
-pub struct LatchkeyPrincipalSpec {
-    pub principal_id: String,
-    pub auth_mode: String,
-    pub enabled: bool,
-    pub policy_refs: Option<Vec<String>>,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthMode {
+    Oidc,
+    GatewaySecret,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OidcIdentity {
+    pub issuer: String,
+    pub subject_match: Option<String>,
+    pub client_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GatewaySecretIdentity {
+    pub client_id: String,
+    pub secret_ref: SecretKeyRef,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretKeyRef {
+    pub name: String,
+    pub key: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenPolicy {
+    pub capability_tokens_enabled: Option<bool>,
+    #[serde(rename = "capabilityTTLSeconds")]
+    #[schemars(range(min = 60, max = 120))]
+    pub capability_ttl_seconds: Option<u32>,
+    pub require_request_binding: Option<bool>,
+    pub allow_tool_discovery: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct LatchkeyPrincipalStatus {
     pub conditions: Option<Vec<String>>,
 }
 
-#[derive(CustomResource, Debug, Clone, Serialize, Deserialize, JsonSchema)]
-#[kube(
-    group = "latchkey.dev",
-    version = "v1alpha1",
-    kind = "LatchkeyPolicy",
-    plural = "latchkeypolicies",
-    namespaced,
-    status = "LatchkeyPolicyStatus"
-)]
-pub struct LatchkeyPolicySpec {
-    pub subjects: Vec<String>,
-    pub scopes: Vec<String>,
-    pub break_glass: Option<bool>,
-    pub audit_level: Option<String>,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditLevel {
+    Normal,
+    Verbose,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct LatchkeyPolicyStatus {
     pub conditions: Option<Vec<String>>,
 }
+
+/// Schema for free-form JSON such as per-operation parameter schemas.
+pub fn preserve_unknown_fields(_: &mut SchemaGenerator) -> Schema {
+    serde_json::from_value(serde_json::json!({
+        "type": "object",
+        "nullable": true,
+        "x-kubernetes-preserve-unknown-fields": true,
+    }))
+    .expect("static schema is valid")
+}
@@ -0,0 +1,101 @@
+use super::{
+    AuditLevel, AuthMode, GatewaySecretIdentity, LatchkeyPolicyStatus, LatchkeyPrincipalStatus,
+    LatchkeyServerStatus, LatchkeyToolStatus, OidcIdentity, TokenPolicy, Transport,
+};
+use kube::CustomResource;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(CustomResource, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[kube(
+    group = "latchkey.dev",
+    version = "v1alpha1",
+    kind = "LatchkeyServer",
+    plural = "latchkeyservers",
+    shortname = "lks",
+    category = "latchkey",
+    namespaced,
+    status = "LatchkeyServerStatus",
+    printcolumn = r#"{"name":"Image","type":"string","jsonPath":".spec.image"}"#,
+    printcolumn = r#"{"name":"Transport","type":"string","jsonPath":".spec.transport"}"#,
+    printcolumn = r#"{"name":"Ready","type":"integer","jsonPath":".status.readyReplicas"}"#,
+    printcolumn = r#"{"name":"Age","type":"date","jsonPath":".metadata.creationTimestamp"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct LatchkeyServerSpec {
+    pub image: String,
+    pub replicas: Option<i32>,
+    pub transport: Option<Transport>,
+    pub service_port: Option<u16>,
+}
+
+#[derive(CustomResource, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[kube(
+    group = "latchkey.dev",
+    version = "v1alpha1",
+    kind = "LatchkeyTool",
+    plural = "latchkeytools",
+    shortname = "lkt",
+    category = "latchkey",
+    namespaced,
+    status = "LatchkeyToolStatus",
+    printcolumn = r#"{"name":"Tool","type":"string","jsonPath":".spec.toolName"}"#,
+    printcolumn = r#"{"name":"Server","type":"string","jsonPath":".spec.serverRef"}"#,
+    printcolumn = r#"{"name":"Age","type":"date","jsonPath":".metadata.creationTimestamp"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct LatchkeyToolSpec {
+    pub tool_name: String,
+    pub server_ref: String,
+    pub tool_selector: Option<String>,
+    pub max_payload_bytes: Option<u64>,
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(CustomResource, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[kube(
+    group = "latchkey.dev",
+    version = "v1alpha1",
+    kind = "LatchkeyPrincipal",
+    plural = "latchkeyprincipals",
+    shortname = "lkp",
+    category = "latchkey",
+    namespaced,
+    status = "LatchkeyPrincipalStatus",
+    printcolumn = r#"{"name":"Principal","type":"string","jsonPath":".spec.principalId"}"#,
+    printcolumn = r#"{"name":"Auth","type":"string","jsonPath":".spec.authMode"}"#,
+    printcolumn = r#"{"name":"Enabled","type":"boolean","jsonPath":".spec.enabled"}"#,
+    printcolumn = r#"{"name":"Age","type":"date","jsonPath":".metadata.creationTimestamp"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct LatchkeyPrincipalSpec {
+    pub principal_id: String,
+    pub auth_mode: AuthMode,
+    pub enabled: bool,
+    pub oidc: Option<OidcIdentity>,
+    pub gateway_secret: Option<GatewaySecretIdentity>,
+    pub policy_refs: Option<Vec<String>>,
+    pub token_policy: Option<TokenPolicy>,
+}
+
+#[derive(CustomResource, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[kube(
+    group = "latchkey.dev",
+    version = "v1alpha1",
+    kind = "LatchkeyPolicy",
+    plural = "latchkeypolicies",
+    shortname = "lkpol",
+    category = "latchkey",
+    namespaced,
+    status = "LatchkeyPolicyStatus",
+    printcolumn = r#"{"name":"Break-Glass","type":"boolean","jsonPath":".spec.breakGlass"}"#,
+    printcolumn = r#"{"name":"Audit","type":"string","jsonPath":".spec.auditLevel"}"#,
+    printcolumn = r#"{"name":"Age","type":"date","jsonPath":".metadata.creationTimestamp"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct LatchkeyPolicySpec {
+    pub subjects: Vec<String>,
+    pub scopes: Vec<String>,
+    pub break_glass: Option<bool>,
+    pub audit_level: Option<AuditLevel>,
+}
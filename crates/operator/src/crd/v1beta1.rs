@@ -0,0 +1,314 @@
+use super::{
+    preserve_unknown_fields, AuditLevel, AuthMode, GatewaySecretIdentity, LatchkeyPolicyStatus,
+    LatchkeyPrincipalStatus, LatchkeyServerStatus, LatchkeyToolStatus, OidcIdentity, TokenPolicy,
+    Transport,
+};
+use kube::CustomResource;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+#[derive(CustomResource, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[kube(
+    group = "latchkey.dev",
+    version = "v1beta1",
+    kind = "LatchkeyServer",
+    plural = "latchkeyservers",
+    shortname = "lks",
+    category = "latchkey",
+    namespaced,
+    status = "LatchkeyServerStatus",
+    printcolumn = r#"{"name":"Image","type":"string","jsonPath":".spec.image"}"#,
+    printcolumn = r#"{"name":"Transport","type":"string","jsonPath":".spec.transport"}"#,
+    printcolumn = r#"{"name":"Ready","type":"integer","jsonPath":".status.readyReplicas"}"#,
+    printcolumn = r#"{"name":"Age","type":"date","jsonPath":".metadata.creationTimestamp"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct LatchkeyServerSpec {
+    pub image: String,
+    pub replicas: Option<i32>,
+    pub transport: Option<Transport>,
+    /// How to start a `transport: stdio` server from its image. Required for stdio servers, since
+    /// the operator replaces the container entrypoint with the MCP bridge.
+    pub stdio: Option<StdioCommand>,
+    pub service_port: Option<u16>,
+    pub resources: Option<ServerResources>,
+    pub security_context: Option<ServerSecurityContext>,
+    pub health: Option<HealthProbes>,
+    pub pod_metadata: Option<PodMetadata>,
+    pub secrets: Option<Vec<SecretBinding>>,
+    /// Destinations the tool server may reach. Unset means no egress at all, not even DNS.
+    pub egress_policy: Option<EgressPolicy>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StdioCommand {
+    pub command: Vec<String>,
+    pub args: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EgressPolicy {
+    #[serde(default)]
+    pub allow: Vec<EgressRule>,
+    /// Hostnames reached through the injected egress proxy, which NetworkPolicy cannot express.
+    pub hosts: Option<Vec<EgressHost>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EgressHost {
+    /// Exact name (`api.github.com`) or leading wildcard (`*.githubusercontent.com`).
+    pub host: String,
+    /// Defaults to `[443]`.
+    pub ports: Option<Vec<u16>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EgressRule {
+    /// IPv4 or IPv6 CIDR, e.g. `203.0.113.0/24`.
+    pub cidr: String,
+    /// CIDRs inside `cidr` to carve back out.
+    pub except: Option<Vec<String>>,
+    /// Unset allows every port on `cidr`.
+    pub ports: Option<Vec<EgressPort>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EgressPort {
+    pub port: u16,
+    /// Defaults to `TCP`.
+    pub protocol: Option<EgressProtocol>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum EgressProtocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+/// Binds one upstream credential to the tool server pod. Exactly one source must be set.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretBinding {
+    pub name: String,
+    pub source: SecretSource,
+    pub mount: SecretMount,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretSource {
+    pub kubernetes_secret: Option<KubernetesSecretSource>,
+    pub vault_secret: Option<VaultSecretSource>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct KubernetesSecretSource {
+    pub name: String,
+    pub key: String,
+}
+
+/// Delivered by the injected Vault agent, which logs in with the pod's service account and keeps
+/// the file current. `path` may be a KV path (`secret/data/github`) or a dynamic secrets role
+/// (`database/creds/readonly`). Only `file` mounts are supported.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct VaultSecretSource {
+    /// Defaults to the operator's `LATCHKEY_VAULT_ADDR`.
+    pub address: Option<String>,
+    /// Kubernetes auth method mount; defaults to `kubernetes`.
+    pub auth_mount: Option<String>,
+    pub role: String,
+    pub path: String,
+    pub key: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretMount {
+    pub mode: SecretMountMode,
+    /// Env var name for `env` mounts. For `file` mounts, `<envName>_FILE` is set to the file path.
+    pub env_name: Option<String>,
+    /// Directory for `file` mounts; must live under `/var/run/secrets/latchkey/`.
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SecretMountMode {
+    Env,
+    File,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerResources {
+    pub requests: Option<BTreeMap<String, String>>,
+    pub limits: Option<BTreeMap<String, String>>,
+}
+
+/// Overrides on top of the hardened baseline; the operator always enforces non-root, no
+/// privilege escalation and dropped capabilities.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerSecurityContext {
+    pub run_as_user: Option<i64>,
+    pub read_only_root_filesystem: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthProbes {
+    pub readiness_path: Option<String>,
+    pub liveness_path: Option<String>,
+    pub period_seconds: Option<i32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PodMetadata {
+    pub labels: Option<BTreeMap<String, String>>,
+    pub annotations: Option<BTreeMap<String, String>>,
+}
+
+#[derive(CustomResource, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[kube(
+    group = "latchkey.dev",
+    version = "v1beta1",
+    kind = "LatchkeyTool",
+    plural = "latchkeytools",
+    shortname = "lkt",
+    category = "latchkey",
+    namespaced,
+    status = "LatchkeyToolStatus",
+    printcolumn = r#"{"name":"Tool","type":"string","jsonPath":".spec.toolName"}"#,
+    printcolumn = r#"{"name":"Server","type":"string","jsonPath":".spec.serverRef"}"#,
+    printcolumn = r#"{"name":"Age","type":"date","jsonPath":".metadata.creationTimestamp"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct LatchkeyToolSpec {
+    pub tool_name: String,
+    pub server_ref: String,
+    pub tool_selector: Option<String>,
+    pub operations: Option<Vec<ToolOperation>>,
+    pub limits: Option<ToolLimits>,
+    pub audit: Option<ToolAudit>,
+    pub visibility: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolOperation {
+    pub op_name: String,
+    pub allowed: Option<bool>,
+    #[serde(default)]
+    #[schemars(schema_with = "preserve_unknown_fields")]
+    pub schema: Option<Value>,
+    pub risk: Option<ToolRisk>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolRisk {
+    Read,
+    Write,
+    Destructive,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolLimits {
+    pub max_payload_bytes: Option<u64>,
+    pub max_response_bytes: Option<u64>,
+    pub timeout_ms: Option<u64>,
+    pub rate_limit: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolAudit {
+    pub redaction_rules: Option<Vec<RedactionRule>>,
+}
+
+/// `path` is a JSON pointer or a dotted glob starting at `params` or `response`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RedactionRule {
+    pub path: String,
+    pub action: RedactionAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RedactionAction {
+    Drop,
+    Mask,
+    Hash,
+}
+
+#[derive(CustomResource, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[kube(
+    group = "latchkey.dev",
+    version = "v1beta1",
+    kind = "LatchkeyPrincipal",
+    plural = "latchkeyprincipals",
+    shortname = "lkp",
+    category = "latchkey",
+    namespaced,
+    status = "LatchkeyPrincipalStatus",
+    printcolumn = r#"{"name":"Principal","type":"string","jsonPath":".spec.principalId"}"#,
+    printcolumn = r#"{"name":"Auth","type":"string","jsonPath":".spec.authMode"}"#,
+    printcolumn = r#"{"name":"Enabled","type":"boolean","jsonPath":".spec.enabled"}"#,
+    printcolumn = r#"{"name":"Age","type":"date","jsonPath":".metadata.creationTimestamp"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct LatchkeyPrincipalSpec {
+    pub principal_id: String,
+    pub auth_mode: AuthMode,
+    pub enabled: bool,
+    pub oidc: Option<OidcIdentity>,
+    pub gateway_secret: Option<GatewaySecretIdentity>,
+    pub policy_refs: Option<Vec<String>>,
+    pub token_policy: Option<TokenPolicy>,
+}
+
+#[derive(CustomResource, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[kube(
+    group = "latchkey.dev",
+    version = "v1beta1",
+    kind = "LatchkeyPolicy",
+    plural = "latchkeypolicies",
+    shortname = "lkpol",
+    category = "latchkey",
+    namespaced,
+    status = "LatchkeyPolicyStatus",
+    printcolumn = r#"{"name":"Break-Glass","type":"boolean","jsonPath":".spec.breakGlass"}"#,
+    printcolumn = r#"{"name":"Audit","type":"string","jsonPath":".spec.auditLevel"}"#,
+    printcolumn = r#"{"name":"Age","type":"date","jsonPath":".metadata.creationTimestamp"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct LatchkeyPolicySpec {
+    pub subjects: Vec<String>,
+    pub scopes: Vec<String>,
+    pub constraints: Option<PolicyConstraints>,
+    pub break_glass: Option<bool>,
+    pub audit_level: Option<AuditLevel>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyConstraints {
+    pub allowed_operations: Option<Vec<String>>,
+    pub rate_limit_per_minute: Option<u32>,
+    pub time_windows: Option<Vec<String>>,
+    pub max_concurrency: Option<u32>,
+}
@@ -0,0 +1,126 @@
+use crate::crd::{v1alpha1, v1beta1, STORAGE_VERSION};
+use anyhow::Context;
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::{
+    CustomResourceConversion, CustomResourceDefinition, ServiceReference, WebhookClientConfig,
+    WebhookConversion,
+};
+use kube::core::crd::merge_crds;
+use kube::CustomResourceExt;
+use std::path::Path;
+
+const HEADER: &str = "# Generated by `latchkey-operator crdgen`. Do not edit by hand.\n";
+
+pub const CONVERSION_SERVICE: &str = "latchkey-operator-webhook";
+pub const CONVERSION_NAMESPACE: &str = "latchkey-system";
+pub const CONVERSION_PATH: &str = "/convert";
+
+pub fn crds() -> anyhow::Result<Vec<CustomResourceDefinition>> {
+    [
+        (v1alpha1::LatchkeyServer::crd(), v1beta1::LatchkeyServer::crd()),
+        (v1alpha1::LatchkeyTool::crd(), v1beta1::LatchkeyTool::crd()),
+        (v1alpha1::LatchkeyPrincipal::crd(), v1beta1::LatchkeyPrincipal::crd()),
+        (v1alpha1::LatchkeyPolicy::crd(), v1beta1::LatchkeyPolicy::crd()),
+    ]
+    .into_iter()
+    .map(|(alpha, beta)| {
+        let mut crd = merge_crds(vec![beta, alpha], STORAGE_VERSION)?;
+        crd.spec.conversion = Some(webhook_conversion());
+        Ok(crd)
+    })
+    .collect()
+}
+
+/// The caBundle is left empty here and injected by the operator at startup.
+fn webhook_conversion() -> CustomResourceConversion {
+    CustomResourceConversion {
+        strategy: "Webhook".to_string(),
+        webhook: Some(WebhookConversion {
+            client_config: Some(WebhookClientConfig {
+                service: Some(ServiceReference {
+                    name: CONVERSION_SERVICE.to_string(),
+                    namespace: CONVERSION_NAMESPACE.to_string(),
+                    path: Some(CONVERSION_PATH.to_string()),
+                    port: Some(443),
+                }),
+                ..WebhookClientConfig::default()
+            }),
+            conversion_review_versions: vec!["v1".to_string()],
+        }),
+    }
+}
+
+pub fn render(crd: &CustomResourceDefinition) -> anyhow::Result<String> {
+    let body = serde_yaml::to_string(crd).context("failed to serialize crd")?;
+    Ok(format!("{HEADER}{body}"))
+}
+
+pub fn file_name(crd: &CustomResourceDefinition) -> String {
+    format!("{}.yaml", crd.spec.names.plural)
+}
+
+/// Writes one manifest per CRD into `out_dir`, or prints them as a multi-document stream.
+pub fn run(out_dir: Option<&Path>) -> anyhow::Result<()> {
+    let mut documents = Vec::new();
+
+    for crd in crds()? {
+        let rendered = render(&crd)?;
+        match out_dir {
+            Some(dir) => {
+                let path = dir.join(file_name(&crd));
+                std::fs::write(&path, rendered)
+                    .with_context(|| format!("failed to write {}", path.display()))?;
+            }
+            None => documents.push(rendered),
+        }
+    }
+
+    if out_dir.is_none() {
+        print!("{}", documents.join("---\n"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHECKED_IN: &[(&str, &str)] = &[
+        (
+            "latchkeyservers.yaml",
+            include_str!("../../../deploy/kustomize/base/crds/latchkeyservers.yaml"),
+        ),
+        (
+            "latchkeytools.yaml",
+            include_str!("../../../deploy/kustomize/base/crds/latchkeytools.yaml"),
+        ),
+        (
+            "latchkeyprincipals.yaml",
+            include_str!("../../../deploy/kustomize/base/crds/latchkeyprincipals.yaml"),
+        ),
+        (
+            "latchkeypolicies.yaml",
+            include_str!("../../../deploy/kustomize/base/crds/latchkeypolicies.yaml"),
+        ),
+    ];
+
+    #[test]
+    fn checked_in_crds_match_generated_output() {
+        let generated = crds().unwrap();
+        assert_eq!(generated.len(), CHECKED_IN.len());
+
+        for crd in &generated {
+            let name = file_name(crd);
+            let (_, expected) = CHECKED_IN
+                .iter()
+                .find(|(file, _)| *file == name)
+                .unwrap_or_else(|| panic!("no checked-in manifest for {name}"));
+
+            assert_eq!(
+                render(crd).unwrap(),
+                *expected,
+                "{name} is stale; run `just crdgen` to regenerate it"
+            );
+        }
+    }
+}
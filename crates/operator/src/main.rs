@@ -1,26 +1,58 @@
+mod bridge;
+mod certs;
+mod conversion;
 mod crd;
-
-use crate::crd::{LatchkeyPolicy, LatchkeyPrincipal, LatchkeyServer, LatchkeyTool};
+mod crdgen;
+mod migrate;
+mod network;
+mod secrets;
+mod server;
+mod webhook;
+
+use crate::crd::{LatchkeyPolicy, LatchkeyPrincipal, LatchkeyTool};
 use anyhow::Context;
 use futures::{StreamExt, TryStreamExt};
 use kube::{Api, Client, ResourceExt};
 use kube_runtime::watcher::{self, Event};
+use std::path::PathBuf;
 use tokio::task::JoinSet;
 use tracing::{error, info};
 use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    if let Some(command) = args.next() {
+        return match command.as_str() {
+            "crdgen" => {
+                let out_dir = match (args.next().as_deref(), args.next()) {
+                    (Some("--out-dir"), Some(dir)) => Some(PathBuf::from(dir)),
+                    (None, None) => None,
+                    _ => anyhow::bail!("usage: latchkey-operator crdgen [--out-dir <dir>]"),
+                };
+                crdgen::run(out_dir.as_deref())
+            }
+            "migrate-storage" => {
+                init_tracing();
+                let client =
+                    Client::try_default().await.context("failed to create kubernetes client")?;
+                migrate::run(client).await
+            }
+            other => anyhow::bail!("unknown subcommand: {other}"),
+        };
+    }
+
     init_tracing();
     info!("operator booted");
 
     let client = Client::try_default().await.context("failed to create kubernetes client")?;
 
     let mut tasks = JoinSet::new();
-    tasks.spawn(watch_servers(client.clone()));
+    tasks.spawn(server::run(client.clone()));
     tasks.spawn(watch_tools(client.clone()));
     tasks.spawn(watch_principals(client.clone()));
-    tasks.spawn(watch_policies(client));
+    tasks.spawn(watch_policies(client.clone()));
+    tasks.spawn(webhook::serve(client, webhook::WebhookConfig::from_env()?));
 
     tokio::select! {
         _ = tokio::signal::ctrl_c() => {
@@ -41,19 +73,6 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn watch_servers(client: Client) -> anyhow::Result<()> {
-    let api: Api<LatchkeyServer> = Api::all(client);
-    let mut stream = watcher::watcher(api, watcher::Config::default()).boxed();
-
-    while let Some(event) =
-        stream.try_next().await.context("latchkeyserver watch stream failure")?
-    {
-        log_event("LatchkeyServer", event);
-    }
-
-    Ok(())
-}
-
 async fn watch_tools(client: Client) -> anyhow::Result<()> {
     let api: Api<LatchkeyTool> = Api::all(client);
     let mut stream = watcher::watcher(api, watcher::Config::default()).boxed();
@@ -0,0 +1,152 @@
+use crate::crd::{
+    LatchkeyPolicy, LatchkeyPrincipal, LatchkeyServer, LatchkeyTool, STORAGE_VERSION,
+};
+use anyhow::Context;
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use k8s_openapi::NamespaceResourceScope;
+use kube::api::{ListParams, Patch, PatchParams, PostParams};
+use kube::{Api, Client, Resource, ResourceExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::json;
+use std::fmt::Debug;
+use tracing::info;
+
+/// Rewrites every Latchkey object in the storage version, then drops older versions from each
+/// CRD's `status.storedVersions` so they can eventually stop being served.
+pub async fn run(client: Client) -> anyhow::Result<()> {
+    migrate::<LatchkeyServer>(&client).await?;
+    migrate::<LatchkeyTool>(&client).await?;
+    migrate::<LatchkeyPrincipal>(&client).await?;
+    migrate::<LatchkeyPolicy>(&client).await?;
+
+    Ok(())
+}
+
+async fn migrate<K>(client: &Client) -> anyhow::Result<()>
+where
+    K: Resource<Scope = NamespaceResourceScope, DynamicType = ()>
+        + Clone
+        + Debug
+        + Serialize
+        + DeserializeOwned,
+{
+    let plural = K::plural(&());
+    let crd_name = format!("{plural}.{}", K::group(&()));
+    let objects = Api::<K>::all(client.clone())
+        .list(&ListParams::default())
+        .await
+        .with_context(|| format!("failed to list {plural}"))?;
+
+    let mut migrated = 0usize;
+    for object in objects {
+        let name = object.name_any();
+        let namespace = object.namespace().unwrap_or_default();
+        let api: Api<K> = Api::namespaced(client.clone(), &namespace);
+
+        // A no-op update is enough: the API server persists the object in the storage version.
+        api.replace(&name, &PostParams::default(), &object)
+            .await
+            .with_context(|| format!("failed to rewrite {plural} {namespace}/{name}"))?;
+        migrated += 1;
+    }
+
+    let crds: Api<CustomResourceDefinition> = Api::all(client.clone());
+    crds.patch_status(
+        &crd_name,
+        &PatchParams::default(),
+        &Patch::Merge(json!({"status": {"storedVersions": [STORAGE_VERSION]}})),
+    )
+    .await
+    .with_context(|| format!("failed to update storedVersions on {crd_name}"))?;
+
+    info!(crd = %crd_name, migrated, storage_version = STORAGE_VERSION, "storage version migrated");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Bytes;
+    use axum::http::{Method, Uri};
+    use axum::{Json, Router};
+    use serde_json::Value;
+    use std::sync::{Arc, Mutex};
+
+    fn list(kind: &str, items: Vec<Value>) -> Value {
+        json!({
+            "apiVersion": "latchkey.dev/v1beta1",
+            "kind": format!("{kind}List"),
+            "metadata": {},
+            "items": items,
+        })
+    }
+
+    fn crd(name: &str) -> Value {
+        json!({
+            "apiVersion": "apiextensions.k8s.io/v1",
+            "kind": "CustomResourceDefinition",
+            "metadata": { "name": name },
+            "spec": {
+                "group": "latchkey.dev",
+                "names": { "kind": "Latchkey", "plural": "latchkeys" },
+                "scope": "Namespaced",
+                "versions": [],
+            },
+        })
+    }
+
+    #[tokio::test]
+    async fn rewrites_every_object_then_drops_old_stored_versions() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        // An API server holding one LatchkeyServer and nothing else.
+        let api_server = Router::new().fallback(move |method: Method, uri: Uri, body: Bytes| {
+            let recorded = recorded.clone();
+            async move {
+                let body: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+                let path = uri.path().to_string();
+                recorded.lock().unwrap().push((method.to_string(), path.clone(), body.clone()));
+                Json(match method {
+                    Method::PUT => body,
+                    Method::PATCH => crd(path.split('/').nth_back(1).unwrap()),
+                    _ if path.ends_with("/latchkeyservers") => list(
+                        "LatchkeyServer",
+                        vec![json!({
+                            "apiVersion": "latchkey.dev/v1beta1",
+                            "kind": "LatchkeyServer",
+                            "metadata": { "name": "github", "namespace": "tools" },
+                            "spec": { "image": "tool:1" },
+                        })],
+                    ),
+                    _ => list("Latchkey", Vec::new()),
+                })
+            }
+        });
+
+        run(Client::new(api_server, "default")).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        let calls: Vec<(&str, &str)> =
+            requests.iter().map(|(method, path, _)| (method.as_str(), path.as_str())).collect();
+        let crds = "/apis/apiextensions.k8s.io/v1/customresourcedefinitions";
+        assert_eq!(
+            calls,
+            vec![
+                ("GET", "/apis/latchkey.dev/v1beta1/latchkeyservers"),
+                ("PUT", "/apis/latchkey.dev/v1beta1/namespaces/tools/latchkeyservers/github"),
+                ("PATCH", &*format!("{crds}/latchkeyservers.latchkey.dev/status")),
+                ("GET", "/apis/latchkey.dev/v1beta1/latchkeytools"),
+                ("PATCH", &*format!("{crds}/latchkeytools.latchkey.dev/status")),
+                ("GET", "/apis/latchkey.dev/v1beta1/latchkeyprincipals"),
+                ("PATCH", &*format!("{crds}/latchkeyprincipals.latchkey.dev/status")),
+                ("GET", "/apis/latchkey.dev/v1beta1/latchkeypolicies"),
+                ("PATCH", &*format!("{crds}/latchkeypolicies.latchkey.dev/status")),
+            ]
+        );
+        assert_eq!(requests[1].2["spec"]["image"], "tool:1");
+        for (_, _, body) in requests.iter().filter(|(method, _, _)| method == "PATCH") {
+            assert_eq!(body, &json!({ "status": { "storedVersions": [STORAGE_VERSION] } }));
+        }
+    }
+}
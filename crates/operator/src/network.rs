@@ -0,0 +1,561 @@
+use crate::crd::v1beta1::{EgressPolicy, EgressPort, EgressProtocol, EgressRule};
+use crate::crd::LatchkeyServer;
+use crate::server;
+use anyhow::Context;
+use k8s_openapi::api::core::v1::{Capabilities, Container, EnvVar, SecurityContext};
+use k8s_openapi::api::networking::v1::{
+    IPBlock, NetworkPolicy, NetworkPolicyEgressRule, NetworkPolicyIngressRule, NetworkPolicyPeer,
+    NetworkPolicyPort, NetworkPolicySpec,
+};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+use kube::ResourceExt;
+use latchkey_core::egress::{AllowedHost, PROXY_ALLOW_ENV, PROXY_PORT};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+const DEFAULT_GATEWAY_NAMESPACE: &str = "latchkey-system";
+const GATEWAY_NAME_LABEL: &str = "latchkey-gateway";
+const NAMESPACE_NAME_LABEL: &str = "kubernetes.io/metadata.name";
+const DNS_NAMESPACE: &str = "kube-system";
+const DNS_LABEL: (&str, &str) = ("k8s-app", "kube-dns");
+const DNS_PORT: i32 = 53;
+
+const DEFAULT_EGRESS_PROXY_IMAGE: &str = "ghcr.io/latchkey/latchkey-egress-proxy:dev";
+const DEFAULT_EGRESS_INIT_IMAGE: &str = "ghcr.io/latchkey/latchkey-egress-init:dev";
+const DEFAULT_HOST_PORT: u16 = 443;
+/// The proxy runs as its own UID so the init rules can tell its traffic from the tool server's.
+/// Every other container is kept off it, since the firewall lets it through unfiltered.
+pub const EGRESS_PROXY_UID: i64 = 65533;
+
+/// Link-local blocks, home to cloud metadata endpoints, kept out of the proxy's any-address rule.
+const LINK_LOCAL: [(&str, &str); 2] = [("0.0.0.0/0", "169.254.0.0/16"), ("::/0", "fe80::/10")];
+
+/// Where routed traffic comes from and how hostname egress is enforced.
+#[derive(Debug, Clone)]
+pub struct NetworkConfig {
+    pub gateway_namespace: String,
+    pub egress_proxy_image: String,
+    /// Needs `sh`, `iptables` and `ip6tables`.
+    pub egress_init_image: String,
+}
+
+impl NetworkConfig {
+    pub fn from_env() -> Self {
+        Self {
+            gateway_namespace: std::env::var("LATCHKEY_GATEWAY_NAMESPACE")
+                .or_else(|_| std::env::var("POD_NAMESPACE"))
+                .unwrap_or_else(|_| DEFAULT_GATEWAY_NAMESPACE.to_string()),
+            egress_proxy_image: std::env::var("LATCHKEY_EGRESS_PROXY_IMAGE")
+                .unwrap_or_else(|_| DEFAULT_EGRESS_PROXY_IMAGE.to_string()),
+            egress_init_image: std::env::var("LATCHKEY_EGRESS_INIT_IMAGE")
+                .unwrap_or_else(|_| DEFAULT_EGRESS_INIT_IMAGE.to_string()),
+        }
+    }
+}
+
+/// Pod fragments that route a tool server's hostname egress through the proxy.
+#[derive(Debug, Default)]
+pub struct EgressSidecar {
+    /// The firewall init container followed by the proxy itself, in start order.
+    pub init_containers: Vec<Container>,
+    /// Proxy settings for every other container in the pod.
+    pub env: Vec<EnvVar>,
+}
+
+/// Isolates one tool server: ingress only from gateway pods on the service port, egress only to
+/// the declared `egressPolicy` destinations plus cluster DNS. Declaring both policy types with no
+/// egress rules denies all egress when `egressPolicy` is unset.
+pub fn network_policy(
+    server: &LatchkeyServer,
+    config: &NetworkConfig,
+) -> anyhow::Result<NetworkPolicy> {
+    let gateway = NetworkPolicyPeer {
+        namespace_selector: Some(match_labels([(NAMESPACE_NAME_LABEL, &config.gateway_namespace)])),
+        pod_selector: Some(match_labels([("app.kubernetes.io/name", GATEWAY_NAME_LABEL)])),
+        ..NetworkPolicyPeer::default()
+    };
+    let ingress = NetworkPolicyIngressRule {
+        from: Some(vec![gateway]),
+        ports: Some(vec![NetworkPolicyPort {
+            port: Some(IntOrString::Int(i32::from(server::service_port(server)))),
+            protocol: Some("TCP".to_string()),
+            ..NetworkPolicyPort::default()
+        }]),
+    };
+
+    let mut egress = Vec::new();
+    if let Some(policy) = server.spec.egress_policy.as_ref() {
+        egress.extend(policy.allow.iter().map(egress_rule));
+        // The proxy resolves names itself, so its ports must be open to any address; the pod
+        // firewall keeps everything but the proxy off them. Link-local stays closed even if the
+        // firewall was never installed.
+        let proxy_ports = host_ports(policy);
+        if !proxy_ports.is_empty() {
+            egress.push(NetworkPolicyEgressRule {
+                to: Some(
+                    LINK_LOCAL
+                        .into_iter()
+                        .map(|(cidr, except)| NetworkPolicyPeer {
+                            ip_block: Some(IPBlock {
+                                cidr: cidr.to_string(),
+                                except: Some(vec![except.to_string()]),
+                            }),
+                            ..NetworkPolicyPeer::default()
+                        })
+                        .collect(),
+                ),
+                ports: Some(
+                    proxy_ports
+                        .into_iter()
+                        .map(|port| egress_port(&EgressPort { port, protocol: None }))
+                        .collect(),
+                ),
+            });
+        }
+        if !egress.is_empty() {
+            egress.push(dns_rule());
+        }
+    }
+
+    Ok(NetworkPolicy {
+        metadata: server::owned_metadata(server)?,
+        spec: Some(NetworkPolicySpec {
+            pod_selector: match_labels(server::selector_labels(server)),
+            policy_types: Some(vec!["Ingress".to_string(), "Egress".to_string()]),
+            ingress: Some(vec![ingress]),
+            egress: Some(egress),
+        }),
+    })
+}
+
+/// The proxy sidecar and its firewall, or `None` when no hostnames are declared.
+///
+/// NetworkPolicy applies to the whole pod, so the init container installs owner-match rules that
+/// let only the proxy's UID open connections beyond loopback, DNS, and the declared CIDRs.
+pub fn egress_sidecar(
+    server: &LatchkeyServer,
+    config: &NetworkConfig,
+) -> anyhow::Result<Option<EgressSidecar>> {
+    let Some(policy) = server.spec.egress_policy.as_ref() else {
+        return Ok(None);
+    };
+    let rules = parse_allow(policy)?;
+    let allowed: Vec<AllowedHost> = policy
+        .hosts
+        .iter()
+        .flatten()
+        .map(|host| AllowedHost {
+            host: host.host.clone(),
+            ports: host.ports.clone().unwrap_or_else(|| vec![DEFAULT_HOST_PORT]),
+        })
+        .collect();
+    if allowed.is_empty() {
+        return Ok(None);
+    }
+
+    let proxy_url = format!("http://127.0.0.1:{PROXY_PORT}");
+    let mut no_proxy = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+    no_proxy.extend(rules.iter().map(|rule| rule.cidr.to_string()));
+    let mut env: Vec<EnvVar> = ["HTTPS_PROXY", "HTTP_PROXY", "https_proxy", "http_proxy"]
+        .into_iter()
+        .map(|name| env_var(name, &proxy_url))
+        .collect();
+    env.push(env_var("NO_PROXY", &no_proxy.join(",")));
+    env.push(env_var("no_proxy", &no_proxy.join(",")));
+
+    let firewall = Container {
+        name: "egress-init".to_string(),
+        image: Some(config.egress_init_image.clone()),
+        command: Some(vec!["/bin/sh".to_string(), "-ec".to_string(), firewall_script(&rules)]),
+        security_context: Some(SecurityContext {
+            allow_privilege_escalation: Some(false),
+            // iptables takes its lock under /run.
+            read_only_root_filesystem: Some(false),
+            run_as_non_root: Some(false),
+            run_as_user: Some(0),
+            capabilities: Some(Capabilities {
+                add: Some(vec!["NET_ADMIN".to_string(), "NET_RAW".to_string()]),
+                drop: Some(vec!["ALL".to_string()]),
+            }),
+            ..SecurityContext::default()
+        }),
+        ..Container::default()
+    };
+
+    let allow_json =
+        serde_json::to_string(&allowed).context("failed to encode egress proxy allowlist")?;
+    let proxy = Container {
+        name: "egress-proxy".to_string(),
+        image: Some(config.egress_proxy_image.clone()),
+        // Native sidecar: started before, and outliving, the tool server.
+        restart_policy: Some("Always".to_string()),
+        env: Some(vec![
+            env_var(PROXY_ALLOW_ENV, &allow_json),
+            env_var("LATCHKEY_EGRESS_SERVER", &server.name_any()),
+        ]),
+        security_context: Some(SecurityContext {
+            run_as_user: Some(EGRESS_PROXY_UID),
+            ..server::container_security_context(true)
+        }),
+        ..Container::default()
+    };
+
+    Ok(Some(EgressSidecar { init_containers: vec![firewall, proxy], env }))
+}
+
+fn host_ports(policy: &EgressPolicy) -> BTreeSet<u16> {
+    policy
+        .hosts
+        .iter()
+        .flatten()
+        .flat_map(|host| host.ports.clone().unwrap_or_else(|| vec![DEFAULT_HOST_PORT]))
+        .collect()
+}
+
+/// An IPv4 or IPv6 block. Parsed by the operator itself, so the root firewall script only ever
+/// sees its canonical form and never the string from the resource.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    fn is_ipv6(&self) -> bool {
+        self.address.is_ipv6()
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        let parsed = value.split_once('/').and_then(|(address, prefix)| {
+            let address: IpAddr = address.parse().ok()?;
+            let prefix: u8 = prefix.parse().ok()?;
+            let max = if address.is_ipv6() { 128 } else { 32 };
+            (prefix <= max).then_some(Self { address, prefix })
+        });
+        parsed.with_context(|| format!("{value:?} is not a CIDR"))
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+struct ParsedRule<'a> {
+    cidr: Cidr,
+    except: Vec<Cidr>,
+    ports: Option<&'a [EgressPort]>,
+}
+
+/// Parses every declared CIDR, naming the first field that does not parse.
+fn parse_allow(policy: &EgressPolicy) -> anyhow::Result<Vec<ParsedRule<'_>>> {
+    policy
+        .allow
+        .iter()
+        .enumerate()
+        .map(|(index, rule)| {
+            let field = format!("spec.egressPolicy.allow[{index}]");
+            Ok(ParsedRule {
+                cidr: rule.cidr.parse().with_context(|| format!("invalid {field}.cidr"))?,
+                except: rule
+                    .except
+                    .iter()
+                    .flatten()
+                    .map(|except| except.parse().with_context(|| format!("invalid {field}.except")))
+                    .collect::<anyhow::Result<_>>()?,
+                ports: rule.ports.as_deref(),
+            })
+        })
+        .collect()
+}
+
+/// Checks what the operator cannot render safely, whether or not admission ran.
+pub fn check_egress_policy(server: &LatchkeyServer) -> anyhow::Result<()> {
+    match server.spec.egress_policy.as_ref() {
+        Some(policy) => parse_allow(policy).map(drop),
+        None => Ok(()),
+    }
+}
+
+/// Only the proxy UID, loopback, replies on accepted connections, DNS, and declared CIDRs may
+/// leave the pod; everything else is rejected so a misconfigured client fails fast.
+fn firewall_script(allow: &[ParsedRule<'_>]) -> String {
+    let mut script = String::new();
+
+    for (tool, ipv6) in [("iptables", false), ("ip6tables", true)] {
+        let mut rules = vec![
+            "-o lo -j ACCEPT".to_string(),
+            "-m conntrack --ctstate ESTABLISHED,RELATED -j ACCEPT".to_string(),
+            format!("-m owner --uid-owner {EGRESS_PROXY_UID} -j ACCEPT"),
+            format!("-p udp --dport {DNS_PORT} -j ACCEPT"),
+            format!("-p tcp --dport {DNS_PORT} -j ACCEPT"),
+        ];
+        for rule in allow.iter().filter(|rule| rule.cidr.is_ipv6() == ipv6) {
+            for except in &rule.except {
+                rules.push(format!("-d {except} -j REJECT"));
+            }
+            match rule.ports {
+                Some(ports) => rules.extend(ports.iter().map(|port| {
+                    let protocol = match port.protocol.unwrap_or_default() {
+                        EgressProtocol::Tcp => "tcp",
+                        EgressProtocol::Udp => "udp",
+                    };
+                    format!("-d {} -p {protocol} --dport {} -j ACCEPT", rule.cidr, port.port)
+                })),
+                None => rules.push(format!("-d {} -j ACCEPT", rule.cidr)),
+            }
+        }
+        rules.push("-j REJECT".to_string());
+
+        let commands: Vec<String> =
+            rules.iter().map(|rule| format!("{tool} -A OUTPUT {rule}")).collect();
+        if ipv6 {
+            // Nodes without IPv6 have no ip6tables OUTPUT chain to program.
+            script.push_str(&format!(
+                "if ip6tables -L OUTPUT >/dev/null 2>&1; then\n{}\nfi\n",
+                commands.join("\n")
+            ));
+        } else {
+            script.push_str(&commands.join("\n"));
+            script.push('\n');
+        }
+    }
+
+    script
+}
+
+fn env_var(name: &str, value: &str) -> EnvVar {
+    EnvVar { name: name.to_string(), value: Some(value.to_string()), ..EnvVar::default() }
+}
+
+fn egress_rule(rule: &EgressRule) -> NetworkPolicyEgressRule {
+    NetworkPolicyEgressRule {
+        to: Some(vec![NetworkPolicyPeer {
+            ip_block: Some(IPBlock { cidr: rule.cidr.clone(), except: rule.except.clone() }),
+            ..NetworkPolicyPeer::default()
+        }]),
+        ports: rule.ports.as_ref().map(|ports| ports.iter().map(egress_port).collect()),
+    }
+}
+
+fn egress_port(port: &EgressPort) -> NetworkPolicyPort {
+    let protocol = match port.protocol.unwrap_or_default() {
+        EgressProtocol::Tcp => "TCP",
+        EgressProtocol::Udp => "UDP",
+    };
+
+    NetworkPolicyPort {
+        port: Some(IntOrString::Int(i32::from(port.port))),
+        protocol: Some(protocol.to_string()),
+        ..NetworkPolicyPort::default()
+    }
+}
+
+fn dns_rule() -> NetworkPolicyEgressRule {
+    NetworkPolicyEgressRule {
+        to: Some(vec![NetworkPolicyPeer {
+            namespace_selector: Some(match_labels([(NAMESPACE_NAME_LABEL, DNS_NAMESPACE)])),
+            pod_selector: Some(match_labels([DNS_LABEL])),
+            ..NetworkPolicyPeer::default()
+        }]),
+        ports: Some(
+            ["UDP", "TCP"]
+                .into_iter()
+                .map(|protocol| NetworkPolicyPort {
+                    port: Some(IntOrString::Int(DNS_PORT)),
+                    protocol: Some(protocol.to_string()),
+                    ..NetworkPolicyPort::default()
+                })
+                .collect(),
+        ),
+    }
+}
+
+fn match_labels<K: ToString, V: ToString>(
+    labels: impl IntoIterator<Item = (K, V)>,
+) -> LabelSelector {
+    let labels: BTreeMap<String, String> =
+        labels.into_iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+
+    LabelSelector { match_labels: Some(labels), ..LabelSelector::default() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::tests::server;
+    use serde_json::{json, Value};
+
+    fn config() -> NetworkConfig {
+        NetworkConfig {
+            gateway_namespace: "latchkey-system".to_string(),
+            egress_proxy_image: "egress-proxy".to_string(),
+            egress_init_image: "egress-init".to_string(),
+        }
+    }
+
+    fn spec_of(spec: Value) -> Value {
+        let policy = network_policy(&server(spec), &config()).unwrap();
+        serde_json::to_value(policy.spec.unwrap()).unwrap()
+    }
+
+    fn dns() -> Value {
+        json!({
+            "to": [{
+                "namespaceSelector": {
+                    "matchLabels": { "kubernetes.io/metadata.name": "kube-system" },
+                },
+                "podSelector": { "matchLabels": { "k8s-app": "kube-dns" } },
+            }],
+            "ports": [{ "port": 53, "protocol": "UDP" }, { "port": 53, "protocol": "TCP" }],
+        })
+    }
+
+    #[test]
+    fn denies_all_egress_without_an_egress_policy() {
+        for spec in [
+            json!({ "image": "tool:1" }),
+            json!({ "image": "tool:1", "egressPolicy": {} }),
+            json!({ "image": "tool:1", "egressPolicy": { "allow": [], "hosts": [] } }),
+        ] {
+            let spec = spec_of(spec);
+            assert_eq!(spec["policyTypes"], json!(["Ingress", "Egress"]));
+            // An empty list, not a missing one: with no DNS rule either, nothing leaves the pod.
+            assert_eq!(spec["egress"], json!([]));
+            assert_eq!(
+                spec["ingress"],
+                json!([{
+                    "from": [{
+                        "namespaceSelector": {
+                            "matchLabels": { "kubernetes.io/metadata.name": "latchkey-system" },
+                        },
+                        "podSelector": {
+                            "matchLabels": { "app.kubernetes.io/name": "latchkey-gateway" },
+                        },
+                    }],
+                    "ports": [{ "port": 8081, "protocol": "TCP" }],
+                }])
+            );
+        }
+    }
+
+    #[test]
+    fn allows_declared_destinations_and_dns_alongside_them() {
+        let spec = spec_of(json!({
+            "image": "tool:1",
+            "egressPolicy": {
+                "allow": [{
+                    "cidr": "203.0.113.0/24",
+                    "except": ["203.0.113.128/25"],
+                    "ports": [{ "port": 5432 }, { "port": 514, "protocol": "UDP" }],
+                }],
+                "hosts": [
+                    { "host": "api.github.com" },
+                    { "host": "*.example.com", "ports": [8443] },
+                ],
+            },
+        }));
+
+        assert_eq!(
+            spec["egress"],
+            json!([
+                {
+                    "to": [{
+                        "ipBlock": { "cidr": "203.0.113.0/24", "except": ["203.0.113.128/25"] },
+                    }],
+                    "ports": [
+                        { "port": 5432, "protocol": "TCP" },
+                        { "port": 514, "protocol": "UDP" },
+                    ],
+                },
+                {
+                    "to": [
+                        { "ipBlock": { "cidr": "0.0.0.0/0", "except": ["169.254.0.0/16"] } },
+                        { "ipBlock": { "cidr": "::/0", "except": ["fe80::/10"] } },
+                    ],
+                    "ports": [
+                        { "port": 443, "protocol": "TCP" },
+                        { "port": 8443, "protocol": "TCP" },
+                    ],
+                },
+                dns(),
+            ])
+        );
+
+        let hosts_only = spec_of(json!({
+            "image": "tool:1",
+            "egressPolicy": { "hosts": [{ "host": "api.github.com" }] },
+        }));
+        assert_eq!(hosts_only["egress"].as_array().unwrap().last(), Some(&dns()));
+    }
+
+    #[test]
+    fn is_owned_by_its_server() {
+        let server = server(json!({ "image": "tool:1", "servicePort": 9000 }));
+        let policy = network_policy(&server, &config()).unwrap();
+
+        let owner = &policy.metadata.owner_references.as_ref().unwrap()[0];
+        assert_eq!((&*owner.kind, &*owner.name), ("LatchkeyServer", "github"));
+        assert_eq!(owner.uid, "9c1f7a3e-0000-4000-8000-000000000001");
+        assert_eq!(owner.controller, Some(true));
+        assert_eq!(policy.metadata.namespace.as_deref(), Some("tools"));
+
+        let spec = serde_json::to_value(policy.spec.unwrap()).unwrap();
+        assert_eq!(
+            spec["podSelector"],
+            json!({ "matchLabels": { "latchkey.dev/server": "github" } })
+        );
+        assert_eq!(spec["ingress"][0]["ports"], json!([{ "port": 9000, "protocol": "TCP" }]));
+    }
+
+    #[test]
+    fn renders_only_parsed_cidrs_into_the_firewall() {
+        let valid = server(json!({
+            "image": "tool:1",
+            "egressPolicy": {
+                "allow": [
+                    { "cidr": "203.0.113.0/24", "except": ["203.0.113.128/25"] },
+                    { "cidr": "2001:0db8:0000::/32", "ports": [{ "port": 443 }] },
+                ],
+                "hosts": [{ "host": "api.github.com" }],
+            },
+        }));
+        assert!(check_egress_policy(&valid).is_ok());
+        let sidecar = egress_sidecar(&valid, &config()).unwrap().unwrap();
+        let script = &sidecar.init_containers[0].command.as_ref().unwrap()[2];
+        assert!(script.contains("iptables -A OUTPUT -d 203.0.113.128/25 -j REJECT\n"));
+        assert!(script.contains("iptables -A OUTPUT -d 203.0.113.0/24 -j ACCEPT\n"));
+        // Written back in canonical form, not as declared.
+        assert!(
+            script.contains("ip6tables -A OUTPUT -d 2001:db8::/32 -p tcp --dport 443 -j ACCEPT")
+        );
+
+        for (allow, error) in [
+            (json!({ "cidr": "10.0.0.0/8; reboot" }), "invalid spec.egressPolicy.allow[0].cidr"),
+            (json!({ "cidr": "$(id)/8" }), "invalid spec.egressPolicy.allow[0].cidr"),
+            (json!({ "cidr": "10.0.0.0/33" }), "invalid spec.egressPolicy.allow[0].cidr"),
+            (json!({ "cidr": "::/129" }), "invalid spec.egressPolicy.allow[0].cidr"),
+            (json!({ "cidr": "10.0.0.0" }), "invalid spec.egressPolicy.allow[0].cidr"),
+            (
+                json!({ "cidr": "10.0.0.0/8", "except": ["10.1.0.0/16 `id`"] }),
+                "invalid spec.egressPolicy.allow[0].except",
+            ),
+        ] {
+            let invalid = server(json!({
+                "image": "tool:1",
+                "egressPolicy": { "allow": [allow], "hosts": [{ "host": "api.github.com" }] },
+            }));
+            let err = check_egress_policy(&invalid).unwrap_err();
+            assert_eq!(err.to_string(), error);
+            assert!(egress_sidecar(&invalid, &config()).is_err());
+        }
+    }
+}
@@ -0,0 +1,575 @@
+use crate::crd::v1beta1::{
+    KubernetesSecretSource, SecretBinding, SecretMountMode, VaultSecretSource,
+};
+use crate::crd::SecretBindingStatus;
+use crate::server;
+use anyhow::Context;
+use futures::future::BoxFuture;
+use k8s_openapi::api::core::v1::{
+    Container, ContainerPort, EmptyDirVolumeSource, EnvVar, EnvVarSource, HTTPGetAction, KeyToPath,
+    Probe, ProjectedVolumeSource, Secret, SecretKeySelector, SecretVolumeSource,
+    ServiceAccountTokenProjection, Volume, VolumeMount, VolumeProjection,
+};
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+use kube::{Api, Client};
+use latchkey_core::vault::{
+    AgentBinding, AGENT_CONFIG_ENV, AGENT_HEALTH_PORT, AGENT_TOKEN_DIR, AGENT_TOKEN_FILE,
+    DEFAULT_AUTH_MOUNT,
+};
+use sha2::{Digest, Sha256};
+
+/// Root for file-mounted credentials; bindings may only mount below it.
+pub const SECRET_ROOT: &str = "/var/run/secrets/latchkey";
+
+/// Owner read-only. The tool server runs as a fixed non-root UID, so group/other bits are never
+/// needed.
+const FILE_DEFAULT_MODE: i32 = 0o400;
+
+/// Annotation on the pod template carrying a digest of the bound Secrets' uids and
+/// resourceVersions. It changes whenever a bound Secret is written, which rolls the Deployment so
+/// env-mounted values are refreshed.
+pub const SECRET_HASH_ANNOTATION: &str = "latchkey.dev/secret-hash";
+
+const DEFAULT_VAULT_AGENT_IMAGE: &str = "ghcr.io/latchkey/latchkey-vault-agent:dev";
+const DEFAULT_VAULT_AUDIENCE: &str = "vault";
+const VAULT_AGENT_CONTAINER: &str = "vault-agent";
+const VAULT_TOKEN_VOLUME: &str = "vault-token";
+const VAULT_TOKEN_TTL_SECONDS: i64 = 600;
+/// Vault-delivered files live in memory-backed emptyDirs, never on node disk.
+const VAULT_VOLUME_SIZE_LIMIT: &str = "1Mi";
+
+/// Operator-wide defaults for secret sources.
+#[derive(Debug, Clone)]
+pub struct SecretsConfig {
+    pub vault_address: Option<String>,
+    pub vault_agent_image: String,
+    /// Audience of the projected token the agent presents to Vault's Kubernetes auth method.
+    pub vault_audience: String,
+}
+
+impl SecretsConfig {
+    pub fn from_env() -> Self {
+        Self {
+            vault_address: std::env::var("LATCHKEY_VAULT_ADDR")
+                .ok()
+                .filter(|value| !value.trim().is_empty()),
+            vault_agent_image: std::env::var("LATCHKEY_VAULT_AGENT_IMAGE")
+                .unwrap_or_else(|_| DEFAULT_VAULT_AGENT_IMAGE.to_string()),
+            vault_audience: std::env::var("LATCHKEY_VAULT_AUDIENCE")
+                .unwrap_or_else(|_| DEFAULT_VAULT_AUDIENCE.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PodSecrets {
+    pub env: Vec<EnvVar>,
+    pub volumes: Vec<Volume>,
+    pub mounts: Vec<VolumeMount>,
+    /// Sidecars that deliver values, run as native sidecars ahead of the tool server.
+    pub init_containers: Vec<Container>,
+    agent_bindings: Vec<AgentBinding>,
+    agent_mounts: Vec<VolumeMount>,
+}
+
+/// Where a binding's value comes from. Each source decides how the value reaches the pod and how
+/// its readiness is observed, so bindings can switch sources without touching reconcile.
+pub trait SecretProvider: Send + Sync {
+    /// Adds what the tool server pod needs to see the value. Values are only ever referenced.
+    fn mount(&self, binding: &SecretBinding, pod: &mut PodSecrets);
+
+    /// Reports whether the value is reachable, feeding anything whose change should roll the
+    /// Deployment into `hasher`.
+    fn resolve<'a>(
+        &'a self,
+        client: &'a Client,
+        namespace: &'a str,
+        binding: &'a SecretBinding,
+        hasher: &'a mut Sha256,
+    ) -> BoxFuture<'a, SecretBindingStatus>;
+}
+
+struct KubernetesSecretProvider<'a> {
+    source: &'a KubernetesSecretSource,
+}
+
+struct VaultProvider<'a> {
+    source: &'a VaultSecretSource,
+    config: &'a SecretsConfig,
+}
+
+/// The provider for a binding's source, or `None` unless exactly one source is set.
+pub fn provider<'a>(
+    binding: &'a SecretBinding,
+    config: &'a SecretsConfig,
+) -> Option<Box<dyn SecretProvider + 'a>> {
+    match (&binding.source.kubernetes_secret, &binding.source.vault_secret) {
+        (Some(source), None) => Some(Box::new(KubernetesSecretProvider { source })),
+        (None, Some(source)) => Some(Box::new(VaultProvider { source, config })),
+        _ => None,
+    }
+}
+
+/// Translates bindings into pod spec fragments. Values are only ever referenced, never copied.
+pub fn pod_secrets(
+    bindings: &[SecretBinding],
+    config: &SecretsConfig,
+) -> anyhow::Result<PodSecrets> {
+    let mut pod = PodSecrets::default();
+
+    for binding in bindings {
+        if let Some(provider) = provider(binding, config) {
+            provider.mount(binding, &mut pod);
+        }
+    }
+
+    if !pod.agent_bindings.is_empty() {
+        let agent = vault_agent(config, &pod)?;
+        pod.init_containers.push(agent);
+        pod.volumes.push(vault_token_volume(config));
+    }
+
+    Ok(pod)
+}
+
+#[derive(Debug, Default)]
+pub struct ResolvedSecrets {
+    pub statuses: Vec<SecretBindingStatus>,
+    /// SHA-256 over every resolved binding's Secret version, or `None` when nothing is bound.
+    pub content_hash: Option<String>,
+}
+
+/// Checks that each binding's source is reachable and digests the bound Secrets' versions. Only
+/// Secret metadata is fetched, so values never reach the operator, its logs, or status.
+pub async fn resolve(
+    client: &Client,
+    namespace: &str,
+    bindings: &[SecretBinding],
+    config: &SecretsConfig,
+) -> ResolvedSecrets {
+    let mut statuses = Vec::with_capacity(bindings.len());
+    let mut hasher = Sha256::new();
+
+    for binding in bindings {
+        let status = match provider(binding, config) {
+            Some(provider) => provider.resolve(client, namespace, binding, &mut hasher).await,
+            None => not_ready(
+                binding,
+                "source must set exactly one of kubernetesSecret or vaultSecret".to_string(),
+            ),
+        };
+        statuses.push(status);
+    }
+
+    let content_hash = (!bindings.is_empty()).then(|| format!("{:x}", hasher.finalize()));
+    ResolvedSecrets { statuses, content_hash }
+}
+
+/// Whether any binding reads from the Secret `name`, used to map Secret changes back to servers.
+pub fn references(bindings: &[SecretBinding], name: &str) -> bool {
+    bindings.iter().any(|binding| {
+        binding.source.kubernetes_secret.as_ref().is_some_and(|source| source.name == name)
+    })
+}
+
+impl SecretProvider for KubernetesSecretProvider<'_> {
+    fn mount(&self, binding: &SecretBinding, pod: &mut PodSecrets) {
+        let source = self.source;
+
+        match binding.mount.mode {
+            SecretMountMode::Env => pod.env.push(EnvVar {
+                name: env_name(binding),
+                value_from: Some(EnvVarSource {
+                    secret_key_ref: Some(SecretKeySelector {
+                        name: source.name.clone(),
+                        key: source.key.clone(),
+                        optional: Some(false),
+                    }),
+                    ..EnvVarSource::default()
+                }),
+                ..EnvVar::default()
+            }),
+            SecretMountMode::File => {
+                let volume = volume_name(binding);
+                let dir = file_dir(binding);
+
+                pod.volumes.push(Volume {
+                    name: volume.clone(),
+                    secret: Some(SecretVolumeSource {
+                        secret_name: Some(source.name.clone()),
+                        items: Some(vec![KeyToPath {
+                            key: source.key.clone(),
+                            path: source.key.clone(),
+                            mode: Some(FILE_DEFAULT_MODE),
+                        }]),
+                        default_mode: Some(FILE_DEFAULT_MODE),
+                        optional: Some(false),
+                    }),
+                    ..Volume::default()
+                });
+                pod.mounts.push(read_only_mount(&volume, &dir));
+                push_file_env(binding, &format!("{dir}/{}", source.key), pod);
+            }
+        }
+    }
+
+    /// Reads metadata only, so a missing key is not reported here. The pod references the key
+    /// as non-optional, so the tool server fails to start with the missing key in its events.
+    fn resolve<'a>(
+        &'a self,
+        client: &'a Client,
+        namespace: &'a str,
+        binding: &'a SecretBinding,
+        hasher: &'a mut Sha256,
+    ) -> BoxFuture<'a, SecretBindingStatus> {
+        Box::pin(async move {
+            let source = self.source;
+            let api: Api<Secret> = Api::namespaced(client.clone(), namespace);
+
+            match api.get_metadata_opt(&source.name).await {
+                Ok(Some(secret)) => {
+                    // A Secret gets a new resourceVersion on every write, so its identity and
+                    // version stand in for its content without the operator ever reading it.
+                    let uid = secret.metadata.uid.unwrap_or_default();
+                    let version = secret.metadata.resource_version.unwrap_or_default();
+                    for part in [&binding.name, &source.key, &uid, &version] {
+                        hasher.update((part.len() as u64).to_be_bytes());
+                        hasher.update(part.as_bytes());
+                    }
+                    ready(binding)
+                }
+                Ok(None) => not_ready(binding, format!("Secret {} not found", source.name)),
+                Err(err) => {
+                    not_ready(binding, format!("failed to look up Secret {}: {err}", source.name))
+                }
+            }
+        })
+    }
+}
+
+impl VaultProvider<'_> {
+    fn address(&self) -> Option<String> {
+        self.source.address.clone().or_else(|| self.config.vault_address.clone())
+    }
+}
+
+impl SecretProvider for VaultProvider<'_> {
+    /// The agent writes into a memory-backed volume shared with the tool server, which mounts it
+    /// read-only. Rotation happens in place, so nothing is fed into the rollout hash.
+    fn mount(&self, binding: &SecretBinding, pod: &mut PodSecrets) {
+        let Some(address) = self.address() else {
+            return;
+        };
+        if binding.mount.mode != SecretMountMode::File {
+            return;
+        }
+
+        let volume = volume_name(binding);
+        let dir = file_dir(binding);
+        let file = format!("{dir}/{}", self.source.key);
+
+        pod.volumes.push(Volume {
+            name: volume.clone(),
+            empty_dir: Some(EmptyDirVolumeSource {
+                medium: Some("Memory".to_string()),
+                size_limit: Some(Quantity(VAULT_VOLUME_SIZE_LIMIT.to_string())),
+            }),
+            ..Volume::default()
+        });
+        pod.mounts.push(read_only_mount(&volume, &dir));
+        pod.agent_mounts.push(VolumeMount {
+            name: volume,
+            mount_path: dir,
+            ..VolumeMount::default()
+        });
+        push_file_env(binding, &file, pod);
+
+        pod.agent_bindings.push(AgentBinding {
+            name: binding.name.clone(),
+            address,
+            auth_mount: self
+                .source
+                .auth_mount
+                .clone()
+                .unwrap_or_else(|| DEFAULT_AUTH_MOUNT.to_string()),
+            role: self.source.role.clone(),
+            path: self.source.path.clone(),
+            key: self.source.key.clone(),
+            file,
+        });
+    }
+
+    /// The operator holds no Vault credentials, so this only checks that the binding can be
+    /// delivered; the agent's startup probe gates the tool server on the value itself.
+    fn resolve<'a>(
+        &'a self,
+        _client: &'a Client,
+        _namespace: &'a str,
+        binding: &'a SecretBinding,
+        _hasher: &'a mut Sha256,
+    ) -> BoxFuture<'a, SecretBindingStatus> {
+        Box::pin(async move {
+            if self.address().is_none() {
+                not_ready(
+                    binding,
+                    "no Vault address: set address or LATCHKEY_VAULT_ADDR".to_string(),
+                )
+            } else if binding.mount.mode != SecretMountMode::File {
+                not_ready(binding, "vaultSecret bindings only support file mounts".to_string())
+            } else {
+                ready(binding)
+            }
+        })
+    }
+}
+
+fn vault_agent(config: &SecretsConfig, pod: &PodSecrets) -> anyhow::Result<Container> {
+    let agent_config = serde_json::to_string(&pod.agent_bindings)
+        .context("failed to encode vault agent config")?;
+    let mut mounts = pod.agent_mounts.clone();
+    mounts.push(read_only_mount(VAULT_TOKEN_VOLUME, AGENT_TOKEN_DIR));
+
+    Ok(Container {
+        name: VAULT_AGENT_CONTAINER.to_string(),
+        image: Some(config.vault_agent_image.clone()),
+        // `Always` on an init container makes it a native sidecar: it starts first, and the tool
+        // server waits for its startup probe, which passes once every file has been written.
+        restart_policy: Some("Always".to_string()),
+        env: Some(vec![EnvVar {
+            name: AGENT_CONFIG_ENV.to_string(),
+            value: Some(agent_config),
+            ..EnvVar::default()
+        }]),
+        ports: Some(vec![ContainerPort {
+            name: Some("agent-health".to_string()),
+            container_port: i32::from(AGENT_HEALTH_PORT),
+            ..ContainerPort::default()
+        }]),
+        startup_probe: Some(Probe {
+            http_get: Some(HTTPGetAction {
+                path: Some("/healthz".to_string()),
+                port: IntOrString::String("agent-health".to_string()),
+                ..HTTPGetAction::default()
+            }),
+            period_seconds: Some(2),
+            failure_threshold: Some(60),
+            ..Probe::default()
+        }),
+        volume_mounts: Some(mounts),
+        security_context: Some(server::container_security_context(true)),
+        ..Container::default()
+    })
+}
+
+/// The only service account credential in the pod: short-lived, audience-bound, and mounted into
+/// the agent alone.
+fn vault_token_volume(config: &SecretsConfig) -> Volume {
+    Volume {
+        name: VAULT_TOKEN_VOLUME.to_string(),
+        projected: Some(ProjectedVolumeSource {
+            sources: Some(vec![VolumeProjection {
+                service_account_token: Some(ServiceAccountTokenProjection {
+                    audience: Some(config.vault_audience.clone()),
+                    expiration_seconds: Some(VAULT_TOKEN_TTL_SECONDS),
+                    path: AGENT_TOKEN_FILE.to_string(),
+                }),
+                ..VolumeProjection::default()
+            }]),
+            default_mode: Some(FILE_DEFAULT_MODE),
+        }),
+        ..Volume::default()
+    }
+}
+
+pub fn env_name(binding: &SecretBinding) -> String {
+    binding
+        .mount
+        .env_name
+        .clone()
+        .unwrap_or_else(|| binding.name.to_uppercase().replace(['-', '.'], "_"))
+}
+
+pub fn file_dir(binding: &SecretBinding) -> String {
+    binding.mount.path.clone().unwrap_or_else(|| format!("{SECRET_ROOT}/{}", binding.name))
+}
+
+fn volume_name(binding: &SecretBinding) -> String {
+    format!("secret-{}", binding.name)
+}
+
+fn read_only_mount(volume: &str, path: &str) -> VolumeMount {
+    VolumeMount {
+        name: volume.to_string(),
+        mount_path: path.to_string(),
+        read_only: Some(true),
+        ..VolumeMount::default()
+    }
+}
+
+fn push_file_env(binding: &SecretBinding, file: &str, pod: &mut PodSecrets) {
+    if binding.mount.env_name.is_some() {
+        pod.env.push(EnvVar {
+            name: format!("{}_FILE", env_name(binding)),
+            value: Some(file.to_string()),
+            ..EnvVar::default()
+        });
+    }
+}
+
+fn ready(binding: &SecretBinding) -> SecretBindingStatus {
+    SecretBindingStatus { name: binding.name.clone(), ready: true, message: None }
+}
+
+fn not_ready(binding: &SecretBinding, message: String) -> SecretBindingStatus {
+    SecretBindingStatus { name: binding.name.clone(), ready: false, message: Some(message) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::header::ACCEPT;
+    use axum::http::{HeaderMap, StatusCode, Uri};
+    use axum::response::IntoResponse;
+    use axum::{Json, Router};
+    use serde_json::{json, Value};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    fn config() -> SecretsConfig {
+        SecretsConfig {
+            vault_address: None,
+            vault_agent_image: "vault-agent".to_string(),
+            vault_audience: "vault".to_string(),
+        }
+    }
+
+    fn binding(value: Value) -> SecretBinding {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn kubernetes(name: &str, secret: &str, mount: Value) -> SecretBinding {
+        binding(json!({
+            "name": name,
+            "source": { "kubernetesSecret": { "name": secret, "key": "token" } },
+            "mount": mount,
+        }))
+    }
+
+    #[test]
+    fn env_bindings_reference_the_secret_key() {
+        let bindings = [kubernetes("github-token", "github", json!({ "mode": "env" }))];
+        let pod = pod_secrets(&bindings, &config()).unwrap();
+
+        assert!(pod.volumes.is_empty() && pod.mounts.is_empty() && pod.init_containers.is_empty());
+        let [env] = &pod.env[..] else { panic!("expected one env var: {:?}", pod.env) };
+        assert_eq!(env.name, "GITHUB_TOKEN");
+        assert_eq!(env.value, None);
+        let selector = env.value_from.as_ref().unwrap().secret_key_ref.as_ref().unwrap();
+        assert_eq!((&*selector.name, &*selector.key), ("github", "token"));
+        assert_eq!(selector.optional, Some(false));
+    }
+
+    #[test]
+    fn file_bindings_mount_owner_read_only() {
+        let bindings = [
+            kubernetes("github-token", "github", json!({ "mode": "file" })),
+            kubernetes(
+                "upstream",
+                "upstream",
+                json!({ "mode": "file", "envName": "UPSTREAM_API_KEY" }),
+            ),
+        ];
+        let pod = pod_secrets(&bindings, &config()).unwrap();
+
+        assert_eq!(pod.volumes.len(), 2);
+        for volume in &pod.volumes {
+            let secret = volume.secret.as_ref().unwrap();
+            assert_eq!(secret.default_mode, Some(0o400));
+            assert!(secret.items.iter().flatten().all(|item| item.mode == Some(0o400)));
+        }
+        let mounts: Vec<_> =
+            pod.mounts.iter().map(|mount| (&*mount.mount_path, mount.read_only)).collect();
+        assert_eq!(
+            mounts,
+            vec![
+                ("/var/run/secrets/latchkey/github-token", Some(true)),
+                ("/var/run/secrets/latchkey/upstream", Some(true)),
+            ]
+        );
+        // Only bindings that name an env var get a pointer to the file.
+        let [env] = &pod.env[..] else { panic!("expected one env var: {:?}", pod.env) };
+        assert_eq!(env.name, "UPSTREAM_API_KEY_FILE");
+        assert_eq!(env.value.as_deref(), Some("/var/run/secrets/latchkey/upstream/token"));
+    }
+
+    #[tokio::test]
+    async fn secrets_are_checked_and_versioned_by_metadata_only() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let version = Arc::new(AtomicU64::new(1));
+        let (recorded, current) = (requests.clone(), version.clone());
+        // `github` exists; `upstream` does not.
+        let api_server = Router::new().fallback(move |uri: Uri, headers: HeaderMap| {
+            let (recorded, current) = (recorded.clone(), current.clone());
+            async move {
+                let accept = headers.get(ACCEPT).and_then(|value| value.to_str().ok());
+                recorded.lock().unwrap().push((uri.path().to_string(), accept.map(str::to_string)));
+                if !uri.path().ends_with("/secrets/github") {
+                    let status = json!({
+                        "apiVersion": "v1",
+                        "kind": "Status",
+                        "status": "Failure",
+                        "reason": "NotFound",
+                        "message": "not found",
+                        "code": 404,
+                    });
+                    return (StatusCode::NOT_FOUND, Json(status)).into_response();
+                }
+                Json(json!({
+                    "apiVersion": "meta.k8s.io/v1",
+                    "kind": "PartialObjectMetadata",
+                    "metadata": {
+                        "name": "github",
+                        "namespace": "tools",
+                        "uid": "8d1c",
+                        "resourceVersion": current.load(Ordering::SeqCst).to_string(),
+                    },
+                }))
+                .into_response()
+            }
+        });
+        let client = Client::new(api_server, "default");
+        let bindings = [
+            kubernetes("github-token", "github", json!({ "mode": "env" })),
+            kubernetes("upstream", "upstream", json!({ "mode": "file" })),
+        ];
+
+        let resolved = resolve(&client, "tools", &bindings, &config()).await;
+
+        let statuses: Vec<_> = resolved
+            .statuses
+            .iter()
+            .map(|status| (&*status.name, status.ready, status.message.as_deref()))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("github-token", true, None),
+                ("upstream", false, Some("Secret upstream not found"))
+            ]
+        );
+        for (path, accept) in requests.lock().unwrap().drain(..) {
+            assert!(path.starts_with("/api/v1/namespaces/tools/secrets/"), "{path}");
+            let accept = accept.unwrap_or_default();
+            assert!(accept.contains("as=PartialObjectMetadata"), "{path} asked for {accept}");
+        }
+
+        // The digest only moves when a bound Secret is written.
+        let hash = resolved.content_hash.unwrap();
+        let again = resolve(&client, "tools", &bindings, &config()).await;
+        assert_eq!(again.content_hash.as_deref(), Some(&*hash));
+        version.store(2, Ordering::SeqCst);
+        let rotated = resolve(&client, "tools", &bindings, &config()).await;
+        assert_ne!(rotated.content_hash.as_deref(), Some(&*hash));
+    }
+}
@@ -0,0 +1,556 @@
+use crate::bridge::{self, BridgeConfig};
+use crate::crd::{LatchkeyServer, LatchkeyServerStatus, SecretBindingStatus};
+use crate::network::{self, NetworkConfig};
+use crate::secrets::{self, SecretsConfig};
+use anyhow::Context;
+use futures::StreamExt;
+use k8s_openapi::api::apps::v1::{Deployment, DeploymentSpec};
+use k8s_openapi::api::core::v1::{
+    Capabilities, Container, ContainerPort, HTTPGetAction, PodSecurityContext, PodSpec,
+    PodTemplateSpec, Probe, ResourceRequirements, SeccompProfile, Secret, SecurityContext, Service,
+    ServicePort, ServiceSpec,
+};
+use k8s_openapi::api::networking::v1::NetworkPolicy;
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{LabelSelector, ObjectMeta};
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+use k8s_openapi::chrono::{SecondsFormat, Utc};
+use kube::api::{Patch, PatchParams};
+use kube::{Api, Client, Resource, ResourceExt};
+use kube_runtime::controller::{Action, Controller};
+use kube_runtime::events::{Event, EventType, Recorder, Reporter};
+use kube_runtime::reflector::ObjectRef;
+use kube_runtime::watcher::{self, metadata_watcher};
+use kube_runtime::WatchStreamExt;
+use latchkey_core::bridge::READY_PATH;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+pub const FIELD_MANAGER: &str = "latchkey-operator";
+pub const SERVER_LABEL: &str = "latchkey.dev/server";
+const DEFAULT_SERVICE_PORT: u16 = 8081;
+const DEFAULT_RUN_AS_USER: i64 = 65532;
+const DEFAULT_PROBE_PATH: &str = "/healthz";
+const DEFAULT_PROBE_PERIOD_SECONDS: i32 = 10;
+const RESYNC_INTERVAL: Duration = Duration::from_secs(300);
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+struct ReconcileContext {
+    client: Client,
+    secrets: SecretsConfig,
+    network: NetworkConfig,
+    bridge: BridgeConfig,
+}
+
+#[derive(Debug)]
+pub struct ReconcileError(anyhow::Error);
+
+impl fmt::Display for ReconcileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#}", self.0)
+    }
+}
+
+impl std::error::Error for ReconcileError {}
+
+impl From<anyhow::Error> for ReconcileError {
+    fn from(err: anyhow::Error) -> Self {
+        Self(err)
+    }
+}
+
+/// Reconciles each `LatchkeyServer` into an owned Deployment, Service, and NetworkPolicy.
+pub async fn run(client: Client) -> anyhow::Result<()> {
+    let servers: Api<LatchkeyServer> = Api::all(client.clone());
+    let deployments: Api<Deployment> = Api::all(client.clone());
+    let services: Api<Service> = Api::all(client.clone());
+    let network_policies: Api<NetworkPolicy> = Api::all(client.clone());
+    let secrets = metadata_watcher(Api::<Secret>::all(client.clone()), watcher::Config::default())
+        .touched_objects();
+
+    let controller = Controller::new(servers, watcher::Config::default());
+    let store = controller.store();
+
+    controller
+        .owns(deployments, watcher::Config::default())
+        .owns(services, watcher::Config::default())
+        .owns(network_policies, watcher::Config::default())
+        // Secrets are not owned, so map each change back to the servers that bind it. Only their
+        // metadata is watched; values are read with a targeted get of bound Secrets alone.
+        .watches_stream(secrets, move |secret| {
+            let name = secret.name_any();
+            let namespace = secret.namespace();
+            store
+                .state()
+                .into_iter()
+                .filter(|server| {
+                    server.namespace() == namespace
+                        && secrets::references(
+                            server.spec.secrets.as_deref().unwrap_or_default(),
+                            &name,
+                        )
+                })
+                .map(|server| ObjectRef::from_obj(&*server))
+                .collect::<Vec<_>>()
+        })
+        .run(
+            reconcile,
+            error_policy,
+            Arc::new(ReconcileContext {
+                client,
+                secrets: SecretsConfig::from_env(),
+                network: NetworkConfig::from_env(),
+                bridge: BridgeConfig::from_env(),
+            }),
+        )
+        .for_each(|result| async move {
+            match result {
+                Ok((object, _)) => {
+                    info!(resource = "LatchkeyServer", name = %object.name, "reconciled")
+                }
+                Err(err) => warn!(resource = "LatchkeyServer", error = %err, "reconcile failed"),
+            }
+        })
+        .await;
+
+    Ok(())
+}
+
+async fn reconcile(
+    server: Arc<LatchkeyServer>,
+    ctx: Arc<ReconcileContext>,
+) -> Result<Action, ReconcileError> {
+    let name = server.name_any();
+    let namespace = server.namespace().context("LatchkeyServer must be namespaced")?;
+    let bindings = server.spec.secrets.clone().unwrap_or_default();
+    let resolved = secrets::resolve(&ctx.client, &namespace, &bindings, &ctx.secrets).await;
+    let servers: Api<LatchkeyServer> = Api::namespaced(ctx.client.clone(), &namespace);
+
+    // Egress CIDRs end up in a script run as root, so a policy the operator cannot parse is never
+    // rolled out, whether or not admission checked it.
+    if let Err(err) = network::check_egress_policy(&server) {
+        warn!(
+            resource = "LatchkeyServer",
+            %name,
+            %namespace,
+            error = %format!("{err:#}"),
+            "rejecting egress policy"
+        );
+        let event = Event {
+            type_: EventType::Warning,
+            reason: "InvalidEgressPolicy".to_string(),
+            note: Some(format!("{err:#}")),
+            action: "Reject".to_string(),
+            secondary: None,
+        };
+        publish_event(&ctx.client, &server, event).await;
+        let mut conditions = conditions(&server, 0, &resolved.statuses);
+        conditions.push("EgressPolicyValid=False".to_string());
+        servers
+            .patch_status(
+                &name,
+                &PatchParams::default(),
+                &Patch::Merge(json!({ "status": { "conditions": conditions } })),
+            )
+            .await
+            .with_context(|| {
+                format!("failed to update LatchkeyServer {namespace}/{name} status")
+            })?;
+        return Ok(Action::requeue(RESYNC_INTERVAL));
+    }
+
+    let params = PatchParams::apply(FIELD_MANAGER).force();
+    let deployments: Api<Deployment> = Api::namespaced(ctx.client.clone(), &namespace);
+    let rotated = rotated(server.status.as_ref(), resolved.content_hash.as_deref());
+
+    let deployment = deployments
+        .patch(
+            &name,
+            &params,
+            &Patch::Apply(&deployment(
+                &server,
+                resolved.content_hash.as_deref(),
+                &ctx.secrets,
+                &ctx.network,
+                &ctx.bridge,
+            )?),
+        )
+        .await
+        .with_context(|| format!("failed to apply Deployment {namespace}/{name}"))?;
+
+    let services: Api<Service> = Api::namespaced(ctx.client.clone(), &namespace);
+    services
+        .patch(&name, &params, &Patch::Apply(&service(&server)?))
+        .await
+        .with_context(|| format!("failed to apply Service {namespace}/{name}"))?;
+
+    let network_policies: Api<NetworkPolicy> = Api::namespaced(ctx.client.clone(), &namespace);
+    network_policies
+        .patch(&name, &params, &Patch::Apply(&network::network_policy(&server, &ctx.network)?))
+        .await
+        .with_context(|| format!("failed to apply NetworkPolicy {namespace}/{name}"))?;
+
+    let last_secret_rotation = if rotated {
+        info!(resource = "LatchkeyServer", %name, %namespace, "bound secret changed, rolling out");
+        let event = Event {
+            type_: EventType::Normal,
+            reason: "SecretRotated".to_string(),
+            note: Some(format!("A bound Secret changed; rolling out Deployment {name}")),
+            action: "Rollout".to_string(),
+            secondary: None,
+        };
+        publish_event(&ctx.client, &server, event).await;
+        Some(Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true))
+    } else {
+        server.status.as_ref().and_then(|status| status.last_secret_rotation.clone())
+    };
+
+    let ready_replicas = deployment.status.and_then(|status| status.ready_replicas).unwrap_or(0);
+    let status = LatchkeyServerStatus {
+        ready_replicas: Some(ready_replicas),
+        endpoints: Some(vec![format!("{name}.{namespace}.svc:{}", service_port(&server))]),
+        conditions: Some(conditions(&server, ready_replicas, &resolved.statuses)),
+        secrets: Some(resolved.statuses),
+        last_secret_rotation,
+        secret_hash: resolved.content_hash,
+    };
+
+    servers
+        .patch_status(&name, &PatchParams::default(), &Patch::Merge(json!({ "status": status })))
+        .await
+        .with_context(|| format!("failed to update LatchkeyServer {namespace}/{name} status"))?;
+
+    Ok(Action::requeue(RESYNC_INTERVAL))
+}
+
+fn error_policy(
+    server: Arc<LatchkeyServer>,
+    err: &ReconcileError,
+    _ctx: Arc<ReconcileContext>,
+) -> Action {
+    warn!(resource = "LatchkeyServer", name = %server.name_any(), error = %err, "scheduling retry");
+    Action::requeue(RETRY_INTERVAL)
+}
+
+/// Whether bound Secret content changed since the last rollout recorded in status. Status is
+/// only written once the rollout is applied, so a failed reconcile reports the rotation again.
+fn rotated(status: Option<&LatchkeyServerStatus>, content_hash: Option<&str>) -> bool {
+    let previous = status.and_then(|status| status.secret_hash.as_deref());
+    matches!((previous, content_hash), (Some(previous), Some(current)) if previous != current)
+}
+
+/// Best effort: a failed event must not block the reconcile it describes.
+async fn publish_event(client: &Client, server: &LatchkeyServer, event: Event) {
+    let reporter = Reporter { controller: FIELD_MANAGER.to_string(), instance: None };
+    let recorder = Recorder::new(client.clone(), reporter, server.object_ref(&()));
+
+    if let Err(err) = recorder.publish(event).await {
+        warn!(resource = "LatchkeyServer", name = %server.name_any(), error = %err, "failed to publish event");
+    }
+}
+
+fn conditions(
+    server: &LatchkeyServer,
+    ready_replicas: i32,
+    secret_statuses: &[SecretBindingStatus],
+) -> Vec<String> {
+    let desired = server.spec.replicas.unwrap_or(1);
+    let ready = if ready_replicas >= desired { "Ready=True" } else { "Ready=False" };
+    let secrets_resolved = if secret_statuses.iter().all(|status| status.ready) {
+        "SecretsResolved=True"
+    } else {
+        "SecretsResolved=False"
+    };
+
+    vec![ready.to_string(), secrets_resolved.to_string()]
+}
+
+pub fn selector_labels(server: &LatchkeyServer) -> BTreeMap<String, String> {
+    BTreeMap::from([(SERVER_LABEL.to_string(), server.name_any())])
+}
+
+fn labels(server: &LatchkeyServer) -> BTreeMap<String, String> {
+    let mut labels = selector_labels(server);
+    labels.insert("app.kubernetes.io/name".to_string(), server.name_any());
+    labels.insert("app.kubernetes.io/component".to_string(), "tool-server".to_string());
+    labels.insert("app.kubernetes.io/managed-by".to_string(), FIELD_MANAGER.to_string());
+    labels
+}
+
+pub fn owned_metadata(server: &LatchkeyServer) -> anyhow::Result<ObjectMeta> {
+    let owner = server.controller_owner_ref(&()).context("LatchkeyServer has no uid yet")?;
+
+    Ok(ObjectMeta {
+        name: Some(server.name_any()),
+        namespace: server.namespace(),
+        labels: Some(labels(server)),
+        owner_references: Some(vec![owner]),
+        ..ObjectMeta::default()
+    })
+}
+
+pub fn service_port(server: &LatchkeyServer) -> u16 {
+    server.spec.service_port.unwrap_or(DEFAULT_SERVICE_PORT)
+}
+
+pub fn deployment(
+    server: &LatchkeyServer,
+    secret_hash: Option<&str>,
+    config: &SecretsConfig,
+    network: &NetworkConfig,
+    bridge_config: &BridgeConfig,
+) -> anyhow::Result<Deployment> {
+    let spec = &server.spec;
+    let security = spec.security_context.clone().unwrap_or_default();
+    let run_as_user = security.run_as_user.unwrap_or(DEFAULT_RUN_AS_USER);
+    // Also refused at admission; checked here too so a webhook outage cannot let it through.
+    if run_as_user == network::EGRESS_PROXY_UID {
+        anyhow::bail!("runAsUser {run_as_user} is reserved for the egress proxy");
+    }
+    let mut pod_secrets =
+        secrets::pod_secrets(spec.secrets.as_deref().unwrap_or_default(), config)?;
+    let egress = network::egress_sidecar(server, network)?.unwrap_or_default();
+
+    // The egress firewall and proxy start before anything that may need to reach out.
+    let mut init_containers = egress.init_containers;
+    for sidecar in &mut pod_secrets.init_containers {
+        sidecar.env.get_or_insert_with(Vec::new).extend(egress.env.iter().cloned());
+    }
+    init_containers.append(&mut pod_secrets.init_containers);
+    pod_secrets.env.extend(egress.env);
+
+    let bridge = bridge::stdio_bridge(server, bridge_config)?;
+    let mut ready_path = None;
+    if let Some(bridge) = &bridge {
+        init_containers.push(bridge.init_container.clone());
+        pod_secrets.volumes.push(bridge.volume.clone());
+        pod_secrets.mounts.push(bridge.mount.clone());
+        pod_secrets.env.extend(bridge.env.iter().cloned());
+        ready_path = Some(READY_PATH.to_string());
+    }
+    let pod_metadata = spec.pod_metadata.clone().unwrap_or_default();
+    let health = spec.health.clone().unwrap_or_default();
+
+    // User labels first so they can never override the selector.
+    let mut pod_labels = pod_metadata.labels.unwrap_or_default();
+    pod_labels.extend(labels(server));
+    let mut pod_annotations = pod_metadata.annotations.unwrap_or_default();
+    if let Some(hash) = secret_hash {
+        pod_annotations.insert(secrets::SECRET_HASH_ANNOTATION.to_string(), hash.to_string());
+    }
+
+    let probe = |path: Option<String>| Probe {
+        http_get: Some(HTTPGetAction {
+            path: Some(path.unwrap_or_else(|| DEFAULT_PROBE_PATH.to_string())),
+            port: IntOrString::String("http".to_string()),
+            ..HTTPGetAction::default()
+        }),
+        period_seconds: Some(health.period_seconds.unwrap_or(DEFAULT_PROBE_PERIOD_SECONDS)),
+        ..Probe::default()
+    };
+
+    let resources = spec.resources.as_ref().map(|resources| ResourceRequirements {
+        requests: resources.requests.as_ref().map(quantities),
+        limits: resources.limits.as_ref().map(quantities),
+        ..ResourceRequirements::default()
+    });
+
+    let container = Container {
+        name: "tool-server".to_string(),
+        image: Some(spec.image.clone()),
+        command: bridge.as_ref().map(|bridge| bridge.command.clone()),
+        args: bridge.map(|bridge| bridge.args),
+        ports: Some(vec![ContainerPort {
+            name: Some("http".to_string()),
+            container_port: i32::from(service_port(server)),
+            ..ContainerPort::default()
+        }]),
+        env: Some(pod_secrets.env).filter(|env| !env.is_empty()),
+        volume_mounts: Some(pod_secrets.mounts).filter(|mounts| !mounts.is_empty()),
+        resources,
+        readiness_probe: Some(probe(health.readiness_path.clone().or(ready_path))),
+        liveness_probe: Some(probe(health.liveness_path.clone())),
+        security_context: Some(container_security_context(
+            security.read_only_root_filesystem.unwrap_or(true),
+        )),
+        ..Container::default()
+    };
+
+    Ok(Deployment {
+        metadata: owned_metadata(server)?,
+        spec: Some(DeploymentSpec {
+            replicas: spec.replicas,
+            selector: LabelSelector {
+                match_labels: Some(selector_labels(server)),
+                ..LabelSelector::default()
+            },
+            template: PodTemplateSpec {
+                metadata: Some(ObjectMeta {
+                    labels: Some(pod_labels),
+                    annotations: Some(pod_annotations)
+                        .filter(|annotations| !annotations.is_empty()),
+                    ..ObjectMeta::default()
+                }),
+                spec: Some(PodSpec {
+                    // Tool servers never talk to the cluster API.
+                    automount_service_account_token: Some(false),
+                    init_containers: Some(init_containers)
+                        .filter(|containers| !containers.is_empty()),
+                    containers: vec![container],
+                    volumes: Some(pod_secrets.volumes).filter(|volumes| !volumes.is_empty()),
+                    security_context: Some(PodSecurityContext {
+                        run_as_non_root: Some(true),
+                        run_as_user: Some(run_as_user),
+                        seccomp_profile: Some(SeccompProfile {
+                            type_: "RuntimeDefault".to_string(),
+                            ..SeccompProfile::default()
+                        }),
+                        ..PodSecurityContext::default()
+                    }),
+                    ..PodSpec::default()
+                }),
+            },
+            ..DeploymentSpec::default()
+        }),
+        ..Deployment::default()
+    })
+}
+
+/// Baseline for every container the operator runs in a tool server pod.
+pub fn container_security_context(read_only_root_filesystem: bool) -> SecurityContext {
+    SecurityContext {
+        allow_privilege_escalation: Some(false),
+        privileged: Some(false),
+        read_only_root_filesystem: Some(read_only_root_filesystem),
+        capabilities: Some(Capabilities {
+            drop: Some(vec!["ALL".to_string()]),
+            ..Capabilities::default()
+        }),
+        ..SecurityContext::default()
+    }
+}
+
+pub fn service(server: &LatchkeyServer) -> anyhow::Result<Service> {
+    Ok(Service {
+        metadata: owned_metadata(server)?,
+        spec: Some(ServiceSpec {
+            selector: Some(selector_labels(server)),
+            ports: Some(vec![ServicePort {
+                name: Some("http".to_string()),
+                port: i32::from(service_port(server)),
+                target_port: Some(IntOrString::String("http".to_string())),
+                ..ServicePort::default()
+            }]),
+            ..ServiceSpec::default()
+        }),
+        ..Service::default()
+    })
+}
+
+fn quantities(values: &BTreeMap<String, String>) -> BTreeMap<String, Quantity> {
+    values.iter().map(|(name, value)| (name.clone(), Quantity(value.clone()))).collect()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    /// A server named `github` in `tools`, as the API server would hand it to the operator.
+    pub fn server(spec: Value) -> LatchkeyServer {
+        let mut server = LatchkeyServer::new("github", serde_json::from_value(spec).unwrap());
+        server.metadata.namespace = Some("tools".to_string());
+        server.metadata.uid = Some("9c1f7a3e-0000-4000-8000-000000000001".to_string());
+        server
+    }
+
+    pub fn deployment_of(server: &LatchkeyServer) -> anyhow::Result<Deployment> {
+        let secrets = SecretsConfig {
+            vault_address: None,
+            vault_agent_image: "vault-agent".to_string(),
+            vault_audience: "vault".to_string(),
+        };
+        let network = NetworkConfig {
+            gateway_namespace: "latchkey-system".to_string(),
+            egress_proxy_image: "egress-proxy".to_string(),
+            egress_init_image: "egress-init".to_string(),
+        };
+        let bridge = BridgeConfig { image: "mcp-bridge".to_string() };
+        deployment(server, None, &secrets, &network, &bridge)
+    }
+
+    #[test]
+    fn refuses_the_egress_proxy_uid() {
+        let proxy_uid = server(json!({
+            "image": "ghcr.io/example/github-mcp:1",
+            "securityContext": { "runAsUser": 65533 },
+        }));
+        let err = deployment_of(&proxy_uid).unwrap_err();
+        assert_eq!(err.to_string(), "runAsUser 65533 is reserved for the egress proxy");
+
+        let default = deployment_of(&server(json!({ "image": "ghcr.io/example/github-mcp:1" })));
+        let pod = default.unwrap().spec.unwrap().template.spec.unwrap();
+        assert_eq!(pod.security_context.unwrap().run_as_user, Some(DEFAULT_RUN_AS_USER));
+    }
+
+    #[test]
+    fn rotation_is_measured_against_the_last_recorded_rollout() {
+        let status = |hash: Option<&str>| LatchkeyServerStatus {
+            secret_hash: hash.map(str::to_string),
+            ..LatchkeyServerStatus::default()
+        };
+        assert!(rotated(Some(&status(Some("a"))), Some("b")));
+        assert!(!rotated(Some(&status(Some("a"))), Some("a")));
+        // Nothing to compare against on the first rollout, or once every binding is removed.
+        assert!(!rotated(None, Some("a")));
+        assert!(!rotated(Some(&status(None)), Some("a")));
+        assert!(!rotated(Some(&status(Some("a"))), None));
+    }
+
+    #[test]
+    fn binds_secrets_into_the_tool_server_by_reference() {
+        let server = server(json!({
+            "image": "ghcr.io/example/github-mcp:1",
+            "secrets": [{
+                "name": "github-token",
+                "source": { "kubernetesSecret": { "name": "github", "key": "token" } },
+                "mount": { "mode": "file", "envName": "GITHUB_TOKEN" },
+            }],
+        }));
+        let pod = deployment_of(&server).unwrap().spec.unwrap().template.spec.unwrap();
+
+        let volume =
+            pod.volumes.iter().flatten().find(|volume| volume.name == "secret-github-token");
+        let secret = volume.unwrap().secret.as_ref().unwrap();
+        assert_eq!(secret.secret_name.as_deref(), Some("github"));
+        assert_eq!(secret.default_mode, Some(0o400));
+        let tool_server = pod
+            .containers
+            .iter()
+            .find(|container| {
+                container
+                    .volume_mounts
+                    .iter()
+                    .flatten()
+                    .any(|mount| mount.name == "secret-github-token")
+            })
+            .unwrap();
+        let env = tool_server.env.iter().flatten().find(|env| env.name == "GITHUB_TOKEN_FILE");
+        assert_eq!(
+            env.unwrap().value.as_deref(),
+            Some("/var/run/secrets/latchkey/github-token/token")
+        );
+
+        let missing = SecretBindingStatus {
+            name: "github-token".to_string(),
+            ready: false,
+            message: Some("Secret github not found".to_string()),
+        };
+        assert_eq!(conditions(&server, 1, &[missing]), ["Ready=True", "SecretsResolved=False"]);
+        assert_eq!(conditions(&server, 0, &[]), ["Ready=False", "SecretsResolved=True"]);
+    }
+}
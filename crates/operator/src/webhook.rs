@@ -0,0 +1,677 @@
+use crate::certs;
+use crate::conversion;
+use crate::crd::v1beta1::{EgressPolicy, SecretBinding, SecretMountMode};
+use crate::crd::{
+    AuditLevel, AuthMode, LatchkeyPolicy, LatchkeyPolicySpec, LatchkeyPrincipal,
+    LatchkeyPrincipalSpec, LatchkeyServer, LatchkeyServerSpec, LatchkeyTool, LatchkeyToolSpec,
+    Transport,
+};
+use crate::network::{Cidr, EGRESS_PROXY_UID};
+use crate::secrets;
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode, routing::get, routing::post, Json, Router};
+use kube::core::admission::{AdmissionRequest, AdmissionResponse, AdmissionReview};
+use kube::core::conversion::{ConversionRequest, ConversionResponse, ConversionReview};
+use kube::core::{DynamicObject, Status};
+use kube::Client;
+use latchkey_core::egress::is_host_pattern;
+use latchkey_core::redaction::RedactionPath;
+use latchkey_core::scope::{is_valid_name, Scope};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+const DEFAULT_BIND: &str = "0.0.0.0:8443";
+const DEFAULT_SERVICE: &str = "latchkey-operator-webhook";
+const DEFAULT_NAMESPACE: &str = "latchkey-system";
+const DEFAULT_CONFIGURATION: &str = "latchkey-validating-webhook";
+const DEFAULT_CA_SECRET: &str = "latchkey-webhook-ca";
+const MIN_CAPABILITY_TTL_SECONDS: u32 = 60;
+const MAX_CAPABILITY_TTL_SECONDS: u32 = 120;
+
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    pub bind: SocketAddr,
+    pub cert_dir: Option<String>,
+    pub service: String,
+    pub namespace: String,
+    pub configuration: String,
+    /// Secret in `namespace` that keeps the self-signed CA across restarts.
+    pub ca_secret: String,
+    pub production_mode: bool,
+}
+
+impl WebhookConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let bind = std::env::var("LATCHKEY_WEBHOOK_BIND")
+            .unwrap_or_else(|_| DEFAULT_BIND.to_string())
+            .parse()
+            .context("invalid LATCHKEY_WEBHOOK_BIND value")?;
+
+        Ok(Self {
+            bind,
+            cert_dir: std::env::var("LATCHKEY_WEBHOOK_CERT_DIR").ok(),
+            service: std::env::var("LATCHKEY_WEBHOOK_SERVICE")
+                .unwrap_or_else(|_| DEFAULT_SERVICE.to_string()),
+            namespace: std::env::var("POD_NAMESPACE")
+                .unwrap_or_else(|_| DEFAULT_NAMESPACE.to_string()),
+            configuration: std::env::var("LATCHKEY_WEBHOOK_CONFIGURATION")
+                .unwrap_or_else(|_| DEFAULT_CONFIGURATION.to_string()),
+            ca_secret: std::env::var("LATCHKEY_WEBHOOK_CA_SECRET")
+                .unwrap_or_else(|_| DEFAULT_CA_SECRET.to_string()),
+            production_mode: std::env::var("LATCHKEY_PRODUCTION_MODE")
+                .map(|value| value == "true")
+                .unwrap_or(false),
+        })
+    }
+}
+
+pub async fn serve(client: Client, config: WebhookConfig) -> anyhow::Result<()> {
+    let tls = certs::bootstrap(&client, &config).await?;
+    let addr = config.bind;
+
+    let app = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/validate", post(validate))
+        .route("/convert", post(convert))
+        .with_state(Arc::new(config));
+
+    info!(%addr, "admission webhook listening");
+    axum_server::bind_rustls(addr, tls)
+        .serve(app.into_make_service())
+        .await
+        .context("admission webhook server failed")
+}
+
+async fn healthz() -> StatusCode {
+    StatusCode::OK
+}
+
+async fn validate(
+    State(config): State<Arc<WebhookConfig>>,
+    Json(review): Json<AdmissionReview<DynamicObject>>,
+) -> Json<AdmissionReview<DynamicObject>> {
+    let request: AdmissionRequest<DynamicObject> = match review.try_into() {
+        Ok(request) => request,
+        Err(err) => {
+            warn!(error = %err, "malformed admission review");
+            return Json(AdmissionResponse::invalid(err.to_string()).into_review());
+        }
+    };
+
+    let mut response = AdmissionResponse::from(&request);
+
+    if let Some(object) = request.object.clone() {
+        let kind = request.kind.kind.as_str();
+        let violations = validate_object(kind, object, &config);
+
+        if !violations.is_empty() {
+            let name = request.name.as_str();
+            info!(kind, name, violations = ?violations, "admission denied");
+            response = response.deny(violations.join("; "));
+        }
+    }
+
+    Json(response.into_review())
+}
+
+async fn convert(Json(review): Json<ConversionReview>) -> Json<ConversionReview> {
+    let request = match ConversionRequest::from_review(review) {
+        Ok(request) => request,
+        Err(err) => {
+            warn!(error = %err, "malformed conversion review");
+            let status = Status::failure(&err.to_string(), "InvalidConversionReview");
+            return Json(ConversionResponse::invalid(status).into_review());
+        }
+    };
+
+    let desired = request.desired_api_version.clone();
+    let objects = request.objects.clone();
+    let response = ConversionResponse::for_request(request);
+
+    let converted: anyhow::Result<Vec<_>> =
+        objects.into_iter().map(|object| conversion::convert(object, &desired)).collect();
+
+    let response = match converted {
+        Ok(objects) => response.success(objects),
+        Err(err) => {
+            warn!(error = %err, desired_api_version = %desired, "conversion failed");
+            response.failure(Status::failure(&format!("{err:#}"), "ConversionFailed"))
+        }
+    };
+
+    Json(response.into_review())
+}
+
+fn validate_object(kind: &str, object: DynamicObject, config: &WebhookConfig) -> Vec<String> {
+    let parsed = match kind {
+        "LatchkeyServer" => {
+            object.try_parse::<LatchkeyServer>().map(|obj| validate_server(&obj.spec, config))
+        }
+        "LatchkeyTool" => object.try_parse::<LatchkeyTool>().map(|obj| validate_tool(&obj.spec)),
+        "LatchkeyPrincipal" => {
+            object.try_parse::<LatchkeyPrincipal>().map(|obj| validate_principal(&obj.spec))
+        }
+        "LatchkeyPolicy" => {
+            object.try_parse::<LatchkeyPolicy>().map(|obj| validate_policy(&obj.spec))
+        }
+        _ => return Vec::new(),
+    };
+
+    parsed.unwrap_or_else(|err| vec![format!("{kind} does not match its schema: {err}")])
+}
+
+pub fn validate_server(spec: &LatchkeyServerSpec, config: &WebhookConfig) -> Vec<String> {
+    let mut violations = Vec::new();
+
+    if spec.image.trim().is_empty() {
+        violations.push("spec.image must not be empty".to_string());
+    } else if config.production_mode && !is_digest_pinned(&spec.image) {
+        violations.push(format!(
+            "spec.image {:?} must be pinned by digest (image@sha256:<64 hex>) in production mode",
+            spec.image
+        ));
+    }
+
+    if spec.replicas.is_some_and(|replicas| replicas < 0) {
+        violations.push("spec.replicas must not be negative".to_string());
+    }
+
+    if spec.service_port == Some(0) {
+        violations.push("spec.servicePort must be between 1 and 65535".to_string());
+    }
+
+    if spec.security_context.as_ref().and_then(|security| security.run_as_user)
+        == Some(EGRESS_PROXY_UID)
+    {
+        violations.push(format!(
+            "spec.securityContext.runAsUser {EGRESS_PROXY_UID} is reserved for the egress proxy"
+        ));
+    }
+
+    let stdio = spec.transport == Some(Transport::Stdio);
+    match &spec.stdio {
+        None if stdio => violations.push("transport stdio requires spec.stdio.command".to_string()),
+        Some(_) if !stdio => {
+            violations.push("spec.stdio is only valid with transport stdio".to_string())
+        }
+        Some(command) if command.command.iter().all(|part| part.trim().is_empty()) => {
+            violations.push("spec.stdio.command must not be empty".to_string())
+        }
+        _ => {}
+    }
+
+    violations
+        .extend(validate_secret_bindings(spec.secrets.as_deref().unwrap_or_default(), config));
+    if let Some(policy) = &spec.egress_policy {
+        violations.extend(validate_egress_policy(policy));
+    }
+
+    violations
+}
+
+fn validate_egress_policy(policy: &EgressPolicy) -> Vec<String> {
+    let mut violations = Vec::new();
+
+    for (index, rule) in policy.allow.iter().enumerate() {
+        let field = format!("spec.egressPolicy.allow[{index}]");
+
+        if !is_cidr(&rule.cidr) {
+            violations.push(format!("{field}.cidr {:?} must be a CIDR", rule.cidr));
+        }
+        for except in rule.except.iter().flatten() {
+            if !is_cidr(except) {
+                violations.push(format!("{field}.except {except:?} must be a CIDR"));
+            }
+        }
+        if rule.ports.iter().flatten().any(|port| port.port == 0) {
+            violations.push(format!("{field}.ports must be between 1 and 65535"));
+        }
+    }
+
+    for (index, host) in policy.hosts.iter().flatten().enumerate() {
+        let field = format!("spec.egressPolicy.hosts[{index}]");
+
+        if !is_host_pattern(&host.host) {
+            violations.push(format!(
+                "{field}.host {:?} must be a lowercase DNS name, optionally starting with `*.`",
+                host.host
+            ));
+        }
+        if host.ports.iter().flatten().any(|port| *port == 0) {
+            violations.push(format!("{field}.ports must be between 1 and 65535"));
+        }
+    }
+
+    violations
+}
+
+fn validate_secret_bindings(bindings: &[SecretBinding], config: &WebhookConfig) -> Vec<String> {
+    let mut violations = Vec::new();
+    let mut names = HashSet::new();
+
+    for (index, binding) in bindings.iter().enumerate() {
+        let field = format!("spec.secrets[{index}]");
+
+        if !is_dns_label(&binding.name) {
+            violations.push(format!("{field}.name {:?} must be a DNS label", binding.name));
+        } else if !names.insert(binding.name.as_str()) {
+            violations.push(format!("{field}.name {:?} is bound more than once", binding.name));
+        }
+
+        match (&binding.source.kubernetes_secret, &binding.source.vault_secret) {
+            (Some(source), None) => {
+                if source.name.trim().is_empty() || source.key.trim().is_empty() {
+                    violations.push(format!(
+                        "{field}.source.kubernetesSecret must set both name and key"
+                    ));
+                }
+            }
+            (None, Some(source)) => {
+                if [&source.role, &source.path, &source.key]
+                    .iter()
+                    .any(|value| value.trim().is_empty())
+                {
+                    violations
+                        .push(format!("{field}.source.vaultSecret must set role, path, and key"));
+                }
+                if binding.mount.mode != SecretMountMode::File {
+                    violations
+                        .push(format!("{field}.mount.mode must be file for vaultSecret bindings"));
+                }
+                match source.address.as_deref() {
+                    Some(address) if config.production_mode && !address.starts_with("https://") => {
+                        violations.push(format!(
+                            "{field}.source.vaultSecret.address {address:?} must use https in production mode"
+                        ));
+                    }
+                    Some(address)
+                        if !address.starts_with("https://") && !address.starts_with("http://") =>
+                    {
+                        violations.push(format!(
+                            "{field}.source.vaultSecret.address {address:?} must be an http(s) URL"
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+            _ => violations.push(format!(
+                "{field}.source must set exactly one of kubernetesSecret or vaultSecret"
+            )),
+        }
+
+        if let Some(env_name) = &binding.mount.env_name {
+            if !is_env_name(env_name) {
+                violations.push(format!(
+                    "{field}.mount.envName {env_name:?} must match [A-Z_][A-Z0-9_]*"
+                ));
+            }
+        }
+
+        match (binding.mount.mode, &binding.mount.path) {
+            (SecretMountMode::Env, Some(_)) => {
+                violations.push(format!("{field}.mount.path is only valid for file mounts"));
+            }
+            (SecretMountMode::File, Some(path)) if !is_secret_path(path) => {
+                violations.push(format!(
+                    "{field}.mount.path {path:?} must be a normalized path under {}/",
+                    secrets::SECRET_ROOT
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    violations
+}
+
+pub fn validate_tool(spec: &LatchkeyToolSpec) -> Vec<String> {
+    let mut violations = Vec::new();
+
+    if !is_valid_name(&spec.tool_name) {
+        violations.push(format!(
+            "spec.toolName {:?} must be non-empty and use only [A-Za-z0-9._-]",
+            spec.tool_name
+        ));
+    }
+
+    if spec.server_ref.trim().is_empty() {
+        violations.push("spec.serverRef must not be empty".to_string());
+    }
+
+    for (index, operation) in spec.operations.iter().flatten().enumerate() {
+        if !is_valid_name(&operation.op_name) {
+            violations.push(format!(
+                "spec.operations[{index}].opName {:?} must be non-empty and use only [A-Za-z0-9._-]",
+                operation.op_name
+            ));
+        }
+    }
+
+    if let Some(limits) = &spec.limits {
+        if limits.max_payload_bytes == Some(0) {
+            violations.push("spec.limits.maxPayloadBytes must be greater than zero".to_string());
+        }
+        if limits.max_response_bytes == Some(0) {
+            violations.push("spec.limits.maxResponseBytes must be greater than zero".to_string());
+        }
+        if limits.timeout_ms == Some(0) {
+            violations.push("spec.limits.timeoutMs must be greater than zero".to_string());
+        }
+        if limits.rate_limit == Some(0) {
+            violations.push("spec.limits.rateLimit must be greater than zero".to_string());
+        }
+    }
+
+    let rules = spec.audit.iter().flat_map(|audit| audit.redaction_rules.iter().flatten());
+    for (index, rule) in rules.enumerate() {
+        if let Err(err) = rule.path.parse::<RedactionPath>() {
+            violations.push(format!("spec.audit.redactionRules[{index}].path: {err}"));
+        }
+    }
+
+    violations
+}
+
+pub fn validate_principal(spec: &LatchkeyPrincipalSpec) -> Vec<String> {
+    let mut violations = Vec::new();
+
+    if spec.principal_id.trim().is_empty() {
+        violations.push("spec.principalId must not be empty".to_string());
+    }
+
+    match spec.auth_mode {
+        AuthMode::Oidc => {
+            match &spec.oidc {
+                None => violations.push("spec.oidc is required when authMode is oidc".to_string()),
+                Some(oidc) => {
+                    if oidc.issuer.trim().is_empty() {
+                        violations.push("spec.oidc.issuer must not be empty".to_string());
+                    }
+                    if oidc.subject_match.is_none() && oidc.client_id.is_none() {
+                        violations.push(
+                            "spec.oidc must set subjectMatch or clientId to identify the caller"
+                                .to_string(),
+                        );
+                    }
+                }
+            }
+            if spec.gateway_secret.is_some() {
+                violations
+                    .push("spec.gatewaySecret must not be set when authMode is oidc".to_string());
+            }
+        }
+        AuthMode::GatewaySecret => {
+            match &spec.gateway_secret {
+                None => violations.push(
+                    "spec.gatewaySecret is required when authMode is gateway-secret".to_string(),
+                ),
+                Some(secret) => {
+                    if secret.client_id.trim().is_empty() {
+                        violations
+                            .push("spec.gatewaySecret.clientId must not be empty".to_string());
+                    }
+                    if secret.secret_ref.name.trim().is_empty()
+                        || secret.secret_ref.key.trim().is_empty()
+                    {
+                        violations.push(
+                            "spec.gatewaySecret.secretRef must set both name and key".to_string(),
+                        );
+                    }
+                }
+            }
+            if spec.oidc.is_some() {
+                violations
+                    .push("spec.oidc must not be set when authMode is gateway-secret".to_string());
+            }
+        }
+    }
+
+    if let Some(ttl) = spec.token_policy.as_ref().and_then(|policy| policy.capability_ttl_seconds) {
+        if !(MIN_CAPABILITY_TTL_SECONDS..=MAX_CAPABILITY_TTL_SECONDS).contains(&ttl) {
+            violations.push(format!(
+                "spec.tokenPolicy.capabilityTTLSeconds must be between {MIN_CAPABILITY_TTL_SECONDS} and {MAX_CAPABILITY_TTL_SECONDS}, got {ttl}"
+            ));
+        }
+    }
+
+    violations
+}
+
+pub fn validate_policy(spec: &LatchkeyPolicySpec) -> Vec<String> {
+    let mut violations = Vec::new();
+
+    if spec.subjects.is_empty() {
+        violations.push("spec.subjects must list at least one subject".to_string());
+    }
+
+    for (index, scope) in spec.scopes.iter().enumerate() {
+        if let Err(err) = scope.parse::<Scope>() {
+            violations.push(format!("spec.scopes[{index}]: {err}"));
+        }
+    }
+
+    if spec.break_glass == Some(true) && spec.audit_level != Some(AuditLevel::Verbose) {
+        violations.push("spec.breakGlass requires spec.auditLevel to be verbose".to_string());
+    }
+
+    violations
+}
+
+fn is_dns_label(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 63
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+}
+
+fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_uppercase() || c == '_')
+        && chars.all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+}
+
+fn is_secret_path(path: &str) -> bool {
+    path.strip_prefix(secrets::SECRET_ROOT).and_then(|rest| rest.strip_prefix('/')).is_some_and(
+        |rest| {
+            rest.split('/').all(|segment| !segment.is_empty() && segment != "." && segment != "..")
+        },
+    )
+}
+
+fn is_cidr(value: &str) -> bool {
+    value.parse::<Cidr>().is_ok()
+}
+
+fn is_digest_pinned(image: &str) -> bool {
+    image.rsplit_once("@sha256:").is_some_and(|(name, digest)| {
+        !name.is_empty() && digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn config(production_mode: bool) -> WebhookConfig {
+        WebhookConfig {
+            bind: DEFAULT_BIND.parse().unwrap(),
+            cert_dir: None,
+            service: DEFAULT_SERVICE.to_string(),
+            namespace: DEFAULT_NAMESPACE.to_string(),
+            configuration: DEFAULT_CONFIGURATION.to_string(),
+            ca_secret: DEFAULT_CA_SECRET.to_string(),
+            production_mode,
+        }
+    }
+
+    fn server(spec: Value) -> Vec<String> {
+        validate_server(&serde_json::from_value(spec).unwrap(), &config(false))
+    }
+
+    const DIGEST: &str = "sha256:0000000000000000000000000000000000000000000000000000000000000000";
+
+    #[test]
+    fn server_violations() {
+        let cases = [
+            (json!({ "image": format!("tool@{DIGEST}") }), vec![]),
+            (
+                json!({ "image": "tool:1" }),
+                vec!["spec.image \"tool:1\" must be pinned by digest (image@sha256:<64 hex>) in production mode"],
+            ),
+            (
+                json!({ "image": "tool@sha256:abc", "replicas": -1, "servicePort": 0 }),
+                vec![
+                    "spec.image \"tool@sha256:abc\" must be pinned by digest (image@sha256:<64 hex>) in production mode",
+                    "spec.replicas must not be negative",
+                    "spec.servicePort must be between 1 and 65535",
+                ],
+            ),
+            (json!({ "image": " " }), vec!["spec.image must not be empty"]),
+            (
+                json!({ "image": format!("tool@{DIGEST}"), "transport": "stdio" }),
+                vec!["transport stdio requires spec.stdio.command"],
+            ),
+        ];
+        for (spec, expected) in cases {
+            let violations = validate_server(&serde_json::from_value(spec).unwrap(), &config(true));
+            assert_eq!(violations, expected);
+        }
+        assert!(
+            server(json!({ "image": "tool:1" })).is_empty(),
+            "tags are fine outside production"
+        );
+    }
+
+    #[test]
+    fn tool_violations() {
+        let cases = [
+            (json!({ "toolName": "github.search", "serverRef": "github" }), vec![]),
+            (
+                json!({ "toolName": "github search", "serverRef": "" }),
+                vec![
+                    "spec.toolName \"github search\" must be non-empty and use only [A-Za-z0-9._-]",
+                    "spec.serverRef must not be empty",
+                ],
+            ),
+            (
+                json!({
+                    "toolName": "t",
+                    "serverRef": "s",
+                    "limits": { "maxPayloadBytes": 0, "timeoutMs": 0 },
+                }),
+                vec![
+                    "spec.limits.maxPayloadBytes must be greater than zero",
+                    "spec.limits.timeoutMs must be greater than zero",
+                ],
+            ),
+        ];
+        for (spec, expected) in cases {
+            assert_eq!(validate_tool(&serde_json::from_value(spec).unwrap()), expected);
+        }
+    }
+
+    #[test]
+    fn principal_violations() {
+        let oidc = json!({ "issuer": "https://issuer.example.com", "clientId": "agent" });
+        let secret =
+            json!({ "clientId": "agent", "secretRef": { "name": "agent", "key": "secret" } });
+        let principal = |auth_mode: &str, extra: Value| {
+            let mut spec =
+                json!({ "principalId": "agent", "authMode": auth_mode, "enabled": true });
+            spec.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+            validate_principal(&serde_json::from_value(spec).unwrap())
+        };
+        let ttl =
+            |ttl: u32| json!({ "oidc": oidc, "tokenPolicy": { "capabilityTTLSeconds": ttl } });
+
+        let cases = [
+            (principal("oidc", json!({ "oidc": oidc })), vec![]),
+            (principal("oidc", ttl(60)), vec![]),
+            (principal("oidc", ttl(120)), vec![]),
+            (
+                principal("oidc", ttl(59)),
+                vec!["spec.tokenPolicy.capabilityTTLSeconds must be between 60 and 120, got 59"],
+            ),
+            (
+                principal("oidc", ttl(121)),
+                vec!["spec.tokenPolicy.capabilityTTLSeconds must be between 60 and 120, got 121"],
+            ),
+            (
+                principal("oidc", json!({ "gatewaySecret": secret })),
+                vec![
+                    "spec.oidc is required when authMode is oidc",
+                    "spec.gatewaySecret must not be set when authMode is oidc",
+                ],
+            ),
+            (
+                principal("gateway-secret", json!({ "oidc": oidc })),
+                vec![
+                    "spec.gatewaySecret is required when authMode is gateway-secret",
+                    "spec.oidc must not be set when authMode is gateway-secret",
+                ],
+            ),
+            (principal("gateway-secret", json!({ "gatewaySecret": secret })), vec![]),
+        ];
+        for (violations, expected) in cases {
+            assert_eq!(violations, expected);
+        }
+    }
+
+    #[test]
+    fn policy_violations() {
+        let policy = |spec: Value| validate_policy(&serde_json::from_value(spec).unwrap());
+        let cases = [
+            (json!({ "subjects": ["agent"], "scopes": ["tools:github.search:read"] }), vec![]),
+            (
+                json!({
+                    "subjects": [],
+                    "scopes": ["github.search:read", "tools:github.search:delete"],
+                }),
+                vec![
+                    "spec.subjects must list at least one subject",
+                    "spec.scopes[0]: invalid scope \"github.search:read\": scopes must start with `tools:`",
+                    "spec.scopes[1]: invalid scope \"tools:github.search:delete\": action must be one of call, read, write, admin or op:<name>",
+                ],
+            ),
+            (
+                json!({
+                    "subjects": ["agent"],
+                    "scopes": [],
+                    "breakGlass": true,
+                    "auditLevel": "normal",
+                }),
+                vec!["spec.breakGlass requires spec.auditLevel to be verbose"],
+            ),
+            (
+                json!({ "subjects": ["agent"], "scopes": [], "breakGlass": true }),
+                vec!["spec.breakGlass requires spec.auditLevel to be verbose"],
+            ),
+            (
+                json!({
+                    "subjects": ["agent"],
+                    "scopes": [],
+                    "breakGlass": true,
+                    "auditLevel": "verbose",
+                }),
+                vec![],
+            ),
+        ];
+        for (spec, expected) in cases {
+            assert_eq!(policy(spec), expected);
+        }
+    }
+
+    #[test]
+    fn rejects_the_egress_proxy_uid() {
+        let spec = |uid| json!({ "image": "tool:1", "securityContext": { "runAsUser": uid } });
+        assert_eq!(
+            server(spec(65533)),
+            vec!["spec.securityContext.runAsUser 65533 is reserved for the egress proxy"]
+        );
+        assert!(server(spec(65532)).is_empty());
+    }
+}
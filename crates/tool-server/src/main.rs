@@ -1,6 +1,9 @@
 use anyhow::Context;
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
 use axum::{extract::State, http::StatusCode, routing::get, routing::post, Json, Router};
-use serde::{Deserialize, Serialize};
+use latchkey_core::trace::{Span, SpanKind, TraceContext, Tracer};
+use serde::Serialize;
 use serde_json::{json, Value};
 use std::{net::SocketAddr, time::Duration};
 use tracing::{error, info};
@@ -10,21 +13,44 @@ const DEFAULT_BIND: &str = "0.0.0.0:8081";
 const DEFAULT_UPSTREAM_URL: &str =
     "http://latchkey-upstream-stub.latchkey-system.svc.cluster.local:8082/v1/upstream";
 const FALLBACK_UPSTREAM_KEY: &str = "dev-upstream-key";
+const PROTOCOL_VERSION: &str = "2025-06-18";
+const TOOL_NAME: &str = "demo.echo";
 
 #[derive(Clone)]
 struct AppState {
     upstream_url: String,
-    upstream_api_key: String,
+    upstream_api_key: UpstreamKey,
     client: reqwest::Client,
+    tracer: Tracer,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct ToolRequest {
-    tool_name: String,
-    #[serde(default)]
-    operation: Option<String>,
-    #[serde(default)]
-    params: Value,
+/// File bindings are re-read per call so credentials rotated on disk (for example by the Vault
+/// agent) take effect without a restart.
+#[derive(Clone)]
+enum UpstreamKey {
+    Static(String),
+    File(String),
+}
+
+impl UpstreamKey {
+    async fn current(&self) -> anyhow::Result<String> {
+        match self {
+            Self::Static(key) => Ok(key.clone()),
+            Self::File(path) => {
+                let key = tokio::fs::read_to_string(path)
+                    .await
+                    .with_context(|| format!("failed to read UPSTREAM_API_KEY_FILE {path}"))?;
+                Ok(key.trim().to_string())
+            }
+        }
+    }
+}
+
+/// Body of the upstream stub's `/v1/upstream` API.
+#[derive(Debug, Serialize)]
+struct UpstreamRequest<'a> {
+    tool_name: &'a str,
+    params: &'a Value,
 }
 
 #[tokio::main]
@@ -37,10 +63,8 @@ async fn main() -> anyhow::Result<()> {
 
     let state = AppState::from_env()?;
 
-    let app = Router::new()
-        .route("/healthz", get(healthz))
-        .route("/v1/tool", post(call_tool))
-        .with_state(state);
+    let app =
+        Router::new().route("/healthz", get(healthz)).route("/mcp", post(mcp)).with_state(state);
 
     let listener = tokio::net::TcpListener::bind(addr)
         .await
@@ -54,59 +78,155 @@ async fn healthz() -> StatusCode {
     StatusCode::OK
 }
 
-async fn call_tool(
+/// Stateless MCP Streamable HTTP endpoint exposing the single `demo.echo` tool.
+async fn mcp(
     State(state): State<AppState>,
-    Json(request): Json<ToolRequest>,
-) -> (StatusCode, Json<Value>) {
-    let response = state
+    headers: HeaderMap,
+    Json(message): Json<Value>,
+) -> Response {
+    let parent = TraceContext::extract(|name| headers.get(name)?.to_str().ok());
+    let mut span = state.tracer.span("POST /mcp", SpanKind::Server, parent.as_ref());
+    let Some(method) = message.get("method").and_then(Value::as_str) else {
+        let error = json!({ "code": -32600, "message": "invalid request" });
+        let body = json!({ "jsonrpc": "2.0", "id": message.get("id"), "error": error });
+        return (StatusCode::BAD_REQUEST, Json(body)).into_response();
+    };
+    let Some(id) = message.get("id").cloned() else {
+        return StatusCode::ACCEPTED.into_response();
+    };
+    span.set_attribute("rpc.method", method);
+
+    let result = match method {
+        "initialize" => Ok(json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": { "tools": {} },
+            "serverInfo": { "name": "latchkey-tool-server", "version": env!("CARGO_PKG_VERSION") },
+        })),
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(json!({ "tools": [echo_tool()] })),
+        "tools/call" => call_tool(&state, &span, &message["params"]).await,
+        _ => Err(json!({ "code": -32601, "message": "method not found" })),
+    };
+
+    let body = match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => {
+            span.set_error(error["message"].as_str().unwrap_or_default());
+            json!({ "jsonrpc": "2.0", "id": id, "error": error })
+        }
+    };
+    Json(body).into_response()
+}
+
+fn echo_tool() -> Value {
+    json!({
+        "name": TOOL_NAME,
+        "description": "Echoes the message back through the upstream stub.",
+        "inputSchema": {
+            "type": "object",
+            "properties": { "message": { "type": "string" } },
+        },
+    })
+}
+
+/// Upstream failures are tool errors (`isError`), not protocol errors, so the model sees them.
+async fn call_tool(state: &AppState, span: &Span, params: &Value) -> Result<Value, Value> {
+    let tool_name = params.get("name").and_then(Value::as_str).unwrap_or_default();
+    if tool_name != TOOL_NAME {
+        return Err(json!({ "code": -32602, "message": format!("unknown tool {tool_name:?}") }));
+    }
+    let arguments = params.get("arguments").cloned().unwrap_or_else(|| json!({}));
+
+    let upstream_api_key = match state.upstream_api_key.current().await {
+        Ok(key) => key,
+        Err(err) => {
+            error!(error = %format!("{err:#}"), "upstream credential unavailable");
+            return Ok(tool_error("upstream_credential"));
+        }
+    };
+
+    let mut span = span.child("upstream call", SpanKind::Client);
+    let mut request = state
         .client
         .post(&state.upstream_url)
-        .header("x-api-key", &state.upstream_api_key)
-        .json(&request)
-        .send()
-        .await;
+        .header("x-api-key", upstream_api_key)
+        .json(&UpstreamRequest { tool_name, params: &arguments });
+    for (name, value) in span.context().headers() {
+        request = request.header(name, value);
+    }
+    let response = request.send().await;
+    if let Some(status) = response.as_ref().ok().map(reqwest::Response::status) {
+        span.set_attribute("http.response.status_code", status.as_u16());
+    }
 
     match response {
         Ok(response) if response.status().is_success() => {
             let payload =
                 response.json::<Value>().await.unwrap_or_else(|_| json!({"status": "ok"}));
 
-            (
-                StatusCode::OK,
-                Json(json!({
-                    "tool_name": request.tool_name,
-                    "operation": request.operation,
-                    "upstream": payload,
-                })),
-            )
+            Ok(json!({
+                "content": [{ "type": "text", "text": payload.to_string() }],
+                "structuredContent": payload,
+                "isError": false,
+            }))
         }
         Ok(response) => {
+            span.set_error("upstream_error");
             error!(status = %response.status(), "upstream returned error status");
-            (StatusCode::BAD_GATEWAY, Json(json!({"error": "upstream_error"})))
+            Ok(tool_error("upstream_error"))
         }
         Err(err) => {
+            span.set_error("upstream_unreachable");
             error!(error = %err, "upstream request failed");
-            (StatusCode::BAD_GATEWAY, Json(json!({"error": "upstream_unreachable"})))
+            Ok(tool_error("upstream_unreachable"))
         }
     }
 }
 
+fn tool_error(reason: &str) -> Value {
+    json!({ "content": [{ "type": "text", "text": reason }], "isError": true })
+}
+
 impl AppState {
     fn from_env() -> anyhow::Result<Self> {
         let upstream_url = std::env::var("LATCHKEY_UPSTREAM_URL")
             .unwrap_or_else(|_| DEFAULT_UPSTREAM_URL.to_string());
-        let upstream_api_key = std::env::var("UPSTREAM_API_KEY")
-            .ok()
-            .filter(|value| !value.trim().is_empty())
-            .unwrap_or_else(|| FALLBACK_UPSTREAM_KEY.to_string());
+        let upstream_api_key = upstream_api_key()?;
 
         let client = reqwest::Client::builder()
             .timeout(Duration::from_secs(3))
             .build()
             .context("failed to construct upstream client")?;
+        let tracer = Tracer::from_env("latchkey-tool-server")
+            .context("failed to construct trace exporter")?;
+
+        Ok(Self { upstream_url, upstream_api_key, client, tracer })
+    }
+}
+
+/// Reads the key from an operator file binding (`UPSTREAM_API_KEY_FILE`) or an env binding
+/// (`UPSTREAM_API_KEY`), in that order. A file binding must be readable at boot.
+fn upstream_api_key() -> anyhow::Result<UpstreamKey> {
+    resolve_upstream_api_key(
+        std::env::var("UPSTREAM_API_KEY_FILE").ok(),
+        std::env::var("UPSTREAM_API_KEY").ok(),
+    )
+}
 
-        Ok(Self { upstream_url, upstream_api_key, client })
+fn resolve_upstream_api_key(
+    file: Option<String>,
+    key: Option<String>,
+) -> anyhow::Result<UpstreamKey> {
+    if let Some(path) = file {
+        std::fs::metadata(&path)
+            .with_context(|| format!("failed to read UPSTREAM_API_KEY_FILE {path}"))?;
+        return Ok(UpstreamKey::File(path));
     }
+
+    Ok(UpstreamKey::Static(
+        key.filter(|value| !value.trim().is_empty())
+            .unwrap_or_else(|| FALLBACK_UPSTREAM_KEY.to_string()),
+    ))
 }
 
 fn init_tracing() {
@@ -119,3 +239,42 @@ fn init_tracing() {
         .with_span_list(false)
         .init();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn file_bindings_win_and_are_reread_on_rotation() {
+        let path =
+            std::env::temp_dir().join(format!("latchkey-tool-server-{}", std::process::id()));
+        std::fs::write(&path, "first\n").unwrap();
+        let path = path.to_string_lossy().into_owned();
+
+        let key = resolve_upstream_api_key(Some(path.clone()), Some("from-env".to_string()));
+        let key = key.unwrap();
+        assert!(matches!(&key, UpstreamKey::File(file) if *file == path));
+        assert_eq!(key.current().await.unwrap(), "first");
+
+        std::fs::write(&path, "second\n").unwrap();
+        assert_eq!(key.current().await.unwrap(), "second");
+
+        std::fs::remove_file(&path).unwrap();
+        let err = key.current().await.unwrap_err();
+        assert_eq!(err.to_string(), format!("failed to read UPSTREAM_API_KEY_FILE {path}"));
+        let err = resolve_upstream_api_key(Some(path.clone()), None).err().unwrap();
+        assert_eq!(err.to_string(), format!("failed to read UPSTREAM_API_KEY_FILE {path}"));
+    }
+
+    #[tokio::test]
+    async fn env_bindings_are_static_with_a_dev_fallback() {
+        let key = resolve_upstream_api_key(None, Some("from-env".to_string())).unwrap();
+        assert!(matches!(&key, UpstreamKey::Static(value) if value == "from-env"));
+        assert_eq!(key.current().await.unwrap(), "from-env");
+
+        for unset in [None, Some(" ".to_string())] {
+            let key = resolve_upstream_api_key(None, unset).unwrap();
+            assert_eq!(key.current().await.unwrap(), FALLBACK_UPSTREAM_KEY);
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use anyhow::Context;
+use axum::extract::State;
 use axum::{http::HeaderMap, http::StatusCode, routing::get, routing::post, Json, Router};
+use latchkey_core::trace::{SpanKind, TraceContext, Tracer};
 use serde::Deserialize;
 use serde_json::{json, Value};
 use std::net::SocketAddr;
@@ -25,8 +27,12 @@ async fn main() -> anyhow::Result<()> {
     let bind = std::env::var("LATCHKEY_UPSTREAM_BIND").unwrap_or_else(|_| DEFAULT_BIND.to_string());
     let addr: SocketAddr = bind.parse().context("invalid LATCHKEY_UPSTREAM_BIND value")?;
 
-    let app =
-        Router::new().route("/healthz", get(healthz)).route("/v1/upstream", post(upstream_call));
+    let tracer =
+        Tracer::from_env("latchkey-upstream-stub").context("failed to construct trace exporter")?;
+    let app = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/v1/upstream", post(upstream_call))
+        .with_state(tracer);
 
     let listener = tokio::net::TcpListener::bind(addr)
         .await
@@ -41,9 +47,13 @@ async fn healthz() -> StatusCode {
 }
 
 async fn upstream_call(
+    State(tracer): State<Tracer>,
     headers: HeaderMap,
     Json(request): Json<UpstreamRequest>,
 ) -> (StatusCode, Json<Value>) {
+    let parent = TraceContext::extract(|name| headers.get(name)?.to_str().ok());
+    let mut span = tracer.span("POST /v1/upstream", SpanKind::Server, parent.as_ref());
+
     let expected = std::env::var("EXPECTED_API_KEY")
         .ok()
         .filter(|value| !value.trim().is_empty())
@@ -53,6 +63,7 @@ async fn upstream_call(
         headers.get("x-api-key").and_then(|value| value.to_str().ok()).unwrap_or_default();
 
     if provided != expected {
+        span.set_error("invalid_api_key");
         return (StatusCode::UNAUTHORIZED, Json(json!({"error": "invalid_api_key"})));
     }
 
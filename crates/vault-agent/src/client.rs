@@ -0,0 +1,165 @@
+use anyhow::Context;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fmt;
+use std::time::Duration;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Minimal Vault HTTP API client: Kubernetes auth login, secret reads, and lease renewal.
+#[derive(Clone)]
+pub struct VaultClient {
+    http: reqwest::Client,
+    address: String,
+}
+
+#[derive(Clone)]
+pub struct Login {
+    pub token: String,
+    /// Zero for tokens that never expire.
+    pub lease_duration: Duration,
+}
+
+impl fmt::Debug for Login {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Login")
+            .field("lease_duration", &self.lease_duration)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Lease {
+    /// Empty for KV reads, which are not leased.
+    pub id: Option<String>,
+    pub duration: Duration,
+    pub renewable: bool,
+}
+
+pub struct SecretValue {
+    pub value: String,
+    pub lease: Lease,
+}
+
+impl fmt::Debug for SecretValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretValue").field("lease", &self.lease).finish_non_exhaustive()
+    }
+}
+
+#[derive(Deserialize)]
+struct AuthResponse {
+    auth: AuthBody,
+}
+
+#[derive(Deserialize)]
+struct AuthBody {
+    client_token: String,
+    #[serde(default)]
+    lease_duration: u64,
+}
+
+#[derive(Deserialize)]
+struct LeaseResponse {
+    #[serde(default)]
+    lease_id: String,
+    #[serde(default)]
+    lease_duration: u64,
+    #[serde(default)]
+    renewable: bool,
+    #[serde(default)]
+    data: Value,
+}
+
+impl LeaseResponse {
+    fn lease(&self) -> Lease {
+        Lease {
+            id: Some(self.lease_id.clone()).filter(|id| !id.is_empty()),
+            duration: Duration::from_secs(self.lease_duration),
+            renewable: self.renewable,
+        }
+    }
+}
+
+impl VaultClient {
+    pub fn new(address: &str) -> anyhow::Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .context("failed to construct vault client")?;
+
+        Ok(Self { http, address: address.trim_end_matches('/').to_string() })
+    }
+
+    pub async fn login(&self, auth_mount: &str, role: &str, jwt: &str) -> anyhow::Result<Login> {
+        let response: AuthResponse = self
+            .http
+            .post(self.url(&format!("auth/{auth_mount}/login")))
+            .json(&json!({ "role": role, "jwt": jwt }))
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .with_context(|| format!("vault login via auth/{auth_mount} as role {role} failed"))?
+            .json()
+            .await
+            .context("vault login returned an unexpected body")?;
+
+        Ok(Login {
+            token: response.auth.client_token,
+            lease_duration: Duration::from_secs(response.auth.lease_duration),
+        })
+    }
+
+    /// Reads `key` from `path`, looking inside `data.data` first so KV v2 paths work unchanged.
+    pub async fn read(&self, token: &str, path: &str, key: &str) -> anyhow::Result<SecretValue> {
+        let response: LeaseResponse = self
+            .http
+            .get(self.url(path))
+            .header("X-Vault-Token", token)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .with_context(|| format!("vault read of {path} failed"))?
+            .json()
+            .await
+            .with_context(|| format!("vault read of {path} returned an unexpected body"))?;
+
+        let data = match response.data.get("data") {
+            Some(Value::Object(inner)) if inner.contains_key(key) => &response.data["data"],
+            _ => &response.data,
+        };
+        let value = match data.get(key) {
+            Some(Value::String(value)) => value.clone(),
+            Some(other) => other.to_string(),
+            None => anyhow::bail!("key {key} not found at {path}"),
+        };
+
+        Ok(SecretValue { value, lease: response.lease() })
+    }
+
+    pub async fn renew(
+        &self,
+        token: &str,
+        lease_id: &str,
+        increment: Duration,
+    ) -> anyhow::Result<Lease> {
+        let response: LeaseResponse = self
+            .http
+            .put(self.url("sys/leases/renew"))
+            .header("X-Vault-Token", token)
+            .json(&json!({ "lease_id": lease_id, "increment": increment.as_secs() }))
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .with_context(|| format!("vault renewal of lease {lease_id} failed"))?
+            .json()
+            .await
+            .context("vault renewal returned an unexpected body")?;
+
+        Ok(response.lease())
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/v1/{}", self.address, path.trim_start_matches('/'))
+    }
+}
@@ -0,0 +1,31 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/// Owner read-only, matching the mode the operator uses for Kubernetes Secret file mounts.
+const FILE_MODE: u32 = 0o400;
+
+/// Replaces `path` with `contents` so readers only ever see the old or the new value.
+///
+/// The value is written to a hidden sibling, synced, and renamed over the target; rename within a
+/// directory is atomic, so a tool server re-reading the file never observes a partial write.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let dir = path.parent().ok_or_else(|| io::Error::other("target has no parent directory"))?;
+    let name = path.file_name().ok_or_else(|| io::Error::other("target has no file name"))?;
+    let staging = dir.join(format!(".{}.tmp", name.to_string_lossy()));
+
+    // A read-only leftover from an interrupted write cannot be reopened for writing.
+    match fs::remove_file(&staging) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+
+    let mut file =
+        OpenOptions::new().write(true).create_new(true).mode(FILE_MODE).open(&staging)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&staging, path)?;
+
+    Ok(())
+}
@@ -0,0 +1,9 @@
+//! Keeps Vault-sourced credentials on disk for a tool server.
+//!
+//! The operator injects this agent as a native sidecar whenever a `LatchkeyServer` binds a
+//! `vaultSecret`. It logs in with the Kubernetes auth method, writes each value to its file, and
+//! then renews the lease (or re-reads the path) so the file always holds a live credential.
+
+pub mod client;
+pub mod file;
+pub mod worker;
@@ -0,0 +1,64 @@
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode, routing::get, Router};
+use latchkey_core::vault::{
+    AgentBinding, AGENT_CONFIG_ENV, AGENT_HEALTH_PORT, AGENT_TOKEN_DIR, AGENT_TOKEN_FILE,
+};
+use latchkey_vault_agent::worker::Worker;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    init_tracing();
+
+    let config = std::env::var(AGENT_CONFIG_ENV)
+        .with_context(|| format!("{AGENT_CONFIG_ENV} must be set"))?;
+    let bindings: Vec<AgentBinding> = serde_json::from_str(&config)
+        .with_context(|| format!("invalid {AGENT_CONFIG_ENV} value"))?;
+    let jwt_path = PathBuf::from(
+        std::env::var("LATCHKEY_VAULT_TOKEN_PATH")
+            .unwrap_or_else(|_| format!("{AGENT_TOKEN_DIR}/{AGENT_TOKEN_FILE}")),
+    );
+    let bind = std::env::var("LATCHKEY_VAULT_AGENT_BIND")
+        .unwrap_or_else(|_| format!("0.0.0.0:{AGENT_HEALTH_PORT}"));
+    let addr: SocketAddr = bind.parse().context("invalid LATCHKEY_VAULT_AGENT_BIND value")?;
+
+    let mut ready = Vec::with_capacity(bindings.len());
+    for binding in bindings {
+        let worker = Worker::new(binding, &jwt_path)?;
+        ready.push(worker.ready());
+        tokio::spawn(worker.run());
+    }
+
+    // Backs the sidecar's startup probe, which holds the tool server until every file exists.
+    let app = Router::new().route("/healthz", get(healthz)).with_state(Arc::new(ready));
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("failed to bind vault agent listener on {addr}"))?;
+
+    info!(%addr, "vault agent booted");
+    axum::serve(listener, app).await.context("vault agent failed")
+}
+
+async fn healthz(State(ready): State<Arc<Vec<Arc<AtomicBool>>>>) -> StatusCode {
+    if ready.iter().all(|ready| ready.load(Ordering::Acquire)) {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+fn init_tracing() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    tracing_subscriber::fmt()
+        .json()
+        .with_env_filter(filter)
+        .with_current_span(false)
+        .with_span_list(false)
+        .init();
+}
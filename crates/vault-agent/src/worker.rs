@@ -0,0 +1,133 @@
+use crate::client::{Lease, VaultClient};
+use crate::file;
+use anyhow::Context;
+use latchkey_core::vault::AgentBinding;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+use tracing::{info, warn};
+
+/// How often unleased values (KV) are re-read to pick up rotations.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Leases renewed to less than this are about to hit their max TTL, so a fresh credential is
+/// issued instead of renewing into expiry.
+const MIN_LEASE: Duration = Duration::from_secs(30);
+
+/// Keeps one binding's file current.
+pub struct Worker {
+    binding: AgentBinding,
+    client: VaultClient,
+    jwt_path: PathBuf,
+    session: Option<Session>,
+    ready: Arc<AtomicBool>,
+}
+
+struct Session {
+    token: String,
+    /// `None` for tokens without a TTL.
+    refresh_at: Option<Instant>,
+}
+
+impl Worker {
+    pub fn new(binding: AgentBinding, jwt_path: &Path) -> anyhow::Result<Self> {
+        let client = VaultClient::new(&binding.address)?;
+
+        Ok(Self {
+            binding,
+            client,
+            jwt_path: jwt_path.to_path_buf(),
+            session: None,
+            ready: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Set once the file has been written at least once.
+    pub fn ready(&self) -> Arc<AtomicBool> {
+        self.ready.clone()
+    }
+
+    pub async fn run(mut self) {
+        loop {
+            match self.sync().await {
+                Ok(lease) => self.hold(lease).await,
+                Err(err) => {
+                    warn!(binding = %self.binding.name, error = %format!("{err:#}"), "vault sync failed");
+                    sleep(RETRY_INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    /// Reads the current value and rewrites the file, logging in first if needed.
+    pub async fn sync(&mut self) -> anyhow::Result<Lease> {
+        let token = self.token().await?;
+        let secret = self.client.read(&token, &self.binding.path, &self.binding.key).await?;
+
+        file::write_atomic(Path::new(&self.binding.file), secret.value.as_bytes())
+            .with_context(|| format!("failed to write {}", self.binding.file))?;
+        self.ready.store(true, Ordering::Release);
+        info!(binding = %self.binding.name, lease = ?secret.lease.id, "credential written");
+
+        Ok(secret.lease)
+    }
+
+    /// Renews `lease` for as long as Vault allows, returning when a fresh read is due.
+    pub async fn hold(&mut self, mut lease: Lease) {
+        let Some(id) = lease.id.clone().filter(|_| lease.renewable) else {
+            let wait =
+                if lease.duration.is_zero() { REFRESH_INTERVAL } else { due(lease.duration) };
+            sleep(wait).await;
+            return;
+        };
+
+        loop {
+            sleep(due(lease.duration)).await;
+
+            let renewed = match self.token().await {
+                Ok(token) => self.client.renew(&token, &id, lease.duration).await,
+                Err(err) => Err(err),
+            };
+            match renewed {
+                Ok(next) if next.renewable && next.duration >= MIN_LEASE => lease = next,
+                Ok(_) => {
+                    info!(binding = %self.binding.name, "lease at max ttl, issuing a new credential");
+                    return;
+                }
+                Err(err) => {
+                    warn!(binding = %self.binding.name, error = %format!("{err:#}"), "lease renewal failed");
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn token(&mut self) -> anyhow::Result<String> {
+        if let Some(session) = &self.session {
+            if session.refresh_at.is_none_or(|at| Instant::now() < at) {
+                return Ok(session.token.clone());
+            }
+        }
+
+        // Projected tokens rotate on disk, so read a fresh one for every login.
+        let jwt = tokio::fs::read_to_string(&self.jwt_path)
+            .await
+            .with_context(|| format!("failed to read {}", self.jwt_path.display()))?;
+        let login =
+            self.client.login(&self.binding.auth_mount, &self.binding.role, jwt.trim()).await?;
+        let refresh_at = Some(login.lease_duration)
+            .filter(|ttl| !ttl.is_zero())
+            .map(|ttl| Instant::now() + due(ttl));
+
+        self.session = Some(Session { token: login.token.clone(), refresh_at });
+        Ok(login.token)
+    }
+}
+
+/// Acts at two thirds of a TTL, leaving headroom for a slow or failed attempt.
+fn due(ttl: Duration) -> Duration {
+    ttl * 2 / 3
+}
@@ -0,0 +1,158 @@
+//! Exercises the agent against an in-process stub of the Vault HTTP API.
+
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use latchkey_core::vault::AgentBinding;
+use latchkey_vault_agent::worker::Worker;
+use serde_json::{json, Value};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+const JWT: &str = "service-account-jwt";
+const TOKEN: &str = "hvs.stub-token";
+const ROLE: &str = "tool-server";
+
+#[derive(Default)]
+struct Stub {
+    issued: AtomicUsize,
+    renewals: AtomicUsize,
+}
+
+async fn login(Json(body): Json<Value>) -> (StatusCode, Json<Value>) {
+    if body["jwt"] != JWT || body["role"] != ROLE {
+        return (StatusCode::FORBIDDEN, Json(json!({ "errors": ["permission denied"] })));
+    }
+    let auth = json!({ "client_token": TOKEN, "lease_duration": 3600, "renewable": true });
+    (StatusCode::OK, Json(json!({ "auth": auth })))
+}
+
+fn authorized(headers: &HeaderMap) -> bool {
+    headers.get("x-vault-token").is_some_and(|token| token == TOKEN)
+}
+
+async fn kv(headers: HeaderMap) -> (StatusCode, Json<Value>) {
+    if !authorized(&headers) {
+        return (StatusCode::FORBIDDEN, Json(json!({ "errors": ["permission denied"] })));
+    }
+    let data = json!({ "data": { "api-key": "kv-value" }, "metadata": { "version": 1 } });
+    (StatusCode::OK, Json(json!({ "lease_duration": 0, "renewable": false, "data": data })))
+}
+
+async fn dynamic(State(stub): State<Arc<Stub>>, headers: HeaderMap) -> (StatusCode, Json<Value>) {
+    if !authorized(&headers) {
+        return (StatusCode::FORBIDDEN, Json(json!({ "errors": ["permission denied"] })));
+    }
+    let issued = stub.issued.fetch_add(1, Ordering::SeqCst) + 1;
+    (
+        StatusCode::OK,
+        Json(json!({
+            "lease_id": format!("database/creds/readonly/{issued}"),
+            "lease_duration": 1,
+            "renewable": true,
+            "data": { "username": "v-readonly", "password": format!("password-{issued}") },
+        })),
+    )
+}
+
+/// Always renews short of the agent's minimum, as Vault does when a lease nears its max TTL.
+async fn renew(State(stub): State<Arc<Stub>>, Json(body): Json<Value>) -> Json<Value> {
+    stub.renewals.fetch_add(1, Ordering::SeqCst);
+    Json(json!({ "lease_id": body["lease_id"], "lease_duration": 1, "renewable": true }))
+}
+
+async fn serve_stub() -> (String, Arc<Stub>) {
+    let stub = Arc::new(Stub::default());
+    let app = Router::new()
+        .route("/v1/auth/kubernetes/login", post(login))
+        .route("/v1/secret/data/github", get(kv))
+        .route("/v1/database/creds/readonly", get(dynamic))
+        .route("/v1/sys/leases/renew", put(renew))
+        .with_state(stub.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    (format!("http://{addr}"), stub)
+}
+
+fn scratch_dir(test: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("latchkey-vault-agent-{}-{test}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("jwt"), JWT).unwrap();
+    dir
+}
+
+fn binding(
+    address: &str,
+    dir: &std::path::Path,
+    role: &str,
+    path: &str,
+    key: &str,
+) -> AgentBinding {
+    AgentBinding {
+        name: "upstream".to_string(),
+        address: address.to_string(),
+        auth_mount: "kubernetes".to_string(),
+        role: role.to_string(),
+        path: path.to_string(),
+        key: key.to_string(),
+        file: dir.join(key).display().to_string(),
+    }
+}
+
+#[tokio::test]
+async fn kv_value_is_written_read_only() {
+    let (address, _) = serve_stub().await;
+    let dir = scratch_dir("kv");
+    let binding = binding(&address, &dir, ROLE, "secret/data/github", "api-key");
+    let mut worker = Worker::new(binding, &dir.join("jwt")).unwrap();
+    let ready = worker.ready();
+
+    let lease = worker.sync().await.unwrap();
+    assert_eq!(lease.id, None);
+    assert!(ready.load(Ordering::Acquire));
+
+    // A second sync must replace the read-only file rather than fail on it.
+    worker.sync().await.unwrap();
+    assert_eq!(std::fs::read_to_string(dir.join("api-key")).unwrap(), "kv-value");
+    let mode = std::fs::metadata(dir.join("api-key")).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o400);
+}
+
+#[tokio::test]
+async fn dynamic_credential_is_reissued_when_renewal_runs_short() {
+    let (address, stub) = serve_stub().await;
+    let dir = scratch_dir("dynamic");
+    let binding = binding(&address, &dir, ROLE, "database/creds/readonly", "password");
+    let mut worker = Worker::new(binding, &dir.join("jwt")).unwrap();
+
+    let lease = worker.sync().await.unwrap();
+    assert_eq!(lease.id.as_deref(), Some("database/creds/readonly/1"));
+    assert_eq!(std::fs::read_to_string(dir.join("password")).unwrap(), "password-1");
+
+    worker.hold(lease).await;
+    assert_eq!(stub.renewals.load(Ordering::SeqCst), 1);
+
+    worker.sync().await.unwrap();
+    assert_eq!(std::fs::read_to_string(dir.join("password")).unwrap(), "password-2");
+}
+
+#[tokio::test]
+async fn rejected_login_leaves_binding_unready() {
+    let (address, _) = serve_stub().await;
+    let dir = scratch_dir("rejected");
+    let binding = binding(&address, &dir, "someone-else", "secret/data/github", "api-key");
+    let mut worker = Worker::new(binding, &dir.join("jwt")).unwrap();
+    let ready = worker.ready();
+
+    let err = worker.sync().await.unwrap_err();
+    assert!(format!("{err:#}").contains("vault login"));
+    assert!(!ready.load(Ordering::Acquire));
+    assert!(!dir.join("api-key").exists());
+}
@@ -44,6 +44,25 @@
           cargoTestFlags = [ "-p" "latchkey-upstream-stub" ];
         });
 
+        vaultAgent = rustPlatform.buildRustPackage (commonArgs // {
+          pname = "latchkey-vault-agent";
+          cargoBuildFlags = [ "-p" "latchkey-vault-agent" ];
+          cargoTestFlags = [ "-p" "latchkey-vault-agent" ];
+        });
+
+        egressProxy = rustPlatform.buildRustPackage (commonArgs // {
+          pname = "latchkey-egress-proxy";
+          cargoBuildFlags = [ "-p" "latchkey-egress-proxy" ];
+          cargoTestFlags = [ "-p" "latchkey-egress-proxy" ];
+        });
+
+        # Statically linked: the operator copies it into arbitrary stdio tool server images.
+        mcpBridge = pkgs.pkgsStatic.rustPlatform.buildRustPackage (commonArgs // {
+          pname = "latchkey-mcp-bridge";
+          cargoBuildFlags = [ "-p" "latchkey-mcp-bridge" ];
+          cargoTestFlags = [ "-p" "latchkey-mcp-bridge" ];
+        });
+
         gatewayImage = pkgs.dockerTools.buildLayeredImage {
           name = "latchkey-gateway";
           tag = "dev";
@@ -97,6 +116,48 @@
           };
         };
 
+        vaultAgentImage = pkgs.dockerTools.buildLayeredImage {
+          name = "latchkey-vault-agent";
+          tag = "dev";
+          contents = [ vaultAgent pkgs.cacert ];
+          config = {
+            Cmd = [ "${vaultAgent}/bin/latchkey-vault-agent" ];
+            ExposedPorts = {
+              "8099/tcp" = { };
+            };
+            Env = [ "RUST_LOG=info" ];
+            User = "65532:65532";
+          };
+        };
+
+        egressProxyImage = pkgs.dockerTools.buildLayeredImage {
+          name = "latchkey-egress-proxy";
+          tag = "dev";
+          contents = [ egressProxy pkgs.cacert ];
+          config = {
+            Cmd = [ "${egressProxy}/bin/latchkey-egress-proxy" ];
+            Env = [ "RUST_LOG=info" ];
+            User = "65533:65533";
+          };
+        };
+
+        # Runs the operator-generated firewall script ahead of the egress proxy sidecar.
+        egressInitImage = pkgs.dockerTools.buildLayeredImage {
+          name = "latchkey-egress-init";
+          tag = "dev";
+          contents = [ pkgs.busybox pkgs.iptables ];
+        };
+
+        mcpBridgeImage = pkgs.dockerTools.buildLayeredImage {
+          name = "latchkey-mcp-bridge";
+          tag = "dev";
+          contents = [ mcpBridge ];
+          config = {
+            Cmd = [ "${mcpBridge}/bin/latchkey-mcp-bridge" ];
+            User = "65532:65532";
+          };
+        };
+
         bootstrapBundle = pkgs.linkFarm "latchkey-bootstrap" [
           {
             name = "gateway";
@@ -114,6 +175,18 @@
             name = "upstream-stub";
             path = upstreamStub;
           }
+          {
+            name = "vault-agent";
+            path = vaultAgent;
+          }
+          {
+            name = "egress-proxy";
+            path = egressProxy;
+          }
+          {
+            name = "mcp-bridge";
+            path = mcpBridge;
+          }
           {
             name = "gateway-image";
             path = gatewayImage;
@@ -130,6 +203,22 @@
             name = "upstream-stub-image";
             path = upstreamStubImage;
           }
+          {
+            name = "vault-agent-image";
+            path = vaultAgentImage;
+          }
+          {
+            name = "egress-proxy-image";
+            path = egressProxyImage;
+          }
+          {
+            name = "egress-init-image";
+            path = egressInitImage;
+          }
+          {
+            name = "mcp-bridge-image";
+            path = mcpBridgeImage;
+          }
         ];
       in {
         packages = {
@@ -140,6 +229,13 @@
           operator-image = operatorImage;
           tool-server-image = toolServerImage;
           upstream-stub-image = upstreamStubImage;
+          vault-agent = vaultAgent;
+          vault-agent-image = vaultAgentImage;
+          egress-proxy = egressProxy;
+          egress-proxy-image = egressProxyImage;
+          egress-init-image = egressInitImage;
+          mcp-bridge = mcpBridge;
+          mcp-bridge-image = mcpBridgeImage;
           default = bootstrapBundle;
         };
 
@@ -148,6 +244,9 @@
           operator = flake-utils.lib.mkApp { drv = operator; };
           tool-server = flake-utils.lib.mkApp { drv = toolServer; };
           upstream-stub = flake-utils.lib.mkApp { drv = upstreamStub; };
+          vault-agent = flake-utils.lib.mkApp { drv = vaultAgent; };
+          egress-proxy = flake-utils.lib.mkApp { drv = egressProxy; };
+          mcp-bridge = flake-utils.lib.mkApp { drv = mcpBridge; };
         };
 
         devShells.default = pkgs.mkShell {